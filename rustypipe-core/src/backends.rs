@@ -0,0 +1,3724 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use async_trait::async_trait;
+use tokio::process::{Child, Command};
+use tokio::sync::watch;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// When set, every backend prints the exact invocation it is about to spawn.
+static TRACE: AtomicBool = AtomicBool::new(false);
+
+/// Enable/disable `--trace` output for all backends.
+pub fn set_trace(enabled: bool) {
+    TRACE.store(enabled, Ordering::Relaxed);
+}
+
+/// Error returned when a backend command exceeds its timeout.
+#[derive(Debug)]
+pub struct TimedOut {
+    pub backend: String,
+    pub secs: u64,
+}
+
+impl std::fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} backend timed out after {}s", self.backend, self.secs)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Error returned when the run was cancelled (`stop_on_fail`, Ctrl+C) while a command was running.
+/// The command has been killed and reaped by then.
+#[derive(Debug)]
+pub struct Cancelled {
+    pub backend: String,
+}
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} backend command was cancelled", self.backend)
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Error returned when a command printed nothing for longer than its `no_output_timeout`.
+#[derive(Debug)]
+pub struct Stalled {
+    pub backend: String,
+    pub secs: u64,
+}
+
+impl std::fmt::Display for Stalled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} backend command produced no output for {}s", self.backend, self.secs)
+    }
+}
+
+impl std::error::Error for Stalled {}
+
+/// Whether `e` means the command was stopped from outside (timeout, stall or cancellation)
+fn interrupted(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<TimedOut>().is_some() || e.downcast_ref::<Stalled>().is_some() || e.downcast_ref::<Cancelled>().is_some()
+}
+
+/// What happens when a command stays silent past its `no_output_timeout`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NoOutputAction {
+    /// Stop the command like a timeout (`Stalled` error)
+    #[default]
+    Fail,
+    /// Log a warning once per silent stretch and keep waiting
+    Warn,
+}
+
+/// When a command last wrote to stdout or stderr, for `no_output_timeout`
+#[derive(Clone)]
+struct LastOutput(Arc<std::sync::Mutex<(std::time::Instant, bool)>>);
+
+impl LastOutput {
+    fn new() -> Self {
+        LastOutput(Arc::new(std::sync::Mutex::new((std::time::Instant::now(), false))))
+    }
+
+    /// Output arrived
+    fn touch(&self) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = (std::time::Instant::now(), false);
+    }
+
+    /// Apply `opts.no_output_timeout` to the silence so far: the `Stalled` error stopping the
+    /// command, or how long until the silence needs checking again
+    fn check(&self, backend: &str, opts: &RunOptions) -> Result<std::time::Duration, anyhow::Error> {
+        let Some(secs) = opts.no_output_timeout else { return Ok(std::time::Duration::MAX) };
+        let limit = std::time::Duration::from_secs(secs);
+        let mut state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let (since, warned) = &mut *state;
+        let idle = since.elapsed();
+        if idle < limit {
+            return Ok(limit - idle);
+        }
+        match opts.no_output_action {
+            NoOutputAction::Fail => Err(Stalled { backend: backend.to_string(), secs }.into()),
+            NoOutputAction::Warn => {
+                if !*warned {
+                    let label = opts.stream.as_ref().map(|s| format!("[{}] ", s.label)).unwrap_or_default();
+                    tracing::warn!("{}{} backend command produced no output for {}s", label, backend, secs);
+                    *warned = true;
+                }
+                Ok(limit)
+            }
+        }
+    }
+
+    /// Resolve with the `Stalled` error once `opts.no_output_timeout` is exceeded; never for
+    /// `NoOutputAction::Warn`
+    async fn stalled(&self, backend: &str, opts: &RunOptions) -> anyhow::Error {
+        if opts.no_output_timeout.is_none() {
+            return std::future::pending().await;
+        }
+        loop {
+            match self.check(backend, opts) {
+                Ok(wait) => tokio::time::sleep(wait).await,
+                Err(e) => return e,
+            }
+        }
+    }
+}
+
+/// Unique-enough name for containers, pods and remote pid files
+fn unique_name() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    format!("rustypipe-{}", now)
+}
+
+/// Resolve once `cancel` flips to true; never for `None` or a dropped sender
+pub async fn cancelled(cancel: Option<&watch::Receiver<bool>>) {
+    if let Some(rx) = cancel {
+        let mut rx = rx.clone();
+        if rx.wait_for(|c| *c).await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending::<()>().await
+}
+
+#[cfg(windows)]
+extern "system" {
+    fn GenerateConsoleCtrlEvent(event: u32, process_group: u32) -> i32;
+    fn CreateJobObjectW(attributes: *mut std::ffi::c_void, name: *const u16) -> *mut std::ffi::c_void;
+    fn AssignProcessToJobObject(job: *mut std::ffi::c_void, process: *mut std::ffi::c_void) -> i32;
+    fn TerminateJobObject(job: *mut std::ffi::c_void, exit_code: u32) -> i32;
+    fn CloseHandle(handle: *mut std::ffi::c_void) -> i32;
+}
+
+/// Job object holding a command and every process it starts, so they die together. The handle
+/// is kept as an integer so futures holding it stay `Send`.
+#[cfg(windows)]
+struct Job(isize);
+
+#[cfg(windows)]
+impl Job {
+    fn attach(child: &Child) -> Option<Job> {
+        let process = child.raw_handle()?;
+        // SAFETY: plain Win32 calls; `process` stays valid while `child` is alive.
+        unsafe {
+            let job = CreateJobObjectW(std::ptr::null_mut(), std::ptr::null());
+            if job.is_null() {
+                return None;
+            }
+            if AssignProcessToJobObject(job, process as *mut std::ffi::c_void) == 0 {
+                CloseHandle(job);
+                return None;
+            }
+            Some(Job(job as isize))
+        }
+    }
+}
+
+#[cfg(windows)]
+impl Drop for Job {
+    fn drop(&mut self) {
+        // SAFETY: the handle came from CreateJobObjectW and is closed once. Closing does not end
+        // the processes; only `ProcessTree::kill` does.
+        unsafe {
+            CloseHandle(self.0 as *mut std::ffi::c_void);
+        }
+    }
+}
+
+/// A spawned command and everything it starts: its own process group on unix, a job object
+/// plus a new console process group on Windows, so a backgrounded grandchild
+/// (`long_server & wait`) is stopped with the shell
+struct ProcessTree {
+    pid: Option<u32>,
+    #[cfg(windows)]
+    job: Option<Job>,
+}
+
+impl ProcessTree {
+    /// Track `child`, right after it was spawned
+    fn new(child: &Child) -> Self {
+        ProcessTree {
+            pid: child.id(),
+            #[cfg(windows)]
+            job: Job::attach(child),
+        }
+    }
+
+    /// Ask every process to exit: SIGTERM on unix, CTRL_BREAK on Windows. Returns whether the
+    /// request could be sent.
+    fn terminate(&self) -> bool {
+        let Some(pid) = self.pid else { return false };
+        #[cfg(unix)]
+        // SAFETY: kill(2) has no memory-safety requirements; -pid targets the child's group.
+        return unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGTERM) == 0 };
+        #[cfg(windows)]
+        // SAFETY: plain Win32 call without pointers; 1 is CTRL_BREAK_EVENT.
+        return unsafe { GenerateConsoleCtrlEvent(1, pid) != 0 };
+        #[allow(unreachable_code)]
+        false
+    }
+
+    /// Kill every process that is left
+    fn kill(&self, child: &mut Child) {
+        #[cfg(unix)]
+        if let Some(pid) = self.pid {
+            // SAFETY: kill(2) has no memory-safety requirements; -pid targets the child's group.
+            unsafe {
+                libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+            }
+        }
+        #[cfg(windows)]
+        if let Some(job) = &self.job {
+            // SAFETY: the handle is a live job object owned by `job`.
+            unsafe {
+                TerminateJobObject(job.0 as *mut std::ffi::c_void, 1);
+            }
+        }
+        let _ = child.start_kill();
+    }
+}
+
+/// Seconds a stopped command gets between the polite signal and SIGKILL (`grace_period`)
+pub const DEFAULT_GRACE_SECS: u64 = 10;
+
+/// Stop `child` after a timeout or cancellation: the polite signal first, SIGKILL once `grace`
+/// seconds passed (or right away with a grace of 0). Returns how it ended, for the error.
+async fn stop(child: &mut Child, tree: &ProcessTree, grace: u64) -> String {
+    let signal = if cfg!(windows) { "CTRL_BREAK" } else { "SIGTERM" };
+    let how = if grace > 0 && tree.terminate() {
+        match tokio::time::timeout(std::time::Duration::from_secs(grace), child.wait()).await {
+            Ok(_) => format!("exited on {}", signal),
+            Err(_) => format!("killed with SIGKILL after ignoring {} for {}s", signal, grace),
+        }
+    } else {
+        "killed with SIGKILL".to_string()
+    };
+    // also whatever the command left behind
+    tree.kill(child);
+    let _ = child.wait().await;
+    how
+}
+
+/// Wait for `child` to exit. On timeout, a stall (no output in `last` for too long) or
+/// cancellation it is stopped (see `stop`) and reaped before the matching `TimedOut` / `Stalled`
+/// / `Cancelled` error is returned, with how it ended as context.
+async fn supervise(backend: &str, child: &mut Child, timeout_secs: Option<u64>, last: &LastOutput, opts: &RunOptions) -> anyhow::Result<std::process::ExitStatus> {
+    let tree = ProcessTree::new(child);
+    let timeout = async {
+        match timeout_secs {
+            Some(secs) => tokio::time::sleep(std::time::Duration::from_secs(secs)).await,
+            None => std::future::pending().await,
+        }
+    };
+    let err: anyhow::Error = tokio::select! {
+        status = child.wait() => return status.with_context(|| format!("waiting for {} child failed", backend)),
+        _ = timeout => TimedOut { backend: backend.to_string(), secs: timeout_secs.unwrap_or_default() }.into(),
+        e = last.stalled(backend, opts) => e,
+        _ = cancelled(opts.cancel.as_ref()) => Cancelled { backend: backend.to_string() }.into(),
+    };
+    let how = stop(child, &tree, opts.grace_period.unwrap_or(DEFAULT_GRACE_SECS)).await;
+    Err(err.context(how))
+}
+
+/// Quote an argument for a POSIX shell (traced command lines, remote ssh commands).
+fn shell_quote(arg: &str) -> String {
+    let safe = !arg.is_empty()
+        && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./:=@,+%".contains(c));
+    if safe {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Print argv, cwd and env deltas of `c` when tracing is enabled; secrets are masked.
+fn trace_command(backend: &str, c: &Command) {
+    if !TRACE.load(Ordering::Relaxed) {
+        return;
+    }
+    use crate::pipeline::secrets::mask;
+    let std_cmd = c.as_std();
+    let mut argv = vec![shell_quote(&std_cmd.get_program().to_string_lossy())];
+    argv.extend(std_cmd.get_args().map(|a| shell_quote(&a.to_string_lossy())));
+    eprintln!("[trace] {} backend: {}", backend, mask(&argv.join(" ")));
+    if let Some(dir) = std_cmd.get_current_dir() {
+        eprintln!("[trace]   cwd: {}", dir.display());
+    }
+    for (k, v) in std_cmd.get_envs() {
+        match v {
+            Some(v) => eprintln!("[trace]   env: {}={}", k.to_string_lossy(), mask(&v.to_string_lossy())),
+            None => eprintln!("[trace]   env: unset {}", k.to_string_lossy()),
+        }
+    }
+}
+
+/// Output destination for a running command (see `RunOptions::stream`)
+#[derive(Debug, Clone)]
+pub struct OutputStream {
+    /// Printed in brackets before every console line, usually the task name
+    pub label: String,
+    /// Also show lines on the console (or as events) as they arrive, not just in the logs
+    pub echo: bool,
+    /// Files lines are appended to as they arrive
+    pub stdout_log: Option<std::path::PathBuf>,
+    pub stderr_log: Option<std::path::PathBuf>,
+}
+
+/// Bytes of each stream a streamed command keeps in memory; anything before is only in the log
+pub const OUTPUT_TAIL_BYTES: usize = 1 << 20;
+
+/// Start of the line replacing output cut from the in-memory tail (see `is_tail`)
+const OMITTED: &str = "[... ";
+
+/// Whether `output` is the tail of a longer stream rather than all of it
+pub fn is_tail(output: &str) -> bool {
+    output.starts_with(OMITTED) && output.lines().next().is_some_and(|l| l.contains(" bytes omitted"))
+}
+
+/// Splits streamed output into lines, writes each one to the log file (and the console with
+/// `echo`) and keeps the last `OUTPUT_TAIL_BYTES` for the final (stdout, stderr) result.
+struct LineSink {
+    label: String,
+    echo: bool,
+    to_stderr: bool,
+    path: Option<PathBuf>,
+    log: Option<std::io::BufWriter<std::fs::File>>,
+    partial: Vec<u8>,
+    tail: Vec<u8>,
+    omitted: usize,
+}
+
+impl LineSink {
+    fn new(stream: &OutputStream, to_stderr: bool) -> Self {
+        let path = if to_stderr { &stream.stderr_log } else { &stream.stdout_log };
+        let log = path.as_ref().and_then(|p| std::fs::OpenOptions::new().create(true).append(true).open(p).ok());
+        LineSink {
+            label: stream.label.clone(),
+            echo: stream.echo,
+            to_stderr,
+            path: path.clone(),
+            log: log.map(std::io::BufWriter::new),
+            partial: Vec::new(),
+            tail: Vec::new(),
+            omitted: 0,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        use std::io::Write;
+        self.tail.extend_from_slice(data);
+        // trimmed in batches, so the copy is amortized
+        if self.tail.len() > 2 * OUTPUT_TAIL_BYTES {
+            let cut = self.tail.len() - OUTPUT_TAIL_BYTES;
+            self.tail.drain(..cut);
+            self.omitted += cut;
+        }
+        self.partial.extend_from_slice(data);
+        while let Some(pos) = self.partial.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=pos).collect();
+            self.emit(&line[..line.len() - 1]);
+        }
+        // a stream without newlines (a binary dump) is written in pieces
+        if self.partial.len() > OUTPUT_TAIL_BYTES {
+            let rest = std::mem::take(&mut self.partial);
+            self.emit(&rest);
+        }
+        if let Some(f) = self.log.as_mut() {
+            let _ = f.flush();
+        }
+    }
+
+    fn emit(&mut self, line: &[u8]) {
+        use std::io::Write;
+        let text = String::from_utf8_lossy(line);
+        let text = crate::pipeline::secrets::mask(text.trim_end_matches('\r'));
+        let text = text.as_ref();
+        if self.echo {
+            if crate::util::json_output() || crate::util::tui() {
+                crate::pipeline::events::output(&self.label, self.to_stderr, text);
+            } else if self.to_stderr {
+                eprintln!("[{}] {}", self.label, text);
+            } else {
+                println!("[{}] {}", self.label, text);
+            }
+        }
+        if let Some(f) = self.log.as_mut() {
+            let _ = writeln!(f, "{}", text);
+        }
+    }
+
+    /// Flush an unterminated last line and return the bytes kept: everything seen, or the tail
+    /// from the first full line on behind a line saying how much was omitted
+    fn finish(mut self) -> Vec<u8> {
+        use std::io::Write;
+        if !self.partial.is_empty() {
+            let rest = std::mem::take(&mut self.partial);
+            self.emit(&rest);
+        }
+        if let Some(f) = self.log.as_mut() {
+            let _ = f.flush();
+        }
+        if self.tail.len() > OUTPUT_TAIL_BYTES {
+            let cut = self.tail.len() - OUTPUT_TAIL_BYTES;
+            self.tail.drain(..cut);
+            self.omitted += cut;
+        }
+        if self.omitted == 0 {
+            return self.tail;
+        }
+        let line_start = self.tail.iter().position(|&b| b == b'\n').map_or(0, |p| p + 1);
+        self.tail.drain(..line_start);
+        let kept = match &self.path {
+            Some(p) if self.log.is_some() => format!("the full output is in {}", p.display()),
+            _ => "the full output was not kept".to_string(),
+        };
+        let mut out = format!("{}{} bytes omitted; {}]\n", OMITTED, self.omitted + line_start, kept).into_bytes();
+        out.append(&mut self.tail);
+        out
+    }
+}
+
+/// Read `reader` to the end, echoing through `sink` when streaming and noting activity in `last`
+async fn pump(mut reader: impl tokio::io::AsyncRead + Unpin, mut sink: Option<LineSink>, last: LastOutput) -> std::io::Result<Vec<u8>> {
+    use tokio::io::AsyncReadExt;
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 8192];
+    loop {
+        let n = reader.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(sink.map(LineSink::finish).unwrap_or(buf));
+        }
+        last.touch();
+        match sink.as_mut() {
+            Some(sink) => sink.push(&chunk[..n]),
+            None => buf.extend_from_slice(&chunk[..n]),
+        }
+    }
+}
+
+/// Spawn `c`, enforce the optional timeout and cancellation and collect (stdout, stderr, exit_status).
+/// On timeout or cancellation the child's process tree is stopped and a `TimedOut` / `Cancelled`
+/// error is returned. With `opts.stream`, output goes to its logs (and the console) line by line
+/// while the command runs and only a bounded tail is kept in memory.
+async fn run_command(backend: &str, mut c: Command, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+    trace_command(backend, &c);
+    // own process group, so killing it also reaches whatever the shell started
+    #[cfg(unix)]
+    c.process_group(0);
+    // CREATE_NEW_PROCESS_GROUP, so CTRL_BREAK can be sent to the command alone; the job object
+    // covering its children is attached in `supervise`
+    #[cfg(windows)]
+    c.creation_flags(0x0000_0200);
+    if opts.stdin.is_some() {
+        c.stdin(std::process::Stdio::piped());
+    }
+    let mut child = c
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("{} backend failed to spawn process", backend))?;
+
+    if let (Some(data), Some(mut pipe)) = (opts.stdin.clone(), child.stdin.take()) {
+        // written in the background (the command may not read all of it); dropping the pipe closes it
+        tokio::spawn(async move {
+            use tokio::io::AsyncWriteExt;
+            let _ = pipe.write_all(&data).await;
+        });
+    }
+    let stdout = child.stdout.take().context("child stdout not captured")?;
+    let stderr = child.stderr.take().context("child stderr not captured")?;
+    let sink = |to_stderr| opts.stream.as_ref().map(|s| LineSink::new(s, to_stderr));
+    let last = LastOutput::new();
+    let out_task = tokio::spawn(pump(stdout, sink(false), last.clone()));
+    let err_task = tokio::spawn(pump(stderr, sink(true), last.clone()));
+    let status = match supervise(backend, &mut child, timeout_secs, &last, opts).await {
+        Ok(status) => status,
+        Err(e) => {
+            // a detached grandchild may still hold the pipes open
+            out_task.abort();
+            err_task.abort();
+            return Err(e);
+        }
+    };
+    let out = out_task.await.context("stdout reader panicked")??;
+    let err = err_task.await.context("stderr reader panicked")??;
+    Ok((String::from_utf8_lossy(&out).to_string(), String::from_utf8_lossy(&err).to_string(), status))
+}
+
+/// Like `run_command`, but with stdin/stdout/stderr attached to a fresh pseudo-terminal so tools
+/// that check `isatty` behave as they would interactively. Output comes back as stdout.
+#[cfg(unix)]
+async fn run_command_pty(backend: &str, mut c: Command, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+    use std::io::Read;
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    trace_command(backend, &c);
+    let (mut master, mut slave): (libc::c_int, libc::c_int) = (0, 0);
+    // SAFETY: openpty only writes the two fds; name/termios/winsize are optional and passed as null.
+    let rc = unsafe { libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), std::ptr::null()) };
+    if rc != 0 {
+        return Err(std::io::Error::last_os_error()).context("openpty failed");
+    }
+    // SAFETY: both fds were just returned by openpty and are owned by nobody else.
+    let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+
+    c.stdin(slave.try_clone()?).stdout(slave.try_clone()?).stderr(slave);
+    // SAFETY: only async-signal-safe calls between fork and exec.
+    unsafe {
+        c.pre_exec(|| {
+            // New session with the pty as controlling terminal.
+            if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    c.kill_on_drop(true);
+    let mut child = c.spawn().with_context(|| format!("{} backend failed to spawn process", backend))?;
+    // Close our copies of the slave side so the reader sees EOF/EIO once the child exits.
+    drop(c);
+
+    let mut master = std::fs::File::from(master);
+    let mut sink = opts.stream.as_ref().map(|s| LineSink::new(s, false));
+    let last = LastOutput::new();
+    let activity = last.clone();
+    let reader = tokio::task::spawn_blocking(move || {
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 8192];
+        loop {
+            match master.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(n) => {
+                    activity.touch();
+                    match sink.as_mut() {
+                        Some(sink) => sink.push(&chunk[..n]),
+                        None => buf.extend_from_slice(&chunk[..n]),
+                    }
+                }
+                // Linux reports EIO on the master once every slave fd is closed.
+                Err(e) if e.raw_os_error() == Some(libc::EIO) => break,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(sink.map(LineSink::finish).unwrap_or(buf))
+    });
+
+    // setsid made the child a process group leader, so supervise can kill the whole session
+    let status = supervise(backend, &mut child, timeout_secs, &last, opts).await?;
+
+    let buf = reader.await.context("pty reader panicked")?.context("reading pty output failed")?;
+    // The terminal turns "\n" into "\r\n"; undo that so output matches the non-tty case.
+    let out = String::from_utf8_lossy(&buf).replace("\r\n", "\n");
+    Ok((out, String::new(), status))
+}
+
+/// Windows has no openpty; run without a terminal rather than failing the task.
+#[cfg(windows)]
+async fn run_command_pty(backend: &str, c: Command, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+    tracing::warn!("tty: true is not supported by the {} backend on Windows; running without a terminal", backend);
+    run_command(backend, c, timeout_secs, opts).await
+}
+
+/// Interpreter a task's command is handed to (`shell:`): `bash`, `sh`, `pwsh`, `powershell`,
+/// `cmd`, `git-bash`, `python`, any other program taking `-c`, or a full argv the command is
+/// appended to (`[bash, -eo, pipefail, -c]`)
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum ShellSpec {
+    Named(String),
+    Argv(Vec<String>),
+}
+
+/// Shells known by name: program and the arguments before the command. `python` is `python3`
+/// outside Windows; `git-bash` is resolved by `git_bash()`.
+const NAMED_SHELLS: &[(&str, &str, &[&str])] = &[
+    ("pwsh", "pwsh", &["-NoLogo", "-NoProfile", "-Command"]),
+    ("powershell", "powershell.exe", &["-NoLogo", "-NoProfile", "-Command"]),
+    ("cmd", "cmd.exe", &["/D", "/C"]),
+    ("git-bash", "bash", &["-c"]),
+    ("python", if cfg!(windows) { "python" } else { "python3" }, &["-c"]),
+];
+
+/// Git for Windows' bash, which a plain `bash` on Windows would miss in favour of WSL's
+/// `System32\bash.exe`; `bash` from PATH elsewhere or when Git is not found
+fn git_bash() -> String {
+    ["ProgramFiles", "ProgramW6432", "LOCALAPPDATA"]
+        .iter()
+        .filter_map(std::env::var_os)
+        .flat_map(|base| [PathBuf::from(&base).join(r"Git\bin\bash.exe"), PathBuf::from(base).join(r"Programs\Git\bin\bash.exe")])
+        .find(|p| p.is_file())
+        .map(|p| p.to_string_lossy().to_string())
+        .unwrap_or_else(|| "bash".to_string())
+}
+
+impl ShellSpec {
+    /// Program and arguments that come before the command
+    pub fn argv(&self) -> Vec<String> {
+        let name = match self {
+            ShellSpec::Argv(argv) => return argv.clone(),
+            ShellSpec::Named(name) => name.as_str(),
+        };
+        match NAMED_SHELLS.iter().find(|(n, _, _)| *n == name) {
+            Some((n, program, args)) => {
+                let program = if *n == "git-bash" { git_bash() } else { program.to_string() };
+                std::iter::once(program).chain(args.iter().map(|a| a.to_string())).collect()
+            }
+            None => vec![name.to_string(), "-c".to_string()],
+        }
+    }
+
+    /// One command running `steps` in order in a single shell session, stopping at the first one
+    /// that fails (with its exit code)
+    pub fn script(&self, steps: &[String]) -> String {
+        let argv = self.argv();
+        let program = argv.first().and_then(|p| Path::new(p).file_stem()).map(|s| s.to_string_lossy().to_lowercase()).unwrap_or_default();
+        match program.as_str() {
+            "pwsh" | "powershell" => steps
+                .iter()
+                .enumerate()
+                .map(|(i, step)| {
+                    format!(
+                        "{}\nif (-not $?) {{ $rc = if ($LASTEXITCODE) {{ $LASTEXITCODE }} else {{ 1 }}; [Console]::Error.WriteLine(\"script step {} failed (exit $rc)\"); exit $rc }}\n",
+                        step,
+                        i + 1
+                    )
+                })
+                .collect(),
+            "cmd" => steps.iter().map(|s| format!("({})", s)).collect::<Vec<_>>().join(" && "),
+            "python" | "python3" => steps.join("\n"),
+            _ => steps
+                .iter()
+                .enumerate()
+                .map(|(i, step)| format!("{{\n{}\n}} || {{ rc=$?; echo \"script step {} failed (exit $rc)\" >&2; exit $rc; }}\n", step, i + 1))
+                .collect(),
+        }
+    }
+
+    /// The host's default: PowerShell on Windows, `sh` elsewhere
+    fn host_default() -> Self {
+        ShellSpec::Named(if cfg!(windows) { "powershell" } else { "sh" }.to_string())
+    }
+
+    /// Command running `cmd` through this shell
+    fn command(&self, cmd: &str) -> Command {
+        let argv = self.argv();
+        let mut c = Command::new(&argv[0]);
+        c.args(&argv[1..]);
+        // cmd.exe does not parse the usual argv quoting: hand it the command line as written
+        #[cfg(windows)]
+        if Path::new(&argv[0]).file_stem().is_some_and(|s| s.eq_ignore_ascii_case("cmd")) {
+            c.raw_arg(cmd);
+            return c;
+        }
+        c.arg(cmd);
+        c
+    }
+}
+
+/// When container backends fetch their image (`pull:`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PullPolicy {
+    Always,
+    IfNotPresent,
+    Never,
+}
+
+impl PullPolicy {
+    /// `docker run --pull` value
+    fn docker(self) -> &'static str {
+        match self {
+            PullPolicy::Always => "always",
+            PullPolicy::IfNotPresent => "missing",
+            PullPolicy::Never => "never",
+        }
+    }
+
+    /// Kubernetes `imagePullPolicy`
+    fn kubernetes(self) -> &'static str {
+        match self {
+            PullPolicy::Always => "Always",
+            PullPolicy::IfNotPresent => "IfNotPresent",
+            PullPolicy::Never => "Never",
+        }
+    }
+}
+
+/// CPU and memory a task may use on a container backend (`resources:`): `--cpus`/`--memory` for
+/// docker, requests and limits for Kubernetes
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Resources {
+    /// A number of CPUs (`2`, `0.5`) or millicores (`500m`)
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_quantity")]
+    pub cpus: Option<String>,
+    /// Bytes, or with a unit: `4Gi`, `512Mi`, `2G`, docker-style `512m`
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_quantity")]
+    pub memory: Option<String>,
+}
+
+/// Quantities may be written as numbers or strings
+pub fn deserialize_quantity<'de, D>(d: D) -> Result<Option<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Quantity {
+        Str(String),
+        Int(u64),
+        Float(f64),
+    }
+    Ok(Option::<Quantity>::deserialize(d)?.map(|q| match q {
+        Quantity::Str(s) => s,
+        Quantity::Int(i) => i.to_string(),
+        Quantity::Float(f) => f.to_string(),
+    }))
+}
+
+impl Resources {
+    /// `cpus` as a number of CPUs
+    pub fn cpu_cores(&self) -> anyhow::Result<Option<f64>> {
+        let Some(cpus) = &self.cpus else { return Ok(None) };
+        let cores = match cpus.strip_suffix('m') {
+            Some(milli) => milli.parse::<f64>().map(|m| m / 1000.0),
+            None => cpus.parse::<f64>(),
+        };
+        match cores {
+            Ok(c) if c > 0.0 => Ok(Some(c)),
+            _ => anyhow::bail!("invalid `cpus` '{}': expected a positive number or millicores like 500m", cpus),
+        }
+    }
+
+    /// `memory` in bytes
+    pub fn memory_bytes(&self) -> anyhow::Result<Option<u64>> {
+        let Some(memory) = &self.memory else { return Ok(None) };
+        let split = memory.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(memory.len());
+        let (number, unit) = memory.split_at(split);
+        let factor: u64 = match unit {
+            "" => 1,
+            "k" | "K" => 1000,
+            "M" => 1000u64.pow(2),
+            "G" => 1000u64.pow(3),
+            "T" => 1000u64.pow(4),
+            // docker's lowercase units are binary
+            "Ki" | "kb" => 1 << 10,
+            "Mi" | "m" | "mb" => 1 << 20,
+            "Gi" | "g" | "gb" => 1 << 30,
+            "Ti" | "t" | "tb" => 1 << 40,
+            _ => anyhow::bail!("invalid `memory` '{}': unknown unit '{}' (use Ki, Mi, Gi, K, M, G or bytes)", memory, unit),
+        };
+        match number.parse::<f64>() {
+            Ok(n) if n > 0.0 => Ok(Some((n * factor as f64) as u64)),
+            _ => anyhow::bail!("invalid `memory` '{}': expected a positive quantity like 4Gi", memory),
+        }
+    }
+
+    /// Kubernetes quantities, used as both requests and limits
+    fn kubernetes(&self) -> BTreeMap<String, String> {
+        let mut out = BTreeMap::new();
+        if let Ok(Some(cores)) = self.cpu_cores() {
+            out.insert("cpu".to_string(), format!("{}m", (cores * 1000.0).round() as u64));
+        }
+        if let Ok(Some(bytes)) = self.memory_bytes() {
+            out.insert("memory".to_string(), bytes.to_string());
+        }
+        out
+    }
+
+    /// `docker run`/`create` options
+    fn docker_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Ok(Some(cores)) = self.cpu_cores() {
+            args.extend(["--cpus".to_string(), cores.to_string()]);
+        }
+        if let Ok(Some(bytes)) = self.memory_bytes() {
+            args.extend(["--memory".to_string(), bytes.to_string()]);
+        }
+        args
+    }
+}
+
+/// Requests and limits of a task's pod: its `resources`, plus `gpus` as `nvidia.com/gpu` (a limit,
+/// which Kubernetes also takes as the request)
+fn kubernetes_resources(opts: &RunOptions) -> (BTreeMap<String, String>, BTreeMap<String, String>) {
+    let requests = opts.resources.as_ref().map(Resources::kubernetes).unwrap_or_default();
+    let mut limits = requests.clone();
+    if let Some(gpus) = &opts.gpus {
+        limits.insert("nvidia.com/gpu".to_string(), gpus.clone());
+    }
+    (requests, limits)
+}
+
+/// Shell argv inside containers and pods, where `sh` is the default
+fn container_shell(opts: &RunOptions) -> Vec<String> {
+    opts.shell.as_ref().map(ShellSpec::argv).unwrap_or_else(|| vec!["sh".to_string(), "-c".to_string()])
+}
+
+/// Per-task execution options handed to backends alongside the command
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Run the command attached to a pseudo-terminal (stdout and stderr are merged)
+    pub tty: bool,
+    /// Extra environment variables for the command
+    pub env: Vec<(String, String)>,
+    /// Write output to logs (and echo it) line by line while the command runs, keeping only a
+    /// bounded tail for the result, instead of collecting all of it in memory
+    pub stream: Option<OutputStream>,
+    /// Flips to true when the run is cancelled; the command is then killed (`Cancelled` error)
+    pub cancel: Option<watch::Receiver<bool>>,
+    /// (container path, host path) pairs copied out of the container after the command exited
+    pub copy_out: Vec<(String, PathBuf)>,
+    /// Interpreter of the command (default: the host shell locally, `sh` elsewhere)
+    pub shell: Option<ShellSpec>,
+    /// Fed to the command's standard input (otherwise it inherits ours)
+    pub stdin: Option<Vec<u8>>,
+    /// Artifact patterns relative to `cwd`; backends running the command on a copy of it elsewhere
+    /// bring the matches back into `cwd`
+    pub artifacts: Vec<String>,
+    /// Overrides the image pull policy of container backends
+    pub pull: Option<PullPolicy>,
+    /// CPU and memory limits on container backends
+    pub resources: Option<Resources>,
+    /// GPUs for the command on container backends: a count, or `all` (docker only)
+    pub gpus: Option<String>,
+    /// Seconds between SIGTERM (CTRL_BREAK on Windows) and SIGKILL when the command is stopped
+    /// (default `DEFAULT_GRACE_SECS`)
+    pub grace_period: Option<u64>,
+    /// Seconds the command may go without writing to stdout or stderr
+    pub no_output_timeout: Option<u64>,
+    /// What exceeding `no_output_timeout` does
+    pub no_output_action: NoOutputAction,
+}
+
+/// Backend trait: run a command and return (stdout, stderr, exit_status)
+#[async_trait]
+pub trait Backend: Send + Sync {
+    async fn run(&self, cmd: &str, cwd: &Path, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)>;
+
+    /// Where `cwd` is mounted when commands run in a container; other absolute paths only exist
+    /// inside the container and are copied out through `RunOptions::copy_out`
+    fn container_workdir(&self) -> Option<&str> {
+        None
+    }
+
+    /// Shell used when the task sets none
+    fn default_shell(&self) -> ShellSpec {
+        ShellSpec::Named("sh".to_string())
+    }
+
+    /// Check, before a run starts, that commands can run at all (daemon reachable, host
+    /// connects, ...); the error says what is missing
+    async fn preflight(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Fetch what the first command would otherwise download (a container image) before the run
+    /// starts, so task timeouts do not include it
+    async fn pull(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// What `pull` fetches and from where, e.g. `alpine:3 (docker)`; backends returning the same
+    /// one are pulled once
+    fn image(&self) -> Option<String> {
+        None
+    }
+
+    /// Start the workers of a `pool` (containers, pods, connections) for tasks running in `dir`,
+    /// once the run starts, so tasks do not wait for them
+    async fn warm(&self, _dir: &Path) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// How long a preflight probe may take
+const PREFLIGHT_TIMEOUT_SECS: u64 = 30;
+
+/// Run a preflight probe to completion; on failure its stderr (or stdout) is the diagnostic
+async fn probe(backend: &str, mut c: Command, what: &str) -> anyhow::Result<String> {
+    c.stdin(std::process::Stdio::null()).kill_on_drop(true);
+    trace_command(backend, &c);
+    let program = c.as_std().get_program().to_string_lossy().to_string();
+    let out = tokio::time::timeout(std::time::Duration::from_secs(PREFLIGHT_TIMEOUT_SECS), c.output())
+        .await
+        .with_context(|| format!("{}: no answer within {}s", what, PREFLIGHT_TIMEOUT_SECS))?
+        .with_context(|| format!("{}: failed to run {}", what, program))?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        let detail = if stderr.trim().is_empty() { stdout.trim() } else { stderr.trim() };
+        anyhow::bail!("{}: {}", what, detail);
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+/// Whether `program` is a file, or found on PATH (with PATHEXT's extensions on Windows)
+pub(crate) fn program_exists(program: &str) -> bool {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return path.is_file();
+    }
+    let exts: Vec<String> = match std::env::var("PATHEXT") {
+        Ok(exts) if cfg!(windows) => std::iter::once(String::new()).chain(exts.split(';').map(str::to_string)).collect(),
+        _ => vec![String::new()],
+    };
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| exts.iter().any(|ext| dir.join(format!("{}{}", program, ext)).is_file())))
+        .unwrap_or(false)
+}
+
+/// Local backend: runs in the task's `shell`, by default the backend's (initially the host shell:
+/// PowerShell on Windows, sh on Unix)
+pub struct LocalBackend {
+    shell: ShellSpec,
+}
+
+impl Default for LocalBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LocalBackend {
+    pub fn new() -> Self {
+        Self { shell: ShellSpec::host_default() }
+    }
+
+    /// Shell for tasks that set none (e.g. `pwsh`, `cmd` or `git-bash` on Windows)
+    pub fn with_shell(mut self, shell: ShellSpec) -> Self {
+        self.shell = shell;
+        self
+    }
+}
+
+#[async_trait]
+impl Backend for LocalBackend {
+    async fn run(&self, cmd: &str, cwd: &Path, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        let mut c = opts.shell.as_ref().unwrap_or(&self.shell).command(cmd);
+        c.current_dir(cwd);
+        c.envs(opts.env.iter().map(|(k, v)| (k, v)));
+        if opts.tty {
+            return run_command_pty("local", c, timeout_secs, opts).await;
+        }
+        run_command("local", c, timeout_secs, opts).await
+    }
+
+    fn default_shell(&self) -> ShellSpec {
+        self.shell.clone()
+    }
+
+    async fn preflight(&self) -> anyhow::Result<()> {
+        let argv = self.shell.argv();
+        match argv.first() {
+            Some(program) if !program_exists(program) => anyhow::bail!("shell `{}` not found", program),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Where the docker backend mounts the task directory
+const DOCKER_WORKDIR: &str = "/workdir";
+
+/// Docker backend: runs the given command inside a Docker container using `docker run`.
+/// - mounts the provided `cwd` into the container at `/workdir`
+/// - sets the container working directory to `/workdir`
+/// - runs `sh -c "<cmd>"` (or the task's `shell`) inside the container (image must provide it)
+///
+/// Note: path handling for Windows host -> Docker mounts may need adjustment depending on the
+/// user's Docker setup (Docker Desktop vs. other runtimes). An engine on another machine cannot
+/// mount the directory; the directory is then copied in and out instead (`with_copy`).
+pub struct DockerBackend {
+    image: String,
+    /// Optional extra args passed to `docker run` (e.g. ["--network", "host"])
+    extra_args: Vec<String>,
+    reuse: bool,
+    /// Global `docker` options selecting the engine (`--host`, `--context`)
+    engine_args: Vec<String>,
+    copy: Option<bool>,
+    pull: Option<PullPolicy>,
+    /// `docker run --user` value
+    user: Option<String>,
+    /// Host variables handed to the container by name
+    pass_env: Vec<String>,
+    /// With `reuse`: the long-lived container and the host directory mounted in it
+    container: tokio::sync::Mutex<Option<(String, PathBuf)>>,
+    /// Containers started ahead of tasks (`pool`)
+    pool: usize,
+    /// Pool containers waiting for a task, with the host directory mounted in each
+    idle: std::sync::Mutex<Vec<(String, PathBuf)>>,
+    /// Every pool container started, removed when the backend is dropped
+    workers: std::sync::Mutex<Vec<String>>,
+}
+
+impl DockerBackend {
+    /// Create a new DockerBackend for `image`.
+    pub fn new(image: impl Into<String>) -> Self {
+        Self {
+            image: image.into(),
+            extra_args: Vec::new(),
+            reuse: false,
+            engine_args: Vec::new(),
+            copy: None,
+            pull: None,
+            user: None,
+            pass_env: Vec::new(),
+            container: tokio::sync::Mutex::new(None),
+            pool: 0,
+            idle: std::sync::Mutex::new(Vec::new()),
+            workers: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Add extra args to the docker run invocation.
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.extra_args = args;
+        self
+    }
+
+    /// Run every task in one container, started for the first task and removed when the backend
+    /// is dropped, with `docker exec`; installed tools and caches survive between tasks.
+    pub fn with_reuse(mut self, reuse: bool) -> Self {
+        self.reuse = reuse;
+        self
+    }
+
+    /// Start `size` containers when the run begins and hand tasks to idle ones with `docker exec`
+    /// (starting another when all are busy); tasks with `resources` or `gpus` get their own.
+    /// They are removed when the backend is dropped.
+    pub fn with_pool(mut self, size: usize) -> Self {
+        self.pool = size;
+        self
+    }
+
+    /// Talk to the engine at `host` (`tcp://build:2376`, `ssh://user@build`) like `DOCKER_HOST`
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.engine_args.extend(["--host".to_string(), host.into()]);
+        self
+    }
+
+    /// Talk to the engine of a `docker context`
+    pub fn with_context(mut self, context: impl Into<String>) -> Self {
+        self.engine_args.extend(["--context".to_string(), context.into()]);
+        self
+    }
+
+    /// Copy the task directory into the container before each command and back afterwards
+    /// instead of mounting it, for engines on another machine (default: with a host or context).
+    /// Files deleted in the container are not deleted on the host.
+    pub fn with_copy(mut self, copy: bool) -> Self {
+        self.copy = Some(copy);
+        self
+    }
+
+    /// When to pull the image (default: if not present); tasks may override it
+    pub fn with_pull(mut self, pull: PullPolicy) -> Self {
+        self.pull = Some(pull);
+        self
+    }
+
+    /// Whether the image is present on the engine
+    async fn image_present(&self) -> bool {
+        let mut c = self.docker();
+        c.args(["image", "inspect", "--format", "{{.Id}}"]).arg(&self.image);
+        probe("docker", c, "image not present").await.is_ok()
+    }
+
+    /// Run commands as `user` (`uid:gid`, a name, ...); `host` is the user running rustypipe, so
+    /// files written to the mounted directory are not owned by root (ignored on Windows)
+    pub fn with_user(mut self, user: impl Into<String>) -> Self {
+        let user = user.into();
+        self.user = if user == "host" { host_user() } else { Some(user) };
+        self
+    }
+
+    /// Hand these host environment variables (when set) to the container, e.g. `CI`
+    pub fn with_pass_env(mut self, names: Vec<String>) -> Self {
+        self.pass_env = names;
+        self
+    }
+
+    /// `--user` and passed-through variables, for `docker run`/`create`/`exec`
+    fn identity_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(u) = &self.user {
+            args.extend(["--user".to_string(), u.clone()]);
+        }
+        // by name only: docker reads the values from its own environment (kept out of traces)
+        for name in self.pass_env.iter().filter(|n| std::env::var_os(n).is_some()) {
+            args.extend(["-e".to_string(), name.clone()]);
+        }
+        args
+    }
+
+    fn copies(&self) -> bool {
+        self.copy.unwrap_or(!self.engine_args.is_empty())
+    }
+
+    /// `docker` talking to the configured engine
+    fn docker(&self) -> Command {
+        let mut c = Command::new("docker");
+        c.args(&self.engine_args);
+        c
+    }
+
+    /// `docker cp`, with `container:path` on one side
+    async fn cp(&self, src: &str, dest: &str) -> anyhow::Result<()> {
+        let mut c = self.docker();
+        c.arg("cp").arg(src).arg(dest);
+        trace_command("docker", &c);
+        let out = c.output().await.context("failed to run docker cp")?;
+        if !out.status.success() {
+            anyhow::bail!("{}", String::from_utf8_lossy(&out.stderr).trim());
+        }
+        Ok(())
+    }
+
+    /// Copy `src` (file or directory) out of a container to the host path `dest`
+    async fn copy_out(&self, container: &str, src: &str, dest: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        self.cp(&format!("{}:{}", container, src), &dest.to_string_lossy()).await
+    }
+
+    /// Copy the contents of `host_path` into `workdir` of a container, through a local copy
+    /// without `.rustypipe` (run records must neither travel nor come back stale)
+    async fn copy_in(&self, container: &str, host_path: &Path, workdir: &str) -> anyhow::Result<()> {
+        let staging = std::env::temp_dir().join(unique_name());
+        let res = match crate::pipeline::workspace::create(host_path, &staging) {
+            Ok(()) => self.cp(&staging.join(".").to_string_lossy(), &format!("{}:{}", container, workdir)).await,
+            Err(e) => Err(e),
+        };
+        let _ = std::fs::remove_dir_all(&staging);
+        res.context("failed to copy the task directory into the container")
+    }
+
+    /// Copy what the command left in `workdir` back into `host_path`
+    async fn copy_back(&self, container: &str, workdir: &str, host_path: &Path) {
+        if let Err(e) = self.cp(&format!("{}:{}/.", container, workdir), &host_path.to_string_lossy()).await {
+            tracing::warn!("failed to copy the task directory out of the container: {:#}", e);
+        }
+    }
+
+    /// Start a container kept alive for `docker exec` (`reuse`, `pool`), with `host_path` mounted
+    /// unless copying; returns its name
+    async fn start_container(&self, host_path: &Path, pull: Option<PullPolicy>) -> anyhow::Result<String> {
+        let name = unique_name();
+        let mut c = self.docker();
+        c.arg("run").arg("-d").arg("--name").arg(&name).arg("-w").arg(DOCKER_WORKDIR);
+        c.args(self.identity_args());
+        if let Some(p) = pull {
+            c.arg(format!("--pull={}", p.docker()));
+        }
+        if !self.copies() {
+            c.arg("-v").arg(format!("{}:{}", docker_mount_path(host_path), DOCKER_WORKDIR));
+        }
+        c.args(&self.extra_args);
+        // kept alive by a command every image has, whatever its entrypoint does
+        c.arg("--entrypoint").arg("tail").arg(&self.image).arg("-f").arg("/dev/null");
+        trace_command("docker", &c);
+        let out = c.output().await.context("failed to run docker")?;
+        if !out.status.success() {
+            anyhow::bail!("failed to start a container: {}", String::from_utf8_lossy(&out.stderr).trim());
+        }
+        Ok(name)
+    }
+
+    /// The working directory of `host_path` inside a container mounting `mounted`
+    fn workdir_in(mounted: &Path, host_path: &Path) -> anyhow::Result<String> {
+        let rel = host_path
+            .strip_prefix(mounted)
+            .with_context(|| format!("{:?} is outside {:?}, which the container mounts", host_path, mounted))?;
+        Ok(rel.components().fold(DOCKER_WORKDIR.to_string(), |acc, c| format!("{}/{}", acc, c.as_os_str().to_string_lossy())))
+    }
+
+    /// The reused container, started with `cwd` mounted if there is none yet; returns its name
+    /// and the working directory of `cwd` inside it
+    async fn reused_container(&self, cwd: &Path, pull: Option<PullPolicy>) -> anyhow::Result<(String, String)> {
+        let host_path = cwd.canonicalize().with_context(|| format!("failed to canonicalize path {:?}", cwd))?;
+        let mut container = self.container.lock().await;
+        if container.is_none() {
+            let name = self.start_container(&host_path, pull).await?;
+            *container = Some((name, host_path.clone()));
+        }
+        let (name, mounted) = container.as_ref().expect("started above");
+        Ok((name.clone(), Self::workdir_in(mounted, &host_path)?))
+    }
+
+    /// `docker exec` a task in the reused container
+    async fn exec(&self, cmd: &str, cwd: &Path, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        if opts.resources.is_some() || opts.gpus.is_some() {
+            tracing::warn!("`resources` and `gpus` do not apply to tasks in a reused docker container");
+        }
+        let (container, workdir) = self.reused_container(cwd, opts.pull.or(self.pull)).await?;
+        self.exec_in(&container, &workdir, cmd, cwd, timeout_secs, opts).await
+    }
+
+    /// Start a pool container with `host_path` mounted, removed with the backend
+    async fn start_worker(&self, host_path: &Path, pull: Option<PullPolicy>) -> anyhow::Result<String> {
+        let name = self.start_container(host_path, pull).await?;
+        self.workers.lock().unwrap_or_else(|e| e.into_inner()).push(name.clone());
+        Ok(name)
+    }
+
+    /// `docker exec` a task in an idle pool container mounting `cwd` (or a new one when all are
+    /// busy), which is handed back afterwards
+    async fn pooled(&self, cmd: &str, cwd: &Path, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        let host_path = cwd.canonicalize().with_context(|| format!("failed to canonicalize path {:?}", cwd))?;
+        let idle = {
+            let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+            idle.iter().position(|(_, mounted)| host_path.starts_with(mounted)).map(|i| idle.swap_remove(i))
+        };
+        let (mut container, mut mounted, mut fresh) = match idle {
+            Some((container, mounted)) => (container, mounted, false),
+            None => (self.start_worker(&host_path, opts.pull.or(self.pull)).await?, host_path.clone(), true),
+        };
+        loop {
+            let res = match Self::workdir_in(&mounted, &host_path) {
+                Ok(workdir) => self.exec_in(&container, &workdir, cmd, cwd, timeout_secs, opts).await,
+                Err(e) => Err(e),
+            };
+            let failed = !res.as_ref().is_ok_and(|(_, _, status)| status.success()) && !res.as_ref().is_err_and(interrupted);
+            if !failed || self.running(&container).await {
+                self.idle.lock().unwrap_or_else(|e| e.into_inner()).push((container, mounted));
+                return res;
+            }
+            // the container stopped (OOM, `docker stop`, a task ending its main process): it is
+            // dropped, and a task that never reached it gets a new one
+            self.retire(&container).await;
+            let never_ran = res.as_ref().is_ok_and(|(_, err, status)| err.contains("is not running") || matches!(status.code(), Some(125 | 126)));
+            if fresh || !never_ran {
+                return res;
+            }
+            (container, mounted, fresh) = (self.start_worker(&host_path, opts.pull.or(self.pull)).await?, host_path.clone(), true);
+        }
+    }
+
+    /// Whether a pool container is still running
+    async fn running(&self, container: &str) -> bool {
+        let mut c = self.docker();
+        c.args(["inspect", "--format", "{{.State.Running}}"]).arg(container);
+        probe("docker", c, "container not found").await.is_ok_and(|out| out.trim() == "true")
+    }
+
+    /// Remove a pool container that stopped
+    async fn retire(&self, container: &str) {
+        tracing::warn!("pool container {} stopped; removing it", container);
+        self.workers.lock().unwrap_or_else(|e| e.into_inner()).retain(|w| w != container);
+        let mut c = self.docker();
+        c.arg("rm").arg("-f").arg(container);
+        trace_command("docker", &c);
+        let _ = c.output().await;
+    }
+
+    /// `docker exec` a task in `container`, in `workdir`
+    async fn exec_in(
+        &self,
+        container: &str,
+        workdir: &str,
+        cmd: &str,
+        cwd: &Path,
+        timeout_secs: Option<u64>,
+        opts: &RunOptions,
+    ) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        let host_path = cwd.canonicalize().with_context(|| format!("failed to canonicalize path {:?}", cwd))?;
+        if self.copies() {
+            let mut mkdir = self.docker();
+            mkdir.arg("exec").arg(container).arg("mkdir").arg("-p").arg(workdir);
+            trace_command("docker", &mkdir);
+            mkdir.output().await.context("failed to run docker")?;
+            self.copy_in(container, &host_path, workdir).await?;
+        }
+        let mut c = self.docker();
+        c.arg("exec").arg("-w").arg(workdir).args(self.identity_args());
+        if opts.tty {
+            c.arg("-t");
+        }
+        if opts.stdin.is_some() {
+            c.arg("-i");
+        }
+        for (k, v) in &opts.env {
+            c.arg("-e").arg(format!("{}={}", k, v));
+        }
+        // killing the client leaves the command running, so it records its pid for the cleanup
+        // (pid files go away with the container)
+        let pid_file = format!("/tmp/{}.pid", unique_name());
+        c.arg(container).arg("sh").arg("-c").arg(format!("echo $$ > {}; exec \"$@\"", pid_file)).arg("sh");
+        c.args(container_shell(opts)).arg(cmd);
+
+        let res = run_command("docker", c, timeout_secs, opts).await;
+        if res.as_ref().is_err_and(interrupted) {
+            let mut cleanup = self.docker();
+            cleanup.arg("exec").arg(container).arg("sh").arg("-c").arg(format!("kill -9 $(cat {pid}) 2>/dev/null; rm -f {pid}", pid = pid_file));
+            trace_command("docker", &cleanup);
+            let _ = cleanup.output().await;
+        } else if self.copies() {
+            self.copy_back(container, workdir, &host_path).await;
+        }
+        if res.is_ok() {
+            for (src, dest) in &opts.copy_out {
+                if let Err(e) = self.copy_out(container, src, dest).await {
+                    tracing::warn!("failed to copy artifact '{}' out of the container: {:#}", src, e);
+                }
+            }
+        }
+        res
+    }
+}
+
+impl Drop for DockerBackend {
+    fn drop(&mut self) {
+        let mut names = std::mem::take(self.workers.get_mut().unwrap_or_else(|e| e.into_inner()));
+        names.extend(self.container.get_mut().take().map(|(name, _)| name));
+        if !names.is_empty() {
+            let mut c = std::process::Command::new("docker");
+            c.args(&self.engine_args).arg("rm").arg("-f").args(&names).stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null());
+            tracing::debug!("docker rm -f {}", names.join(" "));
+            let _ = c.status();
+        }
+    }
+}
+
+/// `uid:gid` of the current user
+#[cfg(unix)]
+fn host_user() -> Option<String> {
+    // SAFETY: getuid and getgid cannot fail
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    Some(format!("{}:{}", uid, gid))
+}
+
+/// Docker Desktop maps file ownership itself
+#[cfg(not(unix))]
+fn host_user() -> Option<String> {
+    None
+}
+
+/// `host_path` as Docker expects it in `-v`: on Windows "C:\path" (or "\\?\C:\path") becomes "/c/path"
+fn docker_mount_path(host_path: &Path) -> String {
+    let host_path_str = host_path.to_string_lossy().to_string();
+    #[cfg(windows)]
+    let host_path_str = {
+        // Replace backslashes with forward slashes first.
+        let mut s = host_path_str.replace('\\', "/");
+
+        // Remove the extended path prefix if present (e.g. "\\?\" -> "//?/" after replace).
+        if s.starts_with("//?/") {
+            s = s.replacen("//?/", "", 1);
+        } else if s.starts_with("/?/") {
+            s = s.replacen("/?/", "", 1);
+        }
+
+        // If path starts with a drive letter like "C:/" convert to "/c/...".
+        if s.len() >= 2 && s.as_bytes()[1] == b':' {
+            if let Some(drive) = s.chars().next() {
+                let drive = drive.to_ascii_lowercase();
+                // skip the "X:" prefix
+                s = format!("/{}{}", drive, &s[2..]);
+            }
+        }
+        s
+    };
+    host_path_str
+}
+
+#[async_trait]
+impl Backend for DockerBackend {
+    async fn run(
+        &self,
+        cmd: &str,
+        cwd: &Path,
+        timeout_secs: Option<u64>,
+        opts: &RunOptions,
+    ) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        if self.reuse {
+            return self.exec(cmd, cwd, timeout_secs, opts).await;
+        }
+        if self.pool > 0 && opts.resources.is_none() && opts.gpus.is_none() {
+            return self.pooled(cmd, cwd, timeout_secs, opts).await;
+        }
+        // Canonicalize the host path to produce an absolute path for the Docker mount.
+        // If canonicalization fails, return an error early with context.
+        let host_path = cwd
+            .canonicalize()
+            .with_context(|| format!("failed to canonicalize path {:?}", cwd))?;
+        let host_path_str = docker_mount_path(&host_path);
+
+        // Inside the container we mount the host dir at /workdir and use that as the working dir.
+        let container_workdir = DOCKER_WORKDIR;
+
+        // Build base docker run command: docker run --rm -w /workdir -v <host_path>:/workdir <extra_args...> <image> sh -c "<cmd>"
+        // Named so an interrupted run can remove the container (killing the client does not).
+        // Copying, the container is created, filled, then started (`docker start -a` exits with
+        // the command's code).
+        let copy = self.copies();
+        let container = unique_name();
+        let mut c = self.docker();
+        c.arg(if copy { "create" } else { "run" }).arg("--name").arg(&container).arg("-w").arg(container_workdir);
+        // artifacts are copied out of the stopped container, which is removed afterwards
+        if opts.copy_out.is_empty() && !copy {
+            c.arg("--rm");
+        }
+        c.args(self.identity_args());
+        if let Some(p) = opts.pull.or(self.pull) {
+            c.arg(format!("--pull={}", p.docker()));
+        }
+        if let Some(r) = &opts.resources {
+            c.args(r.docker_args());
+        }
+        if let Some(g) = &opts.gpus {
+            c.arg("--gpus").arg(g);
+        }
+        if opts.tty {
+            c.arg("-t");
+        }
+        if opts.stdin.is_some() {
+            c.arg("-i");
+        }
+        for (k, v) in &opts.env {
+            c.arg("-e").arg(format!("{}={}", k, v));
+        }
+
+        // Mount the current working directory into the container.
+        if !copy {
+            c.arg("-v").arg(format!("{}:{}", host_path_str, container_workdir));
+        }
+
+        // Append any extra args the backend was created with.
+        for a in &self.extra_args {
+            c.arg(a);
+        }
+
+        // Image and command to run inside container.
+        c.arg(&self.image).args(container_shell(opts)).arg(cmd);
+
+        if copy {
+            trace_command("docker", &c);
+            let out = c.output().await.context("failed to run docker")?;
+            if !out.status.success() {
+                anyhow::bail!("failed to create a container: {}", String::from_utf8_lossy(&out.stderr).trim());
+            }
+            c = self.docker();
+            c.arg("start").arg("-a");
+            if opts.stdin.is_some() {
+                c.arg("-i");
+            }
+            c.arg(&container);
+        }
+        let copied = if copy { self.copy_in(&container, &host_path, container_workdir).await } else { Ok(()) };
+        let res = match copied {
+            Ok(()) => run_command("docker", c, timeout_secs, opts).await,
+            Err(e) => Err(e),
+        };
+        if copy && !res.as_ref().is_err_and(interrupted) {
+            self.copy_back(&container, container_workdir, &host_path).await;
+        }
+        let res = res.map(|(out, mut err, status)| {
+            // SIGKILL, which the kernel's OOM killer sends when the memory limit is reached
+            if status.code() == Some(137) && opts.resources.as_ref().is_some_and(|r| r.memory.is_some()) {
+                err.push_str("container was killed (exit 137), probably for exceeding `resources.memory`\n");
+            }
+            (out, err, status)
+        });
+        if res.is_ok() {
+            for (src, dest) in &opts.copy_out {
+                if let Err(e) = self.copy_out(&container, src, dest).await {
+                    tracing::warn!("failed to copy artifact '{}' out of the container: {:#}", src, e);
+                }
+            }
+        }
+        if copy || !opts.copy_out.is_empty() || res.as_ref().is_err_and(interrupted) {
+            let mut cleanup = self.docker();
+            cleanup.arg("rm").arg("-f").arg(&container);
+            trace_command("docker", &cleanup);
+            let _ = cleanup.output().await;
+        }
+        res
+    }
+
+    fn container_workdir(&self) -> Option<&str> {
+        Some(DOCKER_WORKDIR)
+    }
+
+    /// The daemon answers and the image is present or exists in its registry (without pulling it)
+    async fn preflight(&self) -> anyhow::Result<()> {
+        let mut c = self.docker();
+        c.args(["version", "--format", "{{.Server.Version}}"]);
+        probe("docker", c, "docker daemon not reachable").await?;
+        if self.image_present().await {
+            return Ok(());
+        }
+        if self.pull == Some(PullPolicy::Never) {
+            anyhow::bail!("image {} is not present and `pull` is `never`", self.image);
+        }
+        let mut c = self.docker();
+        c.args(["manifest", "inspect"]).arg(&self.image);
+        probe("docker", c, &format!("cannot pull image {}", self.image)).await?;
+        Ok(())
+    }
+
+    async fn pull(&self) -> anyhow::Result<()> {
+        match self.pull.unwrap_or(PullPolicy::IfNotPresent) {
+            PullPolicy::Never => return Ok(()),
+            PullPolicy::IfNotPresent if self.image_present().await => return Ok(()),
+            _ => {}
+        }
+        // no timeout: this is what keeps large downloads out of the tasks' timeouts
+        let mut c = self.docker();
+        c.arg("pull").arg("--quiet").arg(&self.image).stdin(std::process::Stdio::null());
+        trace_command("docker", &c);
+        let out = c.output().await.context("failed to run docker pull")?;
+        if !out.status.success() {
+            anyhow::bail!("cannot pull image {}: {}", self.image, String::from_utf8_lossy(&out.stderr).trim());
+        }
+        Ok(())
+    }
+
+    fn image(&self) -> Option<String> {
+        let mut engine = vec!["docker".to_string()];
+        engine.extend(self.engine_args.iter().cloned());
+        if let Some(p) = self.pull {
+            engine.push(format!("pull {}", p.docker()));
+        }
+        Some(format!("{} ({})", self.image, engine.join(" ")))
+    }
+
+    async fn warm(&self, dir: &Path) -> anyhow::Result<()> {
+        if self.pool == 0 || self.reuse {
+            return Ok(());
+        }
+        let host_path = dir.canonicalize().with_context(|| format!("failed to canonicalize path {:?}", dir))?;
+        let started = futures::future::join_all((0..self.pool).map(|_| self.start_worker(&host_path, self.pull))).await;
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        for name in started {
+            idle.push((name?, host_path.clone()));
+        }
+        Ok(())
+    }
+}
+
+/// SSH backend: runs commands on a remote host via the `ssh` binary.
+///
+/// This backend shells out to the platform `ssh` client instead of implementing an SSH client
+/// itself. This keeps the implementation small and leverages existing, well-tested clients.
+///
+/// Notes:
+/// - Uses `sh -lc "<cmd>"` on the remote side to allow arbitrary shell command strings; a task's
+///   `shell` is started from there.
+/// - The caller can configure user, port, identity file and additional ssh args.
+/// - Requires `ssh` to be available on the host where this program runs.
+pub struct SSHBackend {
+    host: String,
+    user: Option<String>,
+    port: Option<u16>,
+    key_path: Option<String>,
+    extra_args: Vec<String>,
+    workdir: Option<String>,
+    sync: Option<SyncOptions>,
+    /// ControlMaster socket shared by the backend's connections, unless multiplexing is off
+    control_path: Option<PathBuf>,
+    master_started: tokio::sync::Mutex<bool>,
+    master_up: AtomicBool,
+    /// Open the shared connection when the run starts
+    pool: bool,
+}
+
+/// Upload of the working directory to ssh hosts (`backends.ssh.sync`)
+#[derive(Debug, Clone, Default)]
+pub struct SyncOptions {
+    /// rsync-style patterns left out of the upload: without `/` they match any file or directory
+    /// name, otherwise the path relative to the working directory
+    pub exclude: Vec<String>,
+}
+
+impl SyncOptions {
+    fn excluded(&self, rel: &Path) -> bool {
+        let rel_str = rel.to_string_lossy().replace('\\', "/");
+        self.exclude.iter().any(|p| {
+            let Ok(pattern) = glob::Pattern::new(p.trim_matches('/')) else { return false };
+            if p.trim_matches('/').contains('/') {
+                pattern.matches(&rel_str)
+            } else {
+                rel.components().any(|c| pattern.matches(&c.as_os_str().to_string_lossy()))
+            }
+        })
+    }
+}
+
+/// Whether `rel` (relative to the working directory) is, or is inside, a match of an artifact pattern
+fn artifact_matches(patterns: &[String], rel: &str) -> bool {
+    let opts = glob::MatchOptions { require_literal_separator: true, ..Default::default() };
+    patterns.iter().filter_map(|p| glob::Pattern::new(p.trim_start_matches("./").trim_end_matches('/')).ok()).any(|p| {
+        let mut prefix = String::new();
+        rel.split('/').any(|part| {
+            if !prefix.is_empty() {
+                prefix.push('/');
+            }
+            prefix.push_str(part);
+            p.matches_with(&prefix, opts)
+        })
+    })
+}
+
+/// Remote directory a synced task runs in: the configured one, else a fresh temporary one
+/// (`true`: remove it afterwards)
+fn sync_dir(workdir: Option<&str>) -> (String, bool) {
+    match workdir {
+        Some(dir) => (dir.to_string(), false),
+        None => (format!("/tmp/{}", unique_name()), true),
+    }
+}
+
+impl SSHBackend {
+    /// Create a new SSHBackend targeting `host` (IP or DNS name).
+    pub fn new(host: impl Into<String>) -> Self {
+        Self {
+            host: host.into(),
+            user: None,
+            port: None,
+            key_path: None,
+            extra_args: Vec::new(),
+            workdir: None,
+            sync: None,
+            control_path: cfg!(unix).then(|| std::env::temp_dir().join(format!("{}-ssh", unique_name()))),
+            master_started: tokio::sync::Mutex::new(false),
+            master_up: AtomicBool::new(false),
+            pool: false,
+        }
+    }
+
+    /// Open the shared connection when the run starts rather than for the first task; with
+    /// ControlMaster every command is a session on it, so any `size` above 0 means the same
+    pub fn with_pool(mut self, size: usize) -> Self {
+        self.pool = size > 0;
+        self
+    }
+
+    /// Share one connection between the backend's commands (OpenSSH ControlMaster; on by default
+    /// where supported), so many small tasks do not each pay for a handshake
+    pub fn with_multiplex(mut self, multiplex: bool) -> Self {
+        if !multiplex {
+            self.control_path = None;
+        }
+        self
+    }
+
+    pub fn with_user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    pub fn with_key(mut self, key_path: impl Into<String>) -> Self {
+        self.key_path = Some(key_path.into());
+        self
+    }
+
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.extra_args = args;
+        self
+    }
+
+    /// Remote directory commands run in, created if missing (default: the login directory)
+    pub fn with_workdir(mut self, dir: impl Into<String>) -> Self {
+        self.workdir = Some(dir.into());
+        self
+    }
+
+    /// Upload the working directory with rsync before each command (into the workdir, or a
+    /// temporary directory removed afterwards) and download the task's artifacts after it
+    pub fn with_sync(mut self, sync: SyncOptions) -> Self {
+        self.sync = Some(sync);
+        self
+    }
+
+    fn target(&self) -> String {
+        match &self.user {
+            Some(u) => format!("{}@{}", u, self.host),
+            None => self.host.clone(),
+        }
+    }
+
+    /// Connection options shared by ssh, cleanup calls and rsync's `-e`
+    fn ssh_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(p) = self.port {
+            args.extend(["-p".to_string(), p.to_string()]);
+        }
+        if let Some(k) = &self.key_path {
+            args.extend(["-i".to_string(), k.clone()]);
+        }
+        // Prevent ssh from prompting for passwords or host key verification in batch scenarios.
+        // Note: StrictHostKeyChecking=accept-new can be used in some environments,
+        // but it depends on OpenSSH version. We keep it simple and non-interactive.
+        args.extend(["-o", "BatchMode=yes", "-o", "ConnectTimeout=10"].map(String::from));
+        // Append any user-specified extra args (allows overriding / adding options).
+        args.extend(self.extra_args.iter().cloned());
+        if let Some(path) = self.control_path.as_ref().filter(|_| self.master_up.load(Ordering::Relaxed)) {
+            args.extend(["-o".to_string(), format!("ControlPath={}", path.display())]);
+        }
+        args
+    }
+
+    /// Start the shared connection for the first command; without it commands connect on their own
+    async fn ensure_master(&self) {
+        let Some(path) = &self.control_path else { return };
+        let mut started = self.master_started.lock().await;
+        if *started {
+            return;
+        }
+        *started = true;
+        // backgrounded after authentication (-f) with its output detached, so it never holds a
+        // task's pipes open; it exits when dropped or after 5 idle minutes
+        let mut c = Command::new("ssh");
+        c.args(self.ssh_args())
+            .args(["-o", "ControlMaster=yes", "-o", "ControlPersist=300", "-o"])
+            .arg(format!("ControlPath={}", path.display()))
+            .args(["-N", "-f"])
+            .arg(self.target())
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null());
+        trace_command("ssh", &c);
+        match c.status().await {
+            Ok(status) if status.success() => self.master_up.store(true, Ordering::Relaxed),
+            _ => tracing::warn!("could not open a shared ssh connection to {}; connecting per task", self.host),
+        }
+    }
+
+    async fn rsync(&self, args: Vec<String>, cwd: &Path) -> anyhow::Result<()> {
+        let mut c = Command::new("rsync");
+        let ssh = std::iter::once("ssh".to_string()).chain(self.ssh_args()).map(|a| shell_quote(&a)).collect::<Vec<_>>().join(" ");
+        c.arg("-az").arg("-e").arg(ssh).args(args).current_dir(cwd);
+        trace_command("ssh", &c);
+        let out = c.output().await.context("failed to run rsync")?;
+        if !out.status.success() {
+            anyhow::bail!("rsync failed: {}", String::from_utf8_lossy(&out.stderr).trim());
+        }
+        Ok(())
+    }
+
+    async fn upload(&self, sync: &SyncOptions, cwd: &Path, dir: &str) -> anyhow::Result<()> {
+        let mut args = vec![format!("--rsync-path=mkdir -p {} && rsync", shell_quote(dir))];
+        args.extend(sync.exclude.iter().map(|p| format!("--exclude={}", p)));
+        args.extend(["./".to_string(), format!("{}:{}/", self.target(), dir)]);
+        self.rsync(args, cwd).await
+    }
+
+    /// Bring matches of the artifact patterns back from `dir` into `cwd`
+    async fn download(&self, patterns: &[String], cwd: &Path, dir: &str) -> anyhow::Result<()> {
+        let mut args = vec!["--prune-empty-dirs".to_string()];
+        for p in patterns {
+            let p = p.trim_start_matches("./").trim_end_matches('/');
+            args.extend([format!("--include=/{}", p), format!("--include=/{}/**", p)]);
+        }
+        args.extend(["--include=*/".to_string(), "--exclude=*".to_string()]);
+        args.extend([format!("{}:{}/", self.target(), dir), "./".to_string()]);
+        self.rsync(args, cwd).await
+    }
+}
+
+impl Drop for SSHBackend {
+    fn drop(&mut self) {
+        if let (Some(path), true) = (&self.control_path, self.master_up.load(Ordering::Relaxed)) {
+            let mut c = std::process::Command::new("ssh");
+            c.arg("-o").arg(format!("ControlPath={}", path.display())).args(["-O", "exit"]).arg(self.target());
+            c.stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null());
+            let _ = c.status();
+        }
+    }
+}
+
+/// The command run on an ssh host: `cmd` in the task's shell, with the environment exported in
+/// front (ssh does not forward it), in `workdir` if there is one. Without a tty the remote
+/// command survives a killed client, so the wrapper records its pid (sshd makes it a session
+/// leader) in `pid_file` for a cleanup call to kill the whole group.
+fn ssh_remote_command(cmd: &str, opts: &RunOptions, workdir: Option<&str>, pid_file: &str) -> String {
+    let mut remote = String::new();
+    if let Some(dir) = workdir {
+        remote.push_str(&format!("mkdir -p {dir} && cd {dir} || exit 1; ", dir = shell_quote(dir)));
+    }
+    for (k, v) in &opts.env {
+        remote.push_str(&format!("export {}={}; ", k, shell_quote(v)));
+    }
+    match &opts.shell {
+        Some(shell) => {
+            let argv: Vec<String> = shell.argv().iter().map(|a| shell_quote(a)).collect();
+            remote.push_str(&format!("{} {}", argv.join(" "), shell_quote(cmd)));
+        }
+        None => remote.push_str(cmd),
+    }
+    format!("echo $$ > {pid}; sh -lc {cmd}; rc=$?; rm -f {pid}; exit $rc", pid = pid_file, cmd = shell_quote(&remote))
+}
+
+/// Remote command killing what `ssh_remote_command` started
+fn ssh_kill_command(pid_file: &str) -> String {
+    format!("kill -9 -- -$(cat {pid}) 2>/dev/null; rm -f {pid}", pid = pid_file)
+}
+
+#[async_trait]
+impl Backend for SSHBackend {
+    async fn run(&self, cmd: &str, cwd: &Path, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        self.ensure_master().await;
+        // Build ssh invocation.
+        // Use conservative safe defaults: non-interactive (BatchMode) and a connection timeout.
+        let target = self.target();
+        let mut c = Command::new("ssh");
+        c.args(self.ssh_args());
+
+        // Same connection options for the command and a possible cleanup call.
+        let mut cleanup = Command::new("ssh");
+        cleanup.args(c.as_std().get_args()).arg(&target);
+
+        let (dir, temporary) = match &self.sync {
+            Some(sync) => {
+                let (dir, temporary) = sync_dir(self.workdir.as_deref());
+                self.upload(sync, cwd, &dir).await?;
+                (Some(dir), temporary)
+            }
+            None => (self.workdir.clone(), false),
+        };
+
+        // target and remote command.
+        c.arg(target);
+        // Execute via a POSIX shell on remote side to support complex command strings.
+        let pid_file = format!("/tmp/{}.pid", unique_name());
+        c.arg(ssh_remote_command(cmd, opts, dir.as_deref(), &pid_file));
+
+        // For SSH backend we don't change local cwd — remote cwd is controlled by ssh command / remote env.
+
+        let res = run_command("ssh", c, timeout_secs, opts).await;
+        if res.as_ref().is_err_and(interrupted) {
+            cleanup.arg(ssh_kill_command(&pid_file));
+            trace_command("ssh", &cleanup);
+            let _ = cleanup.output().await;
+        }
+        if let (Some(dir), Some(_)) = (&dir, &self.sync) {
+            if res.is_ok() && !opts.artifacts.is_empty() {
+                if let Err(e) = self.download(&opts.artifacts, cwd, dir).await {
+                    tracing::warn!("failed to download artifacts from {}: {:#}", self.host, e);
+                }
+            }
+            if temporary {
+                let mut rm = Command::new("ssh");
+                rm.args(self.ssh_args()).arg(self.target()).arg(format!("rm -rf {}", shell_quote(dir)));
+                trace_command("ssh", &rm);
+                let _ = rm.output().await;
+            }
+        }
+        res
+    }
+
+    /// The host accepts a non-interactive login (opening the shared connection), and has rsync
+    /// on both ends when syncing
+    async fn preflight(&self) -> anyhow::Result<()> {
+        if self.sync.is_some() && !program_exists("rsync") {
+            anyhow::bail!("`sync` needs rsync, which is not installed here");
+        }
+        self.ensure_master().await;
+        let mut c = Command::new("ssh");
+        c.args(self.ssh_args()).arg(self.target());
+        c.arg(if self.sync.is_some() { "command -v rsync >/dev/null || { echo 'rsync is not installed on the host' >&2; exit 1; }" } else { "true" });
+        probe("ssh", c, &format!("cannot connect to {}", self.host)).await?;
+        Ok(())
+    }
+
+    async fn warm(&self, _dir: &Path) -> anyhow::Result<()> {
+        if self.pool {
+            self.ensure_master().await;
+        }
+        Ok(())
+    }
+}
+
+/// SSH backend with an in-process client (libssh2), so the host needs no OpenSSH client.
+///
+/// Authenticates with the configured key file, otherwise with the keys of the running ssh-agent.
+/// The host key must already be in `~/.ssh/known_hosts` (like `ssh -o BatchMode=yes`). Commands
+/// run like with [`SSHBackend`], and the exit code is the remote command's.
+#[derive(Clone)]
+pub struct NativeSshBackend {
+    host: String,
+    user: Option<String>,
+    port: u16,
+    key_path: Option<String>,
+    workdir: Option<String>,
+    sync: Option<SyncOptions>,
+    /// Idle authenticated sessions, reused by later commands unless multiplexing is off
+    sessions: Option<Arc<std::sync::Mutex<Vec<ssh2::Session>>>>,
+    /// Sessions opened when the run starts
+    pool: usize,
+}
+
+impl NativeSshBackend {
+    pub fn new(host: impl Into<String>) -> Self {
+        Self { host: host.into(), user: None, port: 22, key_path: None, workdir: None, sync: None, sessions: Some(Arc::default()), pool: 0 }
+    }
+
+    /// Open `size` sessions when the run starts, so that many concurrent tasks skip the handshake
+    /// (needs multiplexing, which keeps them)
+    pub fn with_pool(mut self, size: usize) -> Self {
+        self.pool = size;
+        self
+    }
+
+    /// Keep sessions open for later commands (on by default), so many small tasks do not each
+    /// pay for a handshake; concurrent commands still get sessions of their own
+    pub fn with_multiplex(mut self, multiplex: bool) -> Self {
+        self.sessions = multiplex.then(Arc::default);
+        self
+    }
+
+    /// An idle session that is still alive, or a new one
+    fn session(&self) -> anyhow::Result<ssh2::Session> {
+        if let Some(pool) = &self.sessions {
+            while let Some(session) = pool.lock().unwrap_or_else(|e| e.into_inner()).pop() {
+                if session.keepalive_send().is_ok() {
+                    return Ok(session);
+                }
+            }
+        }
+        self.connect()
+    }
+
+    /// Upload the working directory over SFTP before each command (into the workdir, or a
+    /// temporary directory removed afterwards) and download the task's artifacts after it
+    pub fn with_sync(mut self, sync: SyncOptions) -> Self {
+        self.sync = Some(sync);
+        self
+    }
+
+    /// Run a helper command to completion on a fresh channel
+    fn exec(session: &ssh2::Session, command: &str) -> anyhow::Result<()> {
+        use std::io::Read;
+        let mut channel = session.channel_session()?;
+        channel.exec(command)?;
+        let mut err = String::new();
+        channel.stderr().read_to_string(&mut err)?;
+        channel.wait_close()?;
+        if channel.exit_status()? != 0 {
+            anyhow::bail!("'{}' failed: {}", command, err.trim());
+        }
+        Ok(())
+    }
+
+    fn upload(session: &ssh2::Session, sync: &SyncOptions, cwd: &Path, dir: &str) -> anyhow::Result<()> {
+        Self::exec(session, &format!("mkdir -p {}", shell_quote(dir)))?;
+        let sftp = session.sftp()?;
+        let mut stack = vec![PathBuf::new()];
+        while let Some(rel) = stack.pop() {
+            for entry in std::fs::read_dir(cwd.join(&rel))? {
+                let entry = entry?;
+                let rel = rel.join(entry.file_name());
+                if sync.excluded(&rel) {
+                    continue;
+                }
+                let remote = Path::new(dir).join(&rel);
+                let meta = entry.metadata()?;
+                if meta.is_dir() {
+                    // already there when syncing into a persistent workdir
+                    let _ = sftp.mkdir(&remote, 0o755);
+                    stack.push(rel);
+                } else if meta.is_file() {
+                    #[cfg(unix)]
+                    let mode = std::os::unix::fs::PermissionsExt::mode(&meta.permissions()) as i32 & 0o777;
+                    #[cfg(not(unix))]
+                    let mode = 0o644;
+                    let flags = ssh2::OpenFlags::WRITE | ssh2::OpenFlags::CREATE | ssh2::OpenFlags::TRUNCATE;
+                    let mut file = sftp.open_mode(&remote, flags, mode, ssh2::OpenType::File).with_context(|| format!("failed to upload {:?}", rel))?;
+                    std::io::copy(&mut std::fs::File::open(entry.path())?, &mut file)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Bring matches of the artifact patterns back from `dir` into `cwd`
+    fn download(session: &ssh2::Session, patterns: &[String], cwd: &Path, dir: &str) -> anyhow::Result<()> {
+        let sftp = session.sftp()?;
+        let mut stack = vec![String::new()];
+        while let Some(rel) = stack.pop() {
+            for (path, stat) in sftp.readdir(Path::new(dir).join(&rel))? {
+                let Some(name) = path.file_name().map(|n| n.to_string_lossy().into_owned()) else { continue };
+                let rel = if rel.is_empty() { name } else { format!("{}/{}", rel, name) };
+                if stat.is_dir() {
+                    stack.push(rel);
+                } else if artifact_matches(patterns, &rel) {
+                    let to = cwd.join(&rel);
+                    if let Some(parent) = to.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    std::io::copy(&mut sftp.open(&path)?, &mut std::fs::File::create(&to)?)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn with_user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    pub fn with_port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn with_key(mut self, key_path: impl Into<String>) -> Self {
+        self.key_path = Some(key_path.into());
+        self
+    }
+
+    /// Remote directory commands run in, created if missing (default: the login directory)
+    pub fn with_workdir(mut self, dir: impl Into<String>) -> Self {
+        self.workdir = Some(dir.into());
+        self
+    }
+
+    /// Open an authenticated session
+    fn connect(&self) -> anyhow::Result<ssh2::Session> {
+        use std::net::ToSocketAddrs;
+        let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")).map(PathBuf::from);
+        let addr = (self.host.as_str(), self.port).to_socket_addrs()?.next().with_context(|| format!("cannot resolve {}", self.host))?;
+        let tcp = std::net::TcpStream::connect_timeout(&addr, std::time::Duration::from_secs(10)).with_context(|| format!("cannot connect to {}", addr))?;
+        let mut session = ssh2::Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.set_timeout(10_000);
+        session.handshake().context("ssh handshake failed")?;
+
+        let (key, _) = session.host_key().context("the server sent no host key")?;
+        let mut known = session.known_hosts()?;
+        if let Some(file) = home.as_ref().map(|h| h.join(".ssh").join("known_hosts")).filter(|f| f.is_file()) {
+            known.read_file(&file, ssh2::KnownHostFileKind::OpenSSH)?;
+        }
+        match known.check_port(&self.host, self.port, key) {
+            ssh2::CheckResult::Match => {}
+            ssh2::CheckResult::Mismatch => anyhow::bail!("the host key of {} does not match ~/.ssh/known_hosts", self.host),
+            ssh2::CheckResult::NotFound => anyhow::bail!("{} is not in ~/.ssh/known_hosts; connect once with ssh to add it", self.host),
+            ssh2::CheckResult::Failure => anyhow::bail!("failed to check the host key of {}", self.host),
+        }
+
+        let user = match &self.user {
+            Some(u) => u.clone(),
+            None => std::env::var("USER").or_else(|_| std::env::var("USERNAME")).context("no ssh user configured and $USER is not set")?,
+        };
+        match &self.key_path {
+            Some(key) => {
+                let key = match (key.strip_prefix("~/"), &home) {
+                    (Some(rest), Some(home)) => home.join(rest),
+                    _ => PathBuf::from(key),
+                };
+                session.userauth_pubkey_file(&user, None, &key, None).with_context(|| format!("authentication as {} with {:?} failed", user, key))?;
+            }
+            None => session.userauth_agent(&user).with_context(|| format!("authentication as {} with ssh-agent failed", user))?,
+        }
+        // reads poll, so timeouts and cancellation are noticed
+        session.set_timeout(0);
+        // lets `keepalive_send` tell whether a pooled session is still connected
+        session.set_keepalive(false, 30);
+        Ok(session)
+    }
+
+    fn run_blocking(&self, cmd: &str, cwd: &Path, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        let session = self.session()?;
+        let (dir, temporary) = match &self.sync {
+            Some(sync) => {
+                let (dir, temporary) = sync_dir(self.workdir.as_deref());
+                Self::upload(&session, sync, cwd, &dir).context("failed to upload the working directory")?;
+                (Some(dir), temporary)
+            }
+            None => (self.workdir.clone(), false),
+        };
+        let res = self.run_command(&session, cmd, dir.as_deref(), timeout_secs, opts);
+        if let (Some(dir), Some(_)) = (&dir, &self.sync) {
+            if res.is_ok() && !opts.artifacts.is_empty() {
+                if let Err(e) = Self::download(&session, &opts.artifacts, cwd, dir) {
+                    tracing::warn!("failed to download artifacts from {}: {:#}", self.host, e);
+                }
+            }
+            if temporary {
+                let _ = Self::exec(&session, &format!("rm -rf {}", shell_quote(dir)));
+            }
+        }
+        // an interrupted command may leave the session in any state
+        if let (Some(pool), Ok(_)) = (&self.sessions, &res) {
+            pool.lock().unwrap_or_else(|e| e.into_inner()).push(session);
+        }
+        res
+    }
+
+    fn run_command(&self, session: &ssh2::Session, cmd: &str, dir: Option<&str>, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        use std::io::{Read, Write};
+        let pid_file = format!("/tmp/{}.pid", unique_name());
+        let remote = ssh_remote_command(cmd, opts, dir, &pid_file);
+        if TRACE.load(Ordering::Relaxed) {
+            eprintln!("[trace] ssh backend (native): {}@{}:{} {}", self.user.as_deref().unwrap_or("$USER"), self.host, self.port, crate::pipeline::secrets::mask(&remote));
+        }
+        let mut channel = session.channel_session()?;
+        if opts.tty {
+            channel.request_pty("xterm", None, None)?;
+        }
+        channel.exec(&remote)?;
+        if let Some(data) = &opts.stdin {
+            channel.write_all(data)?;
+        }
+        channel.send_eof()?;
+
+        let sink = |to_stderr| opts.stream.as_ref().map(|s| LineSink::new(s, to_stderr));
+        let (mut out_sink, mut err_sink) = (sink(false), sink(true));
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let deadline = timeout_secs.map(|s| std::time::Instant::now() + std::time::Duration::from_secs(s));
+        let last = LastOutput::new();
+        let mut buf = [0u8; 8192];
+        session.set_blocking(false);
+        let interrupted: Option<anyhow::Error> = loop {
+            let mut progressed = false;
+            for (to_stderr, sink, all) in [(false, &mut out_sink, &mut out), (true, &mut err_sink, &mut err)] {
+                let read = if to_stderr { channel.stderr().read(&mut buf) } else { channel.read(&mut buf) };
+                match read {
+                    Ok(0) => {}
+                    Ok(n) => {
+                        progressed = true;
+                        last.touch();
+                        match sink.as_mut() {
+                            Some(sink) => sink.push(&buf[..n]),
+                            None => all.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+                    Err(e) => return Err(e).context("reading from the ssh channel failed"),
+                }
+            }
+            if channel.eof() && !progressed {
+                break None;
+            }
+            if deadline.is_some_and(|d| std::time::Instant::now() >= d) {
+                break Some(TimedOut { backend: "ssh".to_string(), secs: timeout_secs.unwrap_or_default() }.into());
+            }
+            if let Err(e) = last.check("ssh", opts) {
+                break Some(e);
+            }
+            if opts.cancel.as_ref().is_some_and(|c| *c.borrow()) {
+                break Some(Cancelled { backend: "ssh".to_string() }.into());
+            }
+            if !progressed {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+            }
+        };
+        session.set_blocking(true);
+        if let Some(e) = interrupted {
+            let _ = channel.close();
+            if let Ok(mut cleanup) = session.channel_session() {
+                let _ = cleanup.exec(&ssh_kill_command(&pid_file));
+                let _ = cleanup.wait_close();
+            }
+            return Err(e);
+        }
+        channel.wait_close()?;
+        let code = channel.exit_status()?;
+        let out = out_sink.map(LineSink::finish).unwrap_or(out);
+        let err = err_sink.map(LineSink::finish).unwrap_or(err);
+        Ok((String::from_utf8_lossy(&out).to_string(), String::from_utf8_lossy(&err).to_string(), crate::util::exit_status(code)))
+    }
+}
+
+#[async_trait]
+impl Backend for NativeSshBackend {
+    async fn run(&self, cmd: &str, cwd: &Path, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        // libssh2 blocks, so the session lives on a blocking thread
+        let (backend, cmd, cwd, opts) = (self.clone(), cmd.to_string(), cwd.to_path_buf(), opts.clone());
+        tokio::task::spawn_blocking(move || backend.run_blocking(&cmd, &cwd, timeout_secs, &opts)).await.context("ssh session panicked")?
+    }
+
+    /// Connects and authenticates; the session is kept for the first task when multiplexing
+    async fn preflight(&self) -> anyhow::Result<()> {
+        let backend = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let session = backend.session().with_context(|| format!("cannot connect to {}", backend.host))?;
+            if let Some(pool) = &backend.sessions {
+                pool.lock().unwrap_or_else(|e| e.into_inner()).push(session);
+            }
+            Ok(())
+        })
+        .await
+        .context("ssh session panicked")?
+    }
+
+    async fn warm(&self, _dir: &Path) -> anyhow::Result<()> {
+        let Some(pool) = &self.sessions else { return Ok(()) };
+        // preflight may have left one open already
+        let open = pool.lock().unwrap_or_else(|e| e.into_inner()).len();
+        let connects = (open..self.pool).map(|_| {
+            let backend = self.clone();
+            tokio::task::spawn_blocking(move || backend.connect())
+        });
+        for session in futures::future::join_all(connects).await {
+            let session = session.context("ssh session panicked")?.with_context(|| format!("cannot connect to {}", self.host))?;
+            pool.lock().unwrap_or_else(|e| e.into_inner()).push(session);
+        }
+        Ok(())
+    }
+}
+
+/// Kubernetes backend: runs workloads inside the cluster using the `kubectl` binary.
+///
+/// This implementation shells out to `kubectl` to keep the dependency surface small and to
+/// leverage an already-configured kubeconfig or in-cluster configuration via the CLI.
+/// It creates a short-lived Pod via `kubectl run --rm` and executes the provided command
+/// in that ephemeral pod using the provided image. The pod name is generated to avoid collisions.
+///
+/// Requirements & notes:
+/// - Requires `kubectl` to be available and configured (context/namespace) on the machine where
+///   this program runs.
+/// - This backend is intended for short-lived commands. For long-running or production workloads,
+///   consider a more robust controller-based approach.
+pub struct KubernetesBackend {
+    image: String,
+    namespace: Option<String>,
+    /// Additional args passed to `kubectl run`, e.g. ["--serviceaccount=xxx"]
+    extra_args: Vec<String>,
+    pull: Option<PullPolicy>,
+    /// Pods started ahead of tasks (`pool`)
+    pool: usize,
+    /// Pool pods waiting for a task
+    idle: std::sync::Mutex<Vec<String>>,
+    /// Every pool pod started, deleted when the backend is dropped
+    workers: std::sync::Mutex<Vec<String>>,
+}
+
+impl KubernetesBackend {
+    /// Create a backend that will run commands inside a pod instantiated from `image`.
+    pub fn new(image: impl Into<String>) -> Self {
+        Self {
+            image: image.into(),
+            namespace: None,
+            extra_args: Vec::new(),
+            pull: None,
+            pool: 0,
+            idle: std::sync::Mutex::new(Vec::new()),
+            workers: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Start `size` pods when the run begins and hand tasks to idle ones with `kubectl exec`
+    /// (starting another when all are busy); tasks with `resources` or `gpus` get their own pod.
+    /// They are deleted when the backend is dropped.
+    pub fn with_pool(mut self, size: usize) -> Self {
+        self.pool = size;
+        self
+    }
+
+    /// Poll a pull pod until its container ran (the image is on the node) or the pull failed;
+    /// no timeout, like `docker pull`
+    async fn wait_pulled(&self, pod: &str) -> anyhow::Result<()> {
+        loop {
+            let mut c = self.kubectl();
+            c.args(["get", "pod", pod, "-o", "jsonpath={.status.phase} {.status.containerStatuses[0].state.waiting.reason}"]);
+            let out = probe("kubernetes", c, &format!("cannot get pod {}", pod)).await?;
+            let mut fields = out.split_whitespace();
+            let (phase, reason) = (fields.next().unwrap_or(""), fields.next().unwrap_or(""));
+            match (phase, reason) {
+                ("Running" | "Succeeded" | "Failed", _) => return Ok(()),
+                (_, "ErrImagePull" | "ImagePullBackOff" | "InvalidImageName" | "ErrImageNeverPull") => {
+                    anyhow::bail!("cannot pull image {}: {}", self.image, reason)
+                }
+                _ => tokio::time::sleep(std::time::Duration::from_secs(2)).await,
+            }
+        }
+    }
+
+    /// `kubectl` in the configured namespace
+    fn kubectl(&self) -> Command {
+        let mut c = Command::new("kubectl");
+        if let Some(ns) = &self.namespace {
+            c.arg("--namespace").arg(ns);
+        }
+        c
+    }
+
+    /// Start a pool pod and wait until it is ready; returns its name
+    async fn start_worker(&self, pull: Option<PullPolicy>) -> anyhow::Result<String> {
+        let name = unique_name();
+        let mut c = self.kubectl();
+        c.arg("run").arg(&name).arg("--restart=Never").arg("--image").arg(&self.image);
+        if let Some(p) = pull {
+            c.arg(format!("--image-pull-policy={}", p.kubernetes()));
+        }
+        c.args(&self.extra_args);
+        // kept alive by a command every image has, whatever its entrypoint does
+        c.args(["--command", "--", "tail", "-f", "/dev/null"]);
+        trace_command("kubernetes", &c);
+        let out = c.output().await.context("failed to run kubectl")?;
+        if !out.status.success() {
+            anyhow::bail!("failed to start a pod: {}", String::from_utf8_lossy(&out.stderr).trim());
+        }
+        self.workers.lock().unwrap_or_else(|e| e.into_inner()).push(name.clone());
+        let mut wait = self.kubectl();
+        wait.arg("wait").arg("--for=condition=Ready").arg(format!("pod/{}", name)).arg("--timeout=300s");
+        trace_command("kubernetes", &wait);
+        let out = wait.output().await.context("failed to run kubectl")?;
+        if !out.status.success() {
+            anyhow::bail!("pod {} did not become ready: {}", name, String::from_utf8_lossy(&out.stderr).trim());
+        }
+        Ok(name)
+    }
+
+    /// `kubectl exec` a task in an idle pool pod (or a new one when all are busy), which is
+    /// handed back afterwards
+    async fn pooled(&self, cmd: &str, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        let idle = self.idle.lock().unwrap_or_else(|e| e.into_inner()).pop();
+        let (mut pod, mut fresh) = match idle {
+            Some(pod) => (pod, false),
+            None => (self.start_worker(opts.pull.or(self.pull)).await?, true),
+        };
+        loop {
+            let res = self.exec_in(&pod, cmd, timeout_secs, opts).await;
+            let failed = !res.as_ref().is_ok_and(|(_, _, status)| status.success()) && !res.as_ref().is_err_and(interrupted);
+            if !failed || self.running(&pod).await {
+                self.idle.lock().unwrap_or_else(|e| e.into_inner()).push(pod);
+                return res;
+            }
+            // the pod was evicted, deleted or has completed: it is dropped, and a task that
+            // never reached it gets a new one
+            self.retire(&pod).await;
+            let never_ran = res.as_ref().is_ok_and(|(_, err, _)| err.contains("NotFound") || err.contains("cannot exec into a container"));
+            if fresh || !never_ran {
+                return res;
+            }
+            (pod, fresh) = (self.start_worker(opts.pull.or(self.pull)).await?, true);
+        }
+    }
+
+    /// Whether a pool pod is still running
+    async fn running(&self, pod: &str) -> bool {
+        let mut c = self.kubectl();
+        c.args(["get", "pod", pod, "-o", "jsonpath={.status.phase}"]);
+        probe("kubernetes", c, "pod not found").await.is_ok_and(|out| out.trim() == "Running")
+    }
+
+    /// Delete a pool pod that stopped
+    async fn retire(&self, pod: &str) {
+        tracing::warn!("pool pod {} is gone or not running; deleting it", pod);
+        self.workers.lock().unwrap_or_else(|e| e.into_inner()).retain(|w| w != pod);
+        let mut c = self.kubectl();
+        c.args(["delete", "pod", pod, "--wait=false", "--ignore-not-found"]);
+        trace_command("kubernetes", &c);
+        let _ = c.output().await;
+    }
+
+    /// `kubectl exec` a task in `pod`
+    async fn exec_in(&self, pod: &str, cmd: &str, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        let mut c = self.kubectl();
+        c.arg("exec");
+        if opts.stdin.is_some() {
+            c.arg("--stdin");
+        }
+        // killing kubectl leaves the command running, so it records its pid for the cleanup
+        let pid_file = format!("/tmp/{}.pid", unique_name());
+        c.arg(pod).arg("--").arg("sh").arg("-c").arg(format!("echo $$ > {}; exec \"$@\"", pid_file)).arg("sh");
+        if !opts.env.is_empty() {
+            c.arg("env").args(opts.env.iter().map(|(k, v)| format!("{}={}", k, v)));
+        }
+        c.args(container_shell(opts)).arg(cmd);
+
+        let res = run_command("kubernetes", c, timeout_secs, opts).await;
+        if res.as_ref().is_err_and(interrupted) {
+            let mut cleanup = self.kubectl();
+            cleanup.arg("exec").arg(pod).arg("--").arg("sh").arg("-c").arg(format!("kill -9 $(cat {pid}) 2>/dev/null; rm -f {pid}", pid = pid_file));
+            trace_command("kubernetes", &cleanup);
+            let _ = cleanup.output().await;
+        }
+        res
+    }
+
+    /// `imagePullPolicy` of the pods (default: the cluster's); tasks may override it
+    pub fn with_pull(mut self, pull: PullPolicy) -> Self {
+        self.pull = Some(pull);
+        self
+    }
+
+    pub fn with_namespace(mut self, ns: impl Into<String>) -> Self {
+        self.namespace = Some(ns.into());
+        self
+    }
+
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.extra_args = args;
+        self
+    }
+}
+
+#[async_trait]
+impl Backend for KubernetesBackend {
+    async fn run(&self, cmd: &str, _cwd: &Path, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        if self.pool > 0 && opts.resources.is_none() && opts.gpus.is_none() {
+            return self.pooled(cmd, timeout_secs, opts).await;
+        }
+        // Generate a lightweight unique pod name based on epoch nanos.
+        let pod_name = unique_name();
+
+        // Build kubectl invocation:
+        // kubectl run <pod_name> --rm --restart=Never --image <image> [--namespace NAMESPACE] [extra_args...] -- sh -c "<cmd>"
+        let mut c = Command::new("kubectl");
+        c.arg("run");
+        c.arg(&pod_name);
+        c.arg("--rm"); // remove pod after completion
+        c.arg("--restart=Never"); // run as a pod, not a controller
+        c.arg("--image").arg(&self.image);
+        if let Some(p) = opts.pull.or(self.pull) {
+            c.arg(format!("--image-pull-policy={}", p.kubernetes()));
+        }
+        if opts.resources.is_some() || opts.gpus.is_some() {
+            // the container kubectl generates is named after the pod
+            let (requests, limits) = kubernetes_resources(opts);
+            let overrides = serde_json::json!({ "spec": { "containers": [{ "name": pod_name, "resources": { "requests": requests, "limits": limits } }] } });
+            c.arg("--override-type=strategic").arg(format!("--overrides={}", overrides));
+        }
+        if opts.stdin.is_some() {
+            c.arg("--stdin");
+        }
+
+        if let Some(ns) = &self.namespace {
+            c.arg("--namespace").arg(ns);
+        }
+        for (k, v) in &opts.env {
+            c.arg("--env").arg(format!("{}={}", k, v));
+        }
+
+        // Append extra args (user may include serviceaccount, env, etc).
+        for a in &self.extra_args {
+            c.arg(a);
+        }
+
+        // Ensure kubectl treats subsequent args as the container command.
+        c.arg("--");
+        // Use sh -c (or the task's shell) so that the provided cmd string is interpreted inside the pod.
+        c.args(container_shell(opts)).arg(cmd);
+
+        let res = run_command("kubernetes", c, timeout_secs, opts).await;
+        if let Err(e) = &res {
+            if interrupted(e) {
+                // Timeouts and cancellation leave the ephemeral pod running (kubectl is killed, the pod is not).
+                // Best-effort cleanup: delete the created pod.
+                // We ignore errors here because the cluster state may have already removed the pod
+                // or the operation may not be permitted in the current context.
+                let mut cleanup = Command::new("kubectl");
+                cleanup.arg("delete").arg("pod").arg(&pod_name);
+                if let Some(ns) = &self.namespace {
+                    cleanup.arg("--namespace").arg(ns);
+                }
+                trace_command("kubernetes", &cleanup);
+                let _ = cleanup.output().await;
+            }
+        }
+        res
+    }
+
+    /// kubectl reaches the cluster of the current context and may create pods there
+    async fn preflight(&self) -> anyhow::Result<()> {
+        let mut c = Command::new("kubectl");
+        c.args(["auth", "can-i", "create", "pods"]);
+        if let Some(ns) = &self.namespace {
+            c.arg("--namespace").arg(ns);
+        }
+        probe("kubernetes", c, "cannot create pods").await?;
+        Ok(())
+    }
+
+    /// Run a pod that exits at once, which makes a node pull the image, and wait until its
+    /// container got past the pull
+    async fn pull(&self) -> anyhow::Result<()> {
+        let policy = self.pull.unwrap_or(PullPolicy::IfNotPresent);
+        if policy == PullPolicy::Never {
+            return Ok(());
+        }
+        let name = unique_name();
+        let mut c = self.kubectl();
+        c.arg("run").arg(&name).arg("--restart=Never").arg("--image").arg(&self.image);
+        c.arg(format!("--image-pull-policy={}", policy.kubernetes()));
+        c.args(&self.extra_args);
+        c.args(["--command", "--", "true"]).stdin(std::process::Stdio::null());
+        trace_command("kubernetes", &c);
+        let out = c.output().await.context("failed to run kubectl")?;
+        if !out.status.success() {
+            anyhow::bail!("cannot start a pod pulling {}: {}", self.image, String::from_utf8_lossy(&out.stderr).trim());
+        }
+        let res = self.wait_pulled(&name).await;
+        let mut cleanup = self.kubectl();
+        cleanup.args(["delete", "pod", "--wait=false", "--ignore-not-found"]).arg(&name);
+        trace_command("kubernetes", &cleanup);
+        let _ = cleanup.output().await;
+        res
+    }
+
+    fn image(&self) -> Option<String> {
+        let mut cluster = vec!["kubernetes".to_string()];
+        cluster.extend(self.namespace.iter().map(|ns| format!("namespace {}", ns)));
+        cluster.extend(self.extra_args.iter().cloned());
+        if let Some(p) = self.pull {
+            cluster.push(format!("pull {}", p.kubernetes()));
+        }
+        Some(format!("{} ({})", self.image, cluster.join(" ")))
+    }
+
+    async fn warm(&self, _dir: &Path) -> anyhow::Result<()> {
+        let started = futures::future::join_all((0..self.pool).map(|_| self.start_worker(self.pull))).await;
+        let mut idle = self.idle.lock().unwrap_or_else(|e| e.into_inner());
+        for name in started {
+            idle.push(name?);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for KubernetesBackend {
+    fn drop(&mut self) {
+        let names = std::mem::take(self.workers.get_mut().unwrap_or_else(|e| e.into_inner()));
+        if !names.is_empty() {
+            let mut c = std::process::Command::new("kubectl");
+            if let Some(ns) = &self.namespace {
+                c.arg("--namespace").arg(ns);
+            }
+            c.args(["delete", "pod", "--wait=false"]).args(&names).stdout(std::process::Stdio::null()).stderr(std::process::Stdio::null());
+            tracing::debug!("kubectl delete pod {}", names.join(" "));
+            let _ = c.status();
+        }
+    }
+}
+
+/// Kubernetes Job backend: every task becomes a `batch/v1` Job created with `kubectl`.
+///
+/// Unlike `kubectl run`, the cluster enforces the task's timeout (`activeDeadlineSeconds`) and
+/// retries failed pods (`backoffLimit`). Logs of each pod are streamed in turn, the exit code is
+/// the last pod's, and the Job is deleted however the task ends (`ttlSecondsAfterFinished` catches
+/// Jobs rustypipe could not delete).
+pub struct KubernetesJobBackend {
+    image: String,
+    namespace: Option<String>,
+    backoff_limit: u32,
+    /// Resource requests and limits, e.g. `cpu: 500m`
+    requests: BTreeMap<String, String>,
+    limits: BTreeMap<String, String>,
+    ttl_secs: u32,
+    pull: Option<PullPolicy>,
+}
+
+impl KubernetesJobBackend {
+    pub fn new(image: impl Into<String>) -> Self {
+        Self {
+            image: image.into(),
+            namespace: None,
+            backoff_limit: 0,
+            requests: BTreeMap::new(),
+            limits: BTreeMap::new(),
+            ttl_secs: 600,
+            pull: None,
+        }
+    }
+
+    /// `imagePullPolicy` of the pods (default: the cluster's); tasks may override it
+    pub fn with_pull(mut self, pull: PullPolicy) -> Self {
+        self.pull = Some(pull);
+        self
+    }
+
+    pub fn with_namespace(mut self, ns: impl Into<String>) -> Self {
+        self.namespace = Some(ns.into());
+        self
+    }
+
+    /// Pods started again after a failure before the Job fails
+    pub fn with_backoff_limit(mut self, limit: u32) -> Self {
+        self.backoff_limit = limit;
+        self
+    }
+
+    pub fn with_resources(mut self, requests: BTreeMap<String, String>, limits: BTreeMap<String, String>) -> Self {
+        self.requests = requests;
+        self.limits = limits;
+        self
+    }
+
+    /// Seconds a finished Job is kept by the cluster
+    pub fn with_ttl(mut self, secs: u32) -> Self {
+        self.ttl_secs = secs;
+        self
+    }
+
+    fn kubectl(&self) -> Command {
+        let mut c = Command::new("kubectl");
+        if let Some(ns) = &self.namespace {
+            c.arg("--namespace").arg(ns);
+        }
+        c
+    }
+
+    /// Run a kubectl command to completion and return its stdout
+    async fn kubectl_output(&self, args: &[&str], stdin: Option<&str>) -> anyhow::Result<String> {
+        use tokio::io::AsyncWriteExt;
+        let mut c = self.kubectl();
+        c.args(args).stdout(std::process::Stdio::piped()).stderr(std::process::Stdio::piped());
+        if stdin.is_some() {
+            c.stdin(std::process::Stdio::piped());
+        }
+        trace_command("kubernetes", &c);
+        let mut child = c.spawn().context("failed to run kubectl")?;
+        if let (Some(data), Some(mut pipe)) = (stdin, child.stdin.take()) {
+            pipe.write_all(data.as_bytes()).await?;
+        }
+        let out = child.wait_with_output().await?;
+        if !out.status.success() {
+            anyhow::bail!("kubectl {}: {}", args.first().copied().unwrap_or_default(), String::from_utf8_lossy(&out.stderr).trim());
+        }
+        Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+    }
+
+    fn manifest(&self, name: &str, cmd: &str, timeout_secs: Option<u64>, opts: &RunOptions) -> serde_json::Value {
+        let mut command = container_shell(opts);
+        command.push(cmd.to_string());
+        let env: Vec<_> = opts.env.iter().map(|(k, v)| serde_json::json!({ "name": k, "value": v })).collect();
+        // the task's `resources` override the backend's
+        let (mut requests, mut limits) = (self.requests.clone(), self.limits.clone());
+        let (task_requests, task_limits) = kubernetes_resources(opts);
+        requests.extend(task_requests);
+        limits.extend(task_limits);
+        let mut spec = serde_json::json!({
+            "backoffLimit": self.backoff_limit,
+            "ttlSecondsAfterFinished": self.ttl_secs,
+            "template": {
+                "metadata": { "labels": { "app.kubernetes.io/managed-by": "rustypipe" } },
+                "spec": {
+                    "restartPolicy": "Never",
+                    "containers": [{
+                        "name": "task",
+                        "image": self.image,
+                        "command": command,
+                        "env": env,
+                        "resources": { "requests": requests, "limits": limits },
+                    }],
+                },
+            },
+        });
+        if let Some(secs) = timeout_secs {
+            spec["activeDeadlineSeconds"] = secs.into();
+        }
+        if let Some(p) = opts.pull.or(self.pull) {
+            spec["template"]["spec"]["containers"][0]["imagePullPolicy"] = p.kubernetes().into();
+        }
+        serde_json::json!({
+            "apiVersion": "batch/v1",
+            "kind": "Job",
+            "metadata": { "name": name, "labels": { "app.kubernetes.io/managed-by": "rustypipe" } },
+            "spec": spec,
+        })
+    }
+
+    /// Create the Job, follow it to the end and return its output and the last pod's exit code
+    async fn follow(&self, name: &str, cmd: &str, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        let manifest = self.manifest(name, cmd, timeout_secs, opts).to_string();
+        self.kubectl_output(&["create", "-f", "-"], Some(&manifest)).await?;
+        let selector = format!("job-name={}", name);
+        let (mut stdout, mut stderr) = (String::new(), String::new());
+        let mut streamed = std::collections::HashSet::new();
+        loop {
+            // retried pods are streamed one after the other, oldest first
+            let pods: serde_json::Value = serde_json::from_str(&self.kubectl_output(&["get", "pods", "-l", &selector, "-o", "json"], None).await?)?;
+            let mut pods: Vec<&serde_json::Value> = pods["items"].as_array().map(|a| a.iter().collect()).unwrap_or_default();
+            pods.sort_by_key(|p| p["metadata"]["creationTimestamp"].as_str().unwrap_or_default().to_string());
+            for pod in &pods {
+                let pod_name = pod["metadata"]["name"].as_str().unwrap_or_default();
+                if streamed.contains(pod_name) || pod["status"]["phase"] == "Pending" {
+                    continue;
+                }
+                let mut logs = self.kubectl();
+                logs.arg("logs").arg("--follow").arg(pod_name);
+                let (out, err, _) = run_command("kubernetes", logs, None, opts).await?;
+                stdout.push_str(&out);
+                stderr.push_str(&err);
+                streamed.insert(pod_name.to_string());
+            }
+
+            let job: serde_json::Value = serde_json::from_str(&self.kubectl_output(&["get", "job", name, "-o", "json"], None).await?)?;
+            let conditions = job["status"]["conditions"].as_array().cloned().unwrap_or_default();
+            let ended = conditions.iter().find(|c| c["status"] == "True" && (c["type"] == "Complete" || c["type"] == "Failed"));
+            if let Some(condition) = ended.filter(|_| pods.iter().all(|p| streamed.contains(p["metadata"]["name"].as_str().unwrap_or_default()))) {
+                if condition["reason"] == "DeadlineExceeded" {
+                    return Err(TimedOut { backend: "kubernetes".to_string(), secs: timeout_secs.unwrap_or_default() }.into());
+                }
+                let terminated = pods.last().map(|p| &p["status"]["containerStatuses"][0]["state"]["terminated"]);
+                if terminated.is_some_and(|t| t["reason"] == "OOMKilled") {
+                    stderr.push_str("container was OOM-killed: it exceeded its memory limit\n");
+                }
+                let last_code = terminated.and_then(|t| t["exitCode"].as_i64());
+                let code = match (condition["type"] == "Complete", last_code) {
+                    (true, _) => 0,
+                    (false, Some(code)) if code != 0 => code as i32,
+                    (false, _) => {
+                        stderr.push_str(&format!("job failed: {}\n", condition["message"].as_str().or(condition["reason"].as_str()).unwrap_or("unknown reason")));
+                        1
+                    }
+                };
+                return Ok((stdout, stderr, crate::util::exit_status(code)));
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(std::time::Duration::from_secs(2)) => {}
+                _ = cancelled(opts.cancel.as_ref()) => return Err(Cancelled { backend: "kubernetes".to_string() }.into()),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for KubernetesJobBackend {
+    async fn run(&self, cmd: &str, _cwd: &Path, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        if opts.stdin.is_some() {
+            tracing::warn!("stdin is not supported by Kubernetes Jobs; the task gets none");
+        }
+        let name = unique_name();
+        let res = self.follow(&name, cmd, timeout_secs, opts).await;
+        // pods go with the Job (background cascading deletion)
+        if let Err(e) = self.kubectl_output(&["delete", "job", &name, "--ignore-not-found", "--wait=false"], None).await {
+            tracing::warn!("failed to delete job {}: {:#}", name, e);
+        }
+        res
+    }
+
+    /// kubectl reaches the cluster of the current context and may create Jobs there
+    async fn preflight(&self) -> anyhow::Result<()> {
+        let mut c = self.kubectl();
+        c.args(["auth", "can-i", "create", "jobs.batch"]);
+        probe("kubernetes", c, "cannot create jobs").await?;
+        Ok(())
+    }
+}
+
+/// WSL backend: runs commands in a WSL distribution with `wsl.exe`, in the Linux view of the
+/// task's Windows directory (`C:\\src\\app` is `/mnt/c/src/app`), so Windows hosts can use Linux
+/// toolchains without Docker. The environment is exported in front of the command.
+pub struct WslBackend {
+    distro: Option<String>,
+    user: Option<String>,
+}
+
+impl WslBackend {
+    /// Run in the default distribution
+    pub fn new() -> Self {
+        Self { distro: None, user: None }
+    }
+
+    pub fn with_distro(mut self, distro: impl Into<String>) -> Self {
+        self.distro = Some(distro.into());
+        self
+    }
+
+    pub fn with_user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+}
+
+impl Default for WslBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Path of a Windows directory inside WSL: drives are mounted at `/mnt/<letter>`
+pub fn wsl_path(path: &Path) -> String {
+    let s = path.to_string_lossy().replace('\\', "/");
+    let s = s.strip_prefix("//?/").unwrap_or(&s);
+    match s.as_bytes() {
+        [drive, b':', rest @ ..] if drive.is_ascii_alphabetic() => {
+            format!("/mnt/{}{}", drive.to_ascii_lowercase() as char, String::from_utf8_lossy(rest))
+        }
+        _ => s.to_string(),
+    }
+}
+
+#[async_trait]
+impl Backend for WslBackend {
+    async fn run(&self, cmd: &str, cwd: &Path, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        let dir = cwd.canonicalize().with_context(|| format!("failed to canonicalize path {:?}", cwd))?;
+        let mut c = Command::new("wsl");
+        if let Some(d) = &self.distro {
+            c.arg("--distribution").arg(d);
+        }
+        if let Some(u) = &self.user {
+            c.arg("--user").arg(u);
+        }
+        c.arg("--cd").arg(wsl_path(&dir));
+        // Windows variables do not reach the distribution (short of WSLENV), so they are exported;
+        // rustypipe's own files ($RUSTYPIPE_ENV, ...) are given as WSL paths
+        let mut script = String::new();
+        for (k, v) in &opts.env {
+            let v = if k.starts_with("RUSTYPIPE_") && Path::new(v).is_absolute() { wsl_path(Path::new(v)) } else { v.clone() };
+            script.push_str(&format!("export {}={}; ", k, shell_quote(&v)));
+        }
+        let argv: Vec<String> = container_shell(opts).iter().map(|a| shell_quote(a)).collect();
+        script.push_str(&format!("exec {} {}", argv.join(" "), shell_quote(cmd)));
+        c.arg("--exec").arg("sh").arg("-c").arg(script);
+        if opts.tty {
+            return run_command_pty("wsl", c, timeout_secs, opts).await;
+        }
+        run_command("wsl", c, timeout_secs, opts).await
+    }
+
+    /// The distribution starts (and has the user)
+    async fn preflight(&self) -> anyhow::Result<()> {
+        let mut c = Command::new("wsl");
+        if let Some(d) = &self.distro {
+            c.arg("--distribution").arg(d);
+        }
+        if let Some(u) = &self.user {
+            c.arg("--user").arg(u);
+        }
+        c.args(["--exec", "true"]);
+        probe("wsl", c, "cannot start the distribution").await?;
+        Ok(())
+    }
+}
+
+/// The `aws` CLI with a region and profile
+#[derive(Clone, Default)]
+struct AwsCli {
+    region: Option<String>,
+    profile: Option<String>,
+}
+
+impl AwsCli {
+    fn command(&self) -> Command {
+        let mut c = Command::new("aws");
+        if let Some(r) = &self.region {
+            c.arg("--region").arg(r);
+        }
+        if let Some(p) = &self.profile {
+            c.arg("--profile").arg(p);
+        }
+        c.args(["--output", "json"]);
+        c
+    }
+
+    /// Run an `aws` command to completion and parse its JSON answer
+    async fn json(&self, backend: &str, args: &[&str]) -> anyhow::Result<serde_json::Value> {
+        let mut c = self.command();
+        c.args(args).stdin(std::process::Stdio::null());
+        trace_command(backend, &c);
+        let out = c.output().await.context("failed to run the aws CLI")?;
+        if !out.status.success() {
+            anyhow::bail!("aws {}: {}", args[..2.min(args.len())].join(" "), String::from_utf8_lossy(&out.stderr).trim());
+        }
+        if out.stdout.iter().all(u8::is_ascii_whitespace) {
+            return Ok(serde_json::Value::Null);
+        }
+        serde_json::from_slice(&out.stdout).context("unexpected answer from the aws CLI")
+    }
+
+    /// Credentials work (`sts get-caller-identity`)
+    async fn check_credentials(&self, backend: &str) -> anyhow::Result<()> {
+        self.json(backend, &["sts", "get-caller-identity"]).await.context("no usable AWS credentials")?;
+        Ok(())
+    }
+}
+
+/// AWS ECS backend: runs every command as a Fargate task with the `aws` CLI.
+///
+/// The task definition is either given (`with_task_definition`, its first container runs the
+/// command) or registered once per backend from the image, with `awslogs` logging. The backend
+/// polls the task until it stops, reading its CloudWatch log stream meanwhile (everything the
+/// container prints arrives as stdout), and stops the task on timeout or cancellation.
+pub struct EcsBackend {
+    cluster: String,
+    image: Option<String>,
+    task_definition: Option<String>,
+    aws: AwsCli,
+    subnets: Vec<String>,
+    security_groups: Vec<String>,
+    public_ip: bool,
+    execution_role: Option<String>,
+    task_role: Option<String>,
+    /// Fargate task size: CPU units and MiB
+    cpu: String,
+    memory: String,
+    log_group: String,
+    /// The task definition in use, resolved for the first command
+    resolved: tokio::sync::Mutex<Option<EcsTaskDefinition>>,
+}
+
+/// What commands need to know about the task definition they run in
+#[derive(Clone)]
+struct EcsTaskDefinition {
+    arn: String,
+    container: String,
+    /// `awslogs` group and stream prefix, if the container logs to CloudWatch
+    logs: Option<(String, String)>,
+}
+
+impl EcsBackend {
+    /// Run Fargate tasks in `cluster`
+    pub fn new(cluster: impl Into<String>) -> Self {
+        Self {
+            cluster: cluster.into(),
+            image: None,
+            task_definition: None,
+            aws: AwsCli::default(),
+            subnets: Vec::new(),
+            security_groups: Vec::new(),
+            public_ip: true,
+            execution_role: None,
+            task_role: None,
+            cpu: "256".to_string(),
+            memory: "512".to_string(),
+            log_group: "/rustypipe".to_string(),
+            resolved: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// Register a task definition running `image` (needs `with_execution_role` for logging)
+    pub fn with_image(mut self, image: impl Into<String>) -> Self {
+        self.image = Some(image.into());
+        self
+    }
+
+    /// Use an existing task definition (family, `family:revision` or ARN)
+    pub fn with_task_definition(mut self, task_definition: impl Into<String>) -> Self {
+        self.task_definition = Some(task_definition.into());
+        self
+    }
+
+    pub fn with_region(mut self, region: impl Into<String>) -> Self {
+        self.aws.region = Some(region.into());
+        self
+    }
+
+    /// Named profile of the AWS CLI configuration
+    pub fn with_profile(mut self, profile: impl Into<String>) -> Self {
+        self.aws.profile = Some(profile.into());
+        self
+    }
+
+    /// Subnets and security groups of the tasks' network interface; `public_ip` is needed to
+    /// pull public images from subnets without a NAT gateway
+    pub fn with_network(mut self, subnets: Vec<String>, security_groups: Vec<String>, public_ip: bool) -> Self {
+        self.subnets = subnets;
+        self.security_groups = security_groups;
+        self.public_ip = public_ip;
+        self
+    }
+
+    /// Role ECS pulls the image and writes logs with (registered task definitions)
+    pub fn with_execution_role(mut self, role: impl Into<String>) -> Self {
+        self.execution_role = Some(role.into());
+        self
+    }
+
+    /// Role the command runs as (registered task definitions)
+    pub fn with_task_role(mut self, role: impl Into<String>) -> Self {
+        self.task_role = Some(role.into());
+        self
+    }
+
+    /// Fargate task size of registered task definitions, e.g. `1024` CPU units and `2048` MiB
+    pub fn with_size(mut self, cpu: impl Into<String>, memory: impl Into<String>) -> Self {
+        self.cpu = cpu.into();
+        self.memory = memory.into();
+        self
+    }
+
+    /// CloudWatch log group of registered task definitions (default `/rustypipe`, created if missing)
+    pub fn with_log_group(mut self, group: impl Into<String>) -> Self {
+        self.log_group = group.into();
+        self
+    }
+
+    async fn aws_json(&self, args: &[&str]) -> anyhow::Result<serde_json::Value> {
+        self.aws.json("ecs", args).await
+    }
+
+    /// The task definition commands run in, described or registered on first use
+    async fn task_definition(&self) -> anyhow::Result<EcsTaskDefinition> {
+        let mut resolved = self.resolved.lock().await;
+        if let Some(def) = resolved.as_ref() {
+            return Ok(def.clone());
+        }
+        let answer = match (&self.task_definition, &self.image) {
+            (Some(name), _) => self.aws_json(&["ecs", "describe-task-definition", "--task-definition", name]).await?,
+            (None, Some(image)) => self.aws_json(&["ecs", "register-task-definition", "--cli-input-json", &self.registration(image).await?.to_string()]).await?,
+            (None, None) => anyhow::bail!("the ecs backend needs an image or a task definition"),
+        };
+        let def = &answer["taskDefinition"];
+        let container = &def["containerDefinitions"][0];
+        let logs = (container["logConfiguration"]["logDriver"] == "awslogs").then(|| {
+            let options = &container["logConfiguration"]["options"];
+            (
+                options["awslogs-group"].as_str().unwrap_or_default().to_string(),
+                options["awslogs-stream-prefix"].as_str().unwrap_or_default().to_string(),
+            )
+        });
+        let def = EcsTaskDefinition {
+            arn: def["taskDefinitionArn"].as_str().context("no task definition ARN in the answer")?.to_string(),
+            container: container["name"].as_str().context("the task definition has no container")?.to_string(),
+            logs,
+        };
+        *resolved = Some(def.clone());
+        Ok(def)
+    }
+
+    /// `register-task-definition` input for `image`
+    async fn registration(&self, image: &str) -> anyhow::Result<serde_json::Value> {
+        // awslogs needs the region explicitly
+        let region = match &self.aws.region {
+            Some(r) => r.clone(),
+            None => {
+                let mut c = self.aws.command();
+                c.args(["configure", "get", "region"]);
+                let out = c.output().await.context("failed to run the aws CLI")?;
+                let region = String::from_utf8_lossy(&out.stdout).trim().to_string();
+                anyhow::ensure!(!region.is_empty(), "no AWS region configured; set `region`");
+                region
+            }
+        };
+        let family: String = format!("rustypipe-{}", image).chars().map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' }).take(255).collect();
+        let mut input = serde_json::json!({
+            "family": family,
+            "requiresCompatibilities": ["FARGATE"],
+            "networkMode": "awsvpc",
+            "cpu": self.cpu,
+            "memory": self.memory,
+            "containerDefinitions": [{
+                "name": "task",
+                "image": image,
+                "essential": true,
+                "logConfiguration": {
+                    "logDriver": "awslogs",
+                    "options": {
+                        "awslogs-group": self.log_group,
+                        "awslogs-region": region,
+                        "awslogs-stream-prefix": "rustypipe",
+                        "awslogs-create-group": "true",
+                    },
+                },
+            }],
+        });
+        if let Some(role) = &self.execution_role {
+            input["executionRoleArn"] = role.as_str().into();
+        }
+        if let Some(role) = &self.task_role {
+            input["taskRoleArn"] = role.as_str().into();
+        }
+        Ok(input)
+    }
+
+    /// Read the log events of `stream` after `token`, feeding them to `sink` or `buf`; returns the
+    /// token to continue from
+    async fn read_logs(&self, group: &str, stream: &str, mut token: Option<String>, sink: &mut Option<LineSink>, buf: &mut Vec<u8>) -> Option<String> {
+        loop {
+            let mut args = vec!["logs", "get-log-events", "--log-group-name", group, "--log-stream-name", stream, "--start-from-head"];
+            if let Some(t) = &token {
+                args.extend(["--next-token", t]);
+            }
+            // the stream appears with the container's first output
+            let Ok(page) = self.aws_json(&args).await else { return token };
+            let events = page["events"].as_array().cloned().unwrap_or_default();
+            for e in &events {
+                let line = format!("{}\n", e["message"].as_str().unwrap_or_default());
+                match sink.as_mut() {
+                    Some(sink) => sink.push(line.as_bytes()),
+                    None => buf.extend_from_slice(line.as_bytes()),
+                }
+            }
+            let next = page["nextForwardToken"].as_str().map(str::to_string);
+            if events.is_empty() || next.is_none() || next == token {
+                return next.or(token);
+            }
+            token = next;
+        }
+    }
+
+    async fn stop_task(&self, task_arn: &str, reason: &str) {
+        if let Err(e) = self.aws_json(&["ecs", "stop-task", "--cluster", &self.cluster, "--task", task_arn, "--reason", reason]).await {
+            tracing::warn!("failed to stop ECS task {}: {:#}", task_arn, e);
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for EcsBackend {
+    async fn run(&self, cmd: &str, _cwd: &Path, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        if opts.stdin.is_some() || opts.tty {
+            tracing::warn!("stdin and tty are not supported by the ecs backend");
+        }
+        let def = self.task_definition().await?;
+        let mut command = container_shell(opts);
+        command.push(cmd.to_string());
+        let env: Vec<_> = opts.env.iter().map(|(k, v)| serde_json::json!({ "name": k, "value": v })).collect();
+        let mut overrides = serde_json::json!({ "containerOverrides": [{ "name": def.container, "command": command, "environment": env }] });
+        // a task's `resources` resize the Fargate task (CPU units, MiB)
+        if let Some(r) = &opts.resources {
+            if let Ok(Some(cores)) = r.cpu_cores() {
+                overrides["cpu"] = ((cores * 1024.0).round() as u64).to_string().into();
+            }
+            if let Ok(Some(bytes)) = r.memory_bytes() {
+                overrides["memory"] = (bytes >> 20).to_string().into();
+            }
+        }
+        let network = serde_json::json!({
+            "awsvpcConfiguration": {
+                "subnets": self.subnets,
+                "securityGroups": self.security_groups,
+                "assignPublicIp": if self.public_ip { "ENABLED" } else { "DISABLED" },
+            }
+        });
+        let started = self
+            .aws_json(&[
+                "ecs", "run-task",
+                "--cluster", &self.cluster,
+                "--launch-type", "FARGATE",
+                "--task-definition", &def.arn,
+                "--started-by", "rustypipe",
+                "--network-configuration", &network.to_string(),
+                "--overrides", &overrides.to_string(),
+            ])
+            .await?;
+        let Some(task_arn) = started["tasks"][0]["taskArn"].as_str().map(str::to_string) else {
+            let reason = started["failures"][0]["reason"].as_str().unwrap_or("no task was started");
+            anyhow::bail!("ECS could not start the task: {}", reason);
+        };
+        let task_id = task_arn.rsplit('/').next().unwrap_or_default().to_string();
+        let stream = def.logs.as_ref().map(|(group, prefix)| (group.clone(), format!("{}/{}/{}", prefix, def.container, task_id)));
+
+        let deadline = timeout_secs.map(|s| std::time::Instant::now() + std::time::Duration::from_secs(s));
+        let mut sink = opts.stream.as_ref().map(|s| LineSink::new(s, false));
+        let mut buf = Vec::new();
+        let mut token = None;
+        loop {
+            let described = self.aws_json(&["ecs", "describe-tasks", "--cluster", &self.cluster, "--tasks", &task_arn]).await?;
+            let task = &described["tasks"][0];
+            let stopped = task["lastStatus"] == "STOPPED";
+            if let Some((group, name)) = &stream {
+                token = self.read_logs(group, name, token, &mut sink, &mut buf).await;
+            }
+            if stopped {
+                let stdout = String::from_utf8_lossy(&sink.map(LineSink::finish).unwrap_or(buf)).into_owned();
+                let mut stderr = String::new();
+                let code = match task["containers"][0]["exitCode"].as_i64() {
+                    Some(code) => code as i32,
+                    None => {
+                        stderr.push_str(&format!("task stopped: {}\n", task["stoppedReason"].as_str().unwrap_or("unknown reason")));
+                        1
+                    }
+                };
+                return Ok((stdout, stderr, crate::util::exit_status(code)));
+            }
+            if deadline.is_some_and(|d| std::time::Instant::now() >= d) {
+                self.stop_task(&task_arn, "rustypipe: timed out").await;
+                return Err(TimedOut { backend: "ecs".to_string(), secs: timeout_secs.unwrap_or_default() }.into());
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(std::time::Duration::from_secs(5)) => {}
+                _ = cancelled(opts.cancel.as_ref()) => {
+                    self.stop_task(&task_arn, "rustypipe: cancelled").await;
+                    return Err(Cancelled { backend: "ecs".to_string() }.into());
+                }
+            }
+        }
+    }
+
+    /// Credentials work, the cluster is active and the task definition resolves
+    async fn preflight(&self) -> anyhow::Result<()> {
+        self.aws.check_credentials("ecs").await?;
+        let clusters = self.aws_json(&["ecs", "describe-clusters", "--clusters", &self.cluster]).await?;
+        if clusters["clusters"][0]["status"] != "ACTIVE" {
+            anyhow::bail!("cluster {} not found or not active", self.cluster);
+        }
+        self.task_definition().await?;
+        Ok(())
+    }
+}
+
+/// AWS Lambda backend for short glue steps: invokes a function with the `aws` CLI and returns its
+/// response as the task output.
+///
+/// A command that is a JSON object is sent as the payload as is; any other command is sent as
+/// `{"command": ..., "env": {...}}`. A response object with an `exit_code` supplies the exit code
+/// and its `stdout`/`stderr`; any other response becomes stdout with exit code 0. Function errors
+/// fail the task with the error payload on stderr, followed by the tail of the invocation log.
+/// Cancelling or timing out stops waiting, but not the invocation itself.
+pub struct LambdaBackend {
+    function: String,
+    qualifier: Option<String>,
+    aws: AwsCli,
+}
+
+impl LambdaBackend {
+    /// Invoke `function` (name, ARN or `name:alias`)
+    pub fn new(function: impl Into<String>) -> Self {
+        LambdaBackend { function: function.into(), qualifier: None, aws: AwsCli::default() }
+    }
+
+    /// Version or alias to invoke
+    pub fn with_qualifier(mut self, qualifier: impl Into<String>) -> Self {
+        self.qualifier = Some(qualifier.into());
+        self
+    }
+
+    /// AWS region (default: the CLI configuration)
+    pub fn with_region(mut self, region: impl Into<String>) -> Self {
+        self.aws.region = Some(region.into());
+        self
+    }
+
+    /// Named profile of the AWS CLI configuration
+    pub fn with_profile(mut self, profile: impl Into<String>) -> Self {
+        self.aws.profile = Some(profile.into());
+        self
+    }
+
+    fn payload(cmd: &str, env: &[(String, String)]) -> String {
+        match serde_json::from_str::<serde_json::Value>(cmd.trim()) {
+            Ok(v) if v.is_object() => v.to_string(),
+            _ => {
+                let env: serde_json::Map<_, _> = env.iter().map(|(k, v)| (k.clone(), v.clone().into())).collect();
+                serde_json::json!({ "command": cmd, "env": env }).to_string()
+            }
+        }
+    }
+
+    /// Split a function response into (stdout, stderr, exit code)
+    fn response(body: &[u8]) -> (String, String, i32) {
+        let text = |v: &serde_json::Value| match v {
+            serde_json::Value::Null => String::new(),
+            serde_json::Value::String(s) => s.clone(),
+            v => v.to_string(),
+        };
+        match serde_json::from_slice::<serde_json::Value>(body) {
+            Ok(v) if v["exit_code"].is_i64() => (text(&v["stdout"]), text(&v["stderr"]), v["exit_code"].as_i64().unwrap_or(1) as i32),
+            Ok(v @ serde_json::Value::String(_)) => (text(&v), String::new(), 0),
+            _ => (String::from_utf8_lossy(body).into_owned(), String::new(), 0),
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for LambdaBackend {
+    async fn run(&self, cmd: &str, _cwd: &Path, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        use base64::Engine;
+        if opts.stdin.is_some() || opts.tty {
+            tracing::warn!("stdin and tty are not supported by the lambda backend");
+        }
+        let out_file = std::env::temp_dir().join(format!("{}.json", unique_name()));
+        let mut c = self.aws.command();
+        // the task's own timeout applies, not the CLI's 60s read timeout
+        c.args(["--cli-read-timeout", "0", "lambda", "invoke", "--function-name", &self.function]);
+        if let Some(q) = &self.qualifier {
+            c.arg("--qualifier").arg(q);
+        }
+        c.args(["--log-type", "Tail", "--cli-binary-format", "raw-in-base64-out", "--payload"])
+            .arg(Self::payload(cmd, &opts.env))
+            .arg(&out_file);
+        // the CLI prints invocation metadata, not output worth streaming
+        let quiet = RunOptions { stream: None, stdin: None, ..opts.clone() };
+        let result = run_command("lambda", c, timeout_secs, &quiet).await;
+        let body = std::fs::read(&out_file).unwrap_or_default();
+        let _ = std::fs::remove_file(&out_file);
+        let (meta, cli_err, status) = result?;
+        if !status.success() {
+            anyhow::bail!("aws lambda invoke: {}", cli_err.trim());
+        }
+        let meta: serde_json::Value = serde_json::from_str(&meta).context("unexpected answer from the aws CLI")?;
+
+        let (mut stdout, mut stderr, code) = match meta["FunctionError"].as_str() {
+            Some(kind) => (String::new(), format!("function error ({}): {}\n", kind, String::from_utf8_lossy(&body).trim()), 1),
+            None => Self::response(&body),
+        };
+        if let Some(sink) = opts.stream.as_ref() {
+            let mut out = LineSink::new(sink, false);
+            out.push(stdout.as_bytes());
+            stdout = String::from_utf8_lossy(&out.finish()).into_owned();
+        }
+        if code != 0 {
+            if let Some(log) = meta["LogResult"].as_str().and_then(|l| base64::engine::general_purpose::STANDARD.decode(l).ok()) {
+                stderr.push_str(&String::from_utf8_lossy(&log));
+            }
+        }
+        Ok((stdout, stderr, crate::util::exit_status(code)))
+    }
+
+    /// Credentials work and the function exists
+    async fn preflight(&self) -> anyhow::Result<()> {
+        self.aws.check_credentials("lambda").await?;
+        let mut args = vec!["lambda", "get-function-configuration", "--function-name", &self.function];
+        if let Some(q) = &self.qualifier {
+            args.extend(["--qualifier", q]);
+        }
+        self.aws.json("lambda", &args).await.with_context(|| format!("function {} not found", self.function))?;
+        Ok(())
+    }
+}
+
+/// Google Cloud Run Jobs backend: runs every command as a one-off Cloud Run job with the `gcloud`
+/// CLI.
+///
+/// Each run creates a job from the image (the shell as its command, the task's command as its
+/// argument), executes it once without retries, polls the execution until it completes and reads
+/// its output from Cloud Logging meanwhile. The job is deleted afterwards; on timeout or
+/// cancellation the execution is cancelled first. Cloud Run does not report exit codes, so a
+/// failed execution exits with 1 and its failure message on stderr.
+pub struct CloudRunBackend {
+    image: String,
+    service_account: Option<String>,
+    region: Option<String>,
+    project: Option<String>,
+}
+
+impl CloudRunBackend {
+    /// Run commands in `image`
+    pub fn new(image: impl Into<String>) -> Self {
+        CloudRunBackend { image: image.into(), service_account: None, region: None, project: None }
+    }
+
+    /// Service account the jobs run as (default: the project's compute service account)
+    pub fn with_service_account(mut self, account: impl Into<String>) -> Self {
+        self.service_account = Some(account.into());
+        self
+    }
+
+    /// Cloud Run region (default: the `run/region` gcloud property)
+    pub fn with_region(mut self, region: impl Into<String>) -> Self {
+        self.region = Some(region.into());
+        self
+    }
+
+    /// Google Cloud project (default: the gcloud configuration)
+    pub fn with_project(mut self, project: impl Into<String>) -> Self {
+        self.project = Some(project.into());
+        self
+    }
+
+    fn gcloud(&self) -> Command {
+        let mut c = Command::new("gcloud");
+        if let Some(p) = &self.project {
+            c.arg("--project").arg(p);
+        }
+        c.args(["--quiet", "--format", "json"]);
+        c
+    }
+
+    /// `--region` for `gcloud run` commands
+    fn region_args(&self) -> Vec<String> {
+        self.region.iter().flat_map(|r| ["--region".to_string(), r.clone()]).collect()
+    }
+
+    /// Run a `gcloud` command to completion and parse its JSON answer
+    async fn gcloud_json(&self, args: &[String]) -> anyhow::Result<serde_json::Value> {
+        let mut c = self.gcloud();
+        c.args(args).stdin(std::process::Stdio::null());
+        trace_command("cloudrun", &c);
+        let out = c.output().await.context("failed to run the gcloud CLI")?;
+        if !out.status.success() {
+            anyhow::bail!("gcloud {}: {}", args[..3.min(args.len())].join(" "), String::from_utf8_lossy(&out.stderr).trim());
+        }
+        if out.stdout.iter().all(u8::is_ascii_whitespace) {
+            return Ok(serde_json::Value::Null);
+        }
+        serde_json::from_slice(&out.stdout).context("unexpected answer from the gcloud CLI")
+    }
+
+    /// A gcloud list flag value; uses gcloud's `^DELIM^` escaping so items may contain commas
+    fn list_arg(items: &[String]) -> String {
+        let delim = ["|", "@@", "##", "%%", "~~"].into_iter().find(|d| items.iter().all(|i| !i.contains(d))).unwrap_or("\u{1f}");
+        format!("^{}^{}", delim, items.join(delim))
+    }
+
+    /// Feed the log entries of `execution` not seen yet to the sinks or buffers
+    async fn read_logs(&self, job: &str, execution: &str, seen: &mut std::collections::HashSet<String>, sinks: &mut [Option<LineSink>; 2], bufs: &mut [Vec<u8>; 2]) {
+        let filter = format!(
+            "resource.type=\"cloud_run_job\" AND resource.labels.job_name=\"{}\" AND labels.\"run.googleapis.com/execution_name\"=\"{}\"",
+            job, execution
+        );
+        let args = ["logging", "read", &filter, "--order", "asc", "--freshness", "1d"].map(str::to_string);
+        // entries show up with a short delay; errors just mean nothing new yet
+        let Ok(serde_json::Value::Array(entries)) = self.gcloud_json(&args).await else { return };
+        for e in entries {
+            let id = e["insertId"].as_str().unwrap_or_default().to_string();
+            let Some(text) = e["textPayload"].as_str() else { continue };
+            if !seen.insert(id) {
+                continue;
+            }
+            let i = usize::from(e["logName"].as_str().is_some_and(|l| l.ends_with("%2Fstderr")));
+            let line = format!("{}\n", text.trim_end_matches('\n'));
+            match sinks[i].as_mut() {
+                Some(sink) => sink.push(line.as_bytes()),
+                None => bufs[i].extend_from_slice(line.as_bytes()),
+            }
+        }
+    }
+
+    async fn cancel_execution(&self, execution: &str) {
+        let mut args = vec!["run".to_string(), "jobs".to_string(), "executions".to_string(), "cancel".to_string(), execution.to_string()];
+        args.extend(self.region_args());
+        if let Err(e) = self.gcloud_json(&args).await {
+            tracing::warn!("failed to cancel Cloud Run execution {}: {:#}", execution, e);
+        }
+    }
+
+    async fn delete_job(&self, job: &str) {
+        let mut args = vec!["run".to_string(), "jobs".to_string(), "delete".to_string(), job.to_string(), "--async".to_string()];
+        args.extend(self.region_args());
+        if let Err(e) = self.gcloud_json(&args).await {
+            tracing::warn!("failed to delete Cloud Run job {}: {:#}", job, e);
+        }
+    }
+
+    async fn execute(&self, job: &str, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        let mut args = vec!["run".to_string(), "jobs".to_string(), "execute".to_string(), job.to_string(), "--async".to_string()];
+        args.extend(self.region_args());
+        let started = self.gcloud_json(&args).await?;
+        let execution = started["metadata"]["name"].as_str().context("gcloud did not name the job execution")?.to_string();
+
+        let deadline = timeout_secs.map(|s| std::time::Instant::now() + std::time::Duration::from_secs(s));
+        let mut sinks = [false, true].map(|to_stderr| opts.stream.as_ref().map(|s| LineSink::new(s, to_stderr)));
+        let mut bufs = [Vec::new(), Vec::new()];
+        let mut seen = std::collections::HashSet::new();
+        let mut args = vec!["run".to_string(), "jobs".to_string(), "executions".to_string(), "describe".to_string(), execution.clone()];
+        args.extend(self.region_args());
+        loop {
+            let status = self.gcloud_json(&args).await?["status"].clone();
+            let done = !status["completionTime"].is_null();
+            if done {
+                // give Cloud Logging a moment to catch up with the last lines
+                tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+            }
+            self.read_logs(job, &execution, &mut seen, &mut sinks, &mut bufs).await;
+            if done {
+                let [out_sink, err_sink] = sinks;
+                let [out_buf, err_buf] = bufs;
+                let stdout = String::from_utf8_lossy(&out_sink.map(LineSink::finish).unwrap_or(out_buf)).into_owned();
+                let mut stderr = String::from_utf8_lossy(&err_sink.map(LineSink::finish).unwrap_or(err_buf)).into_owned();
+                if status["succeededCount"].as_i64().unwrap_or(0) > 0 {
+                    return Ok((stdout, stderr, crate::util::exit_status(0)));
+                }
+                let failure = status["conditions"]
+                    .as_array()
+                    .and_then(|c| c.iter().find(|c| c["type"] == "Completed"))
+                    .and_then(|c| c["message"].as_str())
+                    .unwrap_or("execution failed");
+                stderr.push_str(&format!("{}\n", failure));
+                return Ok((stdout, stderr, crate::util::exit_status(1)));
+            }
+            if deadline.is_some_and(|d| std::time::Instant::now() >= d) {
+                self.cancel_execution(&execution).await;
+                return Err(TimedOut { backend: "cloudrun".to_string(), secs: timeout_secs.unwrap_or_default() }.into());
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(std::time::Duration::from_secs(5)) => {}
+                _ = cancelled(opts.cancel.as_ref()) => {
+                    self.cancel_execution(&execution).await;
+                    return Err(Cancelled { backend: "cloudrun".to_string() }.into());
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for CloudRunBackend {
+    async fn run(&self, cmd: &str, _cwd: &Path, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        if opts.stdin.is_some() || opts.tty {
+            tracing::warn!("stdin and tty are not supported by the cloudrun backend");
+        }
+        let job = unique_name();
+        let mut shell = container_shell(opts);
+        let program = shell.remove(0);
+        shell.push(cmd.to_string());
+        let mut args = vec!["run".to_string(), "jobs".to_string(), "create".to_string(), job.clone(), "--image".to_string(), self.image.clone()];
+        args.extend(self.region_args());
+        args.extend(["--command".to_string(), program, "--args".to_string(), Self::list_arg(&shell)]);
+        args.extend(["--max-retries".to_string(), "0".to_string(), "--tasks".to_string(), "1".to_string()]);
+        if let Some(s) = timeout_secs {
+            args.extend(["--task-timeout".to_string(), format!("{}s", s)]);
+        }
+        if !opts.env.is_empty() {
+            let env: Vec<_> = opts.env.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+            args.extend(["--set-env-vars".to_string(), Self::list_arg(&env)]);
+        }
+        if let Some(a) = &self.service_account {
+            args.extend(["--service-account".to_string(), a.clone()]);
+        }
+        if let Some(r) = &opts.resources {
+            if let Ok(Some(cores)) = r.cpu_cores() {
+                args.extend(["--cpu".to_string(), cores.to_string()]);
+            }
+            if let Ok(Some(bytes)) = r.memory_bytes() {
+                args.extend(["--memory".to_string(), format!("{}Mi", bytes >> 20)]);
+            }
+        }
+        self.gcloud_json(&args).await.context("failed to create the Cloud Run job")?;
+        let result = self.execute(&job, timeout_secs, opts).await;
+        self.delete_job(&job).await;
+        result
+    }
+
+    /// gcloud has an active account and can list jobs in the region
+    async fn preflight(&self) -> anyhow::Result<()> {
+        let mut c = self.gcloud();
+        c.args(["auth", "print-access-token"]);
+        probe("cloudrun", c, "gcloud credentials").await?;
+        let mut c = self.gcloud();
+        c.args(["run", "jobs", "list", "--limit", "1"]).args(self.region_args());
+        probe("cloudrun", c, "Cloud Run access").await?;
+        Ok(())
+    }
+}
+
+/// Azure Container Instances backend: runs every command in a fresh container group with the
+/// `az` CLI.
+///
+/// The group is created with restart policy `Never`, polled until its container terminates (log
+/// output arrives as stdout meanwhile, ACI does not separate the streams) and deleted afterwards,
+/// also on timeout or cancellation.
+pub struct AciBackend {
+    image: String,
+    resource_group: String,
+    location: Option<String>,
+    subscription: Option<String>,
+    cpu: f64,
+    memory_gb: f64,
+}
+
+impl AciBackend {
+    /// Run commands in `image`, creating container groups in `resource_group`
+    pub fn new(image: impl Into<String>, resource_group: impl Into<String>) -> Self {
+        AciBackend { image: image.into(), resource_group: resource_group.into(), location: None, subscription: None, cpu: 1.0, memory_gb: 1.5 }
+    }
+
+    /// Azure region (default: the resource group's)
+    pub fn with_location(mut self, location: impl Into<String>) -> Self {
+        self.location = Some(location.into());
+        self
+    }
+
+    /// Subscription name or ID (default: the az CLI's current one)
+    pub fn with_subscription(mut self, subscription: impl Into<String>) -> Self {
+        self.subscription = Some(subscription.into());
+        self
+    }
+
+    /// Default container size in cores and GB (1 and 1.5); a task's `resources` override it
+    pub fn with_size(mut self, cpu: f64, memory_gb: f64) -> Self {
+        self.cpu = cpu;
+        self.memory_gb = memory_gb;
+        self
+    }
+
+    fn az(&self) -> Command {
+        let mut c = Command::new("az");
+        if let Some(s) = &self.subscription {
+            c.arg("--subscription").arg(s);
+        }
+        c.args(["--output", "json"]);
+        c
+    }
+
+    /// Run an `az` command to completion and parse its JSON answer
+    async fn az_json(&self, args: &[String]) -> anyhow::Result<serde_json::Value> {
+        let mut c = self.az();
+        c.args(args).stdin(std::process::Stdio::null());
+        trace_command("aci", &c);
+        let out = c.output().await.context("failed to run the az CLI")?;
+        if !out.status.success() {
+            anyhow::bail!("az {}: {}", args[..2.min(args.len())].join(" "), String::from_utf8_lossy(&out.stderr).trim());
+        }
+        if out.stdout.iter().all(u8::is_ascii_whitespace) {
+            return Ok(serde_json::Value::Null);
+        }
+        serde_json::from_slice(&out.stdout).context("unexpected answer from the az CLI")
+    }
+
+    /// `az container <action> -g <group> -n <name>` arguments
+    fn container_args(&self, action: &str, name: &str) -> Vec<String> {
+        ["container", action, "--resource-group", &self.resource_group, "--name", name].map(str::to_string).to_vec()
+    }
+
+    /// The group's log so far (`az container logs` returns all of it, as a JSON string)
+    async fn logs(&self, name: &str) -> String {
+        match self.az_json(&self.container_args("logs", name)).await {
+            Ok(serde_json::Value::String(s)) => s,
+            _ => String::new(),
+        }
+    }
+
+    async fn delete(&self, name: &str) {
+        let mut args = self.container_args("delete", name);
+        args.push("--yes".to_string());
+        if let Err(e) = self.az_json(&args).await {
+            tracing::warn!("failed to delete container group {}: {:#}", name, e);
+        }
+    }
+
+    async fn wait(&self, name: &str, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        let deadline = timeout_secs.map(|s| std::time::Instant::now() + std::time::Duration::from_secs(s));
+        let mut sink = opts.stream.as_ref().map(|s| LineSink::new(s, false));
+        let mut log = String::new();
+        loop {
+            let group = self.az_json(&self.container_args("show", name)).await?;
+            let state = &group["containers"][0]["instanceView"]["currentState"];
+            let done = state["state"] == "Terminated" || group["provisioningState"] == "Failed";
+            let current = self.logs(name).await;
+            if let Some(new) = current.get(log.len()..).filter(|_| current.starts_with(&log)) {
+                if let Some(sink) = sink.as_mut() {
+                    sink.push(new.as_bytes());
+                }
+                log = current;
+            }
+            if done {
+                let stdout = match sink {
+                    Some(sink) => String::from_utf8_lossy(&sink.finish()).into_owned(),
+                    None => log,
+                };
+                return match state["exitCode"].as_i64() {
+                    Some(code) => Ok((stdout, String::new(), crate::util::exit_status(code as i32))),
+                    None => {
+                        let events: Vec<_> = group["containers"][0]["instanceView"]["events"]
+                            .as_array()
+                            .into_iter()
+                            .flatten()
+                            .filter(|e| e["type"] == "Warning")
+                            .filter_map(|e| e["message"].as_str())
+                            .collect();
+                        let reason = if events.is_empty() { "container group failed".to_string() } else { events.join("\n") };
+                        Ok((stdout, format!("{}\n", reason), crate::util::exit_status(1)))
+                    }
+                };
+            }
+            if deadline.is_some_and(|d| std::time::Instant::now() >= d) {
+                return Err(TimedOut { backend: "aci".to_string(), secs: timeout_secs.unwrap_or_default() }.into());
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(std::time::Duration::from_secs(5)) => {}
+                _ = cancelled(opts.cancel.as_ref()) => return Err(Cancelled { backend: "aci".to_string() }.into()),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Backend for AciBackend {
+    async fn run(&self, cmd: &str, _cwd: &Path, timeout_secs: Option<u64>, opts: &RunOptions) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+        if opts.stdin.is_some() || opts.tty {
+            tracing::warn!("stdin and tty are not supported by the aci backend");
+        }
+        let name = unique_name();
+        let mut argv = container_shell(opts);
+        argv.push(cmd.to_string());
+        let command_line = argv.iter().map(|a| shell_quote(a)).collect::<Vec<_>>().join(" ");
+        let (mut cpu, mut memory) = (self.cpu, self.memory_gb);
+        if let Some(r) = &opts.resources {
+            if let Ok(Some(cores)) = r.cpu_cores() {
+                cpu = cores;
+            }
+            if let Ok(Some(bytes)) = r.memory_bytes() {
+                // ACI sizes memory in steps of 0.1 GB
+                memory = (bytes as f64 / 1e9 * 10.0).ceil() / 10.0;
+            }
+        }
+        let mut args = self.container_args("create", &name);
+        args.extend(["--image".to_string(), self.image.clone(), "--restart-policy".to_string(), "Never".to_string()]);
+        args.extend(["--command-line".to_string(), command_line, "--cpu".to_string(), cpu.to_string(), "--memory".to_string(), memory.to_string()]);
+        if let Some(l) = &self.location {
+            args.extend(["--location".to_string(), l.clone()]);
+        }
+        if !opts.env.is_empty() {
+            args.push("--environment-variables".to_string());
+            args.extend(opts.env.iter().map(|(k, v)| format!("{}={}", k, v)));
+        }
+        args.push("--no-wait".to_string());
+        self.az_json(&args).await.context("failed to create the container group")?;
+        let result = self.wait(&name, timeout_secs, opts).await;
+        self.delete(&name).await;
+        result
+    }
+
+    /// az is logged in and the resource group exists
+    async fn preflight(&self) -> anyhow::Result<()> {
+        let mut c = self.az();
+        c.args(["account", "show"]);
+        probe("aci", c, "Azure login").await?;
+        let mut c = self.az();
+        c.args(["group", "show", "--name", &self.resource_group]);
+        probe("aci", c, "resource group").await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+//! `files:` tasks: portable copy/move/delete/mkdir/template operations, so pipelines don't need
+//! `cp` on Unix and `Copy-Item` on Windows.
+use crate::pipeline::parser::FileOp;
+use crate::util::exit_status;
+use anyhow::Context;
+use std::path::Path;
+
+/// Execute the operations in order, stopping at the first failure
+pub async fn run(
+    ops: &[FileOp],
+    cwd: &Path,
+    interp: &(dyn Fn(&str) -> anyhow::Result<String> + Sync),
+) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+    let mut stdout = String::new();
+    for op in ops {
+        let line = apply(op, cwd, interp)?;
+        stdout.push_str(&line);
+        stdout.push('\n');
+    }
+    Ok((stdout, String::new(), exit_status(0)))
+}
+
+fn apply(op: &FileOp, cwd: &Path, interp: &(dyn Fn(&str) -> anyhow::Result<String> + Sync)) -> anyhow::Result<String> {
+    let resolve = |p: &str| interp(p).map(|p| cwd.join(p));
+    match op {
+        FileOp::Copy { from, to } => {
+            let (src, dst) = (resolve(from)?, resolve(to)?);
+            copy_recursive(&src, &dst).with_context(|| format!("copy {:?} -> {:?} failed", src, dst))?;
+            Ok(format!("copied {} -> {}", src.display(), dst.display()))
+        }
+        FileOp::Move { from, to } => {
+            let (src, dst) = (resolve(from)?, resolve(to)?);
+            if let Some(parent) = dst.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            // rename fails across filesystems; fall back to copy + delete
+            if std::fs::rename(&src, &dst).is_err() {
+                copy_recursive(&src, &dst).with_context(|| format!("move {:?} -> {:?} failed", src, dst))?;
+                remove(&src)?;
+            }
+            Ok(format!("moved {} -> {}", src.display(), dst.display()))
+        }
+        FileOp::Delete(path) => {
+            let p = resolve(path)?;
+            remove(&p).with_context(|| format!("delete {:?} failed", p))?;
+            Ok(format!("deleted {}", p.display()))
+        }
+        FileOp::Mkdir(path) => {
+            let p = resolve(path)?;
+            std::fs::create_dir_all(&p).with_context(|| format!("mkdir {:?} failed", p))?;
+            Ok(format!("created {}", p.display()))
+        }
+        FileOp::Template { from, to } => {
+            let (src, dst) = (resolve(from)?, resolve(to)?);
+            let content = std::fs::read_to_string(&src).with_context(|| format!("failed to read template {:?}", src))?;
+            if let Some(parent) = dst.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            let content = interp(&content).with_context(|| format!("failed to render template {:?}", src))?;
+            std::fs::write(&dst, content).with_context(|| format!("failed to write {:?}", dst))?;
+            Ok(format!("rendered {} -> {}", src.display(), dst.display()))
+        }
+    }
+}
+
+fn copy_recursive(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    if src.is_dir() {
+        std::fs::create_dir_all(dst)?;
+        for entry in std::fs::read_dir(src)? {
+            let entry = entry?;
+            copy_recursive(&entry.path(), &dst.join(entry.file_name()))?;
+        }
+    } else {
+        if let Some(parent) = dst.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(src, dst)?;
+    }
+    Ok(())
+}
+
+fn remove(p: &Path) -> anyhow::Result<()> {
+    if p.is_dir() {
+        std::fs::remove_dir_all(p)?;
+    } else if p.exists() {
+        std::fs::remove_file(p)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,170 @@
+//! `git:` tasks: clone/checkout/fetch/tag/push through libgit2, independent of any `git` binary.
+//!
+//! The task output is the resulting commit id (clone, checkout, fetch, tag) or the pushed refspec,
+//! so downstream tasks can use e.g. `{{checkout.output}}`.
+//! HTTPS credentials come from the environment variable named by `token_env`; SSH remotes use the
+//! running ssh-agent.
+use crate::pipeline::parser::{GitAction, GitSpec};
+use crate::util::exit_status;
+use anyhow::Context;
+use git2::{build::RepoBuilder, Cred, CredentialType, FetchOptions, PushOptions, RemoteCallbacks, Repository};
+use std::path::Path;
+
+/// Execute a `git:` task on a blocking thread (libgit2 is synchronous)
+pub async fn run(
+    spec: &GitSpec,
+    cwd: &Path,
+    interp: &(dyn Fn(&str) -> anyhow::Result<String> + Sync),
+) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+    // Resolve placeholders up front; the blocking closure must own its data.
+    let mut spec = spec.clone();
+    for field in [&mut spec.repo, &mut spec.path, &mut spec.git_ref, &mut spec.tag, &mut spec.message] {
+        if let Some(v) = field.as_mut() {
+            *v = interp(v)?;
+        }
+    }
+    let path = cwd.join(spec.path.clone().unwrap_or_else(|| ".".to_string()));
+
+    let out = tokio::task::spawn_blocking(move || execute(&spec, &path))
+        .await
+        .context("git task panicked")??;
+    Ok((format!("{}\n", out), String::new(), exit_status(0)))
+}
+
+fn execute(spec: &GitSpec, path: &Path) -> anyhow::Result<String> {
+    match spec.action {
+        GitAction::Clone => {
+            let url = spec.repo.as_deref().context("git clone requires `repo`")?;
+            let mut builder = RepoBuilder::new();
+            builder.fetch_options(fetch_options(spec));
+            if let Some(r) = &spec.git_ref {
+                builder.branch(r);
+            }
+            let repo = builder
+                .clone(url, path)
+                .with_context(|| format!("git clone {} into {:?} failed", url, path))?;
+            head_id(&repo)
+        }
+        GitAction::Checkout => {
+            let repo = open(path)?;
+            let r = spec.git_ref.as_deref().context("git checkout requires `ref`")?;
+            let (object, reference) = repo.revparse_ext(r).with_context(|| format!("unknown ref '{}'", r))?;
+            repo.checkout_tree(&object, Some(git2::build::CheckoutBuilder::new().force()))?;
+            match reference.and_then(|re| re.name().map(|n| n.to_string())) {
+                Some(name) if name.starts_with("refs/heads/") => repo.set_head(&name)?,
+                _ => repo.set_head_detached(object.peel_to_commit()?.id())?,
+            }
+            head_id(&repo)
+        }
+        GitAction::Fetch => {
+            let repo = open(path)?;
+            let remote_name = spec.repo.as_deref().unwrap_or("origin");
+            let mut remote = repo
+                .find_remote(remote_name)
+                .or_else(|_| repo.remote_anonymous(remote_name))
+                .with_context(|| format!("unknown remote '{}'", remote_name))?;
+            let refspecs: Vec<String> = spec.git_ref.iter().cloned().collect();
+            remote
+                .fetch(&refspecs, Some(&mut fetch_options(spec)), None)
+                .with_context(|| format!("git fetch from {} failed", remote_name))?;
+            let fetched = repo.find_reference("FETCH_HEAD")?.peel_to_commit()?;
+            Ok(fetched.id().to_string())
+        }
+        GitAction::Tag => {
+            let repo = open(path)?;
+            let name = spec.tag.as_deref().context("git tag requires `tag`")?;
+            let target = repo.revparse_single(spec.git_ref.as_deref().unwrap_or("HEAD"))?;
+            match &spec.message {
+                Some(msg) => {
+                    let sig = repo
+                        .signature()
+                        .or_else(|_| git2::Signature::now("rustypipe", "rustypipe@localhost"))?;
+                    repo.tag(name, &target, &sig, msg, false)?;
+                }
+                None => {
+                    repo.tag_lightweight(name, &target, false)?;
+                }
+            }
+            let id = target.peel_to_commit()?.id().to_string();
+            Ok(id)
+        }
+        GitAction::Push => {
+            let repo = open(path)?;
+            let remote_name = spec.repo.as_deref().unwrap_or("origin");
+            let mut remote = repo
+                .find_remote(remote_name)
+                .or_else(|_| repo.remote_anonymous(remote_name))
+                .with_context(|| format!("unknown remote '{}'", remote_name))?;
+            let refspec = match &spec.git_ref {
+                Some(r) => r.clone(),
+                None => {
+                    let head = repo.head()?;
+                    let name = head.name().context("HEAD is not a branch; set `ref`")?.to_string();
+                    format!("{}:{}", name, name)
+                }
+            };
+
+            let mut rejected: Option<String> = None;
+            {
+                let mut callbacks = credentials(spec);
+                callbacks.push_update_reference(|refname, status| {
+                    if let Some(msg) = status {
+                        rejected = Some(format!("{}: {}", refname, msg));
+                    }
+                    Ok(())
+                });
+                let mut opts = PushOptions::new();
+                opts.remote_callbacks(callbacks);
+                remote
+                    .push(&[refspec.as_str()], Some(&mut opts))
+                    .with_context(|| format!("git push {} {} failed", remote_name, refspec))?;
+            }
+            if let Some(r) = rejected {
+                anyhow::bail!("push rejected: {}", r);
+            }
+            Ok(refspec)
+        }
+    }
+}
+
+fn open(path: &Path) -> anyhow::Result<Repository> {
+    Repository::open(path).with_context(|| format!("{:?} is not a git repository", path))
+}
+
+fn head_id(repo: &Repository) -> anyhow::Result<String> {
+    Ok(repo.head()?.peel_to_commit()?.id().to_string())
+}
+
+fn fetch_options(spec: &GitSpec) -> FetchOptions<'static> {
+    let mut opts = FetchOptions::new();
+    opts.remote_callbacks(credentials(spec));
+    if let Some(depth) = spec.depth {
+        opts.depth(depth);
+    }
+    opts
+}
+
+/// Credential callback: token for HTTPS, ssh-agent for SSH. Gives up after a few attempts so
+/// bad credentials fail instead of looping inside libgit2.
+fn credentials(spec: &GitSpec) -> RemoteCallbacks<'static> {
+    let token = spec.token_env.as_ref().and_then(|name| std::env::var(name).ok());
+    let username = spec.username.clone().unwrap_or_else(|| "x-access-token".to_string());
+    let mut attempts = 0;
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |_url, user_from_url, allowed| {
+        attempts += 1;
+        if attempts > 3 {
+            return Err(git2::Error::from_str("authentication failed"));
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            if let Some(token) = &token {
+                return Cred::userpass_plaintext(&username, token);
+            }
+        }
+        if allowed.contains(CredentialType::SSH_KEY) {
+            return Cred::ssh_key_from_agent(user_from_url.unwrap_or("git"));
+        }
+        Cred::default()
+    });
+    callbacks
+}
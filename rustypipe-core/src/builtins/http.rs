@@ -0,0 +1,46 @@
+//! `http:` tasks: issue one HTTP request from the runner (healthchecks, webhooks, API calls).
+use crate::pipeline::parser::HttpSpec;
+use crate::util::exit_status;
+use anyhow::Context;
+
+/// Execute an `http:` task. Transport errors are returned as `Err` (and retried by the executor);
+/// an unexpected status code fails the task with exit code 1. The response body is the task output.
+pub async fn run(
+    spec: &HttpSpec,
+    interp: &(dyn Fn(&str) -> anyhow::Result<String> + Sync),
+) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+    let method_name = spec.method.as_deref().unwrap_or("GET").to_ascii_uppercase();
+    let method = reqwest::Method::from_bytes(method_name.as_bytes())
+        .with_context(|| format!("invalid HTTP method '{}'", method_name))?;
+    let url = interp(&spec.url)?;
+
+    let client = reqwest::Client::new();
+    let mut req = client.request(method, &url);
+    for (k, v) in &spec.headers {
+        req = req.header(k.as_str(), interp(v)?);
+    }
+    if let Some(body) = &spec.body {
+        req = req.body(interp(body)?);
+    }
+
+    let resp = req.send().await.with_context(|| format!("{} {} failed", method_name, url))?;
+    let status = resp.status();
+    let body = resp.text().await.context("failed to read HTTP response body")?;
+
+    let ok = if spec.expect_status.is_empty() {
+        status.is_success()
+    } else {
+        spec.expect_status.contains(&status.as_u16())
+    };
+    if ok {
+        Ok((body, String::new(), exit_status(0)))
+    } else {
+        let expected = if spec.expect_status.is_empty() {
+            "2xx".to_string()
+        } else {
+            spec.expect_status.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(", ")
+        };
+        let err = format!("{} {} returned {} (expected {})\n", method_name, url, status, expected);
+        Ok((body, err, exit_status(1)))
+    }
+}
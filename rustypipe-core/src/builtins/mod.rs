@@ -0,0 +1,70 @@
+//! Built-in task kinds executed by the runner itself instead of through a shell backend.
+pub mod files;
+pub mod git;
+pub mod http;
+pub mod upload;
+
+use crate::backends::TimedOut;
+use crate::pipeline::parser::TaskDef;
+use std::path::Path;
+
+/// True if the task uses a built-in kind rather than a `run` command
+pub fn is_builtin(task: &TaskDef) -> bool {
+    task.upload.is_some() || task.http.is_some() || task.files.is_some() || task.git.is_some()
+}
+
+/// Human-readable summary of a built-in task, used in logs and metadata in place of the command
+pub fn describe(task: &TaskDef) -> String {
+    if let Some(u) = &task.upload {
+        return format!("upload {} -> {}", u.paths.join(" "), u.to);
+    }
+    if let Some(h) = &task.http {
+        return format!("http {} {}", h.method.as_deref().unwrap_or("GET"), h.url);
+    }
+    if let Some(ops) = &task.files {
+        return format!("files ({} operation(s))", ops.len());
+    }
+    if let Some(g) = &task.git {
+        let action = format!("{:?}", g.action).to_lowercase();
+        return format!("git {} {}", action, g.repo.as_deref().unwrap_or("")).trim_end().to_string();
+    }
+    String::new()
+}
+
+/// Run a built-in task. `interp` resolves `{{...}}` placeholders in string fields.
+/// Returns (stdout, stderr, exit_status) like `Backend::run`, honouring the task timeout.
+pub async fn run(
+    task: &TaskDef,
+    cwd: &Path,
+    timeout_secs: Option<u64>,
+    interp: &(dyn Fn(&str) -> anyhow::Result<String> + Sync),
+) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+    let fut = dispatch(task, cwd, interp);
+    match timeout_secs {
+        Some(secs) => match tokio::time::timeout(std::time::Duration::from_secs(secs), fut).await {
+            Ok(res) => res,
+            Err(_) => Err(TimedOut { backend: "builtin".to_string(), secs }.into()),
+        },
+        None => fut.await,
+    }
+}
+
+async fn dispatch(
+    task: &TaskDef,
+    cwd: &Path,
+    interp: &(dyn Fn(&str) -> anyhow::Result<String> + Sync),
+) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+    if let Some(spec) = &task.upload {
+        return upload::run(spec, cwd, interp).await;
+    }
+    if let Some(spec) = &task.http {
+        return http::run(spec, interp).await;
+    }
+    if let Some(ops) = &task.files {
+        return files::run(ops, cwd, interp).await;
+    }
+    if let Some(spec) = &task.git {
+        return git::run(spec, cwd, interp).await;
+    }
+    anyhow::bail!("task '{}' is not a built-in task", task.name)
+}
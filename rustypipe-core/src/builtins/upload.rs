@@ -0,0 +1,337 @@
+//! `upload:` tasks: push files to S3, GCS or Azure Blob Storage over HTTPS without any cloud CLI.
+//!
+//! Credentials come from the usual environment variables of each provider:
+//! - S3: `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, optional `AWS_SESSION_TOKEN`,
+//!   `AWS_REGION`/`AWS_DEFAULT_REGION` and `AWS_ENDPOINT_URL` (S3-compatible stores, path-style)
+//! - GCS: `GOOGLE_OAUTH_ACCESS_TOKEN` (e.g. from `gcloud auth print-access-token`)
+//! - Azure: `AZURE_STORAGE_SAS_TOKEN`
+use crate::pipeline::parser::UploadSpec;
+use crate::util::exit_status;
+use anyhow::Context;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Parsed upload destination
+#[derive(Debug, Clone)]
+pub enum Destination {
+    S3 { bucket: String, prefix: String },
+    Gcs { bucket: String, prefix: String },
+    Azure { account: String, container: String, prefix: String },
+}
+
+impl Destination {
+    pub fn parse(url: &str) -> anyhow::Result<Self> {
+        let (scheme, rest) = url
+            .split_once("://")
+            .with_context(|| format!("upload destination '{}' has no scheme", url))?;
+        let mut parts = rest.splitn(2, '/');
+        let first = parts.next().unwrap_or_default().to_string();
+        let tail = parts.next().unwrap_or_default().trim_matches('/').to_string();
+        if first.is_empty() {
+            anyhow::bail!("upload destination '{}' has no bucket", url);
+        }
+        match scheme {
+            "s3" => Ok(Destination::S3 { bucket: first, prefix: tail }),
+            "gs" => Ok(Destination::Gcs { bucket: first, prefix: tail }),
+            "az" => {
+                let (container, prefix) = match tail.split_once('/') {
+                    Some((c, p)) => (c.to_string(), p.to_string()),
+                    None => (tail.clone(), String::new()),
+                };
+                if container.is_empty() {
+                    anyhow::bail!("azure destination '{}' must be az://account/container[/prefix]", url);
+                }
+                Ok(Destination::Azure { account: first, container, prefix })
+            }
+            other => anyhow::bail!("unsupported upload scheme '{}' (expected s3, gs or az)", other),
+        }
+    }
+
+    fn prefix(&self) -> &str {
+        match self {
+            Destination::S3 { prefix, .. } | Destination::Gcs { prefix, .. } | Destination::Azure { prefix, .. } => prefix,
+        }
+    }
+
+    /// Object key for a file, relative to the destination prefix
+    pub fn key_for(&self, rel: &str) -> String {
+        if self.prefix().is_empty() {
+            rel.to_string()
+        } else {
+            format!("{}/{}", self.prefix(), rel)
+        }
+    }
+
+    /// Display URL of an uploaded object
+    pub fn url_for(&self, key: &str) -> String {
+        match self {
+            Destination::S3 { bucket, .. } => format!("s3://{}/{}", bucket, key),
+            Destination::Gcs { bucket, .. } => format!("gs://{}/{}", bucket, key),
+            Destination::Azure { account, container, .. } => format!("az://{}/{}/{}", account, container, key),
+        }
+    }
+}
+
+/// Checksums computed locally before upload
+pub struct Checksums {
+    pub sha256_hex: String,
+    pub md5_base64: String,
+}
+
+pub fn checksums(data: &[u8]) -> Checksums {
+    Checksums {
+        sha256_hex: hex::encode(Sha256::digest(data)),
+        md5_base64: base64::engine::general_purpose::STANDARD.encode(Md5::digest(data)),
+    }
+}
+
+/// Expand the spec's paths/globs relative to `cwd` into (absolute path, key-relative path) pairs.
+fn collect_files(paths: &[String], cwd: &Path) -> anyhow::Result<Vec<(PathBuf, String)>> {
+    let base = cwd.canonicalize().with_context(|| format!("failed to canonicalize {:?}", cwd))?;
+    let mut files = Vec::new();
+    for p in paths {
+        let pattern = if Path::new(p).is_absolute() { PathBuf::from(p) } else { cwd.join(p) };
+        let pattern_str = pattern.to_string_lossy().to_string();
+        let mut matched = false;
+        for entry in glob::glob(&pattern_str).with_context(|| format!("invalid upload pattern '{}'", p))? {
+            let path = entry?;
+            if !path.is_file() {
+                continue;
+            }
+            matched = true;
+            let path = path.canonicalize()?;
+            let rel = match path.strip_prefix(&base) {
+                Ok(r) => r.to_path_buf(),
+                Err(_) => PathBuf::from(path.file_name().unwrap_or_default()),
+            };
+            let rel = rel.to_string_lossy().replace('\\', "/");
+            files.push((path, rel));
+        }
+        if !matched {
+            anyhow::bail!("upload path '{}' matched no files", p);
+        }
+    }
+    Ok(files)
+}
+
+/// Execute an `upload:` task
+pub async fn run(
+    spec: &UploadSpec,
+    cwd: &Path,
+    interp: &(dyn Fn(&str) -> anyhow::Result<String> + Sync),
+) -> anyhow::Result<(String, String, std::process::ExitStatus)> {
+    let dest = Destination::parse(&interp(&spec.to)?)?;
+    let paths = spec.paths.iter().map(|p| interp(p)).collect::<anyhow::Result<Vec<String>>>()?;
+    let files = collect_files(&paths, cwd)?;
+    let attempts = spec.retries.unwrap_or(3).max(1);
+    let client = reqwest::Client::new();
+
+    let mut stdout = String::new();
+    let mut stderr = String::new();
+    for (path, rel) in files {
+        let data = tokio::fs::read(&path).await.with_context(|| format!("failed to read {:?}", path))?;
+        let sums = checksums(&data);
+        let key = dest.key_for(&rel);
+
+        let mut attempt = 0u32;
+        loop {
+            attempt += 1;
+            match put_object(&client, &dest, &key, &data, &sums).await {
+                Ok(()) => break,
+                Err(e) if attempt < attempts => {
+                    stderr.push_str(&format!("upload of {} attempt {} failed: {:#}; retrying\n", rel, attempt, e));
+                    tokio::time::sleep(Duration::from_secs(1 << (attempt - 1).min(5))).await;
+                }
+                Err(e) => return Err(e.context(format!("upload of {} failed after {} attempt(s)", rel, attempt))),
+            }
+        }
+        stdout.push_str(&format!("{} -> {} sha256={}\n", rel, dest.url_for(&key), sums.sha256_hex));
+    }
+
+    Ok((stdout, stderr, exit_status(0)))
+}
+
+/// Upload one object; the provider verifies the Content-MD5 (or we compare the returned hash).
+pub async fn put_object(
+    client: &reqwest::Client,
+    dest: &Destination,
+    key: &str,
+    data: &[u8],
+    sums: &Checksums,
+) -> anyhow::Result<()> {
+    match dest {
+        Destination::S3 { bucket, .. } => put_s3(client, bucket, key, data, sums).await,
+        Destination::Gcs { bucket, .. } => put_gcs(client, bucket, key, data, sums).await,
+        Destination::Azure { account, container, .. } => put_azure(client, account, container, key, data, sums).await,
+    }
+}
+
+fn env(name: &str) -> anyhow::Result<String> {
+    std::env::var(name).with_context(|| format!("environment variable {} is required", name))
+}
+
+/// AWS credentials and region from the environment, for SigV4-signed requests
+pub(crate) struct AwsCredentials {
+    access_key: String,
+    secret_key: String,
+    pub session_token: Option<String>,
+    pub region: String,
+}
+
+impl AwsCredentials {
+    pub fn from_env() -> anyhow::Result<Self> {
+        Ok(AwsCredentials {
+            access_key: env("AWS_ACCESS_KEY_ID")?,
+            secret_key: env("AWS_SECRET_ACCESS_KEY")?,
+            session_token: std::env::var("AWS_SESSION_TOKEN").ok(),
+            region: std::env::var("AWS_REGION")
+                .or_else(|_| std::env::var("AWS_DEFAULT_REGION"))
+                .unwrap_or_else(|_| "us-east-1".to_string()),
+        })
+    }
+
+    /// `authorization` header of a request without query string; `headers` are the signed
+    /// headers (lowercase names, including `host` and `x-amz-date`)
+    pub fn authorization(&self, service: &str, method: &str, canonical_path: &str, headers: &[(String, String)], payload_sha256: &str) -> String {
+        let mut headers = headers.to_vec();
+        headers.sort();
+        let amz_date = headers.iter().find(|(k, _)| k == "x-amz-date").map(|(_, v)| v.as_str()).unwrap_or_default();
+        let date = amz_date.get(..8).unwrap_or_default();
+        let signed_headers = headers.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>().join(";");
+        let canonical_headers: String = headers.iter().map(|(k, v)| format!("{}:{}\n", k, v.trim())).collect();
+        let canonical_request = format!(
+            "{}\n{}\n\n{}\n{}\n{}",
+            method, canonical_path, canonical_headers, signed_headers, payload_sha256
+        );
+
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let k_date = hmac_sha256(format!("AWS4{}", self.secret_key).as_bytes(), date);
+        let k_region = hmac_sha256(&k_date, &self.region);
+        let k_service = hmac_sha256(&k_region, service);
+        let k_signing = hmac_sha256(&k_service, "aws4_request");
+        let signature = hex::encode(hmac_sha256(&k_signing, &string_to_sign));
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key, scope, signed_headers, signature
+        )
+    }
+}
+
+/// Percent-encode a URI path per SigV4 rules (keeps unreserved characters and `/`).
+fn uri_encode_path(s: &str) -> String {
+    let mut out = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => out.push(b as char),
+            _ => out.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    out
+}
+
+fn hmac_sha256(key: &[u8], msg: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts any key length");
+    mac.update(msg.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+async fn put_s3(client: &reqwest::Client, bucket: &str, key: &str, data: &[u8], sums: &Checksums) -> anyhow::Result<()> {
+    let creds = AwsCredentials::from_env()?;
+    let (region, session_token) = (&creds.region, &creds.session_token);
+
+    // Virtual-hosted style for AWS, path style for custom endpoints (MinIO, R2, ...)
+    let (base, path) = match std::env::var("AWS_ENDPOINT_URL") {
+        Ok(endpoint) => (endpoint.trim_end_matches('/').to_string(), format!("/{}/{}", bucket, key)),
+        Err(_) => (format!("https://{}.s3.{}.amazonaws.com", bucket, region), format!("/{}", key)),
+    };
+    let canonical_path = uri_encode_path(&path);
+    let url = reqwest::Url::parse(&format!("{}{}", base, canonical_path))?;
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+        None => url.host_str().unwrap_or_default().to_string(),
+    };
+
+    let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let mut headers = vec![
+        ("host".to_string(), host),
+        ("x-amz-content-sha256".to_string(), sums.sha256_hex.clone()),
+        ("x-amz-date".to_string(), amz_date.clone()),
+    ];
+    if let Some(token) = session_token {
+        headers.push(("x-amz-security-token".to_string(), token.clone()));
+    }
+    let authorization = creds.authorization("s3", "PUT", &canonical_path, &headers, &sums.sha256_hex);
+
+    let mut req = client
+        .put(url)
+        .header("x-amz-content-sha256", &sums.sha256_hex)
+        .header("x-amz-date", &amz_date)
+        .header("content-md5", &sums.md5_base64)
+        .header("authorization", authorization)
+        .body(data.to_vec());
+    if let Some(token) = session_token {
+        req = req.header("x-amz-security-token", token);
+    }
+    check_response(req.send().await?).await.map(|_| ())
+}
+
+async fn put_gcs(client: &reqwest::Client, bucket: &str, key: &str, data: &[u8], sums: &Checksums) -> anyhow::Result<()> {
+    let token = env("GOOGLE_OAUTH_ACCESS_TOKEN")?;
+    let mut url = reqwest::Url::parse(&format!("https://storage.googleapis.com/upload/storage/v1/b/{}/o", bucket))?;
+    url.query_pairs_mut().append_pair("uploadType", "media").append_pair("name", key);
+
+    let resp = client.post(url).bearer_auth(token).body(data.to_vec()).send().await?;
+    let body = check_response(resp).await?;
+    // GCS reports the stored object's MD5; compare it with what we sent.
+    let meta: serde_json::Value = serde_json::from_str(&body).context("unexpected GCS response")?;
+    match meta.get("md5Hash").and_then(|v| v.as_str()) {
+        Some(remote) if remote == sums.md5_base64 => Ok(()),
+        Some(remote) => anyhow::bail!("checksum mismatch for {}: local md5 {} remote {}", key, sums.md5_base64, remote),
+        None => Ok(()),
+    }
+}
+
+async fn put_azure(
+    client: &reqwest::Client,
+    account: &str,
+    container: &str,
+    key: &str,
+    data: &[u8],
+    sums: &Checksums,
+) -> anyhow::Result<()> {
+    let sas = env("AZURE_STORAGE_SAS_TOKEN")?;
+    let url = format!(
+        "https://{}.blob.core.windows.net/{}/{}?{}",
+        account,
+        container,
+        uri_encode_path(key),
+        sas.trim_start_matches('?')
+    );
+    let req = client
+        .put(url)
+        .header("x-ms-blob-type", "BlockBlob")
+        .header("x-ms-version", "2021-08-06")
+        .header("content-md5", &sums.md5_base64)
+        .body(data.to_vec());
+    check_response(req.send().await?).await.map(|_| ())
+}
+
+/// Turn non-2xx responses into errors that include the provider's message
+async fn check_response(resp: reqwest::Response) -> anyhow::Result<String> {
+    let status = resp.status();
+    let body = resp.text().await.unwrap_or_default();
+    if !status.is_success() {
+        anyhow::bail!("storage responded {}: {}", status, body.trim());
+    }
+    Ok(body)
+}
@@ -0,0 +1,34 @@
+//! The engine behind the `rustypipe` CLI: parse, build and run pipelines in-process.
+//!
+//! ```no_run
+//! use rustypipe_core::{events, run_pipeline, Pipeline, RunConfig, RunEvent, TaskDef};
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let pipeline = Pipeline::new("release")
+//!     .with_var("VERSION", "1.2.3")
+//!     .with_task(TaskDef::new("build", "cargo build --release"))
+//!     .with_task(TaskDef::new("package", "tar czf app-{{vars.VERSION}}.tgz target/release/app").with_depends_on("build"));
+//! let updates = events::subscribe();
+//! std::thread::spawn(move || {
+//!     for event in updates {
+//!         if let RunEvent::TaskFinished { task, status, .. } = event {
+//!             println!("{} {:?}", task, status);
+//!         }
+//!     }
+//! });
+//! let run_dir = run_pipeline(pipeline, std::path::Path::new("."), &RunConfig::default()).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Runs are recorded below `.rustypipe/` in the current directory, like CLI runs.
+pub mod backends;
+pub mod builtins;
+pub mod pipeline;
+pub mod plugins;
+pub mod util;
+
+pub use backends::{Backend, RunOptions};
+pub use pipeline::events::{self, RunEvent};
+pub use pipeline::parser::{Pipeline, TaskDef};
+pub use pipeline::{resume_run, run_pipeline, run_pipelines, RunConfig};
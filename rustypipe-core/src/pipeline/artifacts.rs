@@ -0,0 +1,118 @@
+//! `artifacts:` on a task: files or globs, relative to the directory the task ran in, that are
+//! copied into `tasks/<task>/artifacts/` once the task has finished (also when it failed, so
+//! test reports survive). Relative paths are kept; a matched directory is copied as a whole.
+//!
+//! For container backends an absolute path names a path inside the container: below the mounted
+//! task directory it is read from the host, anywhere else the backend copies it out before the
+//! container is removed (literal paths only, no globs), to `artifacts/<path without leading />`.
+use crate::pipeline::manifest::ArtifactRecord;
+use crate::pipeline::secrets;
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// Declared artifacts of a task, split by where they are read from
+#[derive(Debug, Default)]
+pub struct Plan {
+    /// Patterns resolved on the host, relative to the task directory
+    pub host: Vec<String>,
+    /// (container path, destination) pairs for the backend to copy out
+    pub copy_out: Vec<(String, PathBuf)>,
+}
+
+/// Split `patterns` for a task whose backend mounts the task directory at `container_workdir`
+pub fn plan(patterns: &[String], container_workdir: Option<&str>, dest: &Path) -> Plan {
+    let mut plan = Plan::default();
+    for pattern in patterns {
+        let Some(workdir) = container_workdir.filter(|_| pattern.starts_with('/')) else {
+            plan.host.push(pattern.clone());
+            continue;
+        };
+        match pattern.strip_prefix(workdir).and_then(|p| p.strip_prefix('/')) {
+            Some(rel) => plan.host.push(rel.to_string()),
+            None => {
+                if pattern.contains(['*', '?', '[']) {
+                    tracing::warn!("artifact '{}': globs are not supported for paths outside {}", pattern, workdir);
+                    continue;
+                }
+                let rel = pattern.trim_start_matches('/');
+                plan.copy_out.push((pattern.clone(), dest.join(rel)));
+            }
+        }
+    }
+    plan
+}
+
+/// Files below `path` (or `path` itself), each with its path relative to the artifacts directory
+fn files(path: PathBuf, rel: PathBuf) -> std::io::Result<Vec<(PathBuf, PathBuf)>> {
+    let mut out = Vec::new();
+    let mut stack = vec![(path, rel)];
+    while let Some((from, rel)) = stack.pop() {
+        if from.is_dir() {
+            for e in std::fs::read_dir(&from)? {
+                let e = e?;
+                stack.push((e.path(), rel.join(e.file_name())));
+            }
+        } else {
+            out.push((from, rel));
+        }
+    }
+    Ok(out)
+}
+
+fn record(rel: &Path, data: &[u8]) -> ArtifactRecord {
+    ArtifactRecord {
+        path: rel.to_string_lossy().replace('\\', "/"),
+        size: data.len() as u64,
+        sha256: hex::encode(Sha256::digest(data)),
+    }
+}
+
+/// Copy the host artifacts of `plan` from `dir` into `dest` and return the records of those and
+/// of the files the backend already copied out
+pub fn collect(plan: &Plan, dir: &Path, dest: &Path) -> anyhow::Result<Vec<ArtifactRecord>> {
+    let mut records = Vec::new();
+    if plan.host.is_empty() && plan.copy_out.is_empty() {
+        return Ok(records);
+    }
+    // absolute, so matches can be made relative again
+    let dir = &dir.canonicalize().with_context(|| format!("failed to resolve {:?}", dir))?;
+    for pattern in &plan.host {
+        let full = Path::new(&glob::Pattern::escape(&dir.to_string_lossy())).join(pattern);
+        let mut matched = false;
+        for entry in glob::glob(&full.to_string_lossy()).with_context(|| format!("invalid artifact pattern '{}'", pattern))? {
+            let path = entry?;
+            matched = true;
+            // outside the task directory (`../x`, absolute paths) only the file name is kept
+            let rel = path.strip_prefix(dir).ok().filter(|r| !r.starts_with("..")).map(Path::to_path_buf);
+            let rel = rel.or_else(|| path.file_name().map(Into::into)).unwrap_or_default();
+            for (from, rel) in files(path, rel)? {
+                let data = std::fs::read(&from).with_context(|| format!("failed to read artifact {:?}", from))?;
+                let data = secrets::mask_bytes(data);
+                let to = dest.join(&rel);
+                if let Some(parent) = to.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(&to, &data)?;
+                records.push(record(&rel, &data));
+            }
+        }
+        if !matched {
+            tracing::warn!("artifact pattern '{}' matched no files", pattern);
+        }
+    }
+    for (src, copied) in &plan.copy_out {
+        if !copied.exists() {
+            tracing::warn!("artifact '{}' was not copied out of the container", src);
+            continue;
+        }
+        let rel = copied.strip_prefix(dest).unwrap_or(copied).to_path_buf();
+        for (from, rel) in files(copied.clone(), rel)? {
+            secrets::mask_file(&from)?;
+            records.push(record(&rel, &std::fs::read(&from)?));
+        }
+    }
+    records.sort_by(|a, b| a.path.cmp(&b.path));
+    records.dedup_by(|a, b| a.path == b.path);
+    Ok(records)
+}
@@ -0,0 +1,114 @@
+//! Task result cache for tasks with a `cache_key`:
+//! ```text
+//! .rustypipe/cache/<sha256(task name, key)>/
+//!   entry.json           task, key, exit code, when it was stored
+//!   stdout.log / stderr.log
+//!   (entry.json also keeps the task's `outputs:` values)
+//!   env                  $RUSTYPIPE_ENV exports
+//!   artifacts/           copy of the task's artifacts directory (incl. declared `artifacts:`)
+//! ```
+//! The key is interpolated first, so `cache_key: "lint-{{vars.REV}}"` changes with the variable.
+//! Only successful results are stored; a failing task runs again next time.
+use crate::pipeline::manifest::ArtifactRecord;
+use crate::pipeline::workspace;
+use crate::util::write_artifact;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize, Serialize)]
+struct CacheEntry {
+    task: String,
+    key: String,
+    exit_code: i32,
+    created: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    artifacts: Vec<ArtifactRecord>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    outputs: BTreeMap<String, serde_json::Value>,
+}
+
+/// A stored result
+pub struct CacheHit {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+    /// Records of the declared artifacts, as collected when the result was stored
+    pub artifacts: Vec<ArtifactRecord>,
+    /// `outputs:` values
+    pub outputs: BTreeMap<String, serde_json::Value>,
+    dir: PathBuf,
+}
+
+/// A successful result to store
+pub struct Stored<'a> {
+    pub stdout: &'a str,
+    pub stderr: &'a str,
+    pub artifacts: &'a [ArtifactRecord],
+    pub outputs: &'a BTreeMap<String, serde_json::Value>,
+}
+
+fn entry_dir(task: &str, key: &str) -> PathBuf {
+    let digest = Sha256::digest(format!("{}\0{}", task, key));
+    Path::new(".rustypipe").join("cache").join(hex::encode(digest))
+}
+
+/// Look up the result of `task` for `key`
+pub fn lookup(task: &str, key: &str) -> Option<CacheHit> {
+    let dir = entry_dir(task, key);
+    let entry: CacheEntry = serde_json::from_str(&std::fs::read_to_string(dir.join("entry.json")).ok()?).ok()?;
+    if entry.task != task || entry.key != key {
+        return None;
+    }
+    Some(CacheHit {
+        stdout: std::fs::read_to_string(dir.join("stdout.log")).unwrap_or_default(),
+        stderr: std::fs::read_to_string(dir.join("stderr.log")).unwrap_or_default(),
+        exit_code: entry.exit_code,
+        artifacts: entry.artifacts,
+        outputs: entry.outputs,
+        dir,
+    })
+}
+
+impl CacheHit {
+    /// Restore the cached exports and artifacts into a task's run directory
+    pub fn restore(&self, env_file: &Path, artifacts_dir: &Path) -> anyhow::Result<()> {
+        if let Ok(env) = std::fs::read_to_string(self.dir.join("env")) {
+            std::fs::write(env_file, env)?;
+        }
+        let cached = self.dir.join("artifacts");
+        if cached.is_dir() {
+            workspace::create(&cached, artifacts_dir)?;
+        }
+        Ok(())
+    }
+}
+
+/// Store a successful result of `task`, replacing an older entry for the same key
+pub fn store(task: &str, key: &str, result: &Stored, env_file: &Path, artifacts_dir: &Path) -> anyhow::Result<()> {
+    let dir = entry_dir(task, key);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    std::fs::create_dir_all(&dir)?;
+    write_artifact(&dir, "stdout.log", result.stdout)?;
+    write_artifact(&dir, "stderr.log", result.stderr)?;
+    if let Ok(env) = std::fs::read_to_string(env_file) {
+        write_artifact(&dir, "env", &env)?;
+    }
+    if artifacts_dir.is_dir() {
+        workspace::create(artifacts_dir, &dir.join("artifacts"))?;
+    }
+    // written last: an entry without entry.json is never used
+    let entry = CacheEntry {
+        task: task.to_string(),
+        key: key.to_string(),
+        exit_code: 0,
+        created: chrono::Utc::now().to_rfc3339(),
+        artifacts: result.artifacts.to_vec(),
+        outputs: result.outputs.clone(),
+    };
+    write_artifact(&dir, "entry.json", &serde_json::to_string_pretty(&entry)?)?;
+    Ok(())
+}
@@ -0,0 +1,107 @@
+//! `rustypipe run --compare <baseline-run-id>`: golden-output checks between two runs.
+//!
+//! For every task of the baseline run the exit code, `stdout.log` and the files under
+//! `artifacts/` are compared with the new run; any difference (including tasks that ran in only
+//! one of the runs) is reported and fails the command.
+use crate::pipeline::manifest::{RunManifest, TaskRecord};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Relative path -> sha256 of every file below `dir`
+fn hash_tree(dir: &Path) -> BTreeMap<String, String> {
+    let mut out = BTreeMap::new();
+    let mut stack = vec![dir.to_path_buf()];
+    while let Some(d) = stack.pop() {
+        let Ok(entries) = std::fs::read_dir(&d) else { continue };
+        for entry in entries.flatten() {
+            let p = entry.path();
+            if p.is_dir() {
+                stack.push(p);
+            } else if let (Ok(rel), Ok(data)) = (p.strip_prefix(dir), std::fs::read(&p)) {
+                out.insert(rel.to_string_lossy().replace('\\', "/"), hex::encode(Sha256::digest(&data)));
+            }
+        }
+    }
+    out
+}
+
+/// First differing line of two texts, as a short human-readable note
+fn first_difference(a: &str, b: &str) -> String {
+    let (mut la, mut lb) = (a.lines(), b.lines());
+    let mut n = 1;
+    loop {
+        match (la.next(), lb.next()) {
+            (Some(x), Some(y)) if x == y => n += 1,
+            (x, y) => {
+                return format!(
+                    "line {}: expected {:?}, got {:?}",
+                    n,
+                    x.unwrap_or("<end of output>"),
+                    y.unwrap_or("<end of output>")
+                )
+            }
+        }
+    }
+}
+
+/// Differences for one task present in both runs
+fn compare_task(base_dir: &Path, base: &TaskRecord, cur_dir: &Path, cur: &TaskRecord) -> Vec<String> {
+    let mut diffs = Vec::new();
+    if base.exit_code != cur.exit_code {
+        diffs.push(format!("exit code {:?} -> {:?}", base.exit_code, cur.exit_code));
+    }
+    let read = |dir: &Path, t: &TaskRecord| std::fs::read_to_string(dir.join(&t.dir).join("stdout.log")).unwrap_or_default();
+    let (a, b) = (read(base_dir, base), read(cur_dir, cur));
+    if a != b {
+        diffs.push(format!("stdout differs ({})", first_difference(&a, &b)));
+    }
+
+    let a = hash_tree(&base_dir.join(&base.dir).join("artifacts"));
+    let b = hash_tree(&cur_dir.join(&cur.dir).join("artifacts"));
+    for (path, hash) in &a {
+        match b.get(path) {
+            None => diffs.push(format!("artifact {} missing", path)),
+            Some(h) if h != hash => diffs.push(format!("artifact {} differs", path)),
+            _ => {}
+        }
+    }
+    for path in b.keys().filter(|p| !a.contains_key(*p)) {
+        diffs.push(format!("artifact {} is new", path));
+    }
+    diffs
+}
+
+/// Print a per-task report; returns an error if the runs differ
+pub fn compare_runs(baseline_dir: &Path, current_dir: &Path) -> anyhow::Result<()> {
+    let baseline = RunManifest::load(baseline_dir)?;
+    let current = RunManifest::load(current_dir)?;
+    say!("Comparing run {} against baseline {}", current.id, baseline.id);
+
+    let mut differing = 0;
+    for base in &baseline.tasks {
+        let diffs = match current.tasks.iter().find(|t| t.name == base.name) {
+            Some(cur) => compare_task(baseline_dir, base, current_dir, cur),
+            None => vec!["did not run".to_string()],
+        };
+        if diffs.is_empty() {
+            say!("SAME  {}", base.name);
+        } else {
+            differing += 1;
+            say!("DIFF  {}", base.name);
+            for d in diffs {
+                say!("      {}", d);
+            }
+        }
+    }
+    for cur in current.tasks.iter().filter(|c| !baseline.tasks.iter().any(|b| b.name == c.name)) {
+        differing += 1;
+        say!("NEW   {} (not in baseline)", cur.name);
+    }
+
+    if differing > 0 {
+        anyhow::bail!("{} task(s) differ from baseline {}", differing, baseline.id);
+    }
+    say!("All {} task(s) match the baseline", baseline.tasks.len());
+    Ok(())
+}
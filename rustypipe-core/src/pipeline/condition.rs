@@ -0,0 +1,256 @@
+//! Condition expressions of `when:` (whether a task runs at all), `retry_if:` (whether a failed
+//! attempt is retried) and `assert:` (checks a successful task must pass), e.g.
+//! `"{{build.exit_code}} == 0 && vars.ENV == 'prod'"`.
+//!
+//! Operands are quoted strings, bare words and references: `vars.NAME`, `env.NAME`,
+//! `<task>.output`, `<task>.exit_code` and `<task>.outputs.NAME`, written bare or inside `{{ }}`;
+//! any other `{{ }}` block is a template expression (`{{ vars.ENV | lower }}`). In `retry_if:` and
+//! `assert:`, `self.output`, `self.stderr`, `self.exit_code`, `self.outputs.NAME` and
+//! `self.attempt` refer to the task's own attempt. Missing references evaluate to the empty
+//! string. Operators: `== != < <= > >=` (numeric when both sides are numbers), `&& || !` and
+//! parentheses. A lone value is true unless it is empty, `0` or `false`.
+//!
+//! Functions: `contains(s, part)`, `starts_with(s, prefix)`, `ends_with(s, suffix)`,
+//! `matches(s, regex)`, `lower(s)`, `upper(s)`, `trim(s)` and `len(s)`.
+use anyhow::bail;
+use regex::Regex;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    /// Bare word, taken literally
+    Word(String),
+    Quoted(String),
+    Ref(String),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+}
+
+const OPS: [&str; 9] = ["&&", "||", "==", "!=", "<=", ">=", "<", ">", "!"];
+
+fn is_reference(word: &str) -> bool {
+    word.starts_with("vars.")
+        || word.starts_with("env.")
+        || word.starts_with("self.")
+        || word.ends_with(".output")
+        || word.ends_with(".exit_code")
+        || word.contains(".outputs.")
+}
+
+/// Names and argument counts of the functions
+const FUNCTIONS: [(&str, usize); 8] =
+    [("contains", 2), ("starts_with", 2), ("ends_with", 2), ("matches", 2), ("lower", 1), ("upper", 1), ("trim", 1), ("len", 1)];
+
+fn call(name: &str, args: &[Value]) -> anyhow::Result<Value> {
+    let Some((_, arity)) = FUNCTIONS.iter().find(|(f, _)| *f == name) else { bail!("unknown function '{}'", name) };
+    if args.len() != *arity {
+        bail!("{}() takes {} argument(s), got {}", name, arity, args.len());
+    }
+    let s = args[0].text();
+    let arg = |i: usize| args[i].text();
+    Ok(match name {
+        "contains" => Value::Bool(s.contains(&arg(1))),
+        "starts_with" => Value::Bool(s.starts_with(&arg(1))),
+        "ends_with" => Value::Bool(s.ends_with(&arg(1))),
+        "matches" => {
+            let re = Regex::new(&arg(1)).map_err(|e| anyhow::anyhow!("matches(): invalid regex '{}': {}", arg(1), e))?;
+            Value::Bool(re.is_match(&s))
+        }
+        "lower" => Value::Str(s.to_lowercase()),
+        "upper" => Value::Str(s.to_uppercase()),
+        "trim" => Value::Str(s),
+        _ => Value::Str(s.chars().count().to_string()),
+    })
+}
+
+fn tokenize(expr: &str) -> anyhow::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix("{{") {
+            let Some(end) = r.find("}}") else { bail!("unclosed '{{{{' in '{}'", expr) };
+            tokens.push(Token::Ref(r[..end].trim().to_string()));
+            rest = &r[end + 2..];
+        } else if rest.starts_with('\'') || rest.starts_with('"') {
+            let quote = rest.chars().next().unwrap_or('\'');
+            let Some(end) = rest[1..].find(quote) else { bail!("unclosed string in '{}'", expr) };
+            tokens.push(Token::Quoted(rest[1..end + 1].to_string()));
+            rest = &rest[end + 2..];
+        } else if let Some(r) = rest.strip_prefix('(') {
+            tokens.push(Token::LParen);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix(')') {
+            tokens.push(Token::RParen);
+            rest = r;
+        } else if let Some(r) = rest.strip_prefix(',') {
+            tokens.push(Token::Comma);
+            rest = r;
+        } else if let Some(op) = OPS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            let end = rest
+                .find(|c: char| c.is_whitespace() || "()!=<>&|'\"{,".contains(c))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                bail!("unexpected '{}' in '{}'", rest, expr);
+            }
+            let word = &rest[..end];
+            tokens.push(if is_reference(word) { Token::Ref(word.to_string()) } else { Token::Word(word.to_string()) });
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Bool(bool),
+    Str(String),
+}
+
+impl Value {
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            Value::Str(s) => !matches!(s.trim(), "" | "0" | "false"),
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Value::Bool(b) => b.to_string(),
+            Value::Str(s) => s.trim().to_string(),
+        }
+    }
+}
+
+struct Parser<'a, F: Fn(&str) -> Option<String>> {
+    tokens: Vec<Token>,
+    pos: usize,
+    lookup: &'a F,
+}
+
+impl<F: Fn(&str) -> Option<String>> Parser<'_, F> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn eat(&mut self, op: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Op(o)) if *o == op) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn or(&mut self) -> anyhow::Result<Value> {
+        let mut v = self.and()?;
+        while self.eat("||") {
+            let rhs = self.and()?;
+            v = Value::Bool(v.truthy() || rhs.truthy());
+        }
+        Ok(v)
+    }
+
+    fn and(&mut self) -> anyhow::Result<Value> {
+        let mut v = self.not()?;
+        while self.eat("&&") {
+            let rhs = self.not()?;
+            v = Value::Bool(v.truthy() && rhs.truthy());
+        }
+        Ok(v)
+    }
+
+    fn not(&mut self) -> anyhow::Result<Value> {
+        if self.eat("!") {
+            return Ok(Value::Bool(!self.not()?.truthy()));
+        }
+        self.compare()
+    }
+
+    fn compare(&mut self) -> anyhow::Result<Value> {
+        let lhs = self.primary()?;
+        let Some(Token::Op(op)) = self.peek().cloned() else { return Ok(lhs) };
+        if !["==", "!=", "<", "<=", ">", ">="].contains(&op) {
+            return Ok(lhs);
+        }
+        self.pos += 1;
+        let rhs = self.primary()?;
+        let (l, r) = (lhs.text(), rhs.text());
+        let ord = match (l.parse::<f64>(), r.parse::<f64>()) {
+            (Ok(a), Ok(b)) => a.partial_cmp(&b),
+            _ => Some(l.cmp(&r)),
+        };
+        let Some(ord) = ord else { return Ok(Value::Bool(false)) };
+        Ok(Value::Bool(match op {
+            "==" => ord.is_eq(),
+            "!=" => ord.is_ne(),
+            "<" => ord.is_lt(),
+            "<=" => ord.is_le(),
+            ">" => ord.is_gt(),
+            _ => ord.is_ge(),
+        }))
+    }
+
+    fn primary(&mut self) -> anyhow::Result<Value> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::LParen) => {
+                let v = self.or()?;
+                if self.peek() != Some(&Token::RParen) {
+                    bail!("expected ')'");
+                }
+                self.pos += 1;
+                Ok(v)
+            }
+            Some(Token::Word(f)) if self.peek() == Some(&Token::LParen) => {
+                self.pos += 1;
+                let mut args = Vec::new();
+                if self.peek() == Some(&Token::RParen) {
+                    self.pos += 1;
+                } else {
+                    loop {
+                        args.push(self.or()?);
+                        match self.peek() {
+                            Some(Token::Comma) => self.pos += 1,
+                            Some(Token::RParen) => {
+                                self.pos += 1;
+                                break;
+                            }
+                            _ => bail!("expected ',' or ')' in the arguments of {}()", f),
+                        }
+                    }
+                }
+                call(&f, &args)
+            }
+            Some(Token::Word(w)) | Some(Token::Quoted(w)) => Ok(Value::Str(w)),
+            Some(Token::Ref(r)) => Ok(Value::Str((self.lookup)(&r).unwrap_or_default())),
+            Some(t) => bail!("unexpected {:?}", t),
+            None => bail!("unexpected end of expression"),
+        }
+    }
+}
+
+/// Evaluate `expr`; `lookup` resolves references such as `vars.ENV` or `build.exit_code`
+pub fn evaluate(expr: &str, lookup: &impl Fn(&str) -> Option<String>) -> anyhow::Result<bool> {
+    let tokens = tokenize(expr)?;
+    if tokens.is_empty() {
+        bail!("empty expression");
+    }
+    let mut p = Parser { tokens, pos: 0, lookup };
+    let v = p.or()?;
+    if let Some(t) = p.peek() {
+        bail!("unexpected {:?} in '{}'", t, expr);
+    }
+    Ok(v.truthy())
+}
+
+/// Syntax check used by validation
+pub fn check(expr: &str) -> anyhow::Result<()> {
+    evaluate(expr, &|_| None).map(|_| ())
+}
@@ -0,0 +1,188 @@
+//! Progress events of a run. Every event is appended to the run's `events.jsonl`, which
+//! `rustypipe serve` follows for live progress, with `--log-format json` also printed on stdout,
+//! and passed to the in-process observer (`run --tui`) if there is one and to every [`subscribe`]r.
+//! Task output lines are not written to the file (the logs already hold them).
+use crate::pipeline::manifest::{RunStatus, TaskStatus};
+use crate::pipeline::state::enum_name;
+use crate::util;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Mutex;
+
+pub const EVENTS_FILE: &str = "events.jsonl";
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RunEvent {
+    RunStarted {
+        run_id: String,
+        pipeline: Option<String>,
+        /// Every task of the run, setup and teardown included
+        tasks: Vec<String>,
+    },
+    TaskStarted {
+        task: String,
+        backend: String,
+    },
+    TaskFinished {
+        task: String,
+        status: TaskStatus,
+        exit_code: Option<i32>,
+        duration_ms: u64,
+    },
+    PipelineFinished {
+        run_id: String,
+        status: RunStatus,
+        succeeded: usize,
+        failed: usize,
+        skipped: usize,
+        cancelled: usize,
+    },
+    TaskOutput {
+        task: String,
+        stream: String,
+        line: String,
+    },
+}
+
+/// What the observer receives
+#[derive(Debug, Clone)]
+pub enum Update {
+    Event(RunEvent),
+    /// Human-readable output that would otherwise have been printed
+    Message { text: String, stderr: bool },
+}
+
+static OBSERVER: Mutex<Option<Sender<Update>>> = Mutex::new(None);
+
+/// Receivers of [`subscribe`]; dropped ones are removed on the next event
+static SUBSCRIBERS: Mutex<Vec<Sender<RunEvent>>> = Mutex::new(Vec::new());
+
+/// Receive every event from now on, until the receiver is dropped. Unlike [`observe`] there can
+/// be any number of subscribers, and console output stays where it is.
+pub fn subscribe() -> Receiver<RunEvent> {
+    let (tx, rx) = channel();
+    SUBSCRIBERS.lock().unwrap_or_else(|e| e.into_inner()).push(tx);
+    rx
+}
+
+/// Receive every event from now on, until [`unobserve`]
+pub fn observe() -> Receiver<Update> {
+    let (tx, rx) = channel();
+    *OBSERVER.lock().unwrap_or_else(|e| e.into_inner()) = Some(tx);
+    rx
+}
+
+/// Stop forwarding; the receiver sees the channel close
+pub fn unobserve() {
+    OBSERVER.lock().unwrap_or_else(|e| e.into_inner()).take();
+}
+
+fn notify(update: Update) {
+    if let Some(tx) = OBSERVER.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        let _ = tx.send(update);
+    }
+}
+
+/// Pass a message to the observer instead of printing it
+pub fn message(text: String, stderr: bool) {
+    notify(Update::Message { text, stderr });
+}
+
+/// `Write` target for log lines while the console is observed: each write (one log event)
+/// becomes a message, without colour codes
+#[derive(Default)]
+pub struct MessageWriter(Vec<u8>);
+
+impl Write for MessageWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for MessageWriter {
+    fn drop(&mut self) {
+        let text = String::from_utf8_lossy(&self.0);
+        let text = regex::Regex::new(r"\x1b\[[0-9;]*m").map(|re| re.replace_all(&text, "").trim_end().to_string()).unwrap_or_default();
+        if !text.is_empty() {
+            message(text, false);
+        }
+    }
+}
+
+/// A line of task output
+pub fn output(task: &str, stderr: bool, line: &str) {
+    publish(RunEvent::TaskOutput {
+        task: task.to_string(),
+        stream: if stderr { "stderr" } else { "stdout" }.to_string(),
+        line: line.to_string(),
+    });
+}
+
+/// Log `event` and pass it to the observer
+fn publish(event: RunEvent) {
+    match &event {
+        RunEvent::RunStarted { run_id, pipeline, tasks } => {
+            tracing::info!(target: util::EVENTS, event = "run_started", run_id = %run_id, pipeline = pipeline.as_deref(), tasks = ?tasks);
+        }
+        RunEvent::TaskStarted { task, backend } => {
+            tracing::info!(target: util::EVENTS, event = "task_started", task = %task, backend = %backend);
+        }
+        RunEvent::TaskFinished { task, status, exit_code, duration_ms } => tracing::info!(
+            target: util::EVENTS,
+            event = "task_finished",
+            task = %task,
+            status = %enum_name(*status),
+            exit_code = *exit_code,
+            duration_ms = *duration_ms,
+        ),
+        RunEvent::PipelineFinished { run_id, status, succeeded, failed, skipped, cancelled } => tracing::info!(
+            target: util::EVENTS,
+            event = "pipeline_finished",
+            run_id = %run_id,
+            status = %enum_name(*status),
+            succeeded = *succeeded,
+            failed = *failed,
+            skipped = *skipped,
+            cancelled = *cancelled,
+        ),
+        RunEvent::TaskOutput { task, stream, line } => {
+            tracing::info!(target: util::EVENTS, event = "task_output", task = %task, stream = %stream, line = %line);
+        }
+    }
+    SUBSCRIBERS.lock().unwrap_or_else(|e| e.into_inner()).retain(|tx| tx.send(event.clone()).is_ok());
+    crate::plugins::notify(&event);
+    notify(Update::Event(event));
+}
+
+/// Record `event` for the run in `run_dir`
+pub fn emit(run_dir: &Path, event: RunEvent) {
+    let res = (|| {
+        let mut line = serde_json::to_value(&event)?;
+        line["time"] = chrono::Utc::now().to_rfc3339().into();
+        let mut f = std::fs::OpenOptions::new().create(true).append(true).open(run_dir.join(EVENTS_FILE))?;
+        // one write per line, so concurrent tasks do not interleave
+        f.write_all(format!("{}\n", line).as_bytes())?;
+        anyhow::Ok(())
+    })();
+    if let Err(e) = res {
+        tracing::warn!("failed to record event: {:#}", e);
+    }
+    publish(event);
+}
+
+/// Events recorded so far for the run in `run_dir`
+pub fn load(run_dir: &Path) -> Vec<serde_json::Value> {
+    std::fs::read_to_string(run_dir.join(EVENTS_FILE))
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|l| serde_json::from_str(l).ok())
+        .collect()
+}
@@ -0,0 +1,2006 @@
+use crate::pipeline::parser::{ArtifactStoreConfig, BackendDef, HookDef, Pipeline, SecretDef, StdinSpec, TaskDef, check_cycles, load_pipeline, load_resolved_pipeline, resolve_pipeline, validate_pipeline};
+use crate::pipeline::events::{self, RunEvent};
+use crate::pipeline::permits::PrioritySemaphore;
+use crate::pipeline::{artifacts, cache, condition, metrics, secrets, state, storage, telemetry, tools};
+use crate::pipeline::filters::{apply_filters, extract_outputs};
+use crate::pipeline::report::{self, ReportSpec};
+use crate::pipeline::workspace::{self, WorkspaceMode};
+use crate::pipeline::manifest::{resolve_run, sanitize_filename, ArtifactRecord, AttemptRecord, task_dir, Phase, RunManifest, RunSource, RunSpec, RunStatus, TaskRecord, TaskStatus};
+use crate::util::{self, create_run_dir, expand_paths, interpolate_command, parse_env_file, write_artifact, TemplateInputs};
+use crate::backends::{self, AciBackend, Backend, Cancelled, CloudRunBackend, DockerBackend, EcsBackend, KubernetesBackend, LambdaBackend, KubernetesJobBackend, LocalBackend, NativeSshBackend, OutputStream, RunOptions, SSHBackend, ShellSpec, SyncOptions, WslBackend};
+use crate::builtins;
+use crate::plugins::{self, Plugin, TaskCompletion};
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::{watch, Mutex, Notify};
+use tracing::info;
+use chrono::{DateTime, Utc};
+use std::time::{Duration, Instant};
+use anyhow::Context;
+
+/// A pipeline file taking part in a run
+pub(super) struct PipelineInfo {
+    name: String,
+    /// Where tasks run: the pipeline's directory, or its isolated workspace copy
+    dir: PathBuf,
+    source_dir: PathBuf,
+    isolated: bool,
+    collect: Vec<String>,
+    artifact_store: Option<ArtifactStoreConfig>,
+    /// `backends:` instances by name (`local` only when overridden or with a pipeline `shell`)
+    backends: HashMap<String, Arc<dyn Backend>>,
+    /// Pipeline-level `vars:`
+    pub(super) vars: HashMap<String, String>,
+    /// Pipeline-level `env:`
+    env: HashMap<String, String>,
+    /// Host environment visible as `{{env.NAME}}` (filtered by `env_allowlist:`)
+    pub(super) host_env: HashMap<String, String>,
+    /// `secrets:` as declared, and their values once the run has started
+    secrets: BTreeMap<String, SecretDef>,
+    secret_env: Vec<(String, String)>,
+    on_success: Vec<HookDef>,
+    on_failure: Vec<HookDef>,
+    stop_on_fail: bool,
+    /// `<name>:` when several pipelines run together, empty otherwise
+    pub(super) prefix: String,
+    /// Names of the pipeline's `plugins:`
+    plugins: Vec<String>,
+    prepull: bool,
+}
+
+/// Load and validate the pipeline files of a run
+fn load_pipelines(paths: &[PathBuf]) -> anyhow::Result<Vec<(PathBuf, Pipeline)>> {
+    let mut loaded = Vec::new();
+    for path in paths {
+        let p = load_resolved_pipeline(path).with_context(|| format!("failed to load {:?}", path))?;
+        validate_pipeline(&p).with_context(|| format!("invalid pipeline {:?}", path))?;
+        loaded.push((path.clone(), p));
+    }
+    Ok(loaded)
+}
+
+/// Load the pipelines and merge them into one task graph (see `merge_loaded`)
+pub(super) fn merge_pipelines(paths: &[PathBuf]) -> anyhow::Result<(Pipeline, Vec<PipelineInfo>, HashMap<String, usize>)> {
+    merge_loaded(load_pipelines(paths)?)
+}
+
+/// The task called `name`, or the matrix instances of it (`build` → `build[linux]`, ...)
+fn instances<'a>(names: impl Iterator<Item = &'a str>, name: &str) -> Vec<&'a str> {
+    names.filter(|n| *n == name || n.strip_prefix(name).is_some_and(|r| r.starts_with('['))).collect()
+}
+
+/// `{{task.output}}` of a task recorded in `dir` by an earlier run: stdout with `output_filter` applied
+fn recorded_output(dir: &Path, def: &TaskDef) -> String {
+    let stdout = std::fs::read_to_string(dir.join("stdout.log")).unwrap_or_default();
+    if def.output_filter.is_empty() {
+        stdout
+    } else {
+        apply_filters(&def.output_filter, &stdout).unwrap_or_default()
+    }
+}
+
+/// `{{task.outputs.NAME}}` values of a task recorded in `dir` by an earlier run
+fn recorded_named_outputs(dir: &Path) -> BTreeMap<String, serde_json::Value> {
+    std::fs::read_to_string(dir.join(OUTPUTS_FILE)).ok().and_then(|s| serde_json::from_str(&s).ok()).unwrap_or_default()
+}
+
+/// File in a task's directory holding its `outputs:` values
+const OUTPUTS_FILE: &str = "outputs.json";
+
+/// A `--skip`ped task: what its dependents see instead of its result
+struct Given {
+    output: String,
+    /// `outputs:` values
+    named: BTreeMap<String, serde_json::Value>,
+    exit_code: i32,
+    exports: Vec<(String, String)>,
+    /// Run the output was taken from
+    from: Option<String>,
+}
+
+/// Resolve `--skip`: a given output is used as is; otherwise the task's output, exit code and
+/// exports are taken from the `--skip-from` run (where it must have succeeded), or left empty.
+fn resolve_skips(config: &RunConfig, tasks_map: &HashMap<String, TaskDef>) -> anyhow::Result<HashMap<String, Given>> {
+    let from = match &config.skip_from {
+        Some(run) if !config.skip.is_empty() => {
+            let dir = resolve_run(Path::new(".rustypipe"), Some(run))?;
+            Some((RunManifest::load(&dir)?, dir))
+        }
+        _ => None,
+    };
+    let mut given = HashMap::new();
+    for (name, output) in &config.skip {
+        let found = instances(tasks_map.keys().map(String::as_str), name);
+        if found.is_empty() {
+            anyhow::bail!("--skip {}: no such task", name);
+        }
+        for task in found {
+            let g = match (output, &from) {
+                (Some(output), _) => Given { output: output.clone(), named: BTreeMap::new(), exit_code: 0, exports: Vec::new(), from: None },
+                (None, Some((manifest, dir))) => {
+                    let record = manifest
+                        .tasks
+                        .iter()
+                        .find(|t| t.name == task && matches!(t.status, TaskStatus::Succeeded | TaskStatus::CacheHit))
+                        .with_context(|| format!("--skip {}: task did not succeed in run {}", task, manifest.id))?;
+                    let task_dir = dir.join(&record.dir);
+                    Given {
+                        output: recorded_output(&task_dir, &tasks_map[task]),
+                        named: recorded_named_outputs(&task_dir),
+                        exit_code: record.exit_code.unwrap_or(0),
+                        exports: parse_env_file(&std::fs::read_to_string(task_dir.join("env")).unwrap_or_default()),
+                        from: Some(manifest.id.clone()),
+                    }
+                }
+                (None, None) => Given { output: String::new(), named: BTreeMap::new(), exit_code: 0, exports: Vec::new(), from: None },
+            };
+            given.insert(task.to_string(), g);
+        }
+    }
+    Ok(given)
+}
+
+/// `--task`: keep the named main tasks and everything they depend on, transitively; setup and
+/// teardown are kept whole. A name also selects the matrix instances of a task (`build[linux]`).
+pub(super) fn select_tasks(pipeline: &mut Pipeline, selected: &[String]) -> anyhow::Result<()> {
+    if selected.is_empty() {
+        return Ok(());
+    }
+    let deps: HashMap<&str, &[String]> = pipeline.tasks.iter().map(|t| (t.name.as_str(), t.depends_on.as_slice())).collect();
+    let mut stack = Vec::new();
+    for name in selected {
+        let found = instances(deps.keys().copied(), name);
+        if found.is_empty() {
+            anyhow::bail!("--task {}: no such task", name);
+        }
+        stack.extend(found);
+    }
+    let mut keep: HashSet<String> = HashSet::new();
+    while let Some(name) = stack.pop() {
+        if keep.insert(name.to_string()) {
+            // dependencies outside the main tasks (setup) run anyway
+            stack.extend(deps.get(name).copied().unwrap_or_default().iter().map(String::as_str));
+        }
+    }
+    pipeline.tasks.retain(|t| keep.contains(&t.name));
+    Ok(())
+}
+
+/// Merge loaded pipelines into one task graph. With several files, tasks are named
+/// `<pipeline>:<task>` and a dependency written `<pipeline>:<task>` refers to another pipeline of
+/// the same run. Returns the merged pipeline, the per-file info and the task -> pipeline index.
+fn merge_loaded(files: Vec<(PathBuf, Pipeline)>) -> anyhow::Result<(Pipeline, Vec<PipelineInfo>, HashMap<String, usize>)> {
+    let mut loaded = Vec::new();
+    for (path, p) in files {
+        let name = p
+            .name
+            .clone()
+            .unwrap_or_else(|| path.file_stem().unwrap_or_default().to_string_lossy().to_string());
+        if loaded.iter().any(|(n, _, _): &(String, _, _)| *n == name) {
+            anyhow::bail!("two pipelines are named '{}'; give them distinct `name:`s", name);
+        }
+        loaded.push((name, path, p));
+    }
+
+    let multi = loaded.len() > 1;
+    let names: HashSet<String> = loaded.iter().map(|(n, _, _)| n.clone()).collect();
+    let mut infos = Vec::new();
+    let mut task_pipeline = HashMap::new();
+    let mut merged = Pipeline {
+        name: Some(loaded.iter().map(|(n, _, _)| n.as_str()).collect::<Vec<_>>().join(", ")),
+        concurrency: loaded.iter().filter_map(|(_, _, p)| p.concurrency).max(),
+        timeout: loaded.iter().filter_map(|(_, _, p)| p.timeout).min(),
+        // a group shared by several pipelines gets the smallest limit
+        concurrency_groups: loaded.iter().flat_map(|(_, _, p)| p.concurrency_groups.iter()).fold(BTreeMap::new(), |mut groups, (name, &max)| {
+            groups.entry(name.clone()).and_modify(|m: &mut usize| *m = (*m).min(max)).or_insert(max);
+            groups
+        }),
+        stop_on_fail: None,
+        schedule: None,
+        schedules: BTreeMap::new(),
+        step_registry: None,
+        env_allowlist: None,
+        include: Vec::new(),
+        templates: BTreeMap::new(),
+        vars: HashMap::new(),
+        env: HashMap::new(),
+        secrets: BTreeMap::new(),
+        plugins: BTreeMap::new(),
+        plugin_dir: None,
+        backends: BTreeMap::new(),
+        shell: None,
+        prepull: None,
+        workspace: None,
+        collect: Vec::new(),
+        artifact_store: None,
+        setup: Vec::new(),
+        tasks: Vec::new(),
+        teardown: Vec::new(),
+        on_success: Vec::new(),
+        on_failure: Vec::new(),
+    };
+    for (idx, (name, path, p)) in loaded.into_iter().enumerate() {
+        let prefix = if multi { format!("{}:", name) } else { String::new() };
+        let local: HashSet<String> = p.all_tasks().map(|t| t.name.clone()).collect();
+        let phases = [(p.setup, &mut merged.setup), (p.tasks, &mut merged.tasks), (p.teardown, &mut merged.teardown)];
+        for (tasks, into) in phases {
+            for mut t in tasks {
+                t.name = format!("{}{}", prefix, t.name);
+                for dep in &mut t.depends_on {
+                    // anything that is not a local task is an external `<pipeline>:<task>` reference
+                    if local.contains(dep.as_str()) {
+                        *dep = format!("{}{}", prefix, dep);
+                    }
+                }
+                task_pipeline.insert(t.name.clone(), idx);
+                into.push(t);
+            }
+        }
+        let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or_else(|| Path::new(".")).to_path_buf();
+        let host_env = util::host_env(p.env_allowlist.as_deref());
+        let mut vars = p.vars;
+        for (k, v) in &mut vars {
+            *v = interpolate_command(v, &TemplateInputs { env: host_env.clone(), ..Default::default() }).with_context(|| format!("var {}", k))?;
+        }
+        infos.push(PipelineInfo {
+            backends: build_backends(&p.backends, p.shell),
+            vars,
+            host_env,
+            secrets: p.secrets,
+            secret_env: Vec::new(),
+            env: p.env,
+            on_success: p.on_success,
+            on_failure: p.on_failure,
+            name,
+            dir: dir.clone(),
+            source_dir: dir,
+            isolated: p.workspace == Some(WorkspaceMode::Isolated),
+            collect: p.collect,
+            artifact_store: p.artifact_store,
+            stop_on_fail: p.stop_on_fail.unwrap_or(false),
+            prefix,
+            plugins: p.plugins.into_keys().collect(),
+            prepull: p.prepull.unwrap_or(true),
+        });
+    }
+    for phase in [&merged.setup, &merged.tasks, &merged.teardown] {
+        let in_phase: HashSet<&str> = phase.iter().map(|t| t.name.as_str()).collect();
+        for t in phase {
+            for dep in &t.depends_on {
+                if !in_phase.contains(dep.as_str()) {
+                    match dep.split_once(':') {
+                        Some((other, _)) if !names.contains(other) => {
+                            anyhow::bail!("task '{}' depends on '{}' but pipeline '{}' is not part of this run", t.name, dep, other)
+                        }
+                        _ => anyhow::bail!("task '{}' depends on unknown '{}'", t.name, dep),
+                    }
+                }
+            }
+        }
+    }
+    // catches cycles spanning several pipelines
+    for phase in [&merged.setup, &merged.tasks, &merged.teardown] {
+        check_cycles(phase)?;
+    }
+    Ok((merged, infos, task_pipeline))
+}
+
+/// Instantiate the backends declared in a pipeline's `backends:` section, plus its own `local`
+/// backend when it sets a default `shell`
+fn build_backends(defs: &BTreeMap<String, BackendDef>, shell: Option<ShellSpec>) -> HashMap<String, Arc<dyn Backend>> {
+    let mut out: HashMap<String, Arc<dyn Backend>> = HashMap::new();
+    if let Some(shell) = shell.clone() {
+        out.insert("local".to_string(), Arc::new(LocalBackend::new().with_shell(shell)));
+    }
+    for (name, def) in defs {
+        out.insert(name.clone(), build_backend(def, shell.as_ref()));
+    }
+    out
+}
+
+/// One `backends:` entry; `shell` is the pipeline's default for local ones
+fn build_backend(def: &BackendDef, shell: Option<&ShellSpec>) -> Arc<dyn Backend> {
+    match def {
+        BackendDef::Local(l) => match l.shell.as_ref().or(shell) {
+            Some(shell) => Arc::new(LocalBackend::new().with_shell(shell.clone())),
+            None => Arc::new(LocalBackend::new()),
+        },
+        BackendDef::Docker(d) => {
+            let mut b = DockerBackend::new(&d.image).with_args(d.args.clone()).with_reuse(d.reuse.unwrap_or(false));
+            if let Some(h) = &d.host {
+                b = b.with_host(h);
+            }
+            if let Some(c) = &d.context {
+                b = b.with_context(c);
+            }
+            if let Some(copy) = d.copy {
+                b = b.with_copy(copy);
+            }
+            if let Some(u) = &d.user {
+                b = b.with_user(u);
+            }
+            if let Some(p) = d.pull {
+                b = b.with_pull(p);
+            }
+            if let Some(n) = d.pool {
+                b = b.with_pool(n);
+            }
+            Arc::new(b.with_pass_env(d.pass_env.clone()))
+        }
+        BackendDef::Ssh(s) if s.native == Some(true) => {
+            let mut b = NativeSshBackend::new(&s.host);
+            if let Some(u) = &s.user {
+                b = b.with_user(u);
+            }
+            if let Some(p) = s.port {
+                b = b.with_port(p);
+            }
+            if let Some(k) = &s.key {
+                b = b.with_key(k);
+            }
+            if let Some(w) = &s.workdir {
+                b = b.with_workdir(w);
+            }
+            if let Some(sync) = &s.sync {
+                b = b.with_sync(SyncOptions { exclude: sync.exclude.clone() });
+            }
+            if let Some(n) = s.pool {
+                b = b.with_pool(n);
+            }
+            Arc::new(b.with_multiplex(s.multiplex.unwrap_or(true)))
+        }
+        BackendDef::Ssh(s) => {
+            let mut b = SSHBackend::new(&s.host).with_args(s.args.clone());
+            if let Some(u) = &s.user {
+                b = b.with_user(u);
+            }
+            if let Some(p) = s.port {
+                b = b.with_port(p);
+            }
+            if let Some(k) = &s.key {
+                b = b.with_key(k);
+            }
+            if let Some(w) = &s.workdir {
+                b = b.with_workdir(w);
+            }
+            if let Some(sync) = &s.sync {
+                b = b.with_sync(SyncOptions { exclude: sync.exclude.clone() });
+            }
+            if let Some(n) = s.pool {
+                b = b.with_pool(n);
+            }
+            Arc::new(b.with_multiplex(s.multiplex.unwrap_or(true)))
+        }
+        BackendDef::Kubernetes(k) => match &k.job {
+            None => {
+                let mut b = KubernetesBackend::new(&k.image).with_args(k.args.clone());
+                if let Some(ns) = &k.namespace {
+                    b = b.with_namespace(ns);
+                }
+                if let Some(p) = k.pull {
+                    b = b.with_pull(p);
+                }
+                if let Some(n) = k.pool {
+                    b = b.with_pool(n);
+                }
+                Arc::new(b)
+            }
+            Some(job) => {
+                let mut b = KubernetesJobBackend::new(&k.image)
+                    .with_backoff_limit(job.backoff_limit.unwrap_or(0))
+                    .with_resources(job.requests.clone(), job.limits.clone());
+                if let Some(ns) = &k.namespace {
+                    b = b.with_namespace(ns);
+                }
+                if let Some(ttl) = job.ttl_seconds_after_finished {
+                    b = b.with_ttl(ttl);
+                }
+                if let Some(p) = k.pull {
+                    b = b.with_pull(p);
+                }
+                Arc::new(b)
+            }
+        },
+        BackendDef::Ecs(e) => {
+            let mut b = EcsBackend::new(e.cluster.as_deref().unwrap_or("default"))
+                .with_network(e.subnets.clone(), e.security_groups.clone(), e.public_ip.unwrap_or(true))
+                .with_size(e.cpu.as_deref().unwrap_or("256"), e.memory.as_deref().unwrap_or("512"));
+            if let Some(i) = &e.image {
+                b = b.with_image(i);
+            }
+            if let Some(t) = &e.task_definition {
+                b = b.with_task_definition(t);
+            }
+            if let Some(r) = &e.region {
+                b = b.with_region(r);
+            }
+            if let Some(p) = &e.profile {
+                b = b.with_profile(p);
+            }
+            if let Some(r) = &e.execution_role {
+                b = b.with_execution_role(r);
+            }
+            if let Some(r) = &e.task_role {
+                b = b.with_task_role(r);
+            }
+            if let Some(g) = &e.log_group {
+                b = b.with_log_group(g);
+            }
+            Arc::new(b)
+        }
+        BackendDef::Lambda(l) => {
+            let mut b = LambdaBackend::new(&l.function);
+            if let Some(q) = &l.qualifier {
+                b = b.with_qualifier(q);
+            }
+            if let Some(r) = &l.region {
+                b = b.with_region(r);
+            }
+            if let Some(p) = &l.profile {
+                b = b.with_profile(p);
+            }
+            Arc::new(b)
+        }
+        BackendDef::CloudRun(c) => {
+            let mut b = CloudRunBackend::new(&c.image);
+            if let Some(a) = &c.service_account {
+                b = b.with_service_account(a);
+            }
+            if let Some(r) = &c.region {
+                b = b.with_region(r);
+            }
+            if let Some(p) = &c.project {
+                b = b.with_project(p);
+            }
+            Arc::new(b)
+        }
+        BackendDef::Aci(a) => {
+            let mut b = AciBackend::new(&a.image, &a.resource_group).with_size(a.cpu.unwrap_or(1.0), a.memory.unwrap_or(1.5));
+            if let Some(l) = &a.location {
+                b = b.with_location(l);
+            }
+            if let Some(s) = &a.subscription {
+                b = b.with_subscription(s);
+            }
+            Arc::new(b)
+        }
+        BackendDef::Wsl(w) => {
+            let mut b = WslBackend::new();
+            if let Some(d) = &w.distro {
+                b = b.with_distro(d);
+            }
+            if let Some(u) = &w.user {
+                b = b.with_user(u);
+            }
+            Arc::new(b)
+        }
+    }
+}
+
+/// Backends the selected tasks and hooks use, labelled by name (prefixed by the pipeline when
+/// several run together), with the index of their pipeline
+fn used_backends(pipeline: &Pipeline, infos: &[PipelineInfo], task_pipeline: &HashMap<String, usize>) -> Vec<(String, usize, Arc<dyn Backend>)> {
+    let hook_backends = |hooks: &[HookDef]| hooks.iter().map(|h| h.backend.clone().unwrap_or_else(|| "local".to_string())).collect::<Vec<_>>();
+    let mut used: BTreeSet<(usize, String)> = BTreeSet::new();
+    for t in pipeline.all_tasks() {
+        let idx = task_pipeline[&t.name];
+        if !builtins::is_builtin(t) {
+            used.insert((idx, t.backend.clone().unwrap_or_else(|| "local".to_string())));
+        }
+        used.extend(hook_backends(&t.on_success).into_iter().chain(hook_backends(&t.on_failure)).map(|b| (idx, b)));
+    }
+    for (idx, info) in infos.iter().enumerate() {
+        used.extend(hook_backends(&info.on_success).into_iter().chain(hook_backends(&info.on_failure)).map(|b| (idx, b)));
+    }
+    let host: Arc<dyn Backend> = Arc::new(LocalBackend::new());
+    used.into_iter()
+        .map(|(idx, name)| {
+            let info = &infos[idx];
+            let backend = info.backends.get(&name).cloned().unwrap_or_else(|| host.clone());
+            let label = if infos.len() > 1 { format!("{}{}", info.prefix, name) } else { name };
+            (label, idx, backend)
+        })
+        .collect()
+}
+
+/// Check every backend in use before anything runs, so a stopped docker daemon or an
+/// unreachable host fails the run up front with one line per backend instead of halfway
+/// through the graph
+async fn preflight(used: &[(String, usize, Arc<dyn Backend>)]) -> anyhow::Result<()> {
+    let checks = used.iter().map(|(label, _, backend)| async move { (label, backend.preflight().await) });
+    let failures: Vec<String> = futures::future::join_all(checks)
+        .await
+        .into_iter()
+        .filter_map(|(label, res)| res.err().map(|e| format!("  backend '{}': {:#}", label, e)))
+        .collect();
+    if !failures.is_empty() {
+        anyhow::bail!("backend preflight failed (--no-preflight skips it):\n{}", failures.join("\n"));
+    }
+    Ok(())
+}
+
+/// Pull the images of the container backends in use (unless `prepull: false`) all at once before
+/// the first task, so no task's timeout includes a download; backends sharing an image pull it once
+async fn prepull(used: &[(String, usize, Arc<dyn Backend>)]) -> anyhow::Result<()> {
+    let mut images: BTreeMap<String, (Vec<&str>, &Arc<dyn Backend>)> = BTreeMap::new();
+    for (label, _, backend) in used {
+        if let Some(image) = backend.image() {
+            images.entry(image).or_insert_with(|| (Vec::new(), backend)).0.push(label);
+        }
+    }
+    if images.is_empty() {
+        return Ok(());
+    }
+    say!("Pulling {} image(s)", images.len());
+    let pulls = images.iter().map(|(image, (labels, backend))| async move {
+        say!("  pulling {}", image);
+        let started = Instant::now();
+        let res = backend.pull().await;
+        match &res {
+            Ok(()) => say!("  pulled {} in {:.1}s", image, started.elapsed().as_secs_f64()),
+            Err(_) => say!("  failed to pull {}", image),
+        }
+        (labels.join("', '"), res)
+    });
+    let failures: Vec<String> = futures::future::join_all(pulls)
+        .await
+        .into_iter()
+        .filter_map(|(labels, res)| res.err().map(|e| format!("  backend '{}': {:#}", labels, e)))
+        .collect();
+    if !failures.is_empty() {
+        anyhow::bail!("pre-pull failed:\n{}", failures.join("\n"));
+    }
+    Ok(())
+}
+
+/// Start the `pool:` workers of the backends in use in their pipeline's directory; a backend that
+/// cannot start them all starts the missing ones for its tasks
+async fn warm(used: &[(String, usize, Arc<dyn Backend>)], pipelines: &[PipelineInfo]) {
+    let starts = used.iter().map(|(label, idx, backend)| async move { (label, backend.warm(&pipelines[*idx].dir).await) });
+    for (label, res) in futures::future::join_all(starts).await {
+        if let Err(e) = res {
+            note!("Backend '{}': could not start its pool: {:#}", label, e);
+        }
+    }
+}
+
+/// Bookkeeping of one run across its phases
+struct RunState {
+    manifest: RunManifest,
+    ordered_results: Vec<(String, String, String, String)>, // task, cmd, stdout, stderr
+    tallies: HashMap<usize, Tally>,                         // by pipeline index
+    any_failed: bool,
+    teardown_failed: Vec<String>,
+    cancelled: bool,
+    /// Tasks that succeeded in the run being resumed; they are not run again
+    resumed: HashSet<String>,
+}
+
+/// Task counts of one pipeline
+#[derive(Debug, Clone, Copy, Default)]
+struct Tally {
+    succeeded: usize,
+    failed: usize,
+    skipped: usize,
+    cancelled: usize,
+    not_run: usize,
+}
+
+impl std::fmt::Display for Tally {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} succeeded, {} failed, {} skipped", self.succeeded, self.failed, self.skipped)?;
+        if self.cancelled > 0 {
+            write!(f, ", {} cancelled", self.cancelled)?;
+        }
+        if self.not_run > 0 {
+            write!(f, ", {} not run", self.not_run)?;
+        }
+        Ok(())
+    }
+}
+
+/// Error returned for a run that finished as failed, cancelled or timed out; its records are
+/// complete in `run_dir`
+#[derive(Debug)]
+pub struct RunFailed {
+    pub run_dir: PathBuf,
+    pub reason: String,
+}
+
+impl std::fmt::Display for RunFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.reason)
+    }
+}
+
+impl std::error::Error for RunFailed {}
+
+/// Options for one `rustypipe run` invocation
+#[derive(Debug, Clone, Default)]
+pub struct RunConfig {
+    /// Echo task output live, prefixed with the task name, instead of printing it after the run
+    pub stream: bool,
+    /// `--var NAME=value` overrides, applied over every pipeline's `vars:`
+    pub vars: Vec<(String, String)>,
+    /// `--concurrency`: overrides the pipelines' `concurrency`
+    pub concurrency: Option<usize>,
+    /// `--timeout`: overrides the pipelines' `timeout` (seconds)
+    pub timeout: Option<u64>,
+    /// `--stop-on-fail`: abort on the first failure regardless of the pipelines' `stop_on_fail`
+    pub stop_on_fail: bool,
+    /// `--task`: only run these tasks and what they depend on (see `select_tasks`)
+    pub tasks: Vec<String>,
+    /// `--skip TASK[=OUTPUT]`: tasks taken as done without running (see `resolve_skips`)
+    pub skip: Vec<(String, Option<String>)>,
+    /// `--skip-from`: run whose outputs skipped tasks without a given output take over
+    pub skip_from: Option<String>,
+    /// `--report`: reports to write once the run has finished
+    pub reports: Vec<ReportSpec>,
+    /// `--pushgateway`: Prometheus Pushgateway receiving the run's metrics
+    pub pushgateway: Option<String>,
+    /// Cancels the run like Ctrl+C when notified (`rustypipe watch` restarting on a change)
+    pub cancel: Option<Arc<Notify>>,
+    /// `--no-preflight`: start without checking the backends first (see `preflight`)
+    pub skip_preflight: bool,
+}
+
+/// Public entry used by main.rs: run one or more pipeline files under a single scheduler.
+/// Setup tasks run first, then the main tasks (skipped if setup failed), then teardown, which
+/// always runs. Returns the run directory.
+pub async fn run_pipelines(paths: &[PathBuf], config: &RunConfig) -> anyhow::Result<PathBuf> {
+    start_run(load_pipelines(paths)?, config).await
+}
+
+/// Run a pipeline built in code (`Pipeline::new(..).with_task(..)`) as if it had been read from
+/// a file in `dir`: relative paths (includes, `file:` secrets, the tasks' working directory)
+/// resolve against `dir`. Returns the run directory.
+pub async fn run_pipeline(pipeline: Pipeline, dir: &Path, config: &RunConfig) -> anyhow::Result<PathBuf> {
+    let path = dir.join(format!("{}.yaml", pipeline.name.as_deref().unwrap_or("pipeline")));
+    let p = resolve_pipeline(pipeline, &path)?;
+    validate_pipeline(&p).context("invalid pipeline")?;
+    start_run(vec![(path, p)], config).await
+}
+
+/// Record a new run of the loaded pipelines (so it can be resumed) and execute it
+async fn start_run(loaded: Vec<(PathBuf, Pipeline)>, config: &RunConfig) -> anyhow::Result<PathBuf> {
+    // create run dir: manifest.json + per-task directories
+    let base = Path::new(".rustypipe");
+    // pinned to an id before this run exists, so `latest` means the previous run
+    let skip_from = match &config.skip_from {
+        Some(run) => Some(RunManifest::load(&resolve_run(base, Some(run))?)?.id),
+        None => None,
+    };
+    let config = &RunConfig { skip_from, ..config.clone() };
+    let run_dir = create_run_dir(base)?;
+    // record the resolved pipelines so the run can be resumed even if the files change
+    std::fs::create_dir_all(run_dir.join("pipelines"))?;
+    let mut sources = Vec::new();
+    for (i, (path, p)) in loaded.iter().enumerate() {
+        let file = format!("pipelines/{}.yaml", i);
+        std::fs::write(run_dir.join(&file), serde_yaml::to_string(p)?)?;
+        sources.push(RunSource { path: path.clone(), file });
+    }
+    RunSpec {
+        sources,
+        vars: config.vars.clone(),
+        tasks: config.tasks.clone(),
+        skip: config.skip.clone(),
+        skip_from: config.skip_from.clone(),
+    }
+    .save(&run_dir)?;
+    execute(run_dir, loaded, config, None).await
+}
+
+/// `rustypipe resume`: run the failed and unstarted tasks of an earlier run again, in the same
+/// run directory. Main tasks that succeeded are kept together with their outputs and exports;
+/// setup and teardown always run again, since the previous teardown already undid the setup.
+pub async fn resume_run(run: &str, config: &RunConfig) -> anyhow::Result<PathBuf> {
+    let run_dir = resolve_run(Path::new(".rustypipe"), Some(run))?;
+    let manifest = RunManifest::load(&run_dir)?;
+    match manifest.status {
+        RunStatus::Succeeded => anyhow::bail!("run {} succeeded; nothing to resume", manifest.id),
+        RunStatus::Running => note!("Run {} never finished (killed?); resuming it anyway", manifest.id),
+        RunStatus::Failed | RunStatus::Cancelled => {}
+    }
+    let spec = RunSpec::load(&run_dir).context("run cannot be resumed")?;
+    let mut loaded = Vec::new();
+    for s in &spec.sources {
+        // recorded already resolved (matrix and steps expanded)
+        let file = run_dir.join(&s.file);
+        let p = load_pipeline(&file)?;
+        validate_pipeline(&p).with_context(|| format!("invalid pipeline {:?}", file))?;
+        loaded.push((s.path.clone(), p));
+    }
+    let config = RunConfig { vars: spec.vars, tasks: spec.tasks, skip: spec.skip, skip_from: spec.skip_from, ..config.clone() };
+    execute(run_dir, loaded, &config, Some(manifest)).await
+}
+
+/// Run the merged pipelines in `run_dir`; `previous` is the manifest of a run being resumed
+async fn execute(run_dir: PathBuf, loaded: Vec<(PathBuf, Pipeline)>, config: &RunConfig, previous: Option<RunManifest>) -> anyhow::Result<PathBuf> {
+    let (mut pipeline, mut pipelines, task_pipeline) = merge_loaded(loaded)?;
+    select_tasks(&mut pipeline, &config.tasks)?;
+    if config.stop_on_fail {
+        pipelines.iter_mut().for_each(|p| p.stop_on_fail = true);
+    }
+    for p in &mut pipelines {
+        p.secret_env = secrets::resolve(&p.secrets, &p.source_dir).await.with_context(|| format!("pipeline {}", p.name))?;
+    }
+    let used = used_backends(&pipeline, &pipelines, &task_pipeline);
+    if !config.skip_preflight {
+        preflight(&used).await?;
+    }
+    let to_pull: Vec<_> = used.iter().filter(|(_, idx, _)| pipelines[*idx].prepull).cloned().collect();
+    if !to_pull.is_empty() {
+        prepull(&to_pull).await?;
+    }
+
+    info!("Starting pipeline: {:?}", pipeline.name);
+
+    let resuming = previous.is_some();
+    let manifest = match previous {
+        None => {
+            let meta_file = run_dir.join("pipeline.yaml");
+            std::fs::write(&meta_file, serde_yaml::to_string(&pipeline)?)?;
+            RunManifest::new(&run_dir, pipeline.name.clone())
+        }
+        Some(mut m) => {
+            // only succeeded main tasks are kept; everything else gets a fresh record
+            m.tasks.retain(|t| t.phase == Phase::Main && matches!(t.status, TaskStatus::Succeeded | TaskStatus::CacheHit));
+            m.status = RunStatus::Running;
+            m.finished = None;
+            say!("Resuming run {}: {} task(s) already succeeded", m.id, m.tasks.len());
+            m
+        }
+    };
+    save_manifest(&manifest, &run_dir)?;
+
+    for p in pipelines.iter_mut().filter(|p| p.isolated) {
+        let ws = run_dir.join("workspace").join(sanitize_filename(&p.name));
+        // a failed run keeps its workspace; resuming continues in it
+        if !(resuming && ws.is_dir()) {
+            workspace::create(&p.source_dir, &ws)?;
+        }
+        info!("Isolated workspace for {}: {}", p.name, ws.display());
+        p.dir = ws;
+    }
+    warm(&used, &pipelines).await;
+
+    let names = |tasks: &[TaskDef]| tasks.iter().map(|t| t.name.clone()).collect::<Vec<_>>();
+    let (setup, main, teardown) = (names(&pipeline.setup), names(&pipeline.tasks), names(&pipeline.teardown));
+    events::emit(
+        &run_dir,
+        RunEvent::RunStarted {
+            run_id: manifest.id.clone(),
+            pipeline: manifest.pipeline.clone(),
+            tasks: setup.iter().chain(&main).chain(&teardown).cloned().collect(),
+        },
+    );
+    let run_id = manifest.id.clone();
+    let mut task_phase = HashMap::new();
+    for (phase, list) in [(Phase::Setup, &setup), (Phase::Main, &main), (Phase::Teardown, &teardown)] {
+        for n in list {
+            task_phase.insert(n.clone(), phase);
+        }
+    }
+    let tasks_map: HashMap<String, TaskDef> = pipeline
+        .setup
+        .into_iter()
+        .chain(pipeline.tasks)
+        .chain(pipeline.teardown)
+        .map(|t| (t.name.clone(), t))
+        .collect();
+
+    let given = resolve_skips(config, &tasks_map)?;
+
+    // concurrency is shared by all pipelines of the run
+    let concurrency = config.concurrency.or(pipeline.concurrency).unwrap_or(4).max(1);
+    let run_timeout = config.timeout.or(pipeline.timeout);
+    let mutexes = tasks_map.values().filter_map(|t| t.mutex.clone()).map(|m| (m, PrioritySemaphore::new(1))).collect();
+    let groups = pipeline.concurrency_groups.iter().map(|(name, &max)| (name.clone(), PrioritySemaphore::new(max))).collect();
+
+    let ctx_plugin_names: BTreeSet<String> = pipelines.iter().flat_map(|p| p.plugins.iter().cloned()).collect();
+    // state shared by all task futures (interpolation inputs, backends, concurrency control)
+    let ctx = Arc::new(RunContext {
+        pipelines,
+        task_pipeline,
+        task_phase,
+        run_dir: run_dir.clone(),
+        tasks_map,
+        outputs: Mutex::new(HashMap::new()),
+        exit_codes: Mutex::new(HashMap::new()),
+        named_outputs: Mutex::new(HashMap::new()),
+        vars: Mutex::new(config.vars.iter().cloned().collect()),
+        exports: Mutex::new(Vec::new()),
+        local_backend: Arc::new(LocalBackend::new()),
+        sem: PrioritySemaphore::new(concurrency),
+        mutexes,
+        groups,
+        stream: config.stream,
+        cancel: watch::channel(false).0,
+        given,
+        plugins: plugins::active(ctx_plugin_names.iter()),
+    });
+
+    for plugin in &ctx.plugins {
+        plugin.on_pipeline_start(&run_id, pipeline.name.as_deref().unwrap_or_default());
+    }
+
+    // restore the interpolation state of resumed tasks
+    let mut tallies: HashMap<usize, Tally> = HashMap::new();
+    for t in &manifest.tasks {
+        let Some(&idx) = ctx.task_pipeline.get(&t.name) else { continue };
+        tallies.entry(idx).or_default().succeeded += 1;
+        let output = recorded_output(&run_dir.join(&t.dir), &ctx.tasks_map[&t.name]);
+        ctx.outputs.lock().await.insert(t.name.clone(), output);
+        let named = recorded_named_outputs(&run_dir.join(&t.dir));
+        if !named.is_empty() {
+            ctx.named_outputs.lock().await.insert(t.name.clone(), named);
+        }
+        if let Some(code) = t.exit_code {
+            ctx.exit_codes.lock().await.insert(t.name.clone(), code);
+        }
+        if let Ok(content) = std::fs::read_to_string(ctx.env_file(&t.name)) {
+            let exported = parse_env_file(&content);
+            if !exported.is_empty() {
+                ctx.exports.lock().await.push((t.name.clone(), exported));
+            }
+        }
+    }
+
+    // graceful shutdown notify; the pipeline `timeout` shuts down the same way
+    let shutdown_notify = Arc::new(Notify::new());
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let shutdown_listener = {
+        let shutdown_notify = shutdown_notify.clone();
+        let timed_out = timed_out.clone();
+        let cancel = config.cancel.clone();
+        tokio::spawn(async move {
+            let requested = async {
+                match cancel {
+                    Some(cancel) => cancel.notified().await,
+                    None => std::future::pending().await,
+                }
+            };
+            let deadline = async {
+                match run_timeout {
+                    Some(secs) => tokio::time::sleep(Duration::from_secs(secs)).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => note!("Received Ctrl+C — initiating shutdown"),
+                _ = requested => {}
+                _ = deadline => {
+                    note!("Pipeline timeout of {}s reached — stopping", run_timeout.unwrap_or_default());
+                    timed_out.store(true, Ordering::Relaxed);
+                }
+            }
+            shutdown_notify.notify_one();
+        })
+    };
+
+    let mut state = RunState {
+        resumed: manifest.tasks.iter().map(|t| t.name.clone()).collect(),
+        manifest,
+        ordered_results: Vec::new(),
+        tallies,
+        any_failed: false,
+        teardown_failed: Vec::new(),
+        cancelled: false,
+    };
+
+    let mut result = run_graph(&ctx, &setup, &mut state, Some(&shutdown_notify)).await;
+    if result.is_ok() && !state.cancelled {
+        if state.any_failed {
+            note!("Setup failed; skipping the pipeline's tasks.");
+            for name in main.iter().filter(|t| !state.resumed.contains(*t)) {
+                let record = task_record(&ctx, name, "", TaskStatus::Skipped, None, Utc::now(), Duration::ZERO);
+                record_task(&ctx, &mut state.manifest, record, "", "skipped: setup failed\n")?;
+                state.tallies.entry(ctx.task_pipeline[name]).or_default().skipped += 1;
+            }
+        } else {
+            result = run_graph(&ctx, &main, &mut state, Some(&shutdown_notify)).await;
+        }
+    }
+    // later Ctrl+C presses belong to whoever runs next (`watch` runs many times in one process)
+    shutdown_listener.abort();
+    // stopped by Ctrl+C, the timeout or `stop_on_fail`: what never started is still reported
+    if state.cancelled || result.is_err() {
+        record_not_run(&ctx, setup.iter().chain(&main), &mut state)?;
+    }
+    let timed_out = timed_out.load(Ordering::Relaxed);
+    // teardown always runs and cannot be interrupted by Ctrl+C
+    if !teardown.is_empty() {
+        if let Err(e) = run_graph(&ctx, &teardown, &mut state, None).await {
+            note!("Teardown error: {:#}", e);
+            state.teardown_failed.push(format!("{:#}", e));
+        }
+    }
+
+    // pipeline-level hooks, per pipeline file, once everything else is done
+    let mut pipeline_hooks = Vec::new();
+    for (idx, p) in ctx.pipelines.iter().enumerate() {
+        let failed = state.cancelled || result.is_err() || state.tallies.get(&idx).is_some_and(|t| t.failed > 0);
+        let (event, hooks) = if failed { ("on_failure", &p.on_failure) } else { ("on_success", &p.on_success) };
+        let log = run_dir.join("hooks").join(format!("{}.{}.log", sanitize_filename(&p.name), event));
+        for hook in hooks {
+            pipeline_hooks.push(run_hook(ctx.clone(), idx, hook.clone(), event, None, log.clone()));
+        }
+    }
+    futures::future::join_all(pipeline_hooks).await;
+
+    let final_status = if timed_out {
+        RunStatus::Failed
+    } else if state.cancelled {
+        RunStatus::Cancelled
+    } else if result.is_err() || state.any_failed || !state.teardown_failed.is_empty() {
+        RunStatus::Failed
+    } else {
+        RunStatus::Succeeded
+    };
+    finish_manifest(&mut state.manifest, &run_dir, final_status)?;
+    for plugin in &ctx.plugins {
+        plugin.on_pipeline_end(&state.manifest.id, final_status);
+    }
+    for p in ctx.pipelines.iter().filter(|p| p.isolated) {
+        let n = workspace::collect(&p.dir, &p.source_dir, &p.collect)?;
+        if n > 0 {
+            info!("Collected {} file(s) from the {} workspace", n, p.name);
+        }
+        if final_status == RunStatus::Succeeded {
+            std::fs::remove_dir_all(&p.dir)?;
+        } else {
+            note!("Keeping workspace {} for inspection", p.dir.display());
+        }
+    }
+    let _ = std::fs::remove_dir(run_dir.join("workspace")); // only succeeds once empty
+
+    // push logs and artifacts to the configured stores, once per distinct location
+    let mut pushed_to = HashSet::new();
+    for info in &ctx.pipelines {
+        let Some(cfg) = &info.artifact_store else { continue };
+        let inputs = ctx.interpolation_inputs(info).await;
+        let to = match interpolate_command(&cfg.to, &inputs) {
+            Ok(to) => to,
+            Err(e) => {
+                note!("Failed to push the run: artifact store `to`: {:#}", e);
+                continue;
+            }
+        };
+        if !pushed_to.insert(to.clone()) {
+            continue;
+        }
+        let res = async {
+            let store = storage::open(&to, cfg.retries)?;
+            let n = storage::push_run(store.as_ref(), &run_dir).await?;
+            say!("Pushed {} file(s) of run {} to {}", n, state.manifest.id, store.describe());
+            anyhow::Ok(())
+        };
+        if let Err(e) = res.await {
+            note!("Failed to push the run to {}: {:#}", to, e);
+        }
+    }
+    if let Err(e) = report::write_reports(&config.reports, &state.manifest, &run_dir) {
+        note!("Failed to write reports: {:#}", e);
+    }
+    if let Some(otlp) = telemetry::OtlpConfig::from_env() {
+        if let Err(e) = telemetry::export_run(&otlp, &state.manifest).await {
+            note!("Failed to export the run trace to {}: {:#}", otlp.endpoint, e);
+        }
+    }
+    if let Some(url) = &config.pushgateway {
+        if let Err(e) = metrics::push(url, &state.manifest).await {
+            note!("Failed to push metrics to {}: {:#}", url, e);
+        }
+    }
+    if !state.teardown_failed.is_empty() {
+        note!("Teardown failed: {}", state.teardown_failed.join(", "));
+    }
+
+    // print ordered results (already shown live when streaming or as JSON events); also after an
+    // abort, as a partial report
+    for (task, cmd, stdout, stderr) in state.ordered_results.into_iter().filter(|_| !config.stream && !util::json_output() && !util::tui()) {
+        say!("Task: {}", task);
+        say!("Command: {}", cmd);
+        say!("Output: {}", stdout.trim());
+        if !stderr.trim().is_empty() {
+            note!("Error: {}", stderr.trim());
+        }
+        say!("");
+    }
+
+    // per-pipeline breakdown when several pipelines ran together
+    let total = state.tallies.values().fold(Tally::default(), |a, t| Tally {
+        succeeded: a.succeeded + t.succeeded,
+        failed: a.failed + t.failed,
+        skipped: a.skipped + t.skipped,
+        cancelled: a.cancelled + t.cancelled,
+        not_run: a.not_run + t.not_run,
+    });
+    say!("Summary: {}", total);
+    events::emit(
+        &run_dir,
+        RunEvent::PipelineFinished {
+            run_id: state.manifest.id.clone(),
+            status: final_status,
+            succeeded: total.succeeded,
+            failed: total.failed,
+            skipped: total.skipped,
+            cancelled: total.cancelled,
+        },
+    );
+    if ctx.pipelines.len() > 1 {
+        for (idx, p) in ctx.pipelines.iter().enumerate() {
+            let count = ctx.task_pipeline.values().filter(|&&i| i == idx).count();
+            let tally = state.tallies.get(&idx).copied().unwrap_or_default();
+            say!("  {}: {} ({} task(s))", p.name, tally, count);
+        }
+    }
+    let failed = |reason: String| anyhow::Error::new(RunFailed { run_dir: run_dir.clone(), reason });
+    if timed_out {
+        return Err(failed(format!("pipeline timed out after {}s", run_timeout.unwrap_or_default())));
+    }
+    if let Err(e) = result {
+        return Err(failed(format!("{:#}", e)));
+    }
+
+    if !state.teardown_failed.is_empty() {
+        return Err(failed(format!("teardown failed: {}", state.teardown_failed.join(", "))));
+    }
+    // `continue_on_fail` only lets dependents run; the run still fails like its manifest says
+    match final_status {
+        RunStatus::Cancelled => return Err(failed(format!("run {} was cancelled", state.manifest.id))),
+        RunStatus::Failed => return Err(failed(format!("run {} failed: {} task(s) failed", state.manifest.id, total.failed))),
+        _ => {}
+    }
+
+    info!("Pipeline finished (run {})", state.manifest.id);
+    Ok(run_dir)
+}
+
+/// Run one phase's tasks as a DAG. Returns an error when a `stop_on_fail` pipeline has a failing
+/// task (never in teardown, where every task gets its chance to run).
+async fn run_graph(
+    ctx: &Arc<RunContext>,
+    names: &[String],
+    state: &mut RunState,
+    shutdown: Option<&Notify>,
+) -> anyhow::Result<()> {
+    // Build graph structures
+    let mut indegree: HashMap<String, usize> = HashMap::new();
+    let mut adj: HashMap<String, Vec<String>> = HashMap::new();
+    for name in names.iter().filter(|n| !state.resumed.contains(*n)) {
+        let t = &ctx.tasks_map[name];
+        indegree.entry(t.name.clone()).or_insert(0);
+        for dep in t.depends_on.iter().filter(|d| !state.resumed.contains(*d)) {
+            adj.entry(dep.clone()).or_default().push(t.name.clone());
+            *indegree.entry(t.name.clone()).or_insert(0) += 1;
+        }
+    }
+
+    // initial ready tasks, in definition order; pushed by priority so they queue up in that order
+    let mut ready_tasks: Vec<String> = names.iter().filter(|n| indegree.get(*n) == Some(&0)).cloned().collect();
+    by_priority(ctx, &mut ready_tasks);
+
+    let mut running = FuturesUnordered::new();
+    // task hooks run beside the graph; the phase ends once they are done too
+    let mut hooks = FuturesUnordered::new();
+    // spawn initial batch
+    for t in ready_tasks.drain(..) {
+        running.push(spawn_task_future(t, ctx.clone()));
+    }
+
+    let mut current_indegree = indegree;
+    // dependents of failed tasks, recorded as skipped
+    let mut blocked: HashSet<String> = HashSet::new();
+
+    // driver loop: process completed tasks and spawn dependents
+    loop {
+        let next = match shutdown {
+            Some(notify) => tokio::select! {
+                res = running.next() => res,
+                _ = notify.notified() => {
+                    note!("Shutdown requested; stopping spawn of new tasks.");
+                    state.cancelled = true;
+                    cancel_running(ctx, &mut running, state).await?;
+                    break;
+                }
+            },
+            None => running.next().await,
+        };
+        let Some((task_name, res)) = next else { break };
+        let phase = ctx.task_phase[&task_name];
+        let idx = ctx.task_pipeline[&task_name];
+        let mut skipped = false;
+        let succeeded = match res {
+            Ok(TaskOutcome { skipped: true, started, .. }) => {
+                let record = task_record(ctx, &task_name, "", TaskStatus::Skipped, None, started, Duration::ZERO);
+                let output = match ctx.given.get(&task_name) {
+                    Some(given) => {
+                        match &given.from {
+                            Some(run) => say!("Task '{}' skipped (--skip, output of run {})", task_name, run),
+                            None => say!("Task '{}' skipped (--skip)", task_name),
+                        }
+                        record_task(ctx, &mut state.manifest, record, &given.output, "")?;
+                        ctx.exit_codes.lock().await.insert(task_name.clone(), given.exit_code);
+                        if !given.named.is_empty() {
+                            write_artifact(&task_dir(&ctx.run_dir, &task_name), OUTPUTS_FILE, &serde_json::to_string_pretty(&given.named)?)?;
+                            ctx.named_outputs.lock().await.insert(task_name.clone(), given.named.clone());
+                        }
+                        if !given.exports.is_empty() {
+                            ctx.exports.lock().await.push((task_name.clone(), given.exports.clone()));
+                        }
+                        given.output.clone()
+                    }
+                    None => {
+                        let when = ctx.tasks_map[&task_name].when.clone().unwrap_or_default();
+                        say!("Task '{}' skipped (when: {})", task_name, when);
+                        record_task(ctx, &mut state.manifest, record, "", "")?;
+                        // like `{{task.output}}`, declared outputs of a skipped task are empty
+                        let declared = &ctx.tasks_map[&task_name].outputs;
+                        if !declared.is_empty() {
+                            let named = declared.keys().map(|k| (k.clone(), serde_json::Value::from(""))).collect();
+                            ctx.named_outputs.lock().await.insert(task_name.clone(), named);
+                        }
+                        String::new()
+                    }
+                };
+                ctx.outputs.lock().await.insert(task_name.clone(), output);
+                skipped = true;
+                true
+            }
+            Ok(TaskOutcome { cmd, stdout, mut stderr, status: mut exit_status, started, duration, queued, cached, attempts, artifacts, named, .. }) => {
+                if cached {
+                    say!("Task '{}': cache hit", task_name);
+                }
+                // value exposed as {{task.output}}; a filter that cannot be applied fails the task
+                let mut output = stdout.clone();
+                let filters = &ctx.tasks_map[&task_name].output_filter;
+                if exit_status.success() && !filters.is_empty() {
+                    match apply_filters(filters, &stdout) {
+                        Ok(v) => output = v,
+                        Err(e) => {
+                            stderr.push_str(&format!("output_filter: {:#}\n", e));
+                            exit_status = util::exit_status(1);
+                            output = String::new();
+                        }
+                    }
+                }
+
+                // Save logs and the task's manifest entry
+                let status = match (exit_status.success(), cached) {
+                    (true, true) => TaskStatus::CacheHit,
+                    (true, false) => TaskStatus::Succeeded,
+                    (false, _) => TaskStatus::Failed,
+                };
+                let mut record = task_record(ctx, &task_name, &cmd, status, exit_status.code(), started, duration);
+                record.queue_ms = Some(queued.as_millis() as u64);
+                record.attempts = attempts;
+                record.artifacts = artifacts;
+                record_task(ctx, &mut state.manifest, record, &stdout, &stderr)?;
+
+                // store output for interpolation
+                {
+                    let mut out_map = ctx.outputs.lock().await;
+                    out_map.insert(task_name.clone(), output.clone());
+                }
+                if let Some(code) = exit_status.code() {
+                    ctx.exit_codes.lock().await.insert(task_name.clone(), code);
+                }
+                if !named.is_empty() {
+                    write_artifact(&task_dir(&ctx.run_dir, &task_name), OUTPUTS_FILE, &serde_json::to_string_pretty(&named)?)?;
+                    ctx.named_outputs.lock().await.insert(task_name.clone(), named);
+                }
+
+                // collect KEY=value lines the task wrote to $RUSTYPIPE_ENV for its dependents
+                if let Ok(content) = std::fs::read_to_string(ctx.env_file(&task_name)) {
+                    let exported = parse_env_file(&content);
+                    if !exported.is_empty() {
+                        ctx.exports.lock().await.push((task_name.clone(), exported));
+                    }
+                }
+
+                state.ordered_results.push((task_name.clone(), cmd.clone(), output, stderr.clone()));
+                if !exit_status.success() {
+                    note!("Task '{}' failed (code {:?})", task_name, exit_status.code());
+                }
+                exit_status.success()
+            }
+            Err(e) => {
+                note!("Task '{}' failed: {:?}", task_name, e);
+                let record = task_record(ctx, &task_name, "", TaskStatus::Error, None, Utc::now(), Duration::ZERO);
+                record_task(ctx, &mut state.manifest, record, "", &format!("{:#}\n", e))?;
+                false
+            }
+        };
+        task_completed(ctx, &state.manifest, &task_name).await;
+
+        let def = &ctx.tasks_map[&task_name];
+        let (event, task_hooks) = if succeeded { ("on_success", &def.on_success) } else { ("on_failure", &def.on_failure) };
+        let log = task_dir(&ctx.run_dir, &task_name).join(format!("{}.log", event));
+        for hook in task_hooks.iter().filter(|_| !skipped) {
+            hooks.push(run_hook(ctx.clone(), idx, hook.clone(), event, Some(task_name.clone()), log.clone()));
+        }
+
+        let tally = state.tallies.entry(idx).or_default();
+        match (succeeded, skipped) {
+            (_, true) => tally.skipped += 1,
+            (true, _) => tally.succeeded += 1,
+            (false, _) => tally.failed += 1,
+        }
+        let continue_on_fail = def.continue_on_fail.unwrap_or(false);
+        if !succeeded {
+            if phase == Phase::Teardown {
+                state.teardown_failed.push(task_name.clone());
+            } else {
+                state.any_failed = true;
+                // fail-fast behavior
+                if ctx.pipelines[idx].stop_on_fail && !continue_on_fail {
+                    cancel_running(ctx, &mut running, state).await?;
+                    while hooks.next().await.is_some() {}
+                    anyhow::bail!("Task '{}' failed; aborting (stop_on_fail=true)", task_name);
+                }
+            }
+        }
+
+        if !succeeded && !continue_on_fail {
+            // everything downstream of a failure is skipped and never released
+            let mut stack = adj.get(&task_name).cloned().unwrap_or_default();
+            while let Some(d) = stack.pop() {
+                if !blocked.insert(d.clone()) {
+                    continue;
+                }
+                say!("Task '{}' skipped (upstream '{}' failed)", d, task_name);
+                let record = task_record(ctx, &d, "", TaskStatus::Skipped, None, Utc::now(), Duration::ZERO);
+                record_task(ctx, &mut state.manifest, record, "", &format!("skipped: upstream '{}' failed\n", task_name))?;
+                state.tallies.entry(ctx.task_pipeline[&d]).or_default().skipped += 1;
+                stack.extend(adj.get(&d).cloned().unwrap_or_default());
+            }
+        } else {
+            // spawn dependents whose indegree drops to 0
+            if let Some(dependents) = adj.get(&task_name) {
+                for dep in dependents {
+                    if let Some(val) = current_indegree.get_mut(dep) {
+                        *val = val.saturating_sub(1);
+                        if *val == 0 {
+                            ready_tasks.push(dep.clone());
+                        }
+                    }
+                }
+                by_priority(ctx, &mut ready_tasks);
+                for t in ready_tasks.drain(..) {
+                    running.push(spawn_task_future(t, ctx.clone()));
+                }
+            }
+        }
+    }
+    while hooks.next().await.is_some() {}
+    Ok(())
+}
+
+/// Order `tasks` by descending `priority`, keeping the order of equal ones
+fn by_priority(ctx: &RunContext, tasks: &mut [String]) {
+    tasks.sort_by_key(|t| std::cmp::Reverse(ctx.tasks_map[t].priority.unwrap_or(0)));
+}
+
+/// Cancel the tasks still running: kill their commands, wait for them and record how each one
+/// ended. The cancel flag is cleared afterwards so teardown can still run.
+async fn cancel_running<F>(ctx: &RunContext, running: &mut FuturesUnordered<F>, state: &mut RunState) -> anyhow::Result<()>
+where
+    F: Future<Output = (String, anyhow::Result<TaskOutcome>)>,
+{
+    if running.is_empty() {
+        return Ok(());
+    }
+    note!("Cancelling {} running task(s)...", running.len());
+    ctx.cancel.send_replace(true);
+    while let Some((task_name, res)) = running.next().await {
+        let tally = state.tallies.entry(ctx.task_pipeline[&task_name]).or_default();
+        match res {
+            Ok(TaskOutcome { skipped: true, started, .. }) => {
+                tally.skipped += 1;
+                let record = task_record(ctx, &task_name, "", TaskStatus::Skipped, None, started, Duration::ZERO);
+                record_task(ctx, &mut state.manifest, record, "", "")?;
+            }
+            // finished before the kill reached it
+            Ok(TaskOutcome { cmd, stdout, stderr, status, started, duration, queued, cached, attempts, artifacts, .. }) => {
+                let task_status = if status.success() {
+                    tally.succeeded += 1;
+                    if cached { TaskStatus::CacheHit } else { TaskStatus::Succeeded }
+                } else {
+                    tally.failed += 1;
+                    TaskStatus::Failed
+                };
+                let mut record = task_record(ctx, &task_name, &cmd, task_status, status.code(), started, duration);
+                record.queue_ms = Some(queued.as_millis() as u64);
+                record.attempts = attempts;
+                record.artifacts = artifacts;
+                record_task(ctx, &mut state.manifest, record, &stdout, &stderr)?;
+                state.ordered_results.push((task_name.clone(), cmd, stdout, stderr));
+            }
+            // still waiting for a slot when the run was stopped
+            Err(e) if e.downcast_ref::<Cancelled>().is_some_and(|c| c.backend == "scheduler") => {
+                tally.not_run += 1;
+                let record = task_record(ctx, &task_name, "", TaskStatus::NotRun, None, Utc::now(), Duration::ZERO);
+                record_task(ctx, &mut state.manifest, record, "", "not run: the run was stopped\n")?;
+            }
+            Err(e) if e.downcast_ref::<Cancelled>().is_some() => {
+                tally.cancelled += 1;
+                say!("Task '{}' cancelled", task_name);
+                let record = task_record(ctx, &task_name, "", TaskStatus::Cancelled, None, Utc::now(), Duration::ZERO);
+                record_task(ctx, &mut state.manifest, record, "", &format!("{:#}\n", e))?;
+            }
+            Err(e) => {
+                tally.failed += 1;
+                note!("Task '{}' failed: {:?}", task_name, e);
+                let record = task_record(ctx, &task_name, "", TaskStatus::Error, None, Utc::now(), Duration::ZERO);
+                record_task(ctx, &mut state.manifest, record, "", &format!("{:#}\n", e))?;
+            }
+        }
+        task_completed(ctx, &state.manifest, &task_name).await;
+    }
+    ctx.cancel.send_replace(false);
+    Ok(())
+}
+
+/// Record the `tasks` a stopped run never got to as not run
+fn record_not_run<'a>(ctx: &RunContext, tasks: impl Iterator<Item = &'a String>, state: &mut RunState) -> anyhow::Result<()> {
+    let recorded: HashSet<String> = state.manifest.tasks.iter().map(|t| t.name.clone()).collect();
+    for name in tasks.filter(|t| !recorded.contains(*t) && !state.resumed.contains(*t)) {
+        state.tallies.entry(ctx.task_pipeline[name]).or_default().not_run += 1;
+        let record = task_record(ctx, name, "", TaskStatus::NotRun, None, Utc::now(), Duration::ZERO);
+        record_task(ctx, &mut state.manifest, record, "", "not run: the run was stopped\n")?;
+    }
+    Ok(())
+}
+
+/// Validate several pipeline files (paths or glob patterns) and print an aggregated report.
+/// Every file is checked even if an earlier one fails; returns an error if any file failed.
+pub fn validate_pipeline_files(patterns: &[String]) -> anyhow::Result<()> {
+    let mut total = 0usize;
+    let mut failed = 0usize;
+
+    for entry in expand_paths(patterns) {
+        total += 1;
+        let path = match entry {
+            Ok(p) => p,
+            Err(e) => {
+                failed += 1;
+                println!("FAIL  {}", e);
+                continue;
+            }
+        };
+
+        let res = load_resolved_pipeline(&path).and_then(|p| validate_pipeline(&p).map(|_| p));
+        match res {
+            Ok(p) => {
+                println!("OK    {} ({})", path.display(), p.name.unwrap_or_else(|| "<unnamed>".to_string()));
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAIL  {}: {:#}", path.display(), e);
+            }
+        }
+    }
+
+    println!("{} file(s) checked, {} failed", total, failed);
+    if failed > 0 {
+        anyhow::bail!("{} of {} pipeline file(s) failed validation", failed, total);
+    }
+    Ok(())
+}
+
+/// Environment variables one task exported, keyed by task name
+type TaskExports = (String, Vec<(String, String)>);
+
+/// State shared by every task future of one run
+struct RunContext {
+    pipelines: Vec<PipelineInfo>,
+    /// Task name -> index into `pipelines`
+    task_pipeline: HashMap<String, usize>,
+    task_phase: HashMap<String, Phase>,
+    run_dir: PathBuf,
+    tasks_map: HashMap<String, TaskDef>,
+    outputs: Mutex<HashMap<String, String>>,
+    /// Exit codes of finished tasks, for `{{task.exit_code}}` in `when:`
+    exit_codes: Mutex<HashMap<String, i32>>,
+    /// `outputs:` values of finished tasks, for `{{task.outputs.NAME}}`
+    named_outputs: Mutex<HashMap<String, BTreeMap<String, serde_json::Value>>>,
+    vars: Mutex<HashMap<String, String>>,
+    /// Variables exported through $RUSTYPIPE_ENV, in task completion order
+    exports: Mutex<Vec<TaskExports>>,
+    local_backend: Arc<dyn Backend>,
+    sem: PrioritySemaphore,
+    /// Tasks' `mutex:` locks, shared by all pipelines of the run
+    mutexes: HashMap<String, PrioritySemaphore>,
+    /// `concurrency_groups:` limits
+    groups: HashMap<String, PrioritySemaphore>,
+    stream: bool,
+    /// Set while running tasks are being cancelled; backends kill their commands when it flips
+    cancel: watch::Sender<bool>,
+    /// `--skip`ped tasks
+    given: HashMap<String, Given>,
+    /// Plugins of the run's pipelines, whose lifecycle hooks are called
+    plugins: Vec<Arc<dyn Plugin>>,
+}
+
+impl RunContext {
+    /// The backend `name` (default `local`) of `info`'s pipeline
+    fn backend(&self, info: &PipelineInfo, name: Option<&str>) -> anyhow::Result<Arc<dyn Backend>> {
+        let name = name.unwrap_or("local");
+        match info.backends.get(name) {
+            Some(backend) => Ok(backend.clone()),
+            None if name == "local" => Ok(self.local_backend.clone()),
+            None => anyhow::bail!("backend '{}' is not configured", name),
+        }
+    }
+
+    /// File a task can append `KEY=value` lines to (exposed as $RUSTYPIPE_ENV)
+    fn env_file(&self, task_name: &str) -> PathBuf {
+        task_dir(&self.run_dir, task_name).join("env")
+    }
+
+    /// All tasks `task_name` depends on, directly or transitively
+    fn ancestors(&self, task_name: &str) -> HashSet<String> {
+        let mut seen = HashSet::new();
+        let mut stack = vec![task_name.to_string()];
+        while let Some(t) = stack.pop() {
+            if let Some(def) = self.tasks_map.get(&t) {
+                for dep in &def.depends_on {
+                    if seen.insert(dep.clone()) {
+                        stack.push(dep.clone());
+                    }
+                }
+            }
+        }
+        seen
+    }
+
+    /// What templates of `info`'s tasks can reference. Tasks of the same pipeline are also
+    /// reachable without the `<pipeline>:` prefix; `--var` overrides pipeline `vars:`.
+    async fn interpolation_inputs(&self, info: &PipelineInfo) -> TemplateInputs {
+        fn with_local<V: Clone>(mut map: HashMap<String, V>, prefix: &str) -> HashMap<String, V> {
+            if !prefix.is_empty() {
+                let local: Vec<(String, V)> = map.iter().filter_map(|(k, v)| k.strip_prefix(prefix).map(|k| (k.to_string(), v.clone()))).collect();
+                map.extend(local);
+            }
+            map
+        }
+        let mut vars = info.vars.clone();
+        vars.extend(self.vars.lock().await.clone());
+        TemplateInputs {
+            outputs: with_local(self.outputs.lock().await.clone(), &info.prefix),
+            exit_codes: with_local(self.exit_codes.lock().await.clone(), &info.prefix),
+            named: with_local(self.named_outputs.lock().await.clone(), &info.prefix),
+            vars,
+            env: info.host_env.clone(),
+        }
+    }
+
+    /// Environment exported by upstream tasks (and by every task of an earlier phase, so setup
+    /// exports reach all tasks); later completions win on conflicts
+    async fn inherited_env(&self, task_name: &str) -> Vec<(String, String)> {
+        let ancestors = self.ancestors(task_name);
+        let phase = self.task_phase[task_name];
+        let mut env: Vec<(String, String)> = Vec::new();
+        for (task, vars) in self.exports.lock().await.iter() {
+            if ancestors.contains(task) || self.task_phase[task] < phase {
+                for (k, v) in vars {
+                    env.retain(|(existing, _)| existing != k);
+                    env.push((k.clone(), v.clone()));
+                }
+            }
+        }
+        env
+    }
+}
+
+/// Result of executing one task
+struct TaskOutcome {
+    cmd: String,
+    stdout: String,
+    stderr: String,
+    status: std::process::ExitStatus,
+    started: DateTime<Utc>,
+    duration: Duration,
+    /// Wait for a concurrency slot
+    queued: Duration,
+    /// `when:` was false or the task is `--skip`ped; nothing ran
+    skipped: bool,
+    /// Restored from the cache instead of running
+    cached: bool,
+    /// Filled only when the task was retried
+    attempts: Vec<AttemptRecord>,
+    /// Declared `artifacts:` that were collected
+    artifacts: Vec<ArtifactRecord>,
+    /// `outputs:` values, once the task succeeded
+    named: BTreeMap<String, serde_json::Value>,
+}
+
+fn task_record(
+    ctx: &RunContext,
+    task_name: &str,
+    cmd: &str,
+    status: TaskStatus,
+    exit_code: Option<i32>,
+    started: DateTime<Utc>,
+    duration: Duration,
+) -> TaskRecord {
+    let dir = task_dir(&ctx.run_dir, task_name);
+    TaskRecord {
+        name: task_name.to_string(),
+        phase: ctx.task_phase[task_name],
+        command: cmd.to_string(),
+        backend: ctx.tasks_map.get(task_name).and_then(|t| t.backend.clone()),
+        status,
+        exit_code,
+        started: started.to_rfc3339(),
+        finished: (started + chrono::Duration::from_std(duration).unwrap_or_default()).to_rfc3339(),
+        duration_ms: duration.as_millis() as u64,
+        queue_ms: None,
+        dir: dir.strip_prefix(&ctx.run_dir).unwrap_or(&dir).to_string_lossy().replace('\\', "/"),
+        attempts: Vec::new(),
+        artifacts: Vec::new(),
+    }
+}
+
+/// Move the log `name` a retried attempt streamed from `dir` into `attempt_dir`. Output that was
+/// all kept in memory is rewritten from `content` instead, which also has what the task added
+/// after the command (failed assertions).
+fn keep_log(dir: &Path, attempt_dir: &Path, name: &str, content: &str) -> anyhow::Result<()> {
+    if backends::is_tail(content) && std::fs::rename(dir.join(name), attempt_dir.join(name)).is_ok() {
+        return Ok(());
+    }
+    let _ = std::fs::remove_file(dir.join(name));
+    write_artifact(attempt_dir, name, content)
+}
+
+fn append_log(path: &Path, content: &str) -> anyhow::Result<()> {
+    use std::io::Write;
+    std::fs::OpenOptions::new().create(true).append(true).open(path)?.write_all(content.as_bytes())?;
+    Ok(())
+}
+
+/// Write a task's logs and meta.json and append it to the manifest. A log whose output was cut
+/// to a tail in memory is complete on disk already and left as streamed.
+fn record_task(ctx: &RunContext, manifest: &mut RunManifest, record: TaskRecord, stdout: &str, stderr: &str) -> anyhow::Result<()> {
+    let dir = ctx.run_dir.join(&record.dir);
+    std::fs::create_dir_all(&dir)?;
+    for (name, content) in [("stdout.log", stdout), ("stderr.log", stderr)] {
+        if !backends::is_tail(content) {
+            write_artifact(&dir, name, content)?;
+        }
+    }
+    write_artifact(&dir, "meta.json", &serde_json::to_string_pretty(&record)?)?;
+    events::emit(
+        &ctx.run_dir,
+        RunEvent::TaskFinished {
+            task: record.name.clone(),
+            status: record.status,
+            exit_code: record.exit_code,
+            duration_ms: record.duration_ms,
+        },
+    );
+    manifest.tasks.push(record);
+    save_manifest(manifest, &ctx.run_dir)
+}
+
+/// Call the plugins' `on_task_complete` for a task just recorded in `manifest`
+async fn task_completed(ctx: &RunContext, manifest: &RunManifest, task_name: &str) {
+    if ctx.plugins.is_empty() {
+        return;
+    }
+    let Some(record) = manifest.tasks.iter().rev().find(|t| t.name == task_name) else { return };
+    let completion = TaskCompletion {
+        task: task_name.to_string(),
+        status: record.status,
+        exit_code: record.exit_code,
+        duration_ms: record.duration_ms,
+        output: ctx.outputs.lock().await.get(task_name).cloned().unwrap_or_default(),
+        outputs: ctx.named_outputs.lock().await.get(task_name).cloned().unwrap_or_default(),
+    };
+    for plugin in &ctx.plugins {
+        plugin.on_task_complete(&completion);
+    }
+}
+
+/// Write manifest.json and mirror it into the state database
+fn save_manifest(manifest: &RunManifest, run_dir: &Path) -> anyhow::Result<()> {
+    manifest.save(run_dir)?;
+    // run_dir is <base>/runs/<id>
+    if let Some(base) = run_dir.parent().and_then(Path::parent) {
+        if let Err(e) = state::record(base, manifest) {
+            tracing::warn!("failed to update {}: {:#}", state::STATE_DB, e);
+        }
+    }
+    Ok(())
+}
+
+fn finish_manifest(manifest: &mut RunManifest, run_dir: &Path, status: RunStatus) -> anyhow::Result<()> {
+    manifest.status = status;
+    manifest.finished = Some(Utc::now().to_rfc3339());
+    save_manifest(manifest, run_dir)
+}
+
+/// Run one `on_success` / `on_failure` hook of a task (or of the pipeline when `task` is None),
+/// appending its output to `log`. Failures are only reported.
+async fn run_hook(ctx: Arc<RunContext>, pipeline_idx: usize, hook: HookDef, event: &'static str, task: Option<String>, log: PathBuf) {
+    let info = &ctx.pipelines[pipeline_idx];
+    let owner = task.clone().unwrap_or_else(|| format!("pipeline {}", info.name));
+    let res: anyhow::Result<()> = async {
+        let backend = ctx.backend(info, hook.backend.as_deref())?;
+        let inputs = ctx.interpolation_inputs(info).await;
+        let cmd = interpolate_command(&hook.run, &inputs)?;
+        let mut env: Vec<(String, String)> = vec![
+            ("RUSTYPIPE_HOOK".to_string(), event.to_string()),
+            ("RUSTYPIPE_PIPELINE".to_string(), info.name.clone()),
+            ("RUSTYPIPE_RUN_DIR".to_string(), ctx.run_dir.canonicalize()?.to_string_lossy().to_string()),
+        ];
+        if let Some(t) = &task {
+            env.push(("RUSTYPIPE_TASK".to_string(), t.clone()));
+        }
+        env.extend(info.secret_env.iter().cloned());
+        let _permit = ctx.sem.acquire(0).await;
+        let opts = RunOptions { tty: false, env, ..Default::default() };
+        let (stdout, stderr, status) = backend.run(&cmd, &info.dir, hook.timeout, &opts).await?;
+        if let Some(dir) = log.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut f = std::fs::OpenOptions::new().create(true).append(true).open(&log)?;
+        let text = format!("$ {}\n{}{}", cmd, stdout, stderr);
+        std::io::Write::write_all(&mut f, secrets::mask(&text).as_bytes())?;
+        if !status.success() {
+            anyhow::bail!("exited with code {:?}", status.code());
+        }
+        Ok(())
+    }
+    .await;
+    if let Err(e) = res {
+        note!("{} hook of {} failed: {:#}", event, owner, e);
+    }
+}
+
+/// Spawn a future for a single task; resolves to the task name and its outcome
+async fn spawn_task_future(task_name: String, ctx: Arc<RunContext>) -> (String, anyhow::Result<TaskOutcome>) {
+    let res = run_task(&task_name, ctx).await;
+    (task_name, res)
+}
+
+async fn run_task(task_name: &str, ctx: Arc<RunContext>) -> anyhow::Result<TaskOutcome> {
+    if ctx.given.contains_key(task_name) {
+        return Ok(TaskOutcome {
+            cmd: String::new(),
+            stdout: String::new(),
+            stderr: String::new(),
+            status: util::exit_status(0),
+            started: Utc::now(),
+            duration: Duration::ZERO,
+            queued: Duration::ZERO,
+            skipped: true,
+            cached: false,
+            attempts: Vec::new(),
+            artifacts: Vec::new(),
+            named: BTreeMap::new(),
+        });
+    }
+    let queue_clock = Instant::now();
+    let def = &ctx.tasks_map[task_name];
+    let priority = def.priority.unwrap_or(0);
+    // always mutex, then group, then a run slot, so waiting tasks cannot deadlock or sit on a slot
+    let _mutex = match def.mutex.as_ref().and_then(|m| ctx.mutexes.get(m)) {
+        Some(mutex) => Some(mutex.acquire(priority).await),
+        None => None,
+    };
+    let _group = match def.concurrency_group.as_ref().and_then(|g| ctx.groups.get(g)) {
+        Some(group) => Some(group.acquire(priority).await),
+        None => None,
+    };
+    let _permit = ctx.sem.acquire(priority).await;
+    let queued = queue_clock.elapsed();
+    // queued behind the semaphore while the run was being cancelled
+    if *ctx.cancel.borrow() {
+        return Err(Cancelled { backend: "scheduler".to_string() }.into());
+    }
+    let started = Utc::now();
+    let clock = Instant::now();
+
+    let task_def = ctx.tasks_map.get(task_name).expect("task exists").clone();
+    let retries = task_def.retries.unwrap_or(0);
+    let timeout_secs = task_def.timeout;
+    let info = &ctx.pipelines[ctx.task_pipeline[task_name]];
+    let pipeline_dir = &info.dir;
+
+    let backend = ctx.backend(info, task_def.backend.as_deref())?;
+
+    let inputs = ctx.interpolation_inputs(info).await;
+
+    let interp = |s: &str| interpolate_command(s, &inputs);
+    let mut env = ctx.inherited_env(task_name).await;
+    // task `env:` overrides pipeline `env:`; sorted so backend invocations are stable
+    let mut declared: BTreeMap<&String, &String> = info.env.iter().collect();
+    declared.extend(task_def.env.iter());
+    for (k, v) in declared {
+        env.push((k.clone(), interp(v).with_context(|| format!("env {}", k))?));
+    }
+    env.extend(info.secret_env.iter().cloned());
+
+    if let Some(when) = &task_def.when {
+        let run = check_condition(when, &inputs, &env, None).with_context(|| format!("failed to evaluate `when: {}`", when))?;
+        if !run {
+            return Ok(TaskOutcome {
+                cmd: String::new(),
+                stdout: String::new(),
+                stderr: String::new(),
+                status: util::exit_status(0),
+                started,
+                duration: Duration::ZERO,
+                queued,
+                skipped: true,
+                cached: false,
+                attempts: Vec::new(),
+                artifacts: Vec::new(),
+                named: BTreeMap::new(),
+            });
+        }
+    }
+    if !task_def.tools.is_empty() {
+        env.extend(tools::resolve(&task_def.tools, pipeline_dir).await.context("failed to set up `tools`")?);
+    }
+    let artifacts_dir = task_dir(&ctx.run_dir, task_name).join("artifacts");
+    std::fs::create_dir_all(&artifacts_dir)?;
+    let env_file = ctx.env_file(task_name);
+    std::fs::write(&env_file, "")?;
+    env.push(("RUSTYPIPE_ENV".to_string(), env_file.canonicalize()?.to_string_lossy().to_string()));
+    env.push(("RUSTYPIPE_ARTIFACTS".to_string(), artifacts_dir.canonicalize()?.to_string_lossy().to_string()));
+    let backend_name = task_def.backend.clone().unwrap_or_else(|| "local".to_string());
+    events::emit(&ctx.run_dir, RunEvent::TaskStarted { task: task_name.to_string(), backend: backend_name });
+    for plugin in &ctx.plugins {
+        plugin.on_task_start(&task_def);
+    }
+    // output goes to the task's logs as it arrives; JSON and TUI mode always echo it, so output
+    // lines become events as they happen
+    let dir = task_dir(&ctx.run_dir, task_name);
+    let stream = Some(OutputStream {
+        label: task_name.to_string(),
+        echo: ctx.stream || util::json_output() || util::tui(),
+        stdout_log: Some(dir.join("stdout.log")),
+        stderr_log: Some(dir.join("stderr.log")),
+    });
+    let cancel = ctx.cancel.subscribe();
+    let builtin = builtins::is_builtin(&task_def);
+    let workdir = if builtin { None } else { backend.container_workdir() };
+    let artifact_plan = artifacts::plan(&task_def.artifacts, workdir, &artifacts_dir);
+    let stdin = match &task_def.stdin {
+        None => None,
+        Some(StdinSpec::Text(text)) => Some(interp(text).context("stdin")?.into_bytes()),
+        Some(StdinSpec::File { file }) => {
+            let path = pipeline_dir.join(interp(file).context("stdin")?);
+            Some(std::fs::read(&path).with_context(|| format!("failed to read stdin from {:?}", path))?)
+        }
+        Some(StdinSpec::Task { from_task }) => {
+            let output = inputs.outputs.get(from_task).with_context(|| format!("stdin: task '{}' has no output", from_task))?;
+            Some(output.clone().into_bytes())
+        }
+    };
+    let run_opts = RunOptions {
+        tty: task_def.tty.unwrap_or(false),
+        env,
+        stream,
+        cancel: Some(cancel.clone()),
+        copy_out: artifact_plan.copy_out.clone(),
+        shell: match &task_def.nix {
+            Some(nix) => Some(nix.wrap(task_def.shell.as_ref().unwrap_or(&backend.default_shell()))),
+            None => task_def.shell.clone(),
+        },
+        stdin,
+        artifacts: artifact_plan.host.clone(),
+        pull: task_def.pull,
+        resources: task_def.resources.clone(),
+        gpus: task_def.gpus.clone(),
+        grace_period: task_def.grace_period,
+        no_output_timeout: task_def.no_output_timeout,
+        no_output_action: task_def.no_output_action.unwrap_or_default(),
+    };
+    let (cmd, shown) = if builtin {
+        let d = builtins::describe(&task_def);
+        (d.clone(), d)
+    } else if !task_def.script.is_empty() {
+        let steps = task_def.script.iter().map(|s| interp(s)).collect::<anyhow::Result<Vec<_>>>()?;
+        let shell = task_def.shell.clone().unwrap_or_else(|| backend.default_shell());
+        (shell.script(&steps), steps.join("\n"))
+    } else {
+        let c = interp(&task_def.run)?;
+        (c.clone(), c)
+    };
+
+    let cache_key = task_def.cache_key.as_deref().map(interp).transpose()?;
+    if let Some(hit) = cache_key.as_deref().and_then(|key| cache::lookup(task_name, key)) {
+        hit.restore(&env_file, &artifacts_dir)?;
+        return Ok(TaskOutcome {
+            cmd: secrets::mask(&shown).into_owned(),
+            stdout: hit.stdout,
+            stderr: hit.stderr,
+            status: util::exit_status(hit.exit_code),
+            started,
+            duration: clock.elapsed(),
+            queued,
+            skipped: false,
+            cached: true,
+            attempts: Vec::new(),
+            artifacts: hit.artifacts,
+            named: hit.outputs,
+        });
+    }
+
+    let mut attempts = Vec::new();
+    let mut delay = task_def.retry_delay.unwrap_or(0.0);
+    let mut attempt = 0u32;
+    loop {
+        attempt += 1;
+        let attempt_started = Utc::now();
+        let attempt_clock = Instant::now();
+        let run_result = if builtin {
+            tokio::select! {
+                res = builtins::run(&task_def, pipeline_dir, timeout_secs, &interp) => res,
+                _ = backends::cancelled(Some(&cancel)) => Err(Cancelled { backend: "builtin".to_string() }.into()),
+            }
+        } else {
+            backend.run(&cmd, pipeline_dir, timeout_secs, &run_opts).await
+        };
+        let mut run_result = run_result.map(|(stdout, stderr, status)| (secrets::mask(&stdout).into_owned(), secrets::mask(&stderr).into_owned(), status));
+        // declared outputs and assertions belong to the attempt: when they fail, so does the attempt
+        let mut named = BTreeMap::new();
+        if let Ok((stdout, stderr, status)) = &mut run_result {
+            if status.success() {
+                let this = Attempt { number: attempt, stdout, stderr, exit_code: Some(0), named: &BTreeMap::new() };
+                match finish_attempt(&task_def, &inputs, &run_opts.env, pipeline_dir, this) {
+                    Ok(values) => named = values,
+                    Err(e) => {
+                        stderr.push_str(&format!("{:#}\n", e));
+                        *status = util::exit_status(1);
+                    }
+                }
+            }
+        }
+        let failed = !matches!(&run_result, Ok((_, _, status)) if status.success());
+        let cancelled = run_result.as_ref().is_err_and(|e| e.downcast_ref::<Cancelled>().is_some());
+        if retries > 0 {
+            attempts.push(AttemptRecord {
+                attempt,
+                started: Some(attempt_started.to_rfc3339()),
+                exit_code: run_result.as_ref().ok().and_then(|(_, _, s)| s.code()),
+                error: run_result.as_ref().err().map(|e| format!("{:#}", e)),
+                duration_ms: attempt_clock.elapsed().as_millis() as u64,
+            });
+        }
+        let mut retry = failed && !cancelled && attempt <= retries;
+        if let Some(expr) = task_def.retry_if.as_ref().filter(|_| retry) {
+            let error = run_result.as_ref().err().map(|e| format!("{:#}", e));
+            let (stdout, stderr, exit_code) = match &run_result {
+                Ok((stdout, stderr, status)) => (stdout.as_str(), stderr.as_str(), status.code()),
+                Err(_) => ("", error.as_deref().unwrap_or_default(), None),
+            };
+            let this = Attempt { number: attempt, stdout, stderr, exit_code, named: &named };
+            retry = match check_condition(expr, &inputs, &run_opts.env, Some(&this)) {
+                Ok(retry) => retry,
+                Err(e) => {
+                    note!("Task '{}': failed to evaluate `retry_if: {}`: {:#}", task_name, expr, e);
+                    false
+                }
+            };
+            if !retry {
+                note!("Task '{}' attempt {} failed; not retrying (retry_if: {})", task_name, attempt, expr);
+            }
+        }
+        if !retry {
+            let (stdout, stderr, status) = run_result?;
+            let artifacts = artifacts::collect(&artifact_plan, pipeline_dir, &artifacts_dir).context("failed to collect artifacts")?;
+            if let Some(key) = cache_key.as_deref().filter(|_| status.success()) {
+                let stored = cache::Stored { stdout: &stdout, stderr: &stderr, artifacts: &artifacts, outputs: &named };
+                if let Err(e) = cache::store(task_name, key, &stored, &env_file, &artifacts_dir) {
+                    note!("Task '{}': failed to store its result in the cache: {:#}", task_name, e);
+                }
+            }
+            let cmd = secrets::mask(&shown).into_owned();
+            return Ok(TaskOutcome { cmd, stdout, stderr, status, started, duration: clock.elapsed(), queued, skipped: false, cached: false, attempts, artifacts, named });
+        }
+
+        // keep the failed attempt's output, then back off before the next one
+        let attempt_dir = task_dir(&ctx.run_dir, task_name).join("attempts").join(attempt.to_string());
+        std::fs::create_dir_all(&attempt_dir)?;
+        match &run_result {
+            Ok((stdout, stderr, status)) => {
+                keep_log(&dir, &attempt_dir, "stdout.log", stdout)?;
+                keep_log(&dir, &attempt_dir, "stderr.log", stderr)?;
+                note!("Task '{}' attempt {} exited with code {:?}. Retrying...", task_def.name, attempt, status.code());
+            }
+            Err(e) => {
+                // whatever the stopped command printed, then why it stopped
+                for name in ["stdout.log", "stderr.log"] {
+                    let _ = std::fs::rename(dir.join(name), attempt_dir.join(name));
+                }
+                append_log(&attempt_dir.join("stderr.log"), &format!("{:#}\n", e))?;
+                note!("Task '{}' attempt {} failed: {:?}. Retrying...", task_def.name, attempt, e);
+            }
+        }
+        let wait = if task_def.retry_jitter.unwrap_or(false) { jitter(delay) } else { delay };
+        if wait > 0.0 {
+            tokio::select! {
+                _ = tokio::time::sleep(Duration::from_secs_f64(wait)) => {}
+                _ = backends::cancelled(Some(&cancel)) => return Err(Cancelled { backend: "scheduler".to_string() }.into()),
+            }
+        }
+        delay *= task_def.retry_backoff.unwrap_or(2.0);
+    }
+}
+
+/// The task's own attempt, referenced as `self.*` by `retry_if:` and `assert:`
+struct Attempt<'a> {
+    number: u32,
+    stdout: &'a str,
+    stderr: &'a str,
+    exit_code: Option<i32>,
+    named: &'a BTreeMap<String, serde_json::Value>,
+}
+
+/// Evaluate a condition expression (see `condition`) of a task running with `env`
+fn check_condition(expr: &str, inputs: &TemplateInputs, env: &[(String, String)], attempt: Option<&Attempt>) -> anyhow::Result<bool> {
+    let own;
+    let inputs = match attempt {
+        Some(a) => {
+            let mut with_self = inputs.clone();
+            with_self.outputs.insert("self".to_string(), a.stdout.to_string());
+            with_self.exit_codes.extend(a.exit_code.map(|c| ("self".to_string(), c)));
+            with_self.named.insert("self".to_string(), a.named.clone());
+            own = with_self;
+            &own
+        }
+        None => inputs,
+    };
+    let lookup = |r: &str| -> Option<String> {
+        // anything but a plain reference inside `{{ }}` is a template expression, e.g.
+        // `{{ vars.ENV | lower }}`; so are the typed `{{ task.outputs.NAME }}`
+        if r.contains(|c: char| c.is_whitespace() || "|()+~'\"".contains(c)) || r.contains(".outputs.") {
+            return util::evaluate_expression(r, inputs).ok();
+        }
+        match (r, attempt) {
+            ("self.stderr", Some(a)) => return Some(a.stderr.to_string()),
+            ("self.attempt", Some(a)) => return Some(a.number.to_string()),
+            _ => {}
+        }
+        if let Some(name) = r.strip_prefix("vars.") {
+            return inputs.vars.get(name).cloned();
+        }
+        if let Some(name) = r.strip_prefix("env.") {
+            return env.iter().rev().find(|(k, _)| k == name).map(|(_, v)| v.clone()).or_else(|| inputs.env.get(name).cloned());
+        }
+        if let Some(task) = r.strip_suffix(".output") {
+            return inputs.outputs.get(task).map(|o| o.trim().to_string());
+        }
+        let task = r.strip_suffix(".exit_code")?;
+        inputs.exit_codes.get(task).map(|c| c.to_string())
+    };
+    condition::evaluate(expr, &lookup)
+}
+
+/// Extract the declared `outputs:` of a successful attempt, then check its `assert:`s
+fn finish_attempt(task_def: &TaskDef, inputs: &TemplateInputs, env: &[(String, String)], dir: &Path, attempt: Attempt) -> anyhow::Result<BTreeMap<String, serde_json::Value>> {
+    let named = extract_outputs(&task_def.outputs, attempt.stdout, dir).context("outputs")?;
+    let attempt = Attempt { named: &named, ..attempt };
+    for expr in &task_def.assert {
+        if !check_condition(expr, inputs, env, Some(&attempt)).with_context(|| format!("failed to evaluate `assert: {}`", expr))? {
+            anyhow::bail!("assertion failed: {}", expr);
+        }
+    }
+    Ok(named)
+}
+
+/// A random delay between half and all of `secs`
+fn jitter(secs: f64) -> f64 {
+    let r = (uuid::Uuid::new_v4().as_u128() % 1_000_000) as f64 / 1_000_000.0;
+    secs * (0.5 + r / 2.0)
+}
@@ -0,0 +1,288 @@
+//! `output_filter:` post-processing of a task's stdout before it is stored for `{{task.output}}`.
+//!
+//! Filters run in order, each on the previous result:
+//! ```yaml
+//! output_filter:
+//!   - tail: 1                     # keep the last N lines (`head: N` keeps the first N)
+//!   - regex: 'version (\S+)'      # first capture group, or the whole match without groups
+//!   - json: data.items.0.id       # field path, `/json/pointer` or `$.data.items[0].id`; strings are unquoted
+//! ```
+//! JSONPath (`json_path`) supports `.key`, `['key']`, `[0]`, `[-1]`, `[*]`, `.*` and `..key`;
+//! the templates' `jsonpath` filter uses it too: `{{ build.output | jsonpath('$.artifacts[0].url') }}`.
+//! The raw stdout is still written to the task log.
+//!
+//! `outputs:` declares named, typed values extracted the same way, each from stdout or from a
+//! file the task wrote (relative to the directory it ran in):
+//! ```yaml
+//! outputs:
+//!   version: { regex: 'version (\S+)' }
+//!   sha: { tail: 1 }
+//!   count: { file: out/report.json, json: summary.total, type: number }
+//! ```
+//! Line selection (`head`, `tail`) runs before `regex`, and `regex` before `json`. Dependents
+//! reference the values as `{{ task.outputs.NAME }}`.
+use crate::pipeline::secrets;
+use anyhow::Context;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputFilter {
+    /// Keep the first N lines
+    Head(usize),
+    /// Keep the last N lines
+    Tail(usize),
+    /// Extract the first capture group (or the whole match) of the first match
+    Regex(String),
+    /// Extract a field from JSON output: `a.b.0.c`, a JSON pointer `/a/b/0/c` or a JSONPath `$.a.b[0].c`
+    Json(String),
+}
+
+impl OutputFilter {
+    /// Check the filter can be applied at all (e.g. the regex compiles)
+    pub fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            OutputFilter::Regex(re) => {
+                Regex::new(re).with_context(|| format!("invalid regex '{}'", re))?;
+            }
+            OutputFilter::Json(path) if path.starts_with('$') => {
+                parse_json_path(path)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, input: &str) -> anyhow::Result<String> {
+        match self {
+            OutputFilter::Head(n) => Ok(input.lines().take(*n).collect::<Vec<_>>().join("\n")),
+            OutputFilter::Tail(n) => {
+                let lines: Vec<&str> = input.lines().collect();
+                Ok(lines[lines.len().saturating_sub(*n)..].join("\n"))
+            }
+            OutputFilter::Regex(re) => {
+                let re = Regex::new(re)?;
+                let caps = re.captures(input).with_context(|| format!("regex '{}' did not match", re))?;
+                Ok(caps.get(1).or_else(|| caps.get(0)).map(|m| m.as_str().to_string()).unwrap_or_default())
+            }
+            OutputFilter::Json(path) => {
+                let value: serde_json::Value =
+                    serde_json::from_str(input.trim()).context("output is not valid JSON")?;
+                if path.starts_with('$') {
+                    return match select_json(&value, path)? {
+                        serde_json::Value::String(s) => Ok(s),
+                        other => Ok(other.to_string()),
+                    };
+                }
+                let pointer = if path.starts_with('/') {
+                    path.clone()
+                } else {
+                    path.split('.')
+                        .filter(|s| !s.is_empty())
+                        .map(|s| format!("/{}", s.replace('~', "~0").replace('/', "~1")))
+                        .collect()
+                };
+                match value.pointer(&pointer).with_context(|| format!("JSON field '{}' not found", path))? {
+                    serde_json::Value::String(s) => Ok(s.clone()),
+                    other => Ok(other.to_string()),
+                }
+            }
+        }
+    }
+}
+
+/// One step of a JSONPath
+enum PathStep {
+    Key(String),
+    /// Negative indices count from the end
+    Index(i64),
+    /// `[*]` / `.*`: every element or field
+    Wildcard,
+    /// `..key`: `key` at any depth
+    Descend(String),
+}
+
+fn parse_json_path(path: &str) -> anyhow::Result<Vec<PathStep>> {
+    let invalid = || anyhow::anyhow!("invalid JSONPath '{}'", path);
+    let mut rest = path.trim().strip_prefix('$').ok_or_else(invalid)?;
+    let name_end = |s: &str| s.find(['.', '[']).unwrap_or(s.len());
+    let mut steps = Vec::new();
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix("..") {
+            let end = name_end(r);
+            if end == 0 {
+                return Err(invalid());
+            }
+            steps.push(PathStep::Descend(r[..end].to_string()));
+            rest = &r[end..];
+        } else if let Some(r) = rest.strip_prefix('.') {
+            let end = name_end(r);
+            steps.push(match &r[..end] {
+                "" => return Err(invalid()),
+                "*" => PathStep::Wildcard,
+                key => PathStep::Key(key.to_string()),
+            });
+            rest = &r[end..];
+        } else if let Some(r) = rest.strip_prefix('[') {
+            let end = r.find(']').ok_or_else(invalid)?;
+            let inner = r[..end].trim();
+            let quoted = inner.len() >= 2 && ((inner.starts_with('\'') && inner.ends_with('\'')) || (inner.starts_with('"') && inner.ends_with('"')));
+            steps.push(if inner == "*" {
+                PathStep::Wildcard
+            } else if quoted {
+                PathStep::Key(inner[1..inner.len() - 1].to_string())
+            } else {
+                PathStep::Index(inner.parse().map_err(|_| invalid())?)
+            });
+            rest = &r[end + 1..];
+        } else {
+            return Err(invalid());
+        }
+    }
+    Ok(steps)
+}
+
+fn descend<'a>(value: &'a serde_json::Value, key: &str, out: &mut Vec<&'a serde_json::Value>) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            out.extend(fields.get(key));
+            fields.values().for_each(|v| descend(v, key, out));
+        }
+        serde_json::Value::Array(items) => items.iter().for_each(|v| descend(v, key, out)),
+        _ => {}
+    }
+}
+
+/// Every value `path` (JSONPath, see the module docs) matches in `value`
+pub fn json_path<'a>(value: &'a serde_json::Value, path: &str) -> anyhow::Result<Vec<&'a serde_json::Value>> {
+    let mut current = vec![value];
+    for step in parse_json_path(path)? {
+        let mut next = Vec::new();
+        for v in current {
+            match (&step, v) {
+                (PathStep::Key(key), _) => next.extend(v.get(key)),
+                (PathStep::Index(i), serde_json::Value::Array(items)) => {
+                    let i = if *i < 0 { items.len() as i64 + i } else { *i };
+                    next.extend(usize::try_from(i).ok().and_then(|i| items.get(i)));
+                }
+                (PathStep::Index(_), _) => {}
+                (PathStep::Wildcard, serde_json::Value::Array(items)) => next.extend(items),
+                (PathStep::Wildcard, serde_json::Value::Object(fields)) => next.extend(fields.values()),
+                (PathStep::Wildcard, _) => {}
+                (PathStep::Descend(key), _) => descend(v, key, &mut next),
+            }
+        }
+        current = next;
+    }
+    Ok(current)
+}
+
+/// The value at `path`: a single value, or a list when the path has `*` or `..`
+pub fn select_json(value: &serde_json::Value, path: &str) -> anyhow::Result<serde_json::Value> {
+    let found = json_path(value, path)?;
+    let definite = parse_json_path(path)?.iter().all(|s| matches!(s, PathStep::Key(_) | PathStep::Index(_)));
+    match found.as_slice() {
+        [] if definite => anyhow::bail!("JSONPath '{}' matched nothing", path),
+        [one] if definite => Ok((*one).clone()),
+        many => Ok(many.iter().map(|v| (*v).clone()).collect()),
+    }
+}
+
+/// Run all filters in order
+pub fn apply_filters(filters: &[OutputFilter], input: &str) -> anyhow::Result<String> {
+    let mut out = input.to_string();
+    for f in filters {
+        out = f.apply(&out)?;
+    }
+    Ok(out)
+}
+
+/// Type an `outputs:` value is converted to
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputType {
+    #[default]
+    String,
+    Number,
+    Bool,
+    /// Any JSON value, e.g. a list for `{% for %}`
+    Json,
+}
+
+/// One entry of a task's `outputs:`
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct OutputDef {
+    /// Read this file (relative to the task's directory) instead of stdout
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub head: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tail: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json: Option<String>,
+    #[serde(default, rename = "type", skip_serializing_if = "is_string")]
+    pub kind: OutputType,
+}
+
+fn is_string(kind: &OutputType) -> bool {
+    *kind == OutputType::String
+}
+
+impl OutputDef {
+    /// The extraction steps, in the order they run
+    fn filters(&self) -> Vec<OutputFilter> {
+        let mut filters = Vec::new();
+        filters.extend(self.head.map(OutputFilter::Head));
+        filters.extend(self.tail.map(OutputFilter::Tail));
+        filters.extend(self.regex.clone().map(OutputFilter::Regex));
+        filters.extend(self.json.clone().map(OutputFilter::Json));
+        filters
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.file.as_deref().is_some_and(|f| f.trim().is_empty()) {
+            anyhow::bail!("`file` is empty");
+        }
+        self.filters().iter().try_for_each(OutputFilter::validate)
+    }
+
+    /// The value, from `stdout` or the file below `dir`
+    pub fn extract(&self, stdout: &str, dir: &Path) -> anyhow::Result<serde_json::Value> {
+        let input = match &self.file {
+            Some(file) => {
+                let path = dir.join(file);
+                let content = std::fs::read_to_string(&path).with_context(|| format!("failed to read {:?}", path))?;
+                secrets::mask(&content).into_owned()
+            }
+            None => stdout.to_string(),
+        };
+        let text = apply_filters(&self.filters(), &input)?;
+        let text = text.trim();
+        Ok(match self.kind {
+            OutputType::String => text.into(),
+            OutputType::Number => match text.parse::<i64>() {
+                Ok(n) => n.into(),
+                Err(_) => text.parse::<f64>().ok().and_then(serde_json::Number::from_f64).with_context(|| format!("'{}' is not a number", text))?.into(),
+            },
+            OutputType::Bool => match text.to_ascii_lowercase().as_str() {
+                "true" | "yes" | "1" => true.into(),
+                "false" | "no" | "0" | "" => false.into(),
+                _ => anyhow::bail!("'{}' is not a boolean", text),
+            },
+            OutputType::Json => serde_json::from_str(text).with_context(|| format!("'{}' is not valid JSON", text))?,
+        })
+    }
+}
+
+/// Values of all `outputs:` of a task that ran in `dir`
+pub fn extract_outputs(defs: &BTreeMap<String, OutputDef>, stdout: &str, dir: &Path) -> anyhow::Result<BTreeMap<String, serde_json::Value>> {
+    defs.iter()
+        .map(|(name, def)| Ok((name.clone(), def.extract(stdout, dir).with_context(|| format!("output '{}'", name))?)))
+        .collect()
+}
@@ -0,0 +1,84 @@
+//! `rustypipe graph`: render the task dependency graph as Graphviz DOT or Mermaid.
+//! Edges point from a dependency to its dependent; setup and teardown tasks are grouped in
+//! their own clusters.
+use crate::pipeline::executor::merge_pipelines;
+use crate::pipeline::parser::{Pipeline, TaskDef};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+fn phases(p: &Pipeline) -> [(&'static str, &Vec<TaskDef>); 3] {
+    [("setup", &p.setup), ("tasks", &p.tasks), ("teardown", &p.teardown)]
+}
+
+fn render_dot(p: &Pipeline) -> String {
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+    let mut out = String::new();
+    let _ = writeln!(out, "digraph {} {{", quote(p.name.as_deref().unwrap_or("pipeline")));
+    let _ = writeln!(out, "  rankdir=LR;");
+    let _ = writeln!(out, "  node [shape=box];");
+    let clustered = !p.setup.is_empty() || !p.teardown.is_empty();
+    for (label, tasks) in phases(p).into_iter().filter(|(_, t)| !t.is_empty()) {
+        let indent = if clustered { "    " } else { "  " };
+        if clustered {
+            let _ = writeln!(out, "  subgraph cluster_{} {{", label);
+            let _ = writeln!(out, "    label={};", quote(label));
+        }
+        for t in tasks {
+            let _ = writeln!(out, "{}{};", indent, quote(&t.name));
+        }
+        if clustered {
+            let _ = writeln!(out, "  }}");
+        }
+    }
+    for t in p.all_tasks() {
+        for dep in &t.depends_on {
+            let _ = writeln!(out, "  {} -> {};", quote(dep), quote(&t.name));
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn render_mermaid(p: &Pipeline) -> String {
+    // task names may contain `:`, `.` or `-`, so nodes get generated ids and quoted labels
+    let ids: HashMap<&str, String> = p.all_tasks().enumerate().map(|(i, t)| (t.name.as_str(), format!("t{}", i))).collect();
+    let label = |s: &str| format!("\"{}\"", s.replace('"', "#quot;"));
+    let mut out = String::from("flowchart LR\n");
+    let clustered = !p.setup.is_empty() || !p.teardown.is_empty();
+    for (phase, tasks) in phases(p).into_iter().filter(|(_, t)| !t.is_empty()) {
+        let indent = if clustered { "    " } else { "  " };
+        if clustered {
+            let _ = writeln!(out, "  subgraph {}", phase);
+        }
+        for t in tasks {
+            let _ = writeln!(out, "{}{}[{}]", indent, ids[t.name.as_str()], label(&t.name));
+        }
+        if clustered {
+            let _ = writeln!(out, "  end");
+        }
+    }
+    for t in p.all_tasks() {
+        for dep in &t.depends_on {
+            let _ = writeln!(out, "  {} --> {}", ids[dep.as_str()], ids[t.name.as_str()]);
+        }
+    }
+    out
+}
+
+/// Print the merged graph of `paths` to stdout
+pub fn print_graph(paths: &[PathBuf], format: GraphFormat) -> anyhow::Result<()> {
+    let (pipeline, _, _) = merge_pipelines(paths)?;
+    let text = match format {
+        GraphFormat::Dot => render_dot(&pipeline),
+        GraphFormat::Mermaid => render_mermaid(&pipeline),
+    };
+    print!("{}", text);
+    Ok(())
+}
@@ -0,0 +1,111 @@
+//! `rustypipe runs`: list previous runs, newest first, from `.rustypipe/state.db`.
+use crate::pipeline::manifest::{RunManifest, RunStatus, TaskStatus};
+use crate::pipeline::state;
+use chrono::DateTime;
+use serde::Serialize;
+use std::path::Path;
+
+/// One line of the history
+#[derive(Debug, Serialize)]
+pub struct RunSummary {
+    pub id: String,
+    pub pipeline: Option<String>,
+    pub status: RunStatus,
+    pub started: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub finished: Option<String>,
+    /// Unset while the run is still going (or was killed)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub cancelled: usize,
+}
+
+impl RunSummary {
+    fn from_manifest(m: RunManifest) -> Self {
+        let count = |f: fn(TaskStatus) -> bool| m.tasks.iter().filter(|t| f(t.status)).count();
+        let duration_ms = m.finished.as_deref().and_then(|f| {
+            let (start, end) = (DateTime::parse_from_rfc3339(&m.started).ok()?, DateTime::parse_from_rfc3339(f).ok()?);
+            u64::try_from((end - start).num_milliseconds()).ok()
+        });
+        RunSummary {
+            passed: count(|s| matches!(s, TaskStatus::Succeeded | TaskStatus::CacheHit)),
+            failed: count(|s| matches!(s, TaskStatus::Failed | TaskStatus::Error)),
+            skipped: count(|s| s == TaskStatus::Skipped),
+            cancelled: count(|s| matches!(s, TaskStatus::Cancelled | TaskStatus::NotRun)),
+            id: m.id,
+            pipeline: m.pipeline,
+            status: m.status,
+            started: m.started,
+            finished: m.finished,
+            duration_ms,
+        }
+    }
+}
+
+/// Every run with a readable manifest below `base`, newest first (fallback when the state
+/// database cannot be used)
+fn scan_runs(base: &Path) -> Vec<RunSummary> {
+    let mut runs: Vec<RunSummary> = std::fs::read_dir(base.join("runs"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|e| RunManifest::load(&e.path()).ok())
+        .map(RunSummary::from_manifest)
+        .collect();
+    runs.sort_by(|a, b| b.started.cmp(&a.started));
+    runs
+}
+
+fn format_duration(ms: u64) -> String {
+    match ms {
+        0..=59_999 => format!("{:.1}s", ms as f64 / 1000.0),
+        _ => format!("{}m{:02}s", ms / 60_000, ms / 1000 % 60),
+    }
+}
+
+/// Every run below `base`, newest first
+pub fn list_runs(base: &Path) -> Vec<RunSummary> {
+    match state::import_missing(base).and_then(|_| state::load_runs(base)) {
+        Ok(runs) => runs,
+        Err(e) => {
+            tracing::warn!("state database unavailable ({:#}); reading run directories", e);
+            scan_runs(base)
+        }
+    }
+}
+
+/// Print the history as a table, or as a JSON array with `json`
+pub fn print_runs(limit: Option<usize>, json: bool) -> anyhow::Result<()> {
+    let mut runs = list_runs(Path::new(".rustypipe"));
+    runs.truncate(limit.unwrap_or(usize::MAX));
+    if json {
+        println!("{}", serde_json::to_string_pretty(&runs)?);
+        return Ok(());
+    }
+    if runs.is_empty() {
+        println!("No runs yet");
+        return Ok(());
+    }
+    println!("{:<10} {:<20} {:<20} {:>9}  {:<10} {:>6} {:>6} {:>7}", "RUN", "PIPELINE", "STARTED", "DURATION", "STATUS", "PASSED", "FAILED", "SKIPPED");
+    for r in &runs {
+        let started = DateTime::parse_from_rfc3339(&r.started)
+            .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|_| r.started.clone());
+        let status = format!("{:?}", r.status).to_lowercase();
+        println!(
+            "{:<10} {:<20} {:<20} {:>9}  {:<10} {:>6} {:>6} {:>7}",
+            r.id.chars().take(8).collect::<String>(),
+            r.pipeline.as_deref().unwrap_or("-"),
+            started,
+            r.duration_ms.map(format_duration).unwrap_or_else(|| "-".to_string()),
+            status,
+            r.passed,
+            r.failed,
+            r.skipped + r.cancelled
+        );
+    }
+    Ok(())
+}
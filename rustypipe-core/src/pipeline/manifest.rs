@@ -0,0 +1,277 @@
+//! Run directory layout (v2):
+//! ```text
+//! .rustypipe/runs/<id>/
+//!   manifest.json        run summary, rewritten as tasks finish
+//!   pipeline.yaml        the resolved pipeline that was executed
+//!   run.json             what `rustypipe resume` needs: source files and `--var` overrides
+//!   events.jsonl         progress events (task started/finished, run finished), one per line
+//!   pipelines/<n>.yaml   each source pipeline as resolved when the run started
+//!   tasks/<task>/
+//!     stdout.log         written as the output arrives; only its last MiB is kept in memory
+//!     stderr.log
+//!     meta.json          this task's manifest entry
+//!     env                $RUSTYPIPE_ENV exports written by the task
+//!     artifacts/         $RUSTYPIPE_ARTIFACTS, free for the task to fill; also receives the
+//!                        task's declared `artifacts:` (checksums in meta.json)
+//!     attempts/<n>/      stdout.log / stderr.log of failed attempts that were retried
+//!     on_success.log     output of the task's hooks, if any (or on_failure.log)
+//!   hooks/<pipeline>.on_success.log   pipeline-level hook output (or .on_failure.log)
+//! ```
+//! Tools should read `manifest.json` rather than scanning the directory.
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const MANIFEST_VERSION: u32 = 2;
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Succeeded,
+    Failed,
+    /// The task could not be executed at all (backend error, timeout, ...)
+    Error,
+    /// Not run: `when:` was false (dependents still run) or an upstream task failed
+    Skipped,
+    /// Not run: the result was restored from the cache (`cache_key`)
+    CacheHit,
+    /// Killed while running because the run was aborted (`stop_on_fail`, Ctrl+C)
+    Cancelled,
+    /// Never started because the run was stopped first (pipeline `timeout`, Ctrl+C)
+    NotRun,
+}
+
+/// `manifest.json` at the root of a run directory
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RunManifest {
+    pub version: u32,
+    pub id: String,
+    #[serde(default)]
+    pub pipeline: Option<String>,
+    pub started: String,
+    #[serde(default)]
+    pub finished: Option<String>,
+    pub status: RunStatus,
+    #[serde(default)]
+    pub tasks: Vec<TaskRecord>,
+}
+
+/// `run.json`: enough to rebuild a run for `rustypipe resume`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RunSpec {
+    pub sources: Vec<RunSource>,
+    /// `--var` overrides of the original invocation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub vars: Vec<(String, String)>,
+    /// `--task` selection of the original invocation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tasks: Vec<String>,
+    /// `--skip` and `--skip-from` of the original invocation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skip: Vec<(String, Option<String>)>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skip_from: Option<String>,
+}
+
+/// One pipeline file of a run
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct RunSource {
+    /// The file as given on the command line; tasks run in its directory
+    pub path: PathBuf,
+    /// Its resolved definition, relative to the run directory
+    pub file: String,
+}
+
+impl RunSpec {
+    pub const FILE: &'static str = "run.json";
+
+    pub fn load(run_dir: &Path) -> anyhow::Result<Self> {
+        let path = run_dir.join(Self::FILE);
+        let content = std::fs::read_to_string(&path).with_context(|| format!("failed to read {:?}", path))?;
+        serde_json::from_str(&content).with_context(|| format!("failed to parse {:?}", path))
+    }
+
+    pub fn save(&self, run_dir: &Path) -> anyhow::Result<()> {
+        std::fs::write(run_dir.join(Self::FILE), serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Pipeline section a task belongs to
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Setup,
+    #[default]
+    Main,
+    Teardown,
+}
+
+/// One executed task; also written as the task's `meta.json`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct TaskRecord {
+    pub name: String,
+    #[serde(default)]
+    pub phase: Phase,
+    pub command: String,
+    /// `backend:` of the task; unset for the local shell
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    pub status: TaskStatus,
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    pub started: String,
+    pub finished: String,
+    pub duration_ms: u64,
+    /// Time spent waiting for a concurrency slot once the task's dependencies were done
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queue_ms: Option<u64>,
+    /// Task directory relative to the run directory
+    pub dir: String,
+    /// Every attempt when the task was retried; logs of earlier attempts are in `attempts/<n>/`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<AttemptRecord>,
+    /// Files collected from the task's `artifacts:`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<ArtifactRecord>,
+}
+
+/// One collected artifact file
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ArtifactRecord {
+    /// Path below the task's `artifacts/` directory
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// One try of a retried task
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AttemptRecord {
+    pub attempt: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started: Option<String>,
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// Set when the attempt could not be executed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub duration_ms: u64,
+}
+
+impl RunManifest {
+    pub fn new(run_dir: &Path, pipeline: Option<String>) -> Self {
+        RunManifest {
+            version: MANIFEST_VERSION,
+            id: run_dir.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            pipeline,
+            started: chrono::Utc::now().to_rfc3339(),
+            finished: None,
+            status: RunStatus::Running,
+            tasks: Vec::new(),
+        }
+    }
+
+    pub fn load(run_dir: &Path) -> anyhow::Result<Self> {
+        let path = run_dir.join(MANIFEST_FILE);
+        let content = std::fs::read_to_string(&path).with_context(|| format!("failed to read {:?}", path))?;
+        let m: RunManifest = serde_json::from_str(&content).with_context(|| format!("failed to parse {:?}", path))?;
+        if m.version != MANIFEST_VERSION {
+            anyhow::bail!("{:?} has manifest version {}, expected {}", path, m.version, MANIFEST_VERSION);
+        }
+        Ok(m)
+    }
+
+    /// Write atomically so readers never see a half-written manifest
+    pub fn save(&self, run_dir: &Path) -> anyhow::Result<()> {
+        let tmp = run_dir.join(format!("{}.tmp", MANIFEST_FILE));
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&tmp, run_dir.join(MANIFEST_FILE))?;
+        Ok(())
+    }
+}
+
+/// Directory holding one task's logs and artifacts
+pub fn task_dir(run_dir: &Path, task_name: &str) -> PathBuf {
+    run_dir.join("tasks").join(sanitize_filename(task_name))
+}
+
+/// Escape illegal Windows filename characters (and `%` itself) as `%XX`, so different names
+/// never share a file: `ci:build` becomes `ci%3Abuild`, `ci_build` stays as it is
+pub fn sanitize_filename(name: &str) -> String {
+    let illegal = ['<','>','/','\\','|','?','*',':','"','%'];
+    name.chars()
+        .map(|c| if illegal.contains(&c) || c.is_ascii_control() { format!("%{:02X}", c as u32) } else { c.to_string() })
+        .collect()
+}
+
+/// Find a run directory by id (or unique id prefix); `latest` or `None` picks the most recent run
+pub fn resolve_run(base: &Path, id: Option<&str>) -> anyhow::Result<PathBuf> {
+    let runs = base.join("runs");
+    let mut candidates: Vec<(String, PathBuf)> = Vec::new();
+    if let Ok(entries) = std::fs::read_dir(&runs) {
+        for entry in entries.flatten() {
+            let dir = entry.path();
+            if let Ok(m) = RunManifest::load(&dir) {
+                candidates.push((m.started, dir));
+            }
+        }
+    }
+    match id {
+        None | Some("latest") => candidates
+            .into_iter()
+            .max_by(|a, b| a.0.cmp(&b.0))
+            .map(|(_, d)| d)
+            .with_context(|| format!("no runs found in {:?}", runs)),
+        Some(id) => {
+            let matches: Vec<PathBuf> = candidates
+                .into_iter()
+                .map(|(_, d)| d)
+                .filter(|d| d.file_name().is_some_and(|n| n.to_string_lossy().starts_with(id)))
+                .collect();
+            match matches.len() {
+                0 => anyhow::bail!("no run matching '{}'", id),
+                1 => Ok(matches.into_iter().next().unwrap_or_default()),
+                n => anyhow::bail!("'{}' is ambiguous ({} runs match)", id, n),
+            }
+        }
+    }
+}
+
+/// `rustypipe logs [run-id] [task]`: print captured output from a run's manifest
+pub fn print_logs(run: Option<&str>, task: Option<&str>) -> anyhow::Result<()> {
+    let run_dir = resolve_run(Path::new(".rustypipe"), run)?;
+    let manifest = RunManifest::load(&run_dir)?;
+    println!("Run {} ({:?}, {:?})", manifest.id, manifest.pipeline.unwrap_or_default(), manifest.status);
+    let mut shown = 0;
+    for t in manifest.tasks.iter().filter(|t| task.is_none_or(|n| t.name == n)) {
+        shown += 1;
+        let dir = run_dir.join(&t.dir);
+        println!();
+        println!("== {} ({:?}, exit {:?}, {} ms)", t.name, t.status, t.exit_code, t.duration_ms);
+        println!("$ {}", t.command);
+        for (label, file) in [("stdout", "stdout.log"), ("stderr", "stderr.log")] {
+            let content = std::fs::read_to_string(dir.join(file)).unwrap_or_default();
+            if !content.trim().is_empty() {
+                println!("-- {}", label);
+                println!("{}", content.trim_end());
+            }
+        }
+    }
+    if let Some(name) = task {
+        if shown == 0 {
+            anyhow::bail!("task '{}' did not run in {}", name, manifest.id);
+        }
+    }
+    Ok(())
+}
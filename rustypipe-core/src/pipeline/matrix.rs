@@ -0,0 +1,126 @@
+//! `matrix:` on a task expands it at load time into one task per combination of values:
+//! ```yaml
+//! - name: test
+//!   matrix:
+//!     os: [linux, windows]
+//!     toolchain: ["1.70", "1.75"]
+//!   run: cargo +{{matrix.toolchain}} test --target {{matrix.os}}
+//! ```
+//! becomes `test[linux,1.70]`, `test[linux,1.75]`, ... (values in key order). Dependents of `test`
+//! wait for every instance, except that a matrix dependent sharing keys with its dependency only
+//! waits for the instances whose shared values match (`package[linux,...]` -> `test[linux,...]`).
+use crate::pipeline::parser::{Pipeline, TaskDef};
+use crate::pipeline::steps::substitute;
+use anyhow::Context;
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashMap};
+
+/// Matrix values may be written as any scalar; they are used as strings.
+/// Quote versions like `"1.70"`, otherwise YAML reads them as the number 1.7.
+pub fn deserialize_matrix<'de, D>(d: D) -> Result<BTreeMap<String, Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Scalar {
+        Str(String),
+        Int(i64),
+        Float(f64),
+        Bool(bool),
+    }
+    let raw = BTreeMap::<String, Vec<Scalar>>::deserialize(d)?;
+    Ok(raw
+        .into_iter()
+        .map(|(k, vs)| {
+            let vs = vs
+                .into_iter()
+                .map(|v| match v {
+                    Scalar::Str(s) => s,
+                    Scalar::Int(i) => i.to_string(),
+                    Scalar::Float(f) => f.to_string(),
+                    Scalar::Bool(b) => b.to_string(),
+                })
+                .collect();
+            (k, vs)
+        })
+        .collect())
+}
+
+/// One (key, value) pair per matrix key
+type Combination = Vec<(String, String)>;
+
+/// Every combination of the matrix values, keys in sorted order
+fn combinations(matrix: &BTreeMap<String, Vec<String>>) -> Vec<Combination> {
+    let mut out: Vec<Combination> = vec![Vec::new()];
+    for (k, values) in matrix {
+        out = out
+            .into_iter()
+            .flat_map(|combo| {
+                values.iter().map(move |v| {
+                    let mut c = combo.clone();
+                    c.push((k.clone(), v.clone()));
+                    c
+                })
+            })
+            .collect();
+    }
+    out
+}
+
+/// Expand matrix tasks in setup, tasks and teardown
+pub fn expand_matrix(p: &mut Pipeline) -> anyhow::Result<()> {
+    for tasks in [&mut p.setup, &mut p.tasks, &mut p.teardown] {
+        if tasks.iter().any(|t| !t.matrix.is_empty()) {
+            *tasks = expand_list(std::mem::take(tasks))?;
+        }
+    }
+    Ok(())
+}
+
+fn expand_list(tasks: Vec<TaskDef>) -> anyhow::Result<Vec<TaskDef>> {
+    // original name -> its instances with their matrix values
+    let mut instances: HashMap<String, Vec<(String, Combination)>> = HashMap::new();
+    // (task, values) per expanded task, in declaration order
+    let mut expanded: Vec<(TaskDef, Combination)> = Vec::new();
+    for t in tasks {
+        if t.matrix.is_empty() {
+            expanded.push((t, Vec::new()));
+            continue;
+        }
+        if let Some((k, _)) = t.matrix.iter().find(|(_, v)| v.is_empty()) {
+            anyhow::bail!("task '{}': matrix key '{}' has no values", t.name, k);
+        }
+        let mut list = Vec::new();
+        for combo in combinations(&t.matrix) {
+            let values: HashMap<String, String> = combo.iter().cloned().collect();
+            let mut inst = substitute(&t, "matrix", &values).with_context(|| format!("task '{}': failed to expand matrix", t.name))?;
+            inst.matrix.clear();
+            inst.name = format!("{}[{}]", t.name, combo.iter().map(|(_, v)| v.as_str()).collect::<Vec<_>>().join(","));
+            list.push((inst.name.clone(), combo.clone()));
+            expanded.push((inst, combo));
+        }
+        instances.insert(t.name.clone(), list);
+    }
+
+    Ok(expanded
+        .into_iter()
+        .map(|(mut t, values)| {
+            t.depends_on = t
+                .depends_on
+                .iter()
+                .flat_map(|d| match instances.get(d) {
+                    None => vec![d.clone()],
+                    Some(list) => list
+                        .iter()
+                        .filter(|(_, dep_values)| {
+                            dep_values.iter().all(|(k, v)| values.iter().all(|(k2, v2)| k != k2 || v == v2))
+                        })
+                        .map(|(n, _)| n.clone())
+                        .collect(),
+                })
+                .collect();
+            t
+        })
+        .collect())
+}
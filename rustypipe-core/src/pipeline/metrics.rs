@@ -0,0 +1,87 @@
+//! Prometheus metrics of a run, in the text exposition format. `rustypipe run --pushgateway <url>`
+//! pushes them to a Pushgateway once the run has finished, grouped by `job="rustypipe"` and the
+//! pipeline name, so every push replaces the previous run's values of that pipeline:
+//! - `rustypipe_run_success`, `rustypipe_run_duration_seconds`, `rustypipe_run_last_timestamp_seconds`
+//! - `rustypipe_tasks{status}`: number of tasks per status
+//! - `rustypipe_task_success{task}`, `rustypipe_task_duration_seconds{task}`,
+//!   `rustypipe_task_retries{task}`, `rustypipe_task_queue_wait_seconds{task}`
+use crate::pipeline::manifest::{RunManifest, RunStatus, TaskStatus};
+use crate::pipeline::state::enum_name;
+use base64::Engine;
+use std::fmt::Write as _;
+
+/// Escape a label value (`\`, `"` and newlines)
+fn label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn family(out: &mut String, name: &str, help: &str, samples: &[(String, f64)]) {
+    if samples.is_empty() {
+        return;
+    }
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} gauge", name);
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{}{} {}", name, labels, value);
+    }
+}
+
+/// The metrics of a run
+pub fn render(manifest: &RunManifest) -> String {
+    let seconds = |ms: u64| ms as f64 / 1000.0;
+    let timestamp = |s: &str| chrono::DateTime::parse_from_rfc3339(s).map(|d| d.timestamp_millis()).ok();
+    let started = timestamp(&manifest.started);
+    let finished = manifest.finished.as_deref().and_then(timestamp);
+    let task_labels = |task: &str| format!("{{task=\"{}\"}}", label(task));
+    let mut out = String::new();
+
+    family(&mut out, "rustypipe_run_success", "Whether the last run succeeded", &[(String::new(), f64::from(u8::from(manifest.status == RunStatus::Succeeded)))]);
+    if let (Some(s), Some(f)) = (started, finished) {
+        family(&mut out, "rustypipe_run_duration_seconds", "Wall-clock duration of the last run", &[(String::new(), (f - s) as f64 / 1000.0)]);
+        family(&mut out, "rustypipe_run_last_timestamp_seconds", "When the last run finished", &[(String::new(), f as f64 / 1000.0)]);
+    }
+    let statuses = [TaskStatus::Succeeded, TaskStatus::CacheHit, TaskStatus::Failed, TaskStatus::Error, TaskStatus::Skipped, TaskStatus::Cancelled, TaskStatus::NotRun];
+    let counts: Vec<(String, f64)> = statuses
+        .iter()
+        .map(|&s| (format!("{{status=\"{}\"}}", enum_name(s)), manifest.tasks.iter().filter(|t| t.status == s).count() as f64))
+        .collect();
+    family(&mut out, "rustypipe_tasks", "Tasks of the last run per status", &counts);
+
+    let ran: Vec<_> = manifest.tasks.iter().filter(|t| !matches!(t.status, TaskStatus::Skipped | TaskStatus::Cancelled | TaskStatus::NotRun)).collect();
+    let success: Vec<_> = ran
+        .iter()
+        .map(|t| (task_labels(&t.name), f64::from(u8::from(matches!(t.status, TaskStatus::Succeeded | TaskStatus::CacheHit)))))
+        .collect();
+    family(&mut out, "rustypipe_task_success", "Whether the task succeeded in the last run", &success);
+    let durations: Vec<_> = ran.iter().map(|t| (task_labels(&t.name), seconds(t.duration_ms))).collect();
+    family(&mut out, "rustypipe_task_duration_seconds", "Task duration in the last run, retries included", &durations);
+    let retries: Vec<_> = ran.iter().map(|t| (task_labels(&t.name), t.attempts.len().saturating_sub(1) as f64)).collect();
+    family(&mut out, "rustypipe_task_retries", "Retries the task needed in the last run", &retries);
+    let waits: Vec<_> = ran.iter().filter_map(|t| Some((task_labels(&t.name), seconds(t.queue_ms?)))).collect();
+    family(&mut out, "rustypipe_task_queue_wait_seconds", "Time the task waited for a concurrency slot in the last run", &waits);
+    out
+}
+
+/// Replace the metrics of this pipeline on the Pushgateway at `url`
+pub async fn push(url: &str, manifest: &RunManifest) -> anyhow::Result<()> {
+    let pipeline = manifest.pipeline.as_deref().unwrap_or("rustypipe");
+    // grouping key values may not contain `/` unless base64-encoded
+    let group = if pipeline.contains('/') {
+        format!("pipeline@base64/{}", base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(pipeline))
+    } else {
+        format!("pipeline/{}", pipeline)
+    };
+    let endpoint = format!("{}/metrics/job/rustypipe/{}", url.trim_end_matches('/'), group);
+    let resp = reqwest::Client::new()
+        .put(&endpoint)
+        .header("content-type", "text/plain; version=0.0.4")
+        .body(render(manifest))
+        .send()
+        .await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("pushgateway responded {}: {}", status, body.trim());
+    }
+    Ok(())
+}
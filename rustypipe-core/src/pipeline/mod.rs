@@ -0,0 +1,52 @@
+/// `println!` for human-readable run output: moves to stderr when stdout carries JSON events
+/// (`--log-format json`) and to the TUI or progress display when one owns the terminal
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if $crate::util::console_observed() {
+            $crate::pipeline::events::message(format!($($arg)*), false)
+        } else if $crate::util::json_output() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+/// `eprintln!` counterpart of `say!`
+macro_rules! note {
+    ($($arg:tt)*) => {
+        if $crate::util::console_observed() {
+            $crate::pipeline::events::message(format!($($arg)*), true)
+        } else {
+            eprintln!($($arg)*)
+        }
+    };
+}
+
+pub mod parser;
+pub mod executor;
+pub mod steps;
+pub mod matrix;
+pub mod templates;
+pub mod condition;
+pub mod filters;
+pub mod manifest;
+pub mod history;
+pub mod events;
+pub mod state;
+pub mod compare;
+pub mod cache;
+pub mod artifacts;
+pub mod storage;
+pub mod secrets;
+pub mod workspace;
+pub mod plan;
+pub mod graph;
+pub mod report;
+pub mod metrics;
+pub mod telemetry;
+pub mod permits;
+pub mod tools;
+
+pub use executor::{resume_run, run_pipeline, run_pipelines, RunConfig, RunFailed, validate_pipeline_files};
+pub use parser::convert_pipeline_file;
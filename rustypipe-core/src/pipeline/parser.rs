@@ -0,0 +1,1244 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use anyhow::Context;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use crate::pipeline::steps::expand_uses;
+use crate::pipeline::templates::expand_templates;
+use crate::pipeline::matrix::{deserialize_matrix, expand_matrix};
+use crate::pipeline::{condition, secrets, storage};
+use crate::{plugins, util};
+use crate::backends::{deserialize_quantity, NoOutputAction, PullPolicy, Resources, ShellSpec};
+use crate::pipeline::filters::{OutputDef, OutputFilter};
+use crate::pipeline::tools::deserialize_versions;
+use crate::pipeline::workspace::WorkspaceMode;
+
+/// Pipeline and TaskDef with Serialize + Deserialize so we can read & write YAML
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct Pipeline {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+    /// Most tasks of each `concurrency_group` running at once, e.g. `{deploy: 2}`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub concurrency_groups: BTreeMap<String, usize>,
+    /// Seconds setup and tasks may take in total; then running tasks are cancelled and the rest
+    /// is not run (teardown still runs)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_on_fail: Option<bool>,
+    /// Cron expression (`"0 3 * * *"`, local time) on which `rustypipe daemon` runs the pipeline
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+    /// Cron expressions per task tag, e.g. `{nightly: "0 3 * * *"}`: `rustypipe daemon` runs the
+    /// tasks carrying the tag (and what they depend on) on their own schedule
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub schedules: BTreeMap<String, String>,
+    /// Git URL template for `uses:` steps; `{org}` and `{name}` are substituted
+    /// (default `https://github.com/{org}/{name}.git`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub step_registry: Option<String>,
+    /// Values for `{{vars.NAME}}`; `run --var NAME=value` overrides them. Values may use `{{env.NAME}}`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub vars: HashMap<String, String>,
+    /// Host environment variables visible as `{{env.NAME}}`: names or globs such as `CI_*`
+    /// (default: all of them)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env_allowlist: Option<Vec<String>>,
+    /// Environment variables exported to every task; values are interpolated like `run`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    /// Environment variables whose values are masked in all output (see `pipeline::secrets`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub secrets: BTreeMap<String, SecretDef>,
+    /// Plugins preprocessing the tasks when the pipeline is loaded (see `plugins`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub plugins: BTreeMap<String, PluginDef>,
+    /// Where plugins are looked up, relative to the pipeline file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub plugin_dir: Option<String>,
+    /// Named backend instances tasks select with `backend: <name>` (see `BackendDef`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty", deserialize_with = "deserialize_backends")]
+    pub backends: BTreeMap<String, BackendDef>,
+    /// Default `shell` of tasks and hooks running on the local backend
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<ShellSpec>,
+    /// Pull the images of the docker and kubernetes backends in use, in parallel, before the first
+    /// task starts (default true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prepull: Option<bool>,
+    /// `isolated` runs tasks in a per-run copy of the pipeline directory (default `shared`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<WorkspaceMode>,
+    /// Globs copied back from an isolated workspace into the pipeline directory after the run
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub collect: Vec<String>,
+    /// Where logs and artifacts of finished runs are pushed (in addition to the run directory)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_store: Option<ArtifactStoreConfig>,
+    /// Pipeline fragments merged into this one at load time (see `resolve_includes`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Partial task bodies that tasks pull in with `extends:` (see `pipeline::templates`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub templates: BTreeMap<String, serde_yaml::Value>,
+    /// Tasks run before `tasks`; if one fails, `tasks` are skipped (teardown still runs)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub setup: Vec<TaskDef>,
+    #[serde(default)]
+    pub tasks: Vec<TaskDef>,
+    /// Tasks that always run last, even after failures or cancellation; failures are reported separately
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub teardown: Vec<TaskDef>,
+    /// Run after the whole run succeeded
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_success: Vec<HookDef>,
+    /// Run after the run failed or was cancelled
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_failure: Vec<HookDef>,
+}
+
+impl Pipeline {
+    /// An empty pipeline, for building one in code
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: Some(name.into()), ..Default::default() }
+    }
+
+    pub fn with_task(mut self, task: TaskDef) -> Self {
+        self.tasks.push(task);
+        self
+    }
+
+    pub fn with_setup(mut self, task: TaskDef) -> Self {
+        self.setup.push(task);
+        self
+    }
+
+    pub fn with_teardown(mut self, task: TaskDef) -> Self {
+        self.teardown.push(task);
+        self
+    }
+
+    pub fn with_var(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.vars.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn with_env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = Some(concurrency);
+        self
+    }
+
+    /// Setup, main and teardown tasks, in phase order
+    pub fn all_tasks(&self) -> impl Iterator<Item = &TaskDef> {
+        self.setup.iter().chain(&self.tasks).chain(&self.teardown)
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct TaskDef {
+    pub name: String,
+    /// Template from `templates:` this task is merged over
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+    /// Shell command; may be empty when the task uses a built-in kind (e.g. `upload`)
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub run: String,
+    /// Commands run one after another in the same shell session; the first failing one fails the task
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub script: Vec<String>,
+    /// Extra attempts when the task fails (non-zero exit) or cannot be executed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// Seconds to wait before the first retry (default 0)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_delay: Option<f64>,
+    /// Factor the delay grows by after every retry (default 2)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_backoff: Option<f64>,
+    /// Randomize each delay between half and the full value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_jitter: Option<bool>,
+    /// Retry a failed attempt only if this expression is true, e.g. `"contains(self.stderr, 'timeout')"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_if: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>, // seconds
+    /// Seconds a timed-out or cancelled command gets to exit after SIGTERM before SIGKILL (default 10)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grace_period: Option<u64>,
+    /// Seconds the command may go without printing anything (stdout or stderr) before
+    /// `no_output_action` applies, e.g. to catch a hung download under a long `timeout`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_output_timeout: Option<u64>,
+    /// `fail` (default) stops a silent command like a timeout; `warn` only logs a warning
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_output_action: Option<NoOutputAction>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    /// Tasks with a higher priority get a free concurrency slot first (default 0, may be negative)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    /// Tasks sharing a mutex name never run at the same time, whatever their dependencies allow
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mutex: Option<String>,
+    /// Group from `concurrency_groups:` limiting how many of its tasks run at once
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency_group: Option<String>,
+    /// Labels for the pipeline's `schedules:`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Cron expression on which `rustypipe daemon` runs this task (and what it depends on) on its
+    /// own; the pipeline's `schedule:` then leaves it out
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<String>,
+    /// Expand into one task per combination of values; `{{matrix.KEY}}` is substituted
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty", deserialize_with = "deserialize_matrix")]
+    pub matrix: BTreeMap<String, Vec<String>>,
+    /// Run only if this expression is true, e.g. `"{{build.exit_code}} == 0 && vars.ENV == 'prod'"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
+    /// Expressions a successful attempt must satisfy, e.g. `"{{self.outputs.count}} > 0"`; otherwise it fails
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub assert: Vec<String>,
+    /// Environment variables for this task, overriding pipeline-level `env`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    /// Reuse the stored result of an earlier run with the same (interpolated) key; see `pipeline::cache`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_key: Option<String>,
+    /// Run dependents even if this task fails (otherwise they are skipped); the run still fails
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub continue_on_fail: Option<bool>,
+    /// Files or globs (relative to the task's directory) copied into the run directory afterwards
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<String>,
+    /// Post-processing applied to stdout before it is stored for interpolation
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "serde_yaml::with::singleton_map_recursive")]
+    pub output_filter: Vec<OutputFilter>,
+    /// Named, typed values extracted from stdout or files, for `{{task.outputs.NAME}}`; see `pipeline::filters`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub outputs: BTreeMap<String, OutputDef>,
+    /// Run after this task succeeded; not part of the DAG
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_success: Vec<HookDef>,
+    /// Run after this task failed; not part of the DAG
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_failure: Vec<HookDef>,
+    /// Allocate a pseudo-terminal for the command (local and docker backends)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tty: Option<bool>,
+    /// Standard input of the command: literal text, `{ file: path }` or `{ from_task: name }`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stdin: Option<StdinSpec>,
+    /// Interpreter of `run` / `script`: `bash`, `sh`, `pwsh`, `cmd`, `python`, ... or an argv list
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<ShellSpec>,
+    /// Image pull policy on a docker or kubernetes backend, overriding the backend's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pull: Option<PullPolicy>,
+    /// CPU and memory limits on a docker, kubernetes, ecs, cloudrun or aci backend, e.g. `{cpus: 2, memory: 4Gi}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resources: Option<Resources>,
+    /// GPUs on a docker (`--gpus`) or kubernetes (`nvidia.com/gpu`) backend: a count, or `all` on docker
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_quantity")]
+    pub gpus: Option<String>,
+    /// Run the command inside `nix shell` with the listed packages or in a flake's `nix develop` shell
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nix: Option<NixSpec>,
+    /// Toolchain versions resolved through mise or asdf on the local backend, e.g. `{node: 20, terraform: "1.7"}`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty", deserialize_with = "deserialize_versions")]
+    pub tools: BTreeMap<String, String>,
+    /// Built-in artifact upload executed by the runner instead of a shell command
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload: Option<UploadSpec>,
+    /// Built-in HTTP request executed by the runner; the response body becomes the task output
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http: Option<HttpSpec>,
+    /// Built-in portable file operations, run in order
+    #[serde(default, skip_serializing_if = "Option::is_none", with = "serde_yaml::with::singleton_map_recursive")]
+    pub files: Option<Vec<FileOp>>,
+    /// Built-in git operation (clone, checkout, fetch, tag, push)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git: Option<GitSpec>,
+    /// Reusable step reference (`org/step@ref` or a local `./path`), expanded into tasks at load time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uses: Option<String>,
+    /// Inputs passed to the `uses:` step
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub with: HashMap<String, String>,
+}
+
+impl TaskDef {
+    /// A task running the shell command `run`
+    pub fn new(name: impl Into<String>, run: impl Into<String>) -> Self {
+        Self { name: name.into(), run: run.into(), ..Default::default() }
+    }
+
+    pub fn with_depends_on(mut self, task: impl Into<String>) -> Self {
+        self.depends_on.push(task.into());
+        self
+    }
+
+    pub fn with_env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.insert(name.into(), value.into());
+        self
+    }
+
+    pub fn with_backend(mut self, backend: impl Into<String>) -> Self {
+        self.backend = Some(backend.into());
+        self
+    }
+
+    pub fn with_timeout(mut self, secs: u64) -> Self {
+        self.timeout = Some(secs);
+        self
+    }
+
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retries = Some(retries);
+        self
+    }
+
+    pub fn with_when(mut self, when: impl Into<String>) -> Self {
+        self.when = Some(when.into());
+        self
+    }
+
+    /// Names of the task kinds this task declares (`run`, `upload`, ...); exactly one is valid
+    pub fn kinds(&self) -> Vec<&'static str> {
+        let mut kinds = Vec::new();
+        if !self.run.is_empty() {
+            kinds.push("run");
+        }
+        if !self.script.is_empty() {
+            kinds.push("script");
+        }
+        if self.upload.is_some() {
+            kinds.push("upload");
+        }
+        if self.http.is_some() {
+            kinds.push("http");
+        }
+        if self.files.is_some() {
+            kinds.push("files");
+        }
+        if self.git.is_some() {
+            kinds.push("git");
+        }
+        if self.uses.is_some() {
+            kinds.push("uses");
+        }
+        kinds
+    }
+}
+
+/// `stdin:` of a task
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum StdinSpec {
+    /// Literal text, interpolated like `run`
+    Text(String),
+    /// File contents; the path is relative to the task's directory
+    File { file: String },
+    /// Output of a task this one depends on
+    Task { from_task: String },
+}
+
+/// `http:` task body: a single HTTP request
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct HttpSpec {
+    /// HTTP method (default GET)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub headers: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Accepted status codes; any 2xx when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub expect_status: Vec<u16>,
+}
+
+/// One step of a `files:` task; paths are relative to the pipeline directory
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum FileOp {
+    /// Copy a file or directory (recursively)
+    Copy { from: String, to: String },
+    /// Move/rename a file or directory
+    Move { from: String, to: String },
+    /// Remove a file or directory; missing paths are ignored
+    Delete(String),
+    /// Create a directory and its parents
+    Mkdir(String),
+    /// Render a file through `{{...}}` interpolation
+    Template { from: String, to: String },
+}
+
+/// Operation performed by a `git:` task
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GitAction {
+    Clone,
+    Checkout,
+    Fetch,
+    Tag,
+    Push,
+}
+
+/// `git:` task body
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct GitSpec {
+    pub action: GitAction,
+    /// Repository URL (clone) or remote name/URL (fetch, push); defaults to `origin`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    /// Local checkout directory relative to the pipeline directory (default ".")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Branch, tag, commit or refspec to check out / fetch / push
+    #[serde(default, rename = "ref", skip_serializing_if = "Option::is_none")]
+    pub git_ref: Option<String>,
+    /// Shallow clone/fetch depth
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub depth: Option<i32>,
+    /// Tag name for `tag`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Annotation message for `tag` (lightweight tag when absent)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Environment variable holding an HTTPS token/password
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_env: Option<String>,
+    /// Username sent with the token (default `x-access-token`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+}
+
+/// `on_success` / `on_failure` command of a task or pipeline (cleanup, notifications, ...).
+/// Hooks run outside the DAG; their failures are reported but do not change any status.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct HookDef {
+    pub run: String,
+    /// Backend executing the hook (default `local`, independent of the task's backend)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    /// Timeout in seconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+}
+
+/// A `backends:` entry, e.g. `builder: {type: docker, image: "rust:1.78"}`; a task selects it
+/// with `backend: builder`. `local` exists without an entry.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BackendDef {
+    Local(LocalConfig),
+    Docker(DockerConfig),
+    Ssh(SshConfig),
+    Kubernetes(KubernetesConfig),
+    Wsl(WslConfig),
+    Ecs(EcsConfig),
+    Lambda(LambdaConfig),
+    CloudRun(CloudRunConfig),
+    Aci(AciConfig),
+}
+
+/// Backend types, which entries named after one may leave out as `type:`
+const BACKEND_TYPES: &[&str] = &["local", "docker", "ssh", "kubernetes", "wsl", "ecs", "lambda", "cloudrun", "aci"];
+
+/// `backends:` entries, taking `type:` from the name when it is missing (`docker: {image: ...}`)
+fn deserialize_backends<'de, D>(d: D) -> Result<BTreeMap<String, BackendDef>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error;
+    let raw = BTreeMap::<String, serde_yaml::Value>::deserialize(d)?;
+    raw.into_iter()
+        .map(|(name, mut v)| {
+            if let Some(m) = v.as_mapping_mut().filter(|m| !m.contains_key("type") && BACKEND_TYPES.contains(&name.as_str())) {
+                m.insert("type".into(), name.clone().into());
+            }
+            let def = serde_yaml::from_value(v).map_err(|e| D::Error::custom(format!("backend '{}': {}", name, e)))?;
+            Ok((name, def))
+        })
+        .collect()
+}
+
+/// The local machine with another default `shell` than the pipeline's
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct LocalConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell: Option<ShellSpec>,
+}
+
+/// `docker run` in `image` with the pipeline directory mounted at `/workdir`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct DockerConfig {
+    pub image: String,
+    /// Extra `docker run` arguments, e.g. `["--network", "host"]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Run all tasks in one long-lived container with `docker exec`, removed when the run ends
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reuse: Option<bool>,
+    /// Engine to talk to, like `DOCKER_HOST` (`tcp://build:2376`, `ssh://user@build`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// `docker context` to talk to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    /// Copy the task directory in and out with `docker cp` instead of mounting it (default: when
+    /// `host` or `context` is set, since a remote engine cannot mount local paths)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copy: Option<bool>,
+    /// `docker run --user`; `host` runs as the invoking user's uid:gid so files written to the
+    /// task directory are not owned by root
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Host environment variables passed into the container when set, e.g. `[CARGO_HOME, CI]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pass_env: Vec<String>,
+    /// `always`, `if-not-present` (default) or `never`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pull: Option<PullPolicy>,
+    /// Containers started when the run begins, taking tasks with `docker exec` (not with `reuse`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool: Option<usize>,
+}
+
+/// Remote host reached with the `ssh` client or the built-in one
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SshConfig {
+    pub host: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Identity file passed as `ssh -i`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Extra `ssh` arguments (not used by the native client)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Connect with the built-in client instead of the `ssh` binary (key file or ssh-agent auth)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub native: Option<bool>,
+    /// Remote directory tasks run in, created if missing (default: the login directory)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workdir: Option<String>,
+    /// Upload the pipeline directory before each task and download its artifacts afterwards
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync: Option<SshSyncConfig>,
+    /// Reuse connections across tasks (default true; the `ssh` client needs ControlMaster support)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub multiplex: Option<bool>,
+    /// Connections opened when the run begins (the `ssh` client shares one), needs `multiplex`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool: Option<usize>,
+}
+
+/// `backends.ssh.sync`: without a `workdir`, each task gets a temporary remote directory
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct SshSyncConfig {
+    /// Files and directories not uploaded, rsync-style (`.git`, `target/`, `logs/*.log`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+/// Ephemeral pod started with `kubectl run`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct KubernetesConfig {
+    pub image: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// Extra `kubectl run` arguments
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    /// Run tasks as Jobs instead of `kubectl run` pods (`args` do not apply then)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub job: Option<KubernetesJobConfig>,
+    /// `imagePullPolicy`: `always`, `if-not-present` or `never` (default: the cluster's)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pull: Option<PullPolicy>,
+    /// Pods started when the run begins, taking tasks with `kubectl exec` (not with `job`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool: Option<usize>,
+}
+
+/// WSL distribution the task's directory is opened in (as `/mnt/<drive>/...`)
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct WslConfig {
+    /// Distribution name as listed by `wsl --list` (default: the default distribution)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distro: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+}
+
+/// Fargate tasks on AWS ECS, started with the `aws` CLI; exactly one of `image` and
+/// `task_definition` is set
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct EcsConfig {
+    /// Cluster name or ARN (default `default`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cluster: Option<String>,
+    /// Image of a task definition registered for the pipeline
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    /// Existing task definition whose first container runs the commands
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_definition: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// AWS CLI profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subnets: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub security_groups: Vec<String>,
+    /// Give tasks a public IP, needed to pull public images without a NAT gateway (default true)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_ip: Option<bool>,
+    /// Role ECS pulls the image and writes logs with (required with `image`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub execution_role: Option<String>,
+    /// Role the commands run as
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub task_role: Option<String>,
+    /// Task size with `image`: CPU units (default `256`) and MiB (default `512`)
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_quantity")]
+    pub cpu: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none", deserialize_with = "deserialize_quantity")]
+    pub memory: Option<String>,
+    /// CloudWatch log group with `image` (default `/rustypipe`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_group: Option<String>,
+}
+
+/// An AWS Lambda function invoked with the `aws` CLI, for short glue steps; the task's command
+/// (or a JSON object used as the payload) is sent to it and its response becomes the output
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct LambdaConfig {
+    /// Function name or ARN
+    pub function: String,
+    /// Version or alias
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qualifier: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// AWS CLI profile
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+}
+
+/// One-off Cloud Run jobs on Google Cloud, created and executed with the `gcloud` CLI; output is
+/// read from Cloud Logging
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct CloudRunConfig {
+    pub image: String,
+    /// Service account the jobs run as
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_account: Option<String>,
+    /// Region (default: the `run/region` gcloud property)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub project: Option<String>,
+}
+
+/// Azure Container Instances, one container group per task created and deleted with the `az` CLI
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct AciConfig {
+    pub image: String,
+    pub resource_group: String,
+    /// Region (default: the resource group's)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subscription: Option<String>,
+    /// Container size in cores (default 1) and GB (default 1.5); a task's `resources` override it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory: Option<f64>,
+}
+
+/// `backends.kubernetes.job`; the task's `timeout` becomes the Job's `activeDeadlineSeconds`
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct KubernetesJobConfig {
+    /// Pods started again after a failure before the task fails (default 0)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backoff_limit: Option<u32>,
+    /// Resource requests of the task's container, e.g. `{cpu: 500m, memory: 1Gi}`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub requests: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub limits: BTreeMap<String, String>,
+    /// Seconds the cluster keeps a finished Job that could not be deleted (default 600)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_seconds_after_finished: Option<u32>,
+}
+
+/// A `plugins:` entry
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct PluginDef {
+    /// WebAssembly module, relative to the pipeline file, instead of looking the plugin up
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wasm: Option<String>,
+    /// Command starting an external process plugin, run in the pipeline file's directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Mapping passed to the plugin when it is loaded and with every task
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub config: serde_json::Value,
+}
+
+/// Where a `secrets:` value comes from; exactly one of `env`, `file` and `from` is set
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct SecretDef {
+    /// Host environment variable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<String>,
+    /// File, relative to the pipeline file; a trailing newline is dropped
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    /// Secret store: `vault` or `aws` (Secrets Manager), read at `path`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Field of a secret holding several values (Vault data, JSON secret strings)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+/// `artifact_store:` section; see `pipeline::storage`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ArtifactStoreConfig {
+    /// `s3://bucket/prefix`, `gs://bucket/prefix`, `az://account/container/prefix` or a local
+    /// directory; interpolated like `run`
+    pub to: String,
+    /// Upload attempts per file (default 3)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+}
+
+/// `nix:` on a task, e.g. `{packages: [nodejs_20, jq]}` or `{develop: ".#ci"}`
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+pub struct NixSpec {
+    /// Attributes of `nixpkgs`, or full installables such as `github:owner/repo#tool`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<String>,
+    /// Flake whose dev shell runs the command instead, e.g. `.#ci`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub develop: Option<String>,
+    /// Flake `packages` come from, e.g. `github:NixOS/nixpkgs/nixos-24.05` to pin them (default `nixpkgs`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nixpkgs: Option<String>,
+}
+
+impl NixSpec {
+    /// `shell` started through `nix shell` / `nix develop`
+    pub fn wrap(&self, shell: &ShellSpec) -> ShellSpec {
+        let mut argv: Vec<String> = ["nix", "--extra-experimental-features", "nix-command flakes"].map(String::from).to_vec();
+        match &self.develop {
+            Some(flake) => argv.extend(["develop".to_string(), flake.clone()]),
+            None => {
+                let nixpkgs = self.nixpkgs.as_deref().unwrap_or("nixpkgs");
+                argv.push("shell".to_string());
+                argv.extend(self.packages.iter().map(|p| if p.contains('#') { p.clone() } else { format!("{}#{}", nixpkgs, p) }));
+            }
+        }
+        argv.push("--command".to_string());
+        argv.extend(shell.argv());
+        ShellSpec::Argv(argv)
+    }
+}
+
+/// `upload:` task body: push local files to object storage
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct UploadSpec {
+    /// Destination URL: `s3://bucket/prefix`, `gs://bucket/prefix` or `az://account/container/prefix`
+    pub to: String,
+    /// Files or glob patterns relative to the pipeline directory
+    pub paths: Vec<String>,
+    /// Upload attempts per file before failing the task (default 3)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+}
+
+/// On-disk formats a pipeline can be read from / written to, picked by file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineFormat {
+    Yaml,
+    Json,
+    Toml,
+}
+
+impl PipelineFormat {
+    /// Guess the format from the file extension; anything unknown is treated as YAML
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+            Some("json") => PipelineFormat::Json,
+            Some("toml") => PipelineFormat::Toml,
+            _ => PipelineFormat::Yaml,
+        }
+    }
+}
+
+/// Parse pipeline text in the given format
+pub fn parse_pipeline(content: &str, format: PipelineFormat) -> anyhow::Result<Pipeline> {
+    let p = match format {
+        PipelineFormat::Yaml => serde_yaml::from_str(content)?,
+        PipelineFormat::Json => serde_json::from_str(content)?,
+        PipelineFormat::Toml => toml::from_str(content)?,
+    };
+    Ok(p)
+}
+
+/// Serialize a pipeline into the given format
+pub fn serialize_pipeline(p: &Pipeline, format: PipelineFormat) -> anyhow::Result<String> {
+    let s = match format {
+        PipelineFormat::Yaml => serde_yaml::to_string(p)?,
+        PipelineFormat::Json => serde_json::to_string_pretty(p)?,
+        PipelineFormat::Toml => toml::to_string_pretty(p)?,
+    };
+    Ok(s)
+}
+
+/// Load a pipeline file (YAML, JSON or TOML depending on extension)
+pub fn load_pipeline(path: &Path) -> anyhow::Result<Pipeline> {
+    let content = std::fs::read_to_string(path).with_context(|| format!("failed to read {:?}", path))?;
+    let format = PipelineFormat::from_path(path);
+    let p = parse_pipeline(&content, format).with_context(|| format!("failed to parse {:?} as {:?}", path, format))?;
+    Ok(p)
+}
+
+/// Merge the `include:` fragments of the pipeline loaded from `path`, recursively. Include paths
+/// are relative to the including file. A fragment adds its setup, tasks, teardown and hooks (a
+/// task name defined twice is an error) plus the `vars`, `env` and settings the including file
+/// does not set itself. Fragment tasks run in the main pipeline's directory; local `uses:` paths
+/// are rewritten so they keep pointing next to the fragment.
+pub fn resolve_includes(p: &mut Pipeline, path: &Path) -> anyhow::Result<()> {
+    let base_dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+    let mut stack: Vec<PathBuf> = std::fs::canonicalize(path).into_iter().collect();
+    include_into(p, base_dir, Path::new(""), &mut stack)
+}
+
+/// `rel_dir`: directory of the file declaring `p`, relative to `base_dir` (the main file's)
+fn include_into(p: &mut Pipeline, base_dir: &Path, rel_dir: &Path, stack: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let (mut setup, mut tasks, mut teardown) = (Vec::new(), Vec::new(), Vec::new());
+    let (mut on_success, mut on_failure) = (Vec::new(), Vec::new());
+    for inc in std::mem::take(&mut p.include) {
+        let rel = rel_dir.join(&inc);
+        let path = base_dir.join(&rel);
+        let canonical = std::fs::canonicalize(&path).with_context(|| format!("include {:?}: cannot read {:?}", inc, path))?;
+        if stack.contains(&canonical) {
+            anyhow::bail!("include {:?}: include cycle through {:?}", inc, path);
+        }
+        let mut frag = load_pipeline(&path).with_context(|| format!("include {:?}", inc))?;
+        let frag_dir = rel.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+        if !frag_dir.as_os_str().is_empty() {
+            for t in frag.setup.iter_mut().chain(&mut frag.tasks).chain(&mut frag.teardown) {
+                if let Some(uses) = t.uses.as_mut().filter(|u| u.starts_with("./") || u.starts_with("../")) {
+                    *uses = format!("./{}", frag_dir.join(&*uses).display());
+                }
+            }
+        }
+        stack.push(canonical);
+        include_into(&mut frag, base_dir, &frag_dir, stack)?;
+        stack.pop();
+
+        let defined: HashSet<&str> = p.all_tasks().chain(setup.iter()).chain(&tasks).chain(&teardown).map(|t| t.name.as_str()).collect();
+        if let Some(t) = frag.all_tasks().find(|t| defined.contains(t.name.as_str())) {
+            anyhow::bail!("include {:?}: task '{}' is already defined", inc, t.name);
+        }
+        setup.append(&mut frag.setup);
+        tasks.append(&mut frag.tasks);
+        teardown.append(&mut frag.teardown);
+        on_success.append(&mut frag.on_success);
+        on_failure.append(&mut frag.on_failure);
+        for (k, v) in frag.vars {
+            p.vars.entry(k).or_insert(v);
+        }
+        for (k, v) in frag.env {
+            p.env.entry(k).or_insert(v);
+        }
+        for (k, v) in frag.secrets {
+            p.secrets.entry(k).or_insert(v);
+        }
+        for (k, v) in frag.plugins {
+            p.plugins.entry(k).or_insert(v);
+        }
+        for (k, v) in frag.templates {
+            p.templates.entry(k).or_insert(v);
+        }
+        p.collect.append(&mut frag.collect);
+        p.concurrency = p.concurrency.or(frag.concurrency);
+        p.timeout = p.timeout.or(frag.timeout);
+        for (k, v) in frag.concurrency_groups {
+            p.concurrency_groups.entry(k).or_insert(v);
+        }
+        p.stop_on_fail = p.stop_on_fail.or(frag.stop_on_fail);
+        p.schedule = p.schedule.take().or(frag.schedule);
+        for (k, v) in frag.schedules {
+            p.schedules.entry(k).or_insert(v);
+        }
+        p.step_registry = p.step_registry.take().or(frag.step_registry);
+        p.env_allowlist = p.env_allowlist.take().or(frag.env_allowlist);
+        for (k, v) in frag.backends {
+            p.backends.entry(k).or_insert(v);
+        }
+        p.shell = p.shell.take().or(frag.shell);
+        p.prepull = p.prepull.or(frag.prepull);
+        p.workspace = p.workspace.or(frag.workspace);
+        p.artifact_store = p.artifact_store.take().or(frag.artifact_store);
+    }
+    // included tasks and hooks come first, in include order
+    for (included, own) in [(setup, &mut p.setup), (tasks, &mut p.tasks), (teardown, &mut p.teardown)] {
+        own.splice(0..0, included);
+    }
+    for (included, own) in [(on_success, &mut p.on_success), (on_failure, &mut p.on_failure)] {
+        own.splice(0..0, included);
+    }
+    Ok(())
+}
+
+/// Load a pipeline and resolve everything that expands at load time (`include:`, `extends:`,
+/// `matrix:`, then `uses:` steps). This is what run/validate operate on; `load_pipeline` returns the file as written.
+pub fn load_resolved_pipeline(path: &Path) -> anyhow::Result<Pipeline> {
+    resolve_pipeline(load_pipeline(path)?, path)
+}
+
+/// Resolve `include:`, `extends:`, `matrix:` and `uses:` of a pipeline read from (or standing in
+/// for) the file `path`, then let its `plugins:` preprocess the tasks
+pub fn resolve_pipeline(mut pipeline: Pipeline, path: &Path) -> anyhow::Result<Pipeline> {
+    let base_dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+    resolve_includes(&mut pipeline, path)?;
+    expand_templates(&mut pipeline)?;
+    expand_matrix(&mut pipeline)?;
+    expand_uses(&mut pipeline, base_dir)?;
+    if !pipeline.plugins.is_empty() {
+        let dir = plugins::plugin_dir(pipeline.plugin_dir.as_deref(), base_dir);
+        let loaded = plugins::load(&pipeline.plugins, &dir, base_dir)?;
+        for tasks in [&mut pipeline.setup, &mut pipeline.tasks, &mut pipeline.teardown] {
+            plugins::preprocess(&loaded, tasks, &pipeline.vars)?;
+        }
+        plugins::activate(&loaded);
+    }
+    Ok(pipeline)
+}
+
+/// Read a pipeline in one format and write it in another (formats picked by extension).
+/// The pipeline is validated first so a broken file is never silently re-emitted.
+pub fn convert_pipeline_file(input: &Path, output: &Path) -> anyhow::Result<()> {
+    let pipeline = load_pipeline(input)?;
+    // `include:` and `extends:` are written out as is, but the pipeline is checked resolved
+    let mut resolved = pipeline.clone();
+    resolve_includes(&mut resolved, input)?;
+    expand_templates(&mut resolved)?;
+    validate_pipeline(&resolved)?;
+    let out_format = PipelineFormat::from_path(output);
+    let content = serialize_pipeline(&pipeline, out_format)?;
+    std::fs::write(output, content).with_context(|| format!("failed to write {:?}", output))?;
+    println!("Converted {} -> {} ({:?})", input.display(), output.display(), out_format);
+    Ok(())
+}
+
+/// Parse a `schedule:` cron expression (five fields, or aliases like `@daily`)
+pub fn parse_schedule(expr: &str) -> anyhow::Result<croner::Cron> {
+    croner::Cron::from_str(expr).with_context(|| format!("invalid schedule '{}'", expr))
+}
+
+/// Letters, digits and `_`, not starting with a digit
+fn is_plain_name(k: &str) -> bool {
+    k.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') && k.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Validate DAG: unique names, existing deps, cycles
+pub fn validate_pipeline(p: &Pipeline) -> anyhow::Result<()> {
+    let mut names = HashSet::new();
+    for t in p.all_tasks() {
+        if !names.insert(t.name.clone()) {
+            anyhow::bail!("duplicate task name '{}'", t.name);
+        }
+    }
+
+    // env keys end up in `export` lines (ssh) and `-e` flags, so keep them to plain names
+    if let Some(k) = p.env.keys().find(|k| !is_plain_name(k)) {
+        anyhow::bail!("invalid env variable name '{}'", k);
+    }
+    for (name, def) in &p.secrets {
+        if !is_plain_name(name) {
+            anyhow::bail!("invalid secret name '{}'", name);
+        }
+        if [def.env.is_some(), def.file.is_some(), def.from.is_some()].into_iter().filter(|s| *s).count() != 1 {
+            anyhow::bail!("secret '{}' needs exactly one of `env`, `file` or `from`", name);
+        }
+        match &def.from {
+            Some(from) if !secrets::PROVIDERS.contains(&from.as_str()) => {
+                anyhow::bail!("secret '{}': unknown secret store '{}' (expected one of {})", name, from, secrets::PROVIDERS.join(", "))
+            }
+            Some(_) if def.path.is_none() => anyhow::bail!("secret '{}': `from` needs a `path`", name),
+            None if def.path.is_some() || def.key.is_some() => anyhow::bail!("secret '{}': `path` and `key` only apply with `from`", name),
+            _ => {}
+        }
+    }
+    for (name, def) in &p.plugins {
+        if !def.config.is_null() && !def.config.is_object() {
+            anyhow::bail!("plugin '{}': `config` must be a mapping", name);
+        }
+    }
+    if let Some((name, _)) = p.concurrency_groups.iter().find(|(_, &max)| max == 0) {
+        anyhow::bail!("concurrency group '{}' must allow at least one task", name);
+    }
+    if p.shell.as_ref().is_some_and(|shell| shell.argv().first().is_none_or(|p| p.is_empty())) {
+        anyhow::bail!("`shell` is empty");
+    }
+    for t in p.all_tasks() {
+        if let Some(k) = t.env.keys().find(|k| !is_plain_name(k)) {
+            anyhow::bail!("task '{}': invalid env variable name '{}'", t.name, k);
+        }
+        for template in std::iter::once(&t.run).chain(&t.script).chain(t.env.values()).chain(&t.cache_key) {
+            util::check_template(template).with_context(|| format!("task '{}'", t.name))?;
+        }
+        match &t.shell {
+            Some(shell) if shell.argv().first().is_none_or(|p| p.is_empty()) => anyhow::bail!("task '{}': `shell` is empty", t.name),
+            Some(_) if t.run.is_empty() && t.script.is_empty() => anyhow::bail!("task '{}': `shell` only applies to `run` and `script`", t.name),
+            _ => {}
+        }
+        match &t.stdin {
+            Some(_) if t.run.is_empty() && t.script.is_empty() => anyhow::bail!("task '{}': `stdin` only applies to `run` and `script`", t.name),
+            Some(_) if t.tty == Some(true) => anyhow::bail!("task '{}': `stdin` cannot be combined with `tty`", t.name),
+            Some(StdinSpec::Task { from_task }) if !t.depends_on.contains(from_task) => {
+                anyhow::bail!("task '{}': `stdin.from_task: {}` must also be listed in `depends_on`", t.name, from_task)
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(store) = &p.artifact_store {
+        storage::check(store)?;
+    }
+    for (tag, expr) in &p.schedules {
+        parse_schedule(expr).with_context(|| format!("`schedules: {}`", tag))?;
+        if !p.tasks.iter().any(|t| t.tags.contains(tag)) {
+            anyhow::bail!("`schedules: {}`: no task is tagged '{}'", tag, tag);
+        }
+    }
+    if let Some(t) = p.setup.iter().chain(&p.teardown).find(|t| t.schedule.is_some()) {
+        anyhow::bail!("task '{}': only main tasks can have a `schedule`", t.name);
+    }
+    for t in p.tasks.iter().filter(|t| t.schedule.is_some()) {
+        parse_schedule(t.schedule.as_deref().unwrap_or_default()).with_context(|| format!("task '{}'", t.name))?;
+    }
+    if let Some(schedule) = &p.schedule {
+        parse_schedule(schedule)?;
+    }
+
+    // tasks and hooks may only select `local` or a backend configured in `backends:`
+    let unknown = |b: &&str| *b != "local" && !p.backends.contains_key(*b);
+    let known = || std::iter::once("local").chain(p.backends.keys().map(String::as_str)).collect::<Vec<_>>().join(", ");
+    for (name, def) in &p.backends {
+        match def {
+            BackendDef::Local(LocalConfig { shell: Some(shell) }) if shell.argv().first().is_none_or(|p| p.is_empty()) => {
+                anyhow::bail!("backend '{}': `shell` is empty", name)
+            }
+            BackendDef::Ecs(e) if e.image.is_some() == e.task_definition.is_some() => {
+                anyhow::bail!("backend '{}': set exactly one of `image` and `task_definition`", name)
+            }
+            BackendDef::Ecs(e) if e.image.is_some() && e.execution_role.is_none() => {
+                anyhow::bail!("backend '{}': `image` needs an `execution_role` to pull it and write logs", name)
+            }
+            BackendDef::Ecs(e) if e.subnets.is_empty() => anyhow::bail!("backend '{}': Fargate tasks need `subnets`", name),
+            BackendDef::Docker(DockerConfig { pool: Some(_), reuse: Some(true), .. }) => {
+                anyhow::bail!("backend '{}': `pool` and `reuse` cannot be combined", name)
+            }
+            BackendDef::Kubernetes(KubernetesConfig { pool: Some(_), job: Some(_), .. }) => {
+                anyhow::bail!("backend '{}': `pool` does not apply to `job` backends", name)
+            }
+            BackendDef::Ssh(SshConfig { pool: Some(_), multiplex: Some(false), .. }) => {
+                anyhow::bail!("backend '{}': `pool` needs `multiplex`", name)
+            }
+            _ => {}
+        }
+    }
+    for t in p.all_tasks() {
+        if let Some(b) = t.backend.as_deref().filter(unknown) {
+            anyhow::bail!("task '{}' uses backend '{}' which is not configured in `backends:` (known: {})", t.name, b, known());
+        }
+        let container = t.backend.as_ref().and_then(|b| p.backends.get(b)).is_some_and(|def| matches!(def, BackendDef::Docker(_) | BackendDef::Kubernetes(_)));
+        if t.pull.is_some() && !container {
+            anyhow::bail!("task '{}': `pull` only applies to docker and kubernetes backends", t.name);
+        }
+        if let Some(r) = &t.resources {
+            let cloud = t.backend.as_ref().and_then(|b| p.backends.get(b)).is_some_and(|def| matches!(def, BackendDef::Ecs(_) | BackendDef::CloudRun(_) | BackendDef::Aci(_)));
+            if !container && !cloud {
+                anyhow::bail!("task '{}': `resources` only apply to docker, kubernetes, ecs, cloudrun and aci backends", t.name);
+            }
+            r.cpu_cores().and(r.memory_bytes()).with_context(|| format!("task '{}'", t.name))?;
+        }
+        if t.no_output_action.is_some() && t.no_output_timeout.is_none() {
+            anyhow::bail!("task '{}': `no_output_action` needs a `no_output_timeout`", t.name);
+        }
+        if t.no_output_timeout.is_some() {
+            let polled = t.backend.as_ref().and_then(|b| p.backends.get(b)).is_some_and(|def| matches!(def, BackendDef::Ecs(_) | BackendDef::Lambda(_) | BackendDef::CloudRun(_) | BackendDef::Aci(_)));
+            if polled {
+                anyhow::bail!("task '{}': `no_output_timeout` needs a backend streaming output (not ecs, lambda, cloudrun or aci)", t.name);
+            }
+        }
+        if let Some(g) = t.concurrency_group.as_ref().filter(|g| !p.concurrency_groups.contains_key(*g)) {
+            let known: Vec<&str> = p.concurrency_groups.keys().map(String::as_str).collect();
+            anyhow::bail!("task '{}' uses concurrency group '{}' which is not defined in `concurrency_groups:` (known: {})", t.name, g, known.join(", "));
+        }
+        if !t.tools.is_empty() && t.backend.as_ref().and_then(|b| p.backends.get(b)).is_some_and(|def| !matches!(def, BackendDef::Local(_))) {
+            anyhow::bail!("task '{}': `tools` only apply to the local backend", t.name);
+        }
+        if let Some(nix) = &t.nix {
+            if nix.develop.is_some() != nix.packages.is_empty() {
+                anyhow::bail!("task '{}': `nix` needs either `packages` or `develop`", t.name);
+            }
+        }
+        if let Some(gpus) = &t.gpus {
+            let kubernetes = t.backend.as_ref().and_then(|b| p.backends.get(b)).is_some_and(|def| matches!(def, BackendDef::Kubernetes(_)));
+            match gpus.parse::<u32>() {
+                _ if !container => anyhow::bail!("task '{}': `gpus` only apply to docker and kubernetes backends", t.name),
+                Ok(n) if n > 0 => {}
+                Err(_) if gpus == "all" && !kubernetes => {}
+                _ => anyhow::bail!("task '{}': `gpus` must be a positive count{}", t.name, if kubernetes { "" } else { " or `all`" }),
+            }
+        }
+    }
+    let pipeline_hooks = p.on_success.iter().chain(&p.on_failure).map(|h| ("pipeline".to_string(), h));
+    let task_hooks = p.all_tasks().flat_map(|t| t.on_success.iter().chain(&t.on_failure).map(|h| (format!("task '{}'", t.name), h)));
+    for (owner, h) in pipeline_hooks.chain(task_hooks) {
+        if h.run.trim().is_empty() {
+            anyhow::bail!("{}: hook has an empty `run`", owner);
+        }
+        util::check_template(&h.run).with_context(|| format!("{}: hook", owner))?;
+        if let Some(b) = h.backend.as_deref().filter(unknown) {
+            anyhow::bail!("{}: hook uses backend '{}' which is not configured in `backends:` (known: {})", owner, b, known());
+        }
+    }
+
+    // each phase is its own graph: dependencies cannot cross phases
+    validate_tasks(&p.setup).context("setup")?;
+    validate_tasks(&p.tasks)?;
+    validate_tasks(&p.teardown).context("teardown")?;
+    Ok(())
+}
+
+fn validate_tasks(tasks: &[TaskDef]) -> anyhow::Result<()> {
+    // Every task must declare exactly one kind of work
+    for t in tasks {
+        let kinds = t.kinds();
+        match kinds.len() {
+            0 => anyhow::bail!("task '{}' has nothing to do (set `run`, `script` or a built-in kind)", t.name),
+            1 => {}
+            _ => anyhow::bail!("task '{}' declares several kinds: {}", t.name, kinds.join(", ")),
+        }
+    }
+
+    for t in tasks {
+        if t.retry_delay.is_some_and(|d| !d.is_finite() || d < 0.0) || t.retry_backoff.is_some_and(|b| !b.is_finite() || b < 1.0) {
+            anyhow::bail!("task '{}': retry_delay must be >= 0 and retry_backoff >= 1", t.name);
+        }
+        for f in &t.output_filter {
+            f.validate().with_context(|| format!("task '{}'", t.name))?;
+        }
+        for (name, def) in &t.outputs {
+            if !is_plain_name(name) {
+                anyhow::bail!("task '{}': invalid output name '{}'", t.name, name);
+            }
+            def.validate().with_context(|| format!("task '{}': output '{}'", t.name, name))?;
+        }
+        for pattern in &t.artifacts {
+            glob::Pattern::new(pattern).with_context(|| format!("task '{}': invalid artifact pattern '{}'", t.name, pattern))?;
+        }
+        if let Some(when) = &t.when {
+            condition::check(when).with_context(|| format!("task '{}': invalid `when`", t.name))?;
+        }
+        if let Some(retry_if) = &t.retry_if {
+            if t.retries.unwrap_or(0) == 0 {
+                anyhow::bail!("task '{}': `retry_if` needs `retries`", t.name);
+            }
+            condition::check(retry_if).with_context(|| format!("task '{}': invalid `retry_if`", t.name))?;
+        }
+        for assert in &t.assert {
+            condition::check(assert).with_context(|| format!("task '{}': invalid `assert`", t.name))?;
+        }
+    }
+
+    // All depends_on refer to existing tasks (`<pipeline>:<task>` points into another pipeline
+    // of a multi-pipeline run and is checked when the run is assembled)
+    let name_set: HashSet<String> = tasks.iter().map(|t| t.name.clone()).collect();
+    for t in tasks {
+        for dep in &t.depends_on {
+            if !name_set.contains(dep) && !dep.contains(':') {
+                anyhow::bail!("task '{}' depends on unknown '{}'", t.name, dep);
+            }
+        }
+    }
+
+    check_cycles(tasks)
+}
+
+/// Fail if `depends_on` edges between `tasks` form a cycle
+pub fn check_cycles(tasks: &[TaskDef]) -> anyhow::Result<()> {
+    // Build adjacency (dep -> dependents) to check cycles
+    let mut adj: HashMap<String, Vec<String>> = HashMap::new();
+    for t in tasks {
+        for dep in &t.depends_on {
+            adj.entry(dep.clone()).or_default().push(t.name.clone());
+        }
+    }
+
+    // DFS to detect cycles
+    let mut visited: HashMap<String, i32> = HashMap::new(); // 0=unvisited,1=visiting,2=done
+    fn dfs(node: &str, adj: &HashMap<String, Vec<String>>, visited: &mut HashMap<String, i32>) -> Result<(), String> {
+        if let Some(&v) = visited.get(node) {
+            if v == 1 {
+                return Err(format!("cycle detected at {}", node));
+            }
+            if v == 2 {
+                return Ok(());
+            }
+        }
+        visited.insert(node.to_string(), 1);
+        if let Some(nei) = adj.get(node) {
+            for n in nei {
+                dfs(n, adj, visited)?;
+            }
+        }
+        visited.insert(node.to_string(), 2);
+        Ok(())
+    }
+
+    for t in tasks {
+        dfs(&t.name, &adj, &mut visited).map_err(|e| anyhow::anyhow!(e))?;
+    }
+
+    Ok(())
+}
+
+/// Helper: validate pipeline file path (for main)
+pub fn validate_pipeline_file(path: &Path) -> anyhow::Result<()> {
+    let pipeline = load_pipeline(path)?;
+    validate_pipeline(&pipeline)?;
+    println!("Pipeline '{}' validated", pipeline.name.clone().unwrap_or_else(|| "<unnamed>".to_string()));
+    Ok(())
+}
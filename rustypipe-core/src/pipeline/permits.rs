@@ -0,0 +1,108 @@
+//! Concurrency permits handed out by task `priority:`.
+//!
+//! Every ready task is spawned right away and waits here for a permit; when more tasks wait than
+//! permits are free, the highest priority goes first and equal priorities go in arrival order.
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Mutex;
+use tokio::sync::oneshot;
+
+/// A counting semaphore whose waiters are served by priority
+pub struct PrioritySemaphore {
+    state: Mutex<State>,
+}
+
+struct State {
+    available: usize,
+    waiting: BinaryHeap<Waiter>,
+    arrivals: u64,
+}
+
+struct Waiter {
+    priority: i32,
+    seq: u64,
+    wake: oneshot::Sender<()>,
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    /// Higher priority first, then earlier arrival
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority.cmp(&other.priority).then_with(|| other.seq.cmp(&self.seq))
+    }
+}
+
+/// Held while a task runs; hands the permit to the next waiter when dropped
+pub struct Permit<'a> {
+    sem: &'a PrioritySemaphore,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.sem.release();
+    }
+}
+
+/// A waiter's end of the queue; a permit sent after it stopped waiting goes to the next waiter
+struct Waiting<'a> {
+    sem: &'a PrioritySemaphore,
+    woken: oneshot::Receiver<()>,
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.woken.close();
+        if self.woken.try_recv().is_ok() {
+            self.sem.release();
+        }
+    }
+}
+
+impl PrioritySemaphore {
+    pub fn new(permits: usize) -> Self {
+        PrioritySemaphore { state: Mutex::new(State { available: permits, waiting: BinaryHeap::new(), arrivals: 0 }) }
+    }
+
+    /// Wait for a permit, ahead of waiters with a lower `priority`
+    pub async fn acquire(&self, priority: i32) -> Permit<'_> {
+        let woken = {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            if state.available > 0 && state.waiting.is_empty() {
+                state.available -= 1;
+                return Permit { sem: self };
+            }
+            let (wake, woken) = oneshot::channel();
+            state.arrivals += 1;
+            let seq = state.arrivals;
+            state.waiting.push(Waiter { priority, seq, wake });
+            woken
+        };
+        let mut waiting = Waiting { sem: self, woken };
+        let _ = (&mut waiting.woken).await;
+        Permit { sem: self }
+    }
+
+    /// Pass a permit to the best waiter still waiting, or put it back
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        while let Some(next) = state.waiting.pop() {
+            if next.wake.send(()).is_ok() {
+                return;
+            }
+        }
+        state.available += 1;
+    }
+}
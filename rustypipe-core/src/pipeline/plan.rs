@@ -0,0 +1,144 @@
+//! `rustypipe plan` / `run --dry-run`: load, validate and resolve the task graph, then print
+//! what a run would execute, wave by wave, without running anything.
+//!
+//! Commands are shown with `{{vars.NAME}}` and `{{env.NAME}}` filled in; `{{task.output}}` and
+//! `{{task.outputs.NAME}}` placeholders can only be filled at run time and are highlighted. Placeholders that can never
+//! resolve (unknown vars, tasks that are not upstream) are counted and reported, since they fail
+//! the task (or turn into empty strings with `--allow-missing-vars`).
+use crate::{builtins, util};
+use crate::pipeline::executor::{merge_pipelines, select_tasks, RunConfig};
+use crate::pipeline::parser::TaskDef;
+use regex::{Captures, Regex};
+use std::collections::{HashMap, HashSet};
+use std::io::IsTerminal;
+use std::path::PathBuf;
+
+/// Group a phase's tasks into waves: every task of a wave only depends on earlier waves
+fn waves(tasks: &[TaskDef]) -> Vec<Vec<&TaskDef>> {
+    let mut done: HashSet<&str> = HashSet::new();
+    let mut remaining: Vec<&TaskDef> = tasks.iter().collect();
+    let mut out = Vec::new();
+    while !remaining.is_empty() {
+        let (ready, rest): (Vec<&TaskDef>, Vec<&TaskDef>) = remaining
+            .into_iter()
+            .partition(|t| t.depends_on.iter().all(|d| done.contains(d.as_str())));
+        if ready.is_empty() {
+            // validation rejects cycles, so this is unreachable for loaded pipelines
+            break;
+        }
+        done.extend(ready.iter().map(|t| t.name.as_str()));
+        out.push(ready);
+        remaining = rest;
+    }
+    out
+}
+
+fn ancestors<'a>(all: &HashMap<&'a str, &'a TaskDef>, name: &str) -> HashSet<&'a str> {
+    let mut seen = HashSet::new();
+    let mut stack = vec![name];
+    while let Some(t) = stack.pop() {
+        for dep in all.get(t).map(|d| d.depends_on.as_slice()).unwrap_or_default() {
+            if seen.insert(dep.as_str()) {
+                stack.push(dep.as_str());
+            }
+        }
+    }
+    seen
+}
+
+fn highlight(s: &str, color: bool, resolvable: bool) -> String {
+    match (color, resolvable) {
+        (false, _) => s.to_string(),
+        (true, true) => format!("\x1b[33m{}\x1b[0m", s),
+        (true, false) => format!("\x1b[31m{}\x1b[0m", s),
+    }
+}
+
+pub fn print_plan(paths: &[PathBuf], config: &RunConfig) -> anyhow::Result<()> {
+    let (mut pipeline, infos, task_pipeline) = merge_pipelines(paths)?;
+    select_tasks(&mut pipeline, &config.tasks)?;
+    let color = std::io::stdout().is_terminal();
+    // plain references only; expressions (filters, `{% %}` blocks) are shown as written
+    let placeholder = Regex::new(r"\{\{-?\s*([^\s{}|()]+)\s*-?\}\}").unwrap();
+    let all: HashMap<&str, &TaskDef> = pipeline.all_tasks().map(|t| (t.name.as_str(), t)).collect();
+
+    println!(
+        "Plan for {} ({} task(s), concurrency {})",
+        pipeline.name.as_deref().unwrap_or_default(),
+        all.len(),
+        config.concurrency.or(pipeline.concurrency).unwrap_or(4)
+    );
+    let mut pending = 0;
+    let mut broken = Vec::new();
+    let mut earlier: HashSet<&str> = HashSet::new();
+    for (label, tasks) in [("setup", &pipeline.setup), ("tasks", &pipeline.tasks), ("teardown", &pipeline.teardown)] {
+        if tasks.is_empty() {
+            continue;
+        }
+        println!();
+        println!("{}:", label);
+        for (i, wave) in waves(tasks).into_iter().enumerate() {
+            println!("  wave {}", i + 1);
+            for t in wave {
+                let info = &infos[task_pipeline[&t.name]];
+                let mut vars = info.vars.clone();
+                vars.extend(config.vars.iter().cloned());
+                let upstream = ancestors(&all, &t.name);
+                let cmd = if builtins::is_builtin(t) {
+                    builtins::describe(t)
+                } else if !t.script.is_empty() {
+                    t.script.join(" && ")
+                } else {
+                    t.run.clone()
+                };
+                let rendered = placeholder.replace_all(&cmd, |c: &Captures| {
+                    let key = &c[1];
+                    // loop and `{% set %}` variables
+                    if !key.contains('.') {
+                        return c[0].to_string();
+                    }
+                    if let Some(v) = key.strip_prefix("vars.").and_then(|n| vars.get(n)) {
+                        return v.clone();
+                    }
+                    if let Some(v) = key.strip_prefix("env.").and_then(|n| info.host_env.get(n)) {
+                        return v.clone();
+                    }
+                    // outputs of upstream tasks (or of an earlier phase) are filled in at run time
+                    let (task, output) = match key.split_once(".outputs.") {
+                        Some((task, name)) => (Some(task), Some(name)),
+                        None => (key.strip_suffix(".output"), None),
+                    };
+                    let resolvable = task.is_some_and(|task| {
+                        let full = if all.contains_key(task) { task.to_string() } else { format!("{}{}", info.prefix, task) };
+                        let declared = output.is_none_or(|name| all.get(full.as_str()).is_some_and(|d| d.outputs.contains_key(name)));
+                        declared && (upstream.contains(full.as_str()) || earlier.contains(full.as_str()))
+                    });
+                    if resolvable {
+                        pending += 1;
+                    } else {
+                        broken.push(format!("{}: {}", t.name, &c[0]));
+                    }
+                    highlight(&c[0], color, resolvable)
+                });
+                println!("    {} [{}]  $ {}", t.name, t.backend.as_deref().unwrap_or("local"), rendered);
+                if let Some(when) = &t.when {
+                    println!("      when: {}", when);
+                }
+            }
+        }
+        earlier.extend(tasks.iter().map(|t| t.name.as_str()));
+    }
+
+    println!();
+    if pending > 0 {
+        println!("{} placeholder(s) will be filled from upstream task outputs at run time", pending);
+    }
+    if !broken.is_empty() {
+        let effect = if util::allow_missing_vars() { "will be replaced by an empty string" } else { "will fail their task" };
+        println!("{} placeholder(s) can never resolve and {}:", broken.len(), effect);
+        for b in &broken {
+            println!("  {}", b);
+        }
+    }
+    Ok(())
+}
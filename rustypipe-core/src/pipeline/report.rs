@@ -0,0 +1,140 @@
+//! `rustypipe run --report <format>=<path>`: machine-readable reports written once a run has
+//! finished (also when it failed). `junit` maps every task to a test case so results show up in
+//! the test views of Jenkins, GitLab or Buildkite:
+//! - failed tasks get a `<failure>` with their stderr, tasks that could not run an `<error>`
+//! - skipped and cancelled tasks are `<skipped>`; cache hits pass
+//! - setup and teardown tasks use `<pipeline>.setup` / `<pipeline>.teardown` as class name
+use crate::pipeline::manifest::{task_dir, Phase, RunManifest, TaskStatus};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Junit,
+}
+
+/// One `--report` argument
+#[derive(Debug, Clone)]
+pub struct ReportSpec {
+    pub format: ReportFormat,
+    pub path: PathBuf,
+}
+
+/// Parse `junit=<path>`
+pub fn parse_spec(s: &str) -> Result<ReportSpec, String> {
+    let (format, path) = s.split_once('=').ok_or_else(|| format!("expected FORMAT=PATH, got '{}'", s))?;
+    let format = match format {
+        "junit" => ReportFormat::Junit,
+        other => return Err(format!("unknown report format '{}' (supported: junit)", other)),
+    };
+    if path.is_empty() {
+        return Err(format!("missing path in '{}'", s));
+    }
+    Ok(ReportSpec { format, path: PathBuf::from(path) })
+}
+
+/// Write every requested report for the run in `run_dir`
+pub fn write_reports(specs: &[ReportSpec], manifest: &RunManifest, run_dir: &Path) -> anyhow::Result<()> {
+    for spec in specs {
+        let content = match spec.format {
+            ReportFormat::Junit => junit(manifest, run_dir),
+        };
+        if let Some(parent) = spec.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&spec.path, content)?;
+    }
+    Ok(())
+}
+
+/// Escape text for XML attributes and content, dropping characters XML 1.0 does not allow
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if (c as u32) < 0x20 || c == '\u{fffe}' || c == '\u{ffff}' => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn seconds(ms: u64) -> String {
+    format!("{:.3}", ms as f64 / 1000.0)
+}
+
+/// The run as a JUnit XML document with one test suite
+fn junit(manifest: &RunManifest, run_dir: &Path) -> String {
+    let suite = manifest.pipeline.as_deref().unwrap_or("rustypipe");
+    let count = |f: fn(TaskStatus) -> bool| manifest.tasks.iter().filter(|t| f(t.status)).count();
+    let failures = count(|s| s == TaskStatus::Failed);
+    let errors = count(|s| s == TaskStatus::Error);
+    let skipped = count(|s| matches!(s, TaskStatus::Skipped | TaskStatus::Cancelled | TaskStatus::NotRun));
+    let time = seconds(manifest.tasks.iter().map(|t| t.duration_ms).sum());
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites name=\"rustypipe\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{}\">",
+        manifest.tasks.len(), failures, errors, skipped, time
+    );
+    let _ = writeln!(
+        xml,
+        "  <testsuite name=\"{}\" id=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\" skipped=\"{}\" time=\"{}\" timestamp=\"{}\">",
+        xml_escape(suite), xml_escape(&manifest.id), manifest.tasks.len(), failures, errors, skipped, time, xml_escape(&manifest.started)
+    );
+    for t in &manifest.tasks {
+        let classname = match t.phase {
+            Phase::Main => suite.to_string(),
+            Phase::Setup => format!("{}.setup", suite),
+            Phase::Teardown => format!("{}.teardown", suite),
+        };
+        let _ = writeln!(
+            xml,
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{}\">",
+            xml_escape(&t.name), xml_escape(&classname), seconds(t.duration_ms)
+        );
+        let dir = task_dir(run_dir, &t.name);
+        let stdout = std::fs::read_to_string(dir.join("stdout.log")).unwrap_or_default();
+        let stderr = std::fs::read_to_string(dir.join("stderr.log")).unwrap_or_default();
+        match t.status {
+            TaskStatus::Succeeded | TaskStatus::CacheHit => {}
+            TaskStatus::Failed => {
+                let message = match t.exit_code {
+                    Some(code) => format!("exit code {}", code),
+                    None => "killed by a signal".to_string(),
+                };
+                let _ = writeln!(xml, "      <failure message=\"{}\" type=\"failure\">{}</failure>", xml_escape(&message), xml_escape(stderr.trim_end()));
+            }
+            TaskStatus::Error => {
+                // the error itself is recorded as the task's stderr
+                let message = t.attempts.iter().rev().find_map(|a| a.error.as_deref()).or_else(|| stderr.lines().next()).unwrap_or("task could not be executed");
+                let _ = writeln!(xml, "      <error message=\"{}\" type=\"error\">{}</error>", xml_escape(message), xml_escape(stderr.trim_end()));
+            }
+            TaskStatus::Skipped => {
+                let _ = writeln!(xml, "      <skipped/>");
+            }
+            TaskStatus::Cancelled => {
+                let _ = writeln!(xml, "      <skipped message=\"cancelled\"/>");
+            }
+            TaskStatus::NotRun => {
+                let _ = writeln!(xml, "      <skipped message=\"not run\"/>");
+            }
+        }
+        if !stdout.trim().is_empty() {
+            let _ = writeln!(xml, "      <system-out>{}</system-out>", xml_escape(stdout.trim_end()));
+        }
+        if !stderr.trim().is_empty() && !matches!(t.status, TaskStatus::Failed | TaskStatus::Error) {
+            let _ = writeln!(xml, "      <system-err>{}</system-err>", xml_escape(stderr.trim_end()));
+        }
+        xml.push_str("    </testcase>\n");
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
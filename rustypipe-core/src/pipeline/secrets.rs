@@ -0,0 +1,234 @@
+//! `secrets:` are environment variables of every task (and hook) whose values never reach the
+//! run directory or the console:
+//! ```yaml
+//! secrets:
+//!   GITHUB_TOKEN: { env: GH_TOKEN }    # from the host environment
+//!   DEPLOY_KEY: { file: keys/deploy }  # file contents, relative to the pipeline file
+//!   CI_TOKEN: { from: vault, path: kv/ci/token }
+//!   DB_PASSWORD: { from: aws, path: prod/db, key: password }
+//! ```
+//! Values are read when the run starts. From then on they are replaced by `***` in streamed and
+//! captured output, recorded commands and collected text artifacts.
+//!
+//! Secret stores (`from:`) implement `SecretProvider`:
+//! - `vault`: HashiCorp Vault KV (v2, falling back to v1) at `VAULT_ADDR`, authenticated with
+//!   `VAULT_TOKEN` (or `~/.vault-token`); `VAULT_NAMESPACE` is sent when set
+//! - `aws`: AWS Secrets Manager, with the credentials described in `builtins::upload`;
+//!   `AWS_ENDPOINT_URL_SECRETS_MANAGER` overrides the endpoint
+//!
+//! A secret holding several values (Vault data, a JSON secret string) needs `key:` unless it has
+//! exactly one field.
+use crate::builtins::upload::AwsCredentials;
+use crate::pipeline::parser::SecretDef;
+use anyhow::Context;
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::Duration;
+
+const MASK: &str = "***";
+
+/// Values masked in output, longest first so a secret containing another is masked whole
+static MASKED: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+fn register(value: &str) {
+    let mut masked = MASKED.write().unwrap_or_else(|e| e.into_inner());
+    // output is masked line by line when streamed, so every line of a multi-line value counts too
+    let parts = std::iter::once(value).chain(value.lines().map(str::trim)).filter(|v| !v.is_empty());
+    for part in parts {
+        if !masked.iter().any(|m| m == part) {
+            masked.push(part.to_string());
+        }
+    }
+    masked.sort_by_key(|m| std::cmp::Reverse(m.len()));
+}
+
+/// A secret store that `from:` can name
+#[async_trait]
+pub trait SecretProvider: Send + Sync {
+    /// Value of the secret at `path`; `key` picks one field of a secret holding several
+    async fn fetch(&self, path: &str, key: Option<&str>) -> anyhow::Result<String>;
+}
+
+/// Names accepted by `from:`
+pub const PROVIDERS: [&str; 2] = ["vault", "aws"];
+
+pub fn provider(name: &str) -> anyhow::Result<Box<dyn SecretProvider>> {
+    let client = reqwest::Client::builder().timeout(Duration::from_secs(30)).build()?;
+    match name {
+        "vault" => Ok(Box::new(Vault { client })),
+        "aws" => Ok(Box::new(AwsSecretsManager { client })),
+        other => anyhow::bail!("unknown secret store '{}'", other),
+    }
+}
+
+/// The field `key` of a secret's fields, or its only field
+fn pick_field(fields: &serde_json::Map<String, serde_json::Value>, key: Option<&str>) -> anyhow::Result<String> {
+    let value = match key {
+        Some(key) => fields.get(key).with_context(|| format!("the secret has no field '{}'", key))?,
+        None if fields.len() == 1 => fields.values().next().unwrap_or_default(),
+        None => anyhow::bail!("the secret has several fields ({}); set `key`", fields.keys().cloned().collect::<Vec<_>>().join(", ")),
+    };
+    Ok(match value {
+        serde_json::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    })
+}
+
+async fn check_response(resp: reqwest::Response) -> anyhow::Result<serde_json::Value> {
+    let status = resp.status();
+    let body = resp.text().await.unwrap_or_default();
+    if !status.is_success() {
+        anyhow::bail!("secret store responded {}: {}", status, body.trim());
+    }
+    serde_json::from_str(&body).context("unexpected secret store response")
+}
+
+struct Vault {
+    client: reqwest::Client,
+}
+
+impl Vault {
+    fn token() -> anyhow::Result<String> {
+        if let Ok(token) = std::env::var("VAULT_TOKEN") {
+            return Ok(token);
+        }
+        let file = std::env::var_os("HOME").map(|h| Path::new(&h).join(".vault-token"));
+        let token = file.and_then(|f| std::fs::read_to_string(f).ok()).context("VAULT_TOKEN is not set and there is no ~/.vault-token")?;
+        Ok(token.trim().to_string())
+    }
+
+    async fn get(&self, addr: &str, token: &str, api_path: &str) -> anyhow::Result<reqwest::Response> {
+        let mut req = self.client.get(format!("{}/v1/{}", addr, api_path)).header("X-Vault-Token", token);
+        if let Ok(ns) = std::env::var("VAULT_NAMESPACE") {
+            req = req.header("X-Vault-Namespace", ns);
+        }
+        Ok(req.send().await?)
+    }
+}
+
+#[async_trait]
+impl SecretProvider for Vault {
+    async fn fetch(&self, path: &str, key: Option<&str>) -> anyhow::Result<String> {
+        let addr = std::env::var("VAULT_ADDR").context("VAULT_ADDR is not set")?;
+        let addr = addr.trim_end_matches('/');
+        let token = Self::token()?;
+        let path = path.trim_matches('/');
+        // KV v2 keeps the secret at <mount>/data/<path> and nests it one level deeper
+        let (mount, rest) = path.split_once('/').with_context(|| format!("'{}' is no <mount>/<path>", path))?;
+        let resp = self.get(addr, token.as_str(), &format!("{}/data/{}", mount, rest)).await?;
+        let fields = if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            let body = check_response(self.get(addr, token.as_str(), path).await?).await?;
+            body.get("data").cloned()
+        } else {
+            let body = check_response(resp).await?;
+            body.get("data").and_then(|d| d.get("data")).cloned()
+        };
+        match fields {
+            Some(serde_json::Value::Object(fields)) => pick_field(&fields, key),
+            _ => anyhow::bail!("unexpected Vault response for '{}'", path),
+        }
+    }
+}
+
+struct AwsSecretsManager {
+    client: reqwest::Client,
+}
+
+#[async_trait]
+impl SecretProvider for AwsSecretsManager {
+    async fn fetch(&self, path: &str, key: Option<&str>) -> anyhow::Result<String> {
+        let creds = AwsCredentials::from_env()?;
+        let endpoint = std::env::var("AWS_ENDPOINT_URL_SECRETS_MANAGER")
+            .unwrap_or_else(|_| format!("https://secretsmanager.{}.amazonaws.com", creds.region));
+        let url = reqwest::Url::parse(&format!("{}/", endpoint.trim_end_matches('/')))?;
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+        let body = serde_json::json!({ "SecretId": path }).to_string();
+        let amz_date = chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let mut headers = vec![
+            ("content-type".to_string(), "application/x-amz-json-1.1".to_string()),
+            ("host".to_string(), host),
+            ("x-amz-date".to_string(), amz_date),
+            ("x-amz-target".to_string(), "secretsmanager.GetSecretValue".to_string()),
+        ];
+        if let Some(token) = &creds.session_token {
+            headers.push(("x-amz-security-token".to_string(), token.clone()));
+        }
+        let authorization = creds.authorization("secretsmanager", "POST", "/", &headers, &hex::encode(Sha256::digest(body.as_bytes())));
+        let mut req = self.client.post(url).header("authorization", authorization).body(body);
+        for (k, v) in headers.iter().filter(|(k, _)| k != "host") {
+            req = req.header(k.as_str(), v.as_str());
+        }
+        let resp = check_response(req.send().await?).await?;
+        let secret = resp.get("SecretString").and_then(|s| s.as_str()).with_context(|| format!("'{}' has no SecretString", path))?;
+        match (key, serde_json::from_str::<serde_json::Value>(secret)) {
+            (None, Ok(serde_json::Value::Object(fields))) if fields.len() == 1 => pick_field(&fields, None),
+            (None, _) => Ok(secret.to_string()),
+            (Some(key), Ok(serde_json::Value::Object(fields))) => pick_field(&fields, Some(key)),
+            (Some(_), _) => anyhow::bail!("'{}' is no JSON object, so `key` cannot be used", path),
+        }
+    }
+}
+
+/// Read every secret of a pipeline in `dir` and register its value for masking
+pub async fn resolve(defs: &BTreeMap<String, SecretDef>, dir: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    let mut resolved = Vec::new();
+    for (name, def) in defs {
+        let value = match (&def.env, &def.file, &def.from) {
+            (Some(var), _, _) => std::env::var(var).ok().with_context(|| format!("secret '{}': environment variable {} is not set", name, var))?,
+            (None, Some(file), _) => {
+                let path = dir.join(file);
+                let content = std::fs::read_to_string(&path).with_context(|| format!("secret '{}': failed to read {:?}", name, path))?;
+                content.trim_end_matches(['\n', '\r']).to_string()
+            }
+            (None, None, Some(from)) => {
+                let path = def.path.as_deref().unwrap_or_default();
+                provider(from)?
+                    .fetch(path, def.key.as_deref())
+                    .await
+                    .with_context(|| format!("secret '{}': failed to read '{}' from {}", name, path, from))?
+            }
+            (None, None, None) => anyhow::bail!("secret '{}' has no source", name),
+        };
+        register(&value);
+        resolved.push((name.clone(), value));
+    }
+    Ok(resolved)
+}
+
+/// `text` with every secret value replaced by `***`
+pub fn mask(text: &str) -> Cow<'_, str> {
+    let masked = MASKED.read().unwrap_or_else(|e| e.into_inner());
+    let mut out = Cow::Borrowed(text);
+    for value in masked.iter().filter(|v| text.contains(v.as_str())) {
+        out = Cow::Owned(out.replace(value.as_str(), MASK));
+    }
+    out
+}
+
+/// File contents with secrets masked; only text is touched, binary data is returned as is
+pub fn mask_bytes(data: Vec<u8>) -> Vec<u8> {
+    match String::from_utf8(data) {
+        Ok(text) => match mask(&text) {
+            Cow::Owned(masked) => masked.into_bytes(),
+            Cow::Borrowed(_) => text.into_bytes(),
+        },
+        Err(e) => e.into_bytes(),
+    }
+}
+
+/// Mask a file in place (see `mask_bytes`)
+pub fn mask_file(path: &Path) -> anyhow::Result<()> {
+    let data = std::fs::read(path).with_context(|| format!("failed to read {:?}", path))?;
+    let masked = mask_bytes(data.clone());
+    if masked != data {
+        std::fs::write(path, masked).with_context(|| format!("failed to mask {:?}", path))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,152 @@
+//! `.rustypipe/state.db`: SQLite index of runs, their tasks and retry attempts, written by the
+//! executor whenever a run's manifest changes. History queries read it instead of scanning every
+//! run directory; the per-run files stay the source of the logs.
+use crate::pipeline::history::RunSummary;
+use crate::pipeline::manifest::{RunManifest, RunStatus};
+use anyhow::Context;
+use rusqlite::{params, Connection};
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+
+pub const STATE_DB: &str = "state.db";
+
+const SCHEMA_VERSION: i32 = 1;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id          TEXT PRIMARY KEY,
+    pipeline    TEXT,
+    status      TEXT NOT NULL,
+    started     TEXT NOT NULL,
+    finished    TEXT,
+    duration_ms INTEGER
+);
+CREATE TABLE IF NOT EXISTS tasks (
+    run_id      TEXT NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+    name        TEXT NOT NULL,
+    phase       TEXT NOT NULL,
+    status      TEXT NOT NULL,
+    exit_code   INTEGER,
+    command     TEXT NOT NULL,
+    started     TEXT NOT NULL,
+    finished    TEXT NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    PRIMARY KEY (run_id, name)
+);
+CREATE TABLE IF NOT EXISTS attempts (
+    run_id      TEXT NOT NULL,
+    task        TEXT NOT NULL,
+    attempt     INTEGER NOT NULL,
+    exit_code   INTEGER,
+    error       TEXT,
+    duration_ms INTEGER NOT NULL,
+    PRIMARY KEY (run_id, task, attempt),
+    FOREIGN KEY (run_id, task) REFERENCES tasks(run_id, name) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS runs_started ON runs(started);
+";
+
+/// serde name of a status enum (`succeeded`, `cache_hit`, ...)
+pub fn enum_name(v: impl serde::Serialize) -> String {
+    serde_json::to_value(v).ok().and_then(|v| v.as_str().map(str::to_string)).unwrap_or_default()
+}
+
+/// Open (and create or migrate) the database below `base` (normally `.rustypipe`)
+pub fn open(base: &Path) -> anyhow::Result<Connection> {
+    std::fs::create_dir_all(base)?;
+    let path = base.join(STATE_DB);
+    let conn = Connection::open(&path).with_context(|| format!("failed to open {:?}", path))?;
+    // several runs may finish tasks at the same time
+    conn.busy_timeout(Duration::from_secs(10))?;
+    conn.pragma_update(None, "foreign_keys", true)?;
+    let version: i32 = conn.pragma_query_value(None, "user_version", |r| r.get(0))?;
+    if version > SCHEMA_VERSION {
+        anyhow::bail!("{:?} was written by a newer rustypipe (schema {})", path, version);
+    }
+    conn.execute_batch(SCHEMA)?;
+    conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    Ok(conn)
+}
+
+/// Store the current state of a run, replacing what was recorded for it before
+pub fn record(base: &Path, manifest: &RunManifest) -> anyhow::Result<()> {
+    let mut conn = open(base)?;
+    let tx = conn.transaction()?;
+    let duration_ms = manifest.finished.as_deref().and_then(|f| {
+        let start = chrono::DateTime::parse_from_rfc3339(&manifest.started).ok()?;
+        let end = chrono::DateTime::parse_from_rfc3339(f).ok()?;
+        Some((end - start).num_milliseconds())
+    });
+    tx.execute(
+        "INSERT INTO runs (id, pipeline, status, started, finished, duration_ms) VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+         ON CONFLICT(id) DO UPDATE SET pipeline = ?2, status = ?3, started = ?4, finished = ?5, duration_ms = ?6",
+        params![manifest.id, manifest.pipeline, enum_name(manifest.status), manifest.started, manifest.finished, duration_ms],
+    )?;
+    tx.execute("DELETE FROM tasks WHERE run_id = ?1", params![manifest.id])?;
+    for t in &manifest.tasks {
+        tx.execute(
+            "INSERT INTO tasks (run_id, name, phase, status, exit_code, command, started, finished, duration_ms)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![manifest.id, t.name, enum_name(t.phase), enum_name(t.status), t.exit_code, t.command, t.started, t.finished, t.duration_ms as i64],
+        )?;
+        for a in &t.attempts {
+            tx.execute(
+                "INSERT INTO attempts (run_id, task, attempt, exit_code, error, duration_ms) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![manifest.id, t.name, a.attempt, a.exit_code, a.error, a.duration_ms as i64],
+            )?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Record runs that have a directory but no row yet (older runs, failed writes)
+pub fn import_missing(base: &Path) -> anyhow::Result<()> {
+    let known: HashSet<String> = {
+        let conn = open(base)?;
+        let mut stmt = conn.prepare("SELECT id FROM runs")?;
+        let ids = stmt.query_map([], |r| r.get(0))?.collect::<Result<_, _>>()?;
+        ids
+    };
+    for entry in std::fs::read_dir(base.join("runs")).into_iter().flatten().flatten() {
+        if known.contains(entry.file_name().to_string_lossy().as_ref()) {
+            continue;
+        }
+        if let Ok(m) = RunManifest::load(&entry.path()) {
+            record(base, &m)?;
+        }
+    }
+    Ok(())
+}
+
+/// Run history with per-status task counts, newest first
+pub fn load_runs(base: &Path) -> anyhow::Result<Vec<RunSummary>> {
+    let conn = open(base)?;
+    let mut stmt = conn.prepare(
+        "SELECT r.id, r.pipeline, r.status, r.started, r.finished, r.duration_ms,
+                COUNT(CASE WHEN t.status IN ('succeeded', 'cache_hit') THEN 1 END),
+                COUNT(CASE WHEN t.status IN ('failed', 'error') THEN 1 END),
+                COUNT(CASE WHEN t.status = 'skipped' THEN 1 END),
+                COUNT(CASE WHEN t.status = 'cancelled' THEN 1 END)
+         FROM runs r LEFT JOIN tasks t ON t.run_id = r.id
+         GROUP BY r.id
+         ORDER BY r.started DESC",
+    )?;
+    let rows = stmt.query_map([], |r| {
+        let status: String = r.get(2)?;
+        Ok(RunSummary {
+            id: r.get(0)?,
+            pipeline: r.get(1)?,
+            status: serde_json::from_value(serde_json::Value::String(status)).unwrap_or(RunStatus::Failed),
+            started: r.get(3)?,
+            finished: r.get(4)?,
+            duration_ms: r.get::<_, Option<i64>>(5)?.and_then(|d| u64::try_from(d).ok()),
+            passed: r.get::<_, i64>(6)? as usize,
+            failed: r.get::<_, i64>(7)? as usize,
+            skipped: r.get::<_, i64>(8)? as usize,
+            cancelled: r.get::<_, i64>(9)? as usize,
+        })
+    })?;
+    Ok(rows.collect::<Result<_, _>>()?)
+}
@@ -0,0 +1,263 @@
+//! Reusable published steps: `uses: org/step@v1` (or a local `./steps/foo`) on a task is replaced
+//! by the tasks defined in the step's `step.yaml` at load time.
+//!
+//! A step package looks like:
+//! ```yaml
+//! name: cargo-build
+//! backend: local            # default backend for the step's tasks
+//! inputs:
+//!   profile: { default: release }
+//!   target: { required: true }
+//! tasks:
+//!   - name: build
+//!     run: cargo build --profile {{inputs.profile}} --target {{inputs.target}}
+//! ```
+//! A single-task step keeps the name of the task that uses it; multi-task steps are expanded to
+//! `<task>.<step task>` and dependents of `<task>` wait for all of the step's final tasks.
+use crate::pipeline::parser::{Pipeline, TaskDef};
+use anyhow::Context;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+const DEFAULT_REGISTRY: &str = "https://github.com/{org}/{name}.git";
+
+/// Contents of a step package's `step.yaml`
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct StepDef {
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub inputs: HashMap<String, StepInput>,
+    #[serde(default)]
+    pub backend: Option<String>,
+    pub tasks: Vec<TaskDef>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct StepInput {
+    #[serde(default)]
+    pub default: Option<String>,
+    /// Inputs without a default are required unless this is explicitly false
+    #[serde(default)]
+    pub required: Option<bool>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// Replace every task with `uses:` (in setup, tasks and teardown) by the tasks of the referenced step
+pub fn expand_uses(p: &mut Pipeline, base_dir: &Path) -> anyhow::Result<()> {
+    if !p.all_tasks().any(|t| t.uses.is_some()) {
+        return Ok(());
+    }
+    let registry = p.step_registry.clone().unwrap_or_else(|| DEFAULT_REGISTRY.to_string());
+    for tasks in [&mut p.setup, &mut p.tasks, &mut p.teardown] {
+        *tasks = expand_list(std::mem::take(tasks), base_dir, &registry)?;
+    }
+    Ok(())
+}
+
+fn expand_list(tasks: Vec<TaskDef>, base_dir: &Path, registry: &str) -> anyhow::Result<Vec<TaskDef>> {
+    let mut expanded = Vec::new();
+    // task name -> names dependents should wait for
+    let mut replacements: HashMap<String, Vec<String>> = HashMap::new();
+    for t in tasks {
+        let Some(uses) = t.uses.clone() else {
+            expanded.push(t);
+            continue;
+        };
+        let step = fetch_step(&uses, base_dir, registry)
+            .with_context(|| format!("task '{}': failed to load step '{}'", t.name, uses))?;
+        let (tasks, leaves) = instantiate(&t, &step)
+            .with_context(|| format!("task '{}': failed to expand step '{}'", t.name, uses))?;
+        replacements.insert(t.name.clone(), leaves);
+        expanded.extend(tasks);
+    }
+
+    for t in &mut expanded {
+        t.depends_on = t
+            .depends_on
+            .iter()
+            .flat_map(|d| replacements.get(d).cloned().unwrap_or_else(|| vec![d.clone()]))
+            .collect();
+    }
+    Ok(expanded)
+}
+
+/// Turn one `uses:` task into concrete tasks; returns (tasks, names of the final tasks)
+fn instantiate(user: &TaskDef, step: &StepDef) -> anyhow::Result<(Vec<TaskDef>, Vec<String>)> {
+    if step.tasks.is_empty() {
+        anyhow::bail!("step defines no tasks");
+    }
+    for k in user.with.keys() {
+        if !step.inputs.contains_key(k) {
+            anyhow::bail!("unknown input '{}'", k);
+        }
+    }
+    let mut inputs = HashMap::new();
+    for (k, def) in &step.inputs {
+        let value = match (user.with.get(k), &def.default) {
+            (Some(v), _) => v.clone(),
+            (None, Some(d)) => d.clone(),
+            (None, None) if def.required == Some(false) => String::new(),
+            (None, None) => anyhow::bail!("missing required input '{}'", k),
+        };
+        inputs.insert(k.clone(), value);
+    }
+
+    let single = step.tasks.len() == 1;
+    let rename = |n: &str| if single { user.name.clone() } else { format!("{}.{}", user.name, n) };
+    let depended_on: HashSet<&str> = step.tasks.iter().flat_map(|t| t.depends_on.iter().map(|d| d.as_str())).collect();
+
+    let mut tasks = Vec::new();
+    let mut leaves = Vec::new();
+    for st in &step.tasks {
+        let mut t = substitute(st, "inputs", &inputs)?;
+        t.name = rename(&st.name);
+        t.depends_on = if st.depends_on.is_empty() {
+            user.depends_on.clone()
+        } else {
+            st.depends_on.iter().map(|d| rename(d)).collect()
+        };
+        t.backend = user.backend.clone().or(t.backend).or_else(|| step.backend.clone());
+        t.retries = user.retries.or(t.retries);
+        t.retry_delay = user.retry_delay.or(t.retry_delay);
+        t.retry_backoff = user.retry_backoff.or(t.retry_backoff);
+        t.retry_jitter = user.retry_jitter.or(t.retry_jitter);
+        t.timeout = user.timeout.or(t.timeout);
+        t.continue_on_fail = user.continue_on_fail.or(t.continue_on_fail);
+        t.env.extend(user.env.clone());
+        if !depended_on.contains(st.name.as_str()) {
+            leaves.push(t.name.clone());
+        }
+        tasks.push(t);
+    }
+    Ok((tasks, leaves))
+}
+
+/// Replace `{{<namespace>.NAME}}` (e.g. `{{inputs.NAME}}`) in every string field of a task; an
+/// unknown NAME is an error unless `--allow-missing-vars` makes it an empty string
+pub(super) fn substitute(task: &TaskDef, namespace: &str, inputs: &HashMap<String, String>) -> anyhow::Result<TaskDef> {
+    let re = Regex::new(&format!(r"\{{\{{\s*{}\.([A-Za-z0-9_-]+)\s*\}}\}}", regex::escape(namespace))).unwrap();
+    fn walk(v: &mut serde_yaml::Value, re: &Regex, inputs: &HashMap<String, String>, missing: &mut BTreeSet<String>) {
+        match v {
+            serde_yaml::Value::String(s) => {
+                *s = re
+                    .replace_all(s, |c: &regex::Captures| match inputs.get(&c[1]) {
+                        Some(value) => value.clone(),
+                        None => {
+                            missing.insert(c[1].to_string());
+                            String::new()
+                        }
+                    })
+                    .to_string();
+            }
+            serde_yaml::Value::Sequence(seq) => seq.iter_mut().for_each(|x| walk(x, re, inputs, missing)),
+            serde_yaml::Value::Mapping(m) => m.iter_mut().for_each(|(_, x)| walk(x, re, inputs, missing)),
+            serde_yaml::Value::Tagged(t) => walk(&mut t.value, re, inputs, missing),
+            _ => {}
+        }
+    }
+    let mut value = serde_yaml::to_value(task)?;
+    let mut missing = BTreeSet::new();
+    walk(&mut value, &re, inputs, &mut missing);
+    if !missing.is_empty() && !crate::util::allow_missing_vars() {
+        let names = missing.iter().map(|n| format!("{{{{{}.{}}}}}", namespace, n)).collect::<Vec<_>>().join(", ");
+        anyhow::bail!("unknown reference(s) {} (--allow-missing-vars replaces them with empty strings)", names);
+    }
+    Ok(serde_yaml::from_value(value)?)
+}
+
+/// Locate and parse a step: local paths are read directly, `org/name[/subdir]@ref` is fetched
+/// from git into `.rustypipe/steps` (tags and commits are reused on later runs, branches are
+/// fetched again).
+fn fetch_step(uses: &str, base_dir: &Path, registry: &str) -> anyhow::Result<StepDef> {
+    let dir = if uses.starts_with("./") || uses.starts_with("../") || Path::new(uses).is_absolute() {
+        base_dir.join(uses)
+    } else {
+        fetch_remote_step(uses, registry)?
+    };
+    let file = if dir.is_dir() { dir.join("step.yaml") } else { dir };
+    let content = std::fs::read_to_string(&file).with_context(|| format!("failed to read {:?}", file))?;
+    let step: StepDef = serde_yaml::from_str(&content).with_context(|| format!("failed to parse {:?}", file))?;
+    Ok(step)
+}
+
+/// `name@ref` as one directory name: characters other than `[A-Za-z0-9._-]` become `_`, followed
+/// by a short hash of the ref when any did, so `feature/x` and `feature_x` stay apart
+fn checkout_name(name: &str, git_ref: &str) -> String {
+    let clean: String = git_ref.chars().map(|c| if c.is_ascii_alphanumeric() || "._-".contains(c) { c } else { '_' }).collect();
+    if clean == git_ref {
+        format!("{}@{}", name, clean)
+    } else {
+        format!("{}@{}-{}", name, clean, &hex::encode(Sha256::digest(git_ref))[..8])
+    }
+}
+
+/// Whether `git_ref` cannot move in `repo`: a tag, or (a prefix of) the checked-out commit id
+fn immutable_ref(repo: &git2::Repository, git_ref: &str) -> bool {
+    let commit = git_ref.len() >= 7
+        && git_ref.chars().all(|c| c.is_ascii_hexdigit())
+        && repo.head().ok().and_then(|h| h.target()).is_some_and(|id| id.to_string().starts_with(&git_ref.to_ascii_lowercase()));
+    commit || repo.find_reference(&format!("refs/tags/{}", git_ref)).is_ok()
+}
+
+/// Clone `url` into `dir` and check out `git_ref` (default: the remote's HEAD)
+fn clone_step(url: &str, dir: &Path, git_ref: Option<&str>) -> anyhow::Result<()> {
+    let repo = git2::Repository::clone(url, dir).with_context(|| format!("failed to clone {}", url))?;
+    if let Some(r) = git_ref {
+        let object = repo
+            .revparse_single(r)
+            .or_else(|_| repo.revparse_single(&format!("origin/{}", r)))
+            .with_context(|| format!("ref '{}' not found in {}", r, url))?;
+        repo.checkout_tree(&object, Some(git2::build::CheckoutBuilder::new().force()))?;
+        repo.set_head_detached(object.peel_to_commit()?.id())?;
+    }
+    Ok(())
+}
+
+fn fetch_remote_step(uses: &str, registry: &str) -> anyhow::Result<PathBuf> {
+    let (path, git_ref) = match uses.rsplit_once('@') {
+        Some((p, r)) => (p, Some(r)),
+        None => (uses, None),
+    };
+    let mut parts = path.splitn(3, '/');
+    let (org, name) = match (parts.next(), parts.next()) {
+        (Some(o), Some(n)) if !o.is_empty() && !n.is_empty() => (o, n),
+        _ => anyhow::bail!("expected `org/name@ref`, got '{}'", uses),
+    };
+    let subdir = parts.next().unwrap_or("");
+
+    let steps_dir = Path::new(".rustypipe").join("steps").join(org);
+    let dir_name = checkout_name(name, git_ref.unwrap_or("HEAD"));
+    let checkout = steps_dir.join(&dir_name);
+    if let Some(r) = git_ref.filter(|_| checkout.exists()) {
+        if git2::Repository::open(&checkout).is_ok_and(|repo| immutable_ref(&repo, r)) {
+            return Ok(checkout.join(subdir));
+        }
+    }
+    let url = registry.replace("{org}", org).replace("{name}", name);
+    std::fs::create_dir_all(&steps_dir)?;
+    let tmp = steps_dir.join(format!("{}.partial", dir_name));
+    let _ = std::fs::remove_dir_all(&tmp);
+    match clone_step(&url, &tmp, git_ref) {
+        Ok(()) => {
+            let _ = std::fs::remove_dir_all(&checkout);
+            std::fs::rename(&tmp, &checkout)?;
+        }
+        // a branch that cannot be refreshed (offline) keeps its last checkout
+        Err(e) if checkout.exists() => {
+            let _ = std::fs::remove_dir_all(&tmp);
+            tracing::warn!("could not refresh step {}, using the cached checkout: {:#}", uses, e);
+        }
+        Err(e) => {
+            let _ = std::fs::remove_dir_all(&tmp);
+            return Err(e);
+        }
+    }
+    Ok(checkout.join(subdir))
+}
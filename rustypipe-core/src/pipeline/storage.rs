@@ -0,0 +1,119 @@
+//! `artifact_store:` pushes a finished run's logs, metadata and collected artifacts to longer-lived
+//! storage. The run directory itself is the default (local) store; a configured store receives a
+//! copy of it under `<prefix>/<run id>/`, isolated workspaces excluded:
+//! ```yaml
+//! artifact_store:
+//!   to: s3://ci-artifacts/{{vars.BRANCH}}   # gs://bucket/prefix, az://account/container/prefix or a local path
+//! ```
+//! Object stores use the credentials described in `builtins::upload`.
+use crate::builtins::upload::{checksums, put_object, Destination};
+use crate::pipeline::parser::ArtifactStoreConfig;
+use anyhow::Context;
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Where the files of a run are persisted
+#[async_trait]
+pub trait ArtifactStore: Send + Sync {
+    /// Location shown to the user
+    fn describe(&self) -> String;
+    /// Store `data` under `key` (a `/`-separated path below the store's root)
+    async fn put(&self, key: &str, data: &[u8]) -> anyhow::Result<()>;
+}
+
+/// A directory on the local filesystem (or a mounted share)
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+#[async_trait]
+impl ArtifactStore for LocalStore {
+    fn describe(&self) -> String {
+        self.root.display().to_string()
+    }
+
+    async fn put(&self, key: &str, data: &[u8]) -> anyhow::Result<()> {
+        let path = self.root.join(key);
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(&path, data).await?;
+        Ok(())
+    }
+}
+
+/// An S3, GCS or Azure bucket, written through the `upload:` implementation
+pub struct ObjectStore {
+    dest: Destination,
+    client: reqwest::Client,
+    attempts: u32,
+}
+
+#[async_trait]
+impl ArtifactStore for ObjectStore {
+    fn describe(&self) -> String {
+        self.dest.url_for(&self.dest.key_for("")).trim_end_matches('/').to_string()
+    }
+
+    async fn put(&self, key: &str, data: &[u8]) -> anyhow::Result<()> {
+        let key = self.dest.key_for(key);
+        let sums = checksums(data);
+        let mut attempt = 0u32;
+        loop {
+            attempt += 1;
+            match put_object(&self.client, &self.dest, &key, data, &sums).await {
+                Ok(()) => return Ok(()),
+                Err(_) if attempt < self.attempts => tokio::time::sleep(Duration::from_secs(1 << (attempt - 1).min(5))).await,
+                Err(e) => return Err(e.context(format!("upload of {} failed after {} attempt(s)", key, attempt))),
+            }
+        }
+    }
+}
+
+/// Open the store for an (interpolated) `to:` location
+pub fn open(to: &str, retries: Option<u32>) -> anyhow::Result<Box<dyn ArtifactStore>> {
+    if to.contains("://") && !to.starts_with("file://") {
+        return Ok(Box::new(ObjectStore {
+            dest: Destination::parse(to)?,
+            client: reqwest::Client::new(),
+            attempts: retries.unwrap_or(3).max(1),
+        }));
+    }
+    Ok(Box::new(LocalStore { root: PathBuf::from(to.trim_start_matches("file://")) }))
+}
+
+/// Validation: the location must parse unless it is only known once interpolated
+pub fn check(cfg: &ArtifactStoreConfig) -> anyhow::Result<()> {
+    if cfg.to.trim().is_empty() {
+        anyhow::bail!("artifact_store: `to` is empty");
+    }
+    if !cfg.to.contains("{{") {
+        open(&cfg.to, cfg.retries).context("invalid artifact_store")?;
+    }
+    Ok(())
+}
+
+/// Copy every file of `run_dir` (except isolated workspaces) to `store` under `<run id>/`;
+/// returns the number of files pushed
+pub async fn push_run(store: &dyn ArtifactStore, run_dir: &Path) -> anyhow::Result<usize> {
+    let run_id = run_dir.file_name().unwrap_or_default().to_string_lossy().to_string();
+    let mut pushed = 0;
+    let mut stack = vec![run_dir.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let rel = path.strip_prefix(run_dir).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+            if path.is_dir() {
+                if rel != "workspace" {
+                    stack.push(path);
+                }
+                continue;
+            }
+            let data = tokio::fs::read(&path).await?;
+            store.put(&format!("{}/{}", run_id, rel), &data).await?;
+            pushed += 1;
+        }
+    }
+    Ok(pushed)
+}
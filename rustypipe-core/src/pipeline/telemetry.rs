@@ -0,0 +1,234 @@
+//! OpenTelemetry export: once a run has finished it is sent as one trace over OTLP/HTTP (JSON
+//! encoding). The run is the root span, every task a child span and, for retried tasks, every
+//! attempt a child of its task. Configured with the standard variables:
+//! - `OTEL_EXPORTER_OTLP_TRACES_ENDPOINT` (used as is) or `OTEL_EXPORTER_OTLP_ENDPOINT`
+//!   (`/v1/traces` appended); nothing is exported when neither is set
+//! - `OTEL_EXPORTER_OTLP_HEADERS` / `OTEL_EXPORTER_OTLP_TRACES_HEADERS` (`key=value,...`)
+//! - `OTEL_EXPORTER_OTLP_TIMEOUT` / `OTEL_EXPORTER_OTLP_TRACES_TIMEOUT` (milliseconds)
+//! - `OTEL_SERVICE_NAME`, `OTEL_RESOURCE_ATTRIBUTES`
+//! - `OTEL_SDK_DISABLED=true` or an `OTEL_TRACES_EXPORTER` without `otlp` turn export off
+//!
+//! The trace id is the run id, so a resumed run extends the same trace; a W3C `TRACEPARENT`
+//! in the environment (set by some CI systems) makes the run a child of that trace instead.
+use crate::pipeline::manifest::{RunManifest, RunStatus, TaskStatus};
+use crate::pipeline::state::enum_name;
+use crate::util;
+use chrono::{DateTime, Utc};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+/// Where and how to send traces
+#[derive(Debug, Clone)]
+pub struct OtlpConfig {
+    pub endpoint: String,
+    headers: Vec<(String, String)>,
+    timeout: Duration,
+    /// Resource attributes, `service.name` included
+    resource: Vec<(String, String)>,
+}
+
+fn var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|v| !v.trim().is_empty())
+}
+
+/// `key=value,key2=value2`
+fn key_values(s: &str) -> Vec<(String, String)> {
+    s.split(',')
+        .filter_map(|kv| kv.split_once('='))
+        .map(|(k, v)| (k.trim().to_string(), util::percent_decode(v.trim())))
+        .filter(|(k, _)| !k.is_empty())
+        .collect()
+}
+
+impl OtlpConfig {
+    /// Read the `OTEL_*` variables; `None` when trace export is not configured
+    pub fn from_env() -> Option<Self> {
+        if var("OTEL_SDK_DISABLED").is_some_and(|v| v.eq_ignore_ascii_case("true")) {
+            return None;
+        }
+        if var("OTEL_TRACES_EXPORTER").is_some_and(|v| !v.split(',').any(|e| e.trim() == "otlp")) {
+            return None;
+        }
+        let endpoint = var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT")
+            .or_else(|| var("OTEL_EXPORTER_OTLP_ENDPOINT").map(|e| format!("{}/v1/traces", e.trim_end_matches('/'))))?;
+        if let Some(protocol) = var("OTEL_EXPORTER_OTLP_TRACES_PROTOCOL").or_else(|| var("OTEL_EXPORTER_OTLP_PROTOCOL")) {
+            if protocol != "http/json" {
+                tracing::warn!("OTLP protocol '{}' is not supported; sending http/json", protocol);
+            }
+        }
+        let mut headers = var("OTEL_EXPORTER_OTLP_HEADERS").map(|h| key_values(&h)).unwrap_or_default();
+        headers.extend(var("OTEL_EXPORTER_OTLP_TRACES_HEADERS").map(|h| key_values(&h)).unwrap_or_default());
+        let timeout = var("OTEL_EXPORTER_OTLP_TRACES_TIMEOUT")
+            .or_else(|| var("OTEL_EXPORTER_OTLP_TIMEOUT"))
+            .and_then(|t| t.parse().ok())
+            .map(Duration::from_millis)
+            .unwrap_or(Duration::from_secs(10));
+        let mut resource: Vec<(String, String)> = var("OTEL_RESOURCE_ATTRIBUTES").map(|r| key_values(&r)).unwrap_or_default();
+        let service = var("OTEL_SERVICE_NAME").unwrap_or_else(|| "rustypipe".to_string());
+        // OTEL_SERVICE_NAME wins over a service.name resource attribute
+        if var("OTEL_SERVICE_NAME").is_some() || !resource.iter().any(|(k, _)| k == "service.name") {
+            resource.retain(|(k, _)| k != "service.name");
+            resource.push(("service.name".to_string(), service));
+        }
+        Some(OtlpConfig { endpoint, headers, timeout, resource })
+    }
+}
+
+/// Trace id and parent span id from a `TRACEPARENT` like `00-<32 hex>-<16 hex>-01`
+fn traceparent() -> Option<(String, String)> {
+    let tp = var("TRACEPARENT")?;
+    let parts: Vec<&str> = tp.trim().split('-').collect();
+    match parts.as_slice() {
+        [_, trace, span, _] if trace.len() == 32 && span.len() == 16 && hex::decode(trace).is_ok() && hex::decode(span).is_ok() => {
+            Some((trace.to_lowercase(), span.to_lowercase()))
+        }
+        _ => None,
+    }
+}
+
+/// Stable span id, so exporting a resumed run again reuses the ids of unchanged spans
+fn span_id(parts: &[&str]) -> String {
+    hex::encode(&Sha256::digest(parts.join("\0"))[..8])
+}
+
+fn nanos(rfc3339: &str) -> i64 {
+    DateTime::parse_from_rfc3339(rfc3339).ok().and_then(|d| d.timestamp_nanos_opt()).unwrap_or_default()
+}
+
+fn string_attr(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+fn int_attr(key: &str, value: i64) -> Value {
+    json!({ "key": key, "value": { "intValue": value.to_string() } })
+}
+
+fn bool_attr(key: &str, value: bool) -> Value {
+    json!({ "key": key, "value": { "boolValue": value } })
+}
+
+/// OTLP span status: 1 = ok, 2 = error
+fn status(ok: bool, message: Option<&str>) -> Value {
+    match (ok, message) {
+        (true, _) => json!({ "code": 1 }),
+        (false, Some(m)) => json!({ "code": 2, "message": m }),
+        (false, None) => json!({ "code": 2 }),
+    }
+}
+
+fn span(trace_id: &str, id: &str, parent: Option<&str>, name: &str, (start, end): (i64, i64), attributes: Vec<Value>, status: Value) -> Value {
+    let mut span = json!({
+        "traceId": trace_id,
+        "spanId": id,
+        "name": name,
+        "kind": 1,
+        "startTimeUnixNano": start.to_string(),
+        "endTimeUnixNano": end.max(start).to_string(),
+        "attributes": attributes,
+        "status": status,
+    });
+    if let Some(parent) = parent {
+        span["parentSpanId"] = json!(parent);
+    }
+    span
+}
+
+/// The OTLP request body for a run
+fn trace_request(cfg: &OtlpConfig, manifest: &RunManifest) -> Value {
+    let (trace_id, parent) = match traceparent() {
+        Some((trace, span)) => (trace, Some(span)),
+        None => (manifest.id.replace('-', ""), None),
+    };
+    let run_span = span_id(&[&manifest.id]);
+    let run_end = manifest.finished.as_deref().map(nanos).unwrap_or_else(|| Utc::now().timestamp_nanos_opt().unwrap_or_default());
+    let pipeline = manifest.pipeline.as_deref().unwrap_or("rustypipe");
+    let mut spans = vec![span(
+        &trace_id,
+        &run_span,
+        parent.as_deref(),
+        &format!("run {}", pipeline),
+        (nanos(&manifest.started), run_end),
+        vec![
+            string_attr("rustypipe.run.id", &manifest.id),
+            string_attr("rustypipe.pipeline", pipeline),
+            string_attr("rustypipe.run.status", &enum_name(manifest.status)),
+        ],
+        status(manifest.status == RunStatus::Succeeded, None),
+    )];
+    for t in &manifest.tasks {
+        let task_span = span_id(&[&manifest.id, &t.name, &t.started]);
+        let mut attributes = vec![
+            string_attr("rustypipe.task.phase", &enum_name(t.phase)),
+            string_attr("rustypipe.task.status", &enum_name(t.status)),
+            string_attr("rustypipe.task.backend", t.backend.as_deref().unwrap_or("local")),
+            bool_attr("rustypipe.task.cache_hit", t.status == TaskStatus::CacheHit),
+        ];
+        if let Some(code) = t.exit_code {
+            attributes.push(int_attr("rustypipe.task.exit_code", code.into()));
+        }
+        if !t.attempts.is_empty() {
+            attributes.push(int_attr("rustypipe.task.attempts", t.attempts.len() as i64));
+        }
+        let failed = matches!(t.status, TaskStatus::Failed | TaskStatus::Error);
+        let message = match t.status {
+            TaskStatus::Failed => Some(format!("exit code {}", t.exit_code.map_or("-".to_string(), |c| c.to_string()))),
+            TaskStatus::Error => t.attempts.iter().rev().find_map(|a| a.error.clone()),
+            _ => None,
+        };
+        spans.push(span(
+            &trace_id,
+            &task_span,
+            Some(&run_span),
+            &t.name,
+            (nanos(&t.started), nanos(&t.finished)),
+            attributes,
+            status(!failed, message.as_deref()),
+        ));
+        for a in &t.attempts {
+            let start = a.started.as_deref().map(nanos).unwrap_or_else(|| nanos(&t.started));
+            let mut attributes = vec![int_attr("rustypipe.attempt", a.attempt.into())];
+            if let Some(code) = a.exit_code {
+                attributes.push(int_attr("rustypipe.task.exit_code", code.into()));
+            }
+            let ok = a.error.is_none() && a.exit_code == Some(0);
+            spans.push(span(
+                &trace_id,
+                &span_id(&[&manifest.id, &t.name, &t.started, &a.attempt.to_string()]),
+                Some(&task_span),
+                &format!("{} attempt {}", t.name, a.attempt),
+                (start, start + a.duration_ms as i64 * 1_000_000),
+                attributes,
+                status(ok, a.error.as_deref()),
+            ));
+        }
+    }
+    let resource: Vec<Value> = cfg.resource.iter().map(|(k, v)| string_attr(k, v)).collect();
+    json!({
+        "resourceSpans": [{
+            "resource": { "attributes": resource },
+            "scopeSpans": [{
+                "scope": { "name": "rustypipe", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+/// Send the trace of a finished run
+pub async fn export_run(cfg: &OtlpConfig, manifest: &RunManifest) -> anyhow::Result<()> {
+    let mut req = reqwest::Client::new()
+        .post(&cfg.endpoint)
+        .timeout(cfg.timeout)
+        .json(&trace_request(cfg, manifest));
+    for (k, v) in &cfg.headers {
+        req = req.header(k, v);
+    }
+    let resp = req.send().await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        anyhow::bail!("collector responded {}: {}", status, body.trim());
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+//! `templates:` hold partial task bodies that tasks pull in with `extends:`, expanded at load time:
+//! ```yaml
+//! templates:
+//!   remote:
+//!     backend: ssh
+//!     retries: 2
+//!     env: { RUST_LOG: info }
+//!     run_prefix: "set -eu;"
+//! tasks:
+//!   - name: deploy
+//!     extends: remote
+//!     env: { TARGET: prod }
+//!     run: ./deploy.sh
+//! ```
+//! The task is deep-merged over its template: mappings (`env`, `with`, `http`, ...) merge key by
+//! key, any other value set on the task replaces the template's. `run_prefix` is put in front of
+//! the resulting `run` (or the first `script` step). A template may itself `extends:` another template.
+use crate::pipeline::parser::{Pipeline, TaskDef};
+use anyhow::Context;
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+
+/// Merge `over` into `base`: mappings recursively, everything else replaced
+fn deep_merge(base: &mut Value, over: Value) {
+    match (base, over) {
+        (Value::Mapping(base), Value::Mapping(over)) => {
+            for (k, v) in over {
+                match base.get_mut(&k) {
+                    Some(existing) => deep_merge(existing, v),
+                    None => {
+                        base.insert(k, v);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+/// Template `name` with its `extends:` chain applied; `chain` holds the templates being resolved
+fn resolve(templates: &BTreeMap<String, Value>, name: &str, chain: &mut Vec<String>) -> anyhow::Result<Value> {
+    if chain.iter().any(|c| c == name) {
+        anyhow::bail!("template cycle: {} -> {}", chain.join(" -> "), name);
+    }
+    let mut template = templates.get(name).cloned().with_context(|| format!("unknown template '{}'", name))?;
+    let body = template.as_mapping_mut().with_context(|| format!("template '{}' must be a mapping", name))?;
+    match body.remove("extends") {
+        None => Ok(template),
+        Some(Value::String(parent)) => {
+            chain.push(name.to_string());
+            let mut base = resolve(templates, &parent, chain)?;
+            chain.pop();
+            deep_merge(&mut base, template);
+            Ok(base)
+        }
+        Some(_) => anyhow::bail!("template '{}': `extends` must be a template name", name),
+    }
+}
+
+fn apply(task: TaskDef, templates: &BTreeMap<String, Value>) -> anyhow::Result<TaskDef> {
+    let Some(name) = task.extends.clone() else { return Ok(task) };
+    let mut body = resolve(templates, &name, &mut Vec::new())?;
+    let prefix = body.as_mapping_mut().and_then(|m| m.remove("run_prefix"));
+    // only the fields the task sets are serialized, so they are exactly what overrides the template
+    let mut own = serde_yaml::to_value(&task)?;
+    if let Some(m) = own.as_mapping_mut() {
+        m.remove("extends");
+    }
+    deep_merge(&mut body, own);
+    let mut merged: TaskDef = serde_yaml::from_value(body).context("invalid task after applying the template")?;
+    match prefix {
+        None => {}
+        Some(Value::String(prefix)) if !merged.run.is_empty() => merged.run = format!("{} {}", prefix, merged.run),
+        Some(Value::String(prefix)) if !merged.script.is_empty() => merged.script[0] = format!("{} {}", prefix, merged.script[0]),
+        Some(Value::String(prefix)) => anyhow::bail!("template sets `run_prefix: {}` but the task has no `run` or `script`", prefix),
+        Some(_) => anyhow::bail!("`run_prefix` must be a string"),
+    }
+    if merged.kinds().is_empty() {
+        anyhow::bail!("nothing to do after applying template '{}' (set `run`, `script` or a built-in kind)", name);
+    }
+    Ok(merged)
+}
+
+/// Replace every task's `extends:` with the merged template body, then drop `templates:`
+pub fn expand_templates(p: &mut Pipeline) -> anyhow::Result<()> {
+    let templates = std::mem::take(&mut p.templates);
+    for tasks in [&mut p.setup, &mut p.tasks, &mut p.teardown] {
+        *tasks = std::mem::take(tasks)
+            .into_iter()
+            .map(|t| {
+                let name = t.name.clone();
+                apply(t, &templates).with_context(|| format!("task '{}'", name))
+            })
+            .collect::<anyhow::Result<_>>()?;
+    }
+    Ok(())
+}
@@ -0,0 +1,91 @@
+//! `tools:` on a task, e.g. `{node: "20", terraform: "1.7"}`: toolchain versions resolved on the
+//! host through mise (preferred) or asdf before the task runs.
+//!
+//! Missing versions are installed first. With mise the task gets the environment `mise env`
+//! reports for the tools (PATH plus variables like `JAVA_HOME`); with asdf the tools' `bin`
+//! directories are put in front of PATH. Only the task's own environment changes, so other
+//! tasks keep the host's toolchain.
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+/// Environment variables giving a task the requested tool versions
+pub async fn resolve(tools: &BTreeMap<String, String>, dir: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    if crate::backends::program_exists("mise") {
+        with_mise(tools, dir).await
+    } else if crate::backends::program_exists("asdf") {
+        with_asdf(tools, dir).await
+    } else {
+        anyhow::bail!("`tools` need mise or asdf on PATH")
+    }
+}
+
+/// Run `program args` in `dir` and return its stdout
+async fn output(dir: &Path, program: &str, args: &[&str]) -> anyhow::Result<String> {
+    let out = Command::new(program)
+        .args(args)
+        .current_dir(dir)
+        .stdin(std::process::Stdio::null())
+        .output()
+        .await
+        .with_context(|| format!("failed to run {}", program))?;
+    if !out.status.success() {
+        anyhow::bail!("{} {}: {}", program, args.join(" "), String::from_utf8_lossy(&out.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+async fn with_mise(tools: &BTreeMap<String, String>, dir: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    let specs: Vec<String> = tools.iter().map(|(tool, version)| format!("{}@{}", tool, version)).collect();
+    let specs: Vec<&str> = specs.iter().map(String::as_str).collect();
+    output(dir, "mise", &[&["install"][..], &specs].concat()).await?;
+    let env = output(dir, "mise", &[&["env", "--json"][..], &specs].concat()).await?;
+    let env: BTreeMap<String, String> = serde_json::from_str(&env).context("unexpected output of `mise env --json`")?;
+    Ok(env.into_iter().collect())
+}
+
+async fn with_asdf(tools: &BTreeMap<String, String>, dir: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    let plugins = output(dir, "asdf", &["plugin", "list"]).await.unwrap_or_default();
+    let mut bins = Vec::new();
+    for (tool, version) in tools {
+        if !plugins.lines().any(|p| p.trim() == tool) {
+            output(dir, "asdf", &["plugin", "add", tool]).await?;
+        }
+        // asdf wants exact versions: `20` becomes the latest 20.x
+        let version = output(dir, "asdf", &["latest", tool, version]).await.unwrap_or_else(|_| version.clone());
+        output(dir, "asdf", &["install", tool, &version]).await?;
+        let root = output(dir, "asdf", &["where", tool, &version]).await?;
+        bins.push(PathBuf::from(root).join("bin"));
+    }
+    bins.extend(std::env::var_os("PATH").map(|p| std::env::split_paths(&p).collect::<Vec<_>>()).unwrap_or_default());
+    let path = std::env::join_paths(bins).context("tool directory not usable in PATH")?;
+    Ok(vec![("PATH".to_string(), path.to_string_lossy().to_string())])
+}
+
+/// `tools:` map, taking unquoted numbers as versions (`node: 20`); note YAML reads `1.10` as
+/// `1.1`, so such versions need quotes
+pub fn deserialize_versions<'de, D>(d: D) -> Result<BTreeMap<String, String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::Deserialize;
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Version {
+        Str(String),
+        Int(u64),
+        Float(f64),
+    }
+    Ok(BTreeMap::<String, Version>::deserialize(d)?
+        .into_iter()
+        .map(|(tool, v)| {
+            let v = match v {
+                Version::Str(s) => s,
+                Version::Int(i) => i.to_string(),
+                Version::Float(f) => f.to_string(),
+            };
+            (tool, v)
+        })
+        .collect())
+}
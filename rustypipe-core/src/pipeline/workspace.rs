@@ -0,0 +1,113 @@
+//! `workspace: isolated`: run every task in a private copy of the pipeline directory.
+//!
+//! The copy lives in `.rustypipe/runs/<id>/workspace/<pipeline>` and uses reflinks (copy-on-write
+//! clones) where the filesystem supports them, falling back to plain copies. `.rustypipe` itself
+//! is never copied. After the run, files matching the pipeline's `collect:` globs are copied back
+//! into the pipeline directory; the workspace is removed when the run succeeded and kept for
+//! inspection otherwise.
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkspaceMode {
+    /// Tasks run directly in the pipeline directory
+    #[default]
+    Shared,
+    /// Tasks run in a per-run copy of the pipeline directory
+    Isolated,
+}
+
+/// Copy `src` into `dst` (created), skipping `.rustypipe`
+pub fn create(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dst).with_context(|| format!("failed to create workspace {:?}", dst))?;
+    let mut stack = vec![(src.to_path_buf(), dst.to_path_buf())];
+    while let Some((from, to)) = stack.pop() {
+        for entry in std::fs::read_dir(&from).with_context(|| format!("failed to read {:?}", from))? {
+            let entry = entry?;
+            if entry.file_name() == ".rustypipe" {
+                continue;
+            }
+            let (src_path, dst_path) = (entry.path(), to.join(entry.file_name()));
+            let ty = entry.file_type()?;
+            if ty.is_dir() {
+                std::fs::create_dir_all(&dst_path)?;
+                stack.push((src_path, dst_path));
+            } else if ty.is_symlink() {
+                copy_symlink(&src_path, &dst_path)?;
+            } else {
+                clone_file(&src_path, &dst_path).with_context(|| format!("failed to copy {:?}", src_path))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    std::os::unix::fs::symlink(std::fs::read_link(src)?, dst)?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn copy_symlink(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    // symlink creation needs privileges on Windows; copy the target instead
+    std::fs::copy(src, dst)?;
+    Ok(())
+}
+
+/// Reflink the file where possible (btrfs, XFS, ...), otherwise copy it
+#[cfg(target_os = "linux")]
+fn clone_file(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    const FICLONE: libc::c_ulong = 0x40049409;
+    let from = std::fs::File::open(src)?;
+    let to = std::fs::File::create(dst)?;
+    // SAFETY: both descriptors are valid open files for the duration of the call
+    if unsafe { libc::ioctl(to.as_raw_fd(), FICLONE as _, from.as_raw_fd()) } == 0 {
+        to.set_permissions(from.metadata()?.permissions())?;
+        return Ok(());
+    }
+    drop(to);
+    std::fs::copy(src, dst)?;
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn clone_file(src: &Path, dst: &Path) -> anyhow::Result<()> {
+    std::fs::copy(src, dst)?;
+    Ok(())
+}
+
+/// Copy files matching `patterns` (relative to the workspace) back into `dest`; returns the count
+pub fn collect(workspace: &Path, dest: &Path, patterns: &[String]) -> anyhow::Result<usize> {
+    let mut copied = 0;
+    for pattern in patterns {
+        let full = workspace.join(pattern).to_string_lossy().to_string();
+        for path in glob::glob(&full).with_context(|| format!("invalid collect pattern '{}'", pattern))? {
+            let path = path?;
+            let mut files: Vec<PathBuf> = Vec::new();
+            if path.is_dir() {
+                let mut stack = vec![path.clone()];
+                while let Some(d) = stack.pop() {
+                    for e in std::fs::read_dir(&d)? {
+                        let p = e?.path();
+                        if p.is_dir() { stack.push(p) } else { files.push(p) }
+                    }
+                }
+            } else {
+                files.push(path.clone());
+            }
+            for f in files {
+                let target = dest.join(f.strip_prefix(workspace)?);
+                if let Some(parent) = target.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::copy(&f, &target).with_context(|| format!("failed to collect {:?}", f))?;
+                copied += 1;
+            }
+        }
+    }
+    Ok(copied)
+}
@@ -0,0 +1,572 @@
+//! Plugins adjust task definitions before a run (`preprocess`). A pipeline enables them by name
+//! in `plugins:`, each with an optional `config` it receives along with every task:
+//! ```yaml
+//! plugin_dir: tools/plugins      # default: $RUSTYPIPE_PLUGIN_DIR, then .rustypipe/plugins
+//! plugins:
+//!   stamp: { config: { label: nightly } }
+//! ```
+//! A plugin is found in the plugin directory by its name: a dynamic library (`libstamp.so`,
+//! `stamp.dll`, `libstamp.dylib`) or a WebAssembly module (`stamp.wasm`); `wasm: path` (relative
+//! to the pipeline file) names a module elsewhere, and `command: ...` makes it an external process.
+//!
+//! All kinds exchange JSON with the host, so a plugin need not be built with the same compiler
+//! (or in Rust at all). The `config:` mapping of the plugin's `plugins:` entry is passed once when
+//! it is loaded, to the optional `configure`, as `{"config": ...}`; it answers `{}` to accept it or
+//! `{"error": "..."}` to refuse the pipeline. `preprocess` gets `{"task": {...}, "vars": {...}, "config": ...}` and
+//! answers `{"task": {...}}` to replace the task, `{}` to keep it or `{"error": "..."}` to fail
+//! the run.
+//!
+//! Native plugins export a C ABI:
+//! - `rustypipe_plugin_abi_version() -> u32` returns [`PLUGIN_ABI_VERSION`]
+//! - `rustypipe_plugin_preprocess(request: *const c_char) -> *mut c_char`
+//! - optionally `rustypipe_plugin_configure(request: *const c_char) -> *mut c_char`
+//! - `rustypipe_plugin_free(answer: *mut c_char)` releases an answer
+//! - optionally `rustypipe_plugin_on_pipeline_start`, `rustypipe_plugin_on_task_start`,
+//!   `rustypipe_plugin_on_task_complete` and `rustypipe_plugin_on_pipeline_end`
+//!   (`request: *const c_char`), which get the arguments of the [`Plugin`] hooks as JSON
+//!
+//! WebAssembly plugins are sandboxed: they can import nothing but `rustypipe.log(ptr, len)`
+//! (a line for the console), and each call is limited in instructions and memory. They export:
+//! - `memory`, and `rustypipe_alloc(len: i32) -> i32` for the host to write requests into
+//! - `rustypipe_plugin_abi_version() -> i32`
+//! - `rustypipe_plugin_preprocess(ptr: i32, len: i32) -> i64`: the answer as `ptr << 32 | len`
+//! - optionally `rustypipe_plugin_configure(ptr: i32, len: i32) -> i64`, answering the same way
+//! - optionally `rustypipe_plugin_on_event(ptr: i32, len: i32)`, which receives every run event
+//!   (see `pipeline::events`) as JSON
+//! - optionally `rustypipe_plugin_on_pipeline_start`, `..._on_task_start`, `..._on_task_complete`
+//!   and `..._on_pipeline_end` (`ptr: i32, len: i32`), like the native ones
+//!
+//! Process plugins are started once per pipeline (with `sh -c`, in the pipeline file's directory)
+//! and speak JSON-RPC 2.0, one message per line, on stdin/stdout; stderr goes to the console:
+//! - requests `configure` (which may be unknown to the plugin) and `preprocess` with the objects
+//!   above as `params`, answered by a result as above or a JSON-RPC error
+//! - notifications `on_pipeline_start`, `on_task_start`, `on_task_complete` and
+//!   `on_pipeline_end` with the arguments of the [`Plugin`] hooks as `params`, and
+//!   `on_task_finished` with the `task_finished` run event
+use crate::pipeline::events::RunEvent;
+use crate::pipeline::manifest::{RunStatus, TaskStatus};
+use crate::pipeline::parser::{PluginDef, TaskDef};
+use anyhow::Context;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{c_char, CStr, CString};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+/// Version of the native plugin ABI; libraries reporting another one are refused
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+/// Simple plugin trait; real plugins could be dynamic libraries or config-driven
+pub trait Plugin: Send + Sync {
+    fn name(&self) -> &str;
+    fn preprocess(&self, _task: &mut crate::pipeline::parser::TaskDef, _globals: &HashMap<String,String>) -> anyhow::Result<()>;
+    /// Called with every event of a run
+    fn on_event(&self, _event: &RunEvent) {}
+    /// Called once a run of a pipeline using the plugin has started
+    fn on_pipeline_start(&self, _run_id: &str, _pipeline: &str) {}
+    /// Called before a task's command runs
+    fn on_task_start(&self, _task: &TaskDef) {}
+    /// Called when a task has finished, been skipped or cancelled
+    fn on_task_complete(&self, _task: &TaskCompletion) {}
+    /// Called when the run has finished, successfully or not
+    fn on_pipeline_end(&self, _run_id: &str, _status: RunStatus) {}
+}
+
+/// A finished task, as passed to [`Plugin::on_task_complete`]
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskCompletion {
+    pub task: String,
+    pub status: TaskStatus,
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    /// `{{task.output}}`
+    pub output: String,
+    /// Values of the task's `outputs:`
+    pub outputs: BTreeMap<String, serde_json::Value>,
+}
+
+/// The [`Plugin`] hooks native plugins may export, as `rustypipe_plugin_` + name
+const HOOKS: [&str; 4] = ["on_pipeline_start", "on_task_start", "on_task_complete", "on_pipeline_end"];
+
+/// JSON arguments of the [`Plugin::on_pipeline_start`] hook
+fn pipeline_start_params(run_id: &str, pipeline: &str) -> serde_json::Value {
+    serde_json::json!({ "run_id": run_id, "pipeline": pipeline })
+}
+
+/// JSON arguments of the [`Plugin::on_task_start`] hook
+fn task_start_params(task: &TaskDef) -> serde_json::Value {
+    serde_json::json!({ "task": task })
+}
+
+/// JSON arguments of the [`Plugin::on_pipeline_end`] hook
+fn pipeline_end_params(run_id: &str, status: RunStatus) -> serde_json::Value {
+    serde_json::json!({ "run_id": run_id, "status": status })
+}
+
+/// Example trivial plugin that does nothing
+pub struct NoopPlugin;
+impl Plugin for NoopPlugin {
+    fn name(&self) -> &str { "noop" }
+    fn preprocess(&self, _task: &mut crate::pipeline::parser::TaskDef, _globals: &HashMap<String,String>) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+type PreprocessFn = unsafe extern "C" fn(*const c_char) -> *mut c_char;
+type FreeFn = unsafe extern "C" fn(*mut c_char);
+type HookFn = unsafe extern "C" fn(*const c_char);
+
+/// A plugin loaded from a dynamic library
+pub struct NativePlugin {
+    name: String,
+    config: serde_json::Value,
+    preprocess: PreprocessFn,
+    free: FreeFn,
+    /// The optional hooks the library exports, by [`HOOKS`] name
+    hooks: HashMap<&'static str, HookFn>,
+    /// Keeps the functions above loaded
+    _library: libloading::Library,
+}
+
+impl NativePlugin {
+    pub fn load(name: &str, path: &Path, config: serde_json::Value) -> anyhow::Result<Self> {
+        // SAFETY: loading runs the library's initializers; plugins are code the pipeline opted into
+        let library = unsafe { libloading::Library::new(path) }.with_context(|| format!("failed to load {:?}", path))?;
+        // SAFETY: the symbol types are the documented plugin ABI
+        unsafe {
+            let version = library
+                .get::<unsafe extern "C" fn() -> u32>(b"rustypipe_plugin_abi_version")
+                .with_context(|| format!("{:?} is no rustypipe plugin", path))?();
+            if version != PLUGIN_ABI_VERSION {
+                anyhow::bail!("{:?} implements plugin ABI {}, expected {}", path, version, PLUGIN_ABI_VERSION);
+            }
+            let preprocess = *library.get::<PreprocessFn>(b"rustypipe_plugin_preprocess")?;
+            let free = *library.get::<FreeFn>(b"rustypipe_plugin_free")?;
+            let hooks = HOOKS
+                .into_iter()
+                .filter_map(|hook| library.get::<HookFn>(format!("rustypipe_plugin_{}", hook).as_bytes()).ok().map(|f| (hook, *f)))
+                .collect();
+            let plugin = Self { name: name.to_string(), config, preprocess, free, hooks, _library: library };
+            if let Ok(configure) = plugin._library.get::<PreprocessFn>(b"rustypipe_plugin_configure") {
+                check_answer(&plugin.exchange(*configure, &serde_json::json!({ "config": plugin.config }))?).context("invalid config")?;
+            }
+            Ok(plugin)
+        }
+    }
+
+    /// Pass `request` to one of the plugin's functions and return its answer
+    fn exchange(&self, function: PreprocessFn, request: &serde_json::Value) -> anyhow::Result<String> {
+        let request = CString::new(request.to_string())?;
+        // SAFETY: the plugin gets a NUL-terminated string that outlives the call and returns one
+        // it allocated (or null), which goes back to its own `free`
+        unsafe {
+            let ptr = function(request.as_ptr());
+            if ptr.is_null() {
+                anyhow::bail!("no answer");
+            }
+            let answer = CStr::from_ptr(ptr).to_string_lossy().into_owned();
+            (self.free)(ptr);
+            Ok(answer)
+        }
+    }
+
+    /// Pass `params` to the exported hook `hook`, if there is one
+    fn hook(&self, hook: &str, params: serde_json::Value) {
+        let Some(function) = self.hooks.get(hook) else { return };
+        let Ok(request) = CString::new(params.to_string()) else { return };
+        // SAFETY: the plugin gets a NUL-terminated string that outlives the call
+        unsafe { function(request.as_ptr()) }
+    }
+}
+
+impl Plugin for NativePlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn preprocess(&self, task: &mut TaskDef, globals: &HashMap<String, String>) -> anyhow::Result<()> {
+        let answer = self.exchange(self.preprocess, &serde_json::json!({ "task": task, "vars": globals, "config": self.config }))?;
+        apply_answer(task, &answer)
+    }
+
+    fn on_pipeline_start(&self, run_id: &str, pipeline: &str) {
+        self.hook("on_pipeline_start", pipeline_start_params(run_id, pipeline));
+    }
+
+    fn on_task_start(&self, task: &TaskDef) {
+        self.hook("on_task_start", task_start_params(task));
+    }
+
+    fn on_task_complete(&self, task: &TaskCompletion) {
+        self.hook("on_task_complete", serde_json::to_value(task).unwrap_or_default());
+    }
+
+    fn on_pipeline_end(&self, run_id: &str, status: RunStatus) {
+        self.hook("on_pipeline_end", pipeline_end_params(run_id, status));
+    }
+}
+
+/// Apply a plugin's JSON answer (`{"task": ...}`, `{}` or `{"error": ...}`) to `task`
+fn apply_answer(task: &mut TaskDef, answer: &str) -> anyhow::Result<()> {
+    let answer = check_answer(answer)?;
+    if let Some(changed) = answer.get("task") {
+        *task = serde_json::from_value(changed.clone()).context("invalid task in answer")?;
+    }
+    Ok(())
+}
+
+/// Parse a plugin's answer, failing on `{"error": "..."}`
+fn check_answer(answer: &str) -> anyhow::Result<serde_json::Value> {
+    let answer: serde_json::Value = serde_json::from_str(answer).context("invalid answer")?;
+    if let Some(error) = answer.get("error") {
+        anyhow::bail!("{}", error.as_str().map(str::to_string).unwrap_or_else(|| error.to_string()));
+    }
+    Ok(answer)
+}
+
+/// Instructions a WebAssembly plugin may execute per call
+const WASM_FUEL: u64 = 2_000_000_000;
+/// Memory a WebAssembly plugin may grow to
+const WASM_MEMORY: usize = 256 << 20;
+
+struct WasmState {
+    plugin: String,
+    limits: wasmtime::StoreLimits,
+}
+
+/// A sandboxed plugin compiled to WebAssembly
+pub struct WasmPlugin {
+    name: String,
+    config: serde_json::Value,
+    instance: Mutex<(wasmtime::Store<WasmState>, wasmtime::Instance)>,
+}
+
+impl WasmPlugin {
+    pub fn load(name: &str, path: &Path, config: serde_json::Value) -> anyhow::Result<Self> {
+        let mut engine_config = wasmtime::Config::new();
+        engine_config.consume_fuel(true);
+        let engine = wasmtime::Engine::new(&engine_config)?;
+        let bytes = std::fs::read(path).with_context(|| format!("failed to read {:?}", path))?;
+        let module = wasmtime::Module::new(&engine, bytes).with_context(|| format!("{:?} is no WebAssembly module", path))?;
+        let mut linker = wasmtime::Linker::new(&engine);
+        linker.func_wrap("rustypipe", "log", |mut caller: wasmtime::Caller<'_, WasmState>, ptr: i32, len: i32| {
+            let Some(memory) = caller.get_export("memory").and_then(|e| e.into_memory()) else { return };
+            let mut buf = vec![0u8; len.max(0) as usize];
+            if memory.read(&caller, ptr as usize, &mut buf).is_ok() {
+                eprintln!("[plugin {}] {}", caller.data().plugin, String::from_utf8_lossy(&buf));
+            }
+        })?;
+        let limits = wasmtime::StoreLimitsBuilder::new().memory_size(WASM_MEMORY).build();
+        let mut store = wasmtime::Store::new(&engine, WasmState { plugin: name.to_string(), limits });
+        store.limiter(|s| &mut s.limits);
+        store.set_fuel(WASM_FUEL)?;
+        let instance = linker.instantiate(&mut store, &module)?;
+        let version = instance.get_typed_func::<(), i32>(&mut store, "rustypipe_plugin_abi_version").with_context(|| format!("{:?} is no rustypipe plugin", path))?.call(&mut store, ())?;
+        if version != PLUGIN_ABI_VERSION as i32 {
+            anyhow::bail!("{:?} implements plugin ABI {}, expected {}", path, version, PLUGIN_ABI_VERSION);
+        }
+        if instance.get_export(&mut store, "rustypipe_plugin_configure").is_some() {
+            let request = serde_json::json!({ "config": config }).to_string();
+            check_answer(&Self::exchange(&mut store, &instance, "rustypipe_plugin_configure", &request)?).context("invalid config")?;
+        }
+        Ok(Self { name: name.to_string(), config, instance: Mutex::new((store, instance)) })
+    }
+
+    /// Pass `request` to one of the plugin's functions and return its answer
+    fn exchange(store: &mut wasmtime::Store<WasmState>, instance: &wasmtime::Instance, function: &str, request: &str) -> anyhow::Result<String> {
+        store.set_fuel(WASM_FUEL)?;
+        let (ptr, len) = Self::write(store, instance, request.as_bytes())?;
+        let packed = instance.get_typed_func::<(i32, i32), i64>(&mut *store, function)?.call(&mut *store, (ptr, len))?;
+        let (ptr, len) = ((packed >> 32) as u32 as usize, packed as u32 as usize);
+        let memory = instance.get_memory(&mut *store, "memory").context("the plugin exports no memory")?;
+        let answer = memory.data(&*store).get(ptr..ptr + len).context("answer outside the plugin's memory")?;
+        Ok(String::from_utf8_lossy(answer).into_owned())
+    }
+
+    /// Copy `data` into the guest's memory; returns its address
+    fn write(store: &mut wasmtime::Store<WasmState>, instance: &wasmtime::Instance, data: &[u8]) -> anyhow::Result<(i32, i32)> {
+        let len = i32::try_from(data.len()).context("request too large")?;
+        let ptr = instance.get_typed_func::<i32, i32>(&mut *store, "rustypipe_alloc")?.call(&mut *store, len)?;
+        let memory = instance.get_memory(&mut *store, "memory").context("the plugin exports no memory")?;
+        memory.write(&mut *store, ptr as usize, data)?;
+        Ok((ptr, len))
+    }
+
+    /// Pass `request` to the exported `function`, if there is one, which answers nothing
+    fn notify(&self, function: &str, request: &str) {
+        let mut guard = self.instance.lock().unwrap_or_else(|e| e.into_inner());
+        let (store, instance) = &mut *guard;
+        let Ok(handler) = instance.get_typed_func::<(i32, i32), ()>(&mut *store, function) else { return };
+        let res = (|| {
+            store.set_fuel(WASM_FUEL)?;
+            let (ptr, len) = Self::write(store, instance, request.as_bytes())?;
+            handler.call(&mut *store, (ptr, len))
+        })();
+        if let Err(e) = res {
+            tracing::warn!("plugin '{}' failed in {}: {:#}", self.name, function, e);
+        }
+    }
+}
+
+impl Plugin for WasmPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn preprocess(&self, task: &mut TaskDef, globals: &HashMap<String, String>) -> anyhow::Result<()> {
+        let request = serde_json::json!({ "task": task, "vars": globals, "config": self.config }).to_string();
+        let mut guard = self.instance.lock().unwrap_or_else(|e| e.into_inner());
+        let (store, instance) = &mut *guard;
+        let answer = Self::exchange(store, instance, "rustypipe_plugin_preprocess", &request)?;
+        apply_answer(task, &answer)
+    }
+
+    fn on_event(&self, event: &RunEvent) {
+        if let Ok(request) = serde_json::to_string(event) {
+            self.notify("rustypipe_plugin_on_event", &request);
+        }
+    }
+
+    fn on_pipeline_start(&self, run_id: &str, pipeline: &str) {
+        self.notify("rustypipe_plugin_on_pipeline_start", &pipeline_start_params(run_id, pipeline).to_string());
+    }
+
+    fn on_task_start(&self, task: &TaskDef) {
+        self.notify("rustypipe_plugin_on_task_start", &task_start_params(task).to_string());
+    }
+
+    fn on_task_complete(&self, task: &TaskCompletion) {
+        self.notify("rustypipe_plugin_on_task_complete", &serde_json::to_value(task).unwrap_or_default().to_string());
+    }
+
+    fn on_pipeline_end(&self, run_id: &str, status: RunStatus) {
+        self.notify("rustypipe_plugin_on_pipeline_end", &pipeline_end_params(run_id, status).to_string());
+    }
+}
+
+/// A JSON-RPC error response
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    const METHOD_NOT_FOUND: i64 = -32601;
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+struct PluginProcess {
+    child: std::process::Child,
+    stdin: std::process::ChildStdin,
+    stdout: std::io::BufReader<std::process::ChildStdout>,
+    next_id: u64,
+}
+
+/// A plugin running as a separate process, speaking JSON-RPC over stdin/stdout
+pub struct ProcessPlugin {
+    name: String,
+    config: serde_json::Value,
+    process: Mutex<PluginProcess>,
+}
+
+impl ProcessPlugin {
+    pub fn spawn(name: &str, command: &str, dir: &Path, config: serde_json::Value) -> anyhow::Result<Self> {
+        let mut cmd = if cfg!(windows) {
+            let mut c = std::process::Command::new("cmd.exe");
+            c.args(["/D", "/C", command]);
+            c
+        } else {
+            let mut c = std::process::Command::new("sh");
+            c.args(["-c", command]);
+            c
+        };
+        let mut child = cmd
+            .current_dir(dir)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .env("RUSTYPIPE_PLUGIN", name)
+            .spawn()
+            .with_context(|| format!("failed to start '{}'", command))?;
+        let stdin = child.stdin.take().context("no stdin")?;
+        let stdout = std::io::BufReader::new(child.stdout.take().context("no stdout")?);
+        let plugin = Self { name: name.to_string(), config, process: Mutex::new(PluginProcess { child, stdin, stdout, next_id: 1 }) };
+        match plugin.call("configure", serde_json::json!({ "config": plugin.config })) {
+            Err(e) if e.downcast_ref::<RpcError>().is_some_and(|e| e.code == RpcError::METHOD_NOT_FOUND) => {}
+            Err(e) => return Err(e.context("invalid config")),
+            Ok(result) => {
+                check_answer(&result.to_string()).context("invalid config")?;
+            }
+        }
+        Ok(plugin)
+    }
+
+    /// Send a request and wait for its response's `result`
+    fn call(&self, method: &str, params: serde_json::Value) -> anyhow::Result<serde_json::Value> {
+        use std::io::{BufRead, Write};
+        let mut process = self.process.lock().unwrap_or_else(|e| e.into_inner());
+        let id = process.next_id;
+        process.next_id += 1;
+        let request = serde_json::json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        writeln!(process.stdin, "{}", request).and_then(|_| process.stdin.flush()).context("the plugin process exited")?;
+        loop {
+            let mut line = String::new();
+            if process.stdout.read_line(&mut line)? == 0 {
+                anyhow::bail!("the plugin process exited before answering '{}'", method);
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            let response: serde_json::Value = serde_json::from_str(&line).with_context(|| format!("invalid JSON-RPC message: {}", line.trim()))?;
+            // Anything not answering this request (such as a notification) is skipped
+            if response.get("id") != Some(&serde_json::json!(id)) {
+                continue;
+            }
+            if let Some(error) = response.get("error") {
+                return Err(RpcError {
+                    code: error.get("code").and_then(|c| c.as_i64()).unwrap_or_default(),
+                    message: error.get("message").and_then(|m| m.as_str()).map(str::to_string).unwrap_or_else(|| error.to_string()),
+                }
+                .into());
+            }
+            return Ok(response.get("result").cloned().unwrap_or(serde_json::Value::Null));
+        }
+    }
+
+    /// Send a notification, which gets no response; a plugin that went away is only warned about
+    fn hook(&self, method: &str, params: serde_json::Value) {
+        use std::io::Write;
+        let mut process = self.process.lock().unwrap_or_else(|e| e.into_inner());
+        let message = serde_json::json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        if let Err(e) = writeln!(process.stdin, "{}", message).and_then(|_| process.stdin.flush()) {
+            tracing::warn!("plugin '{}' failed to handle {}: {}", self.name, method, e);
+        }
+    }
+}
+
+impl Plugin for ProcessPlugin {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn preprocess(&self, task: &mut TaskDef, globals: &HashMap<String, String>) -> anyhow::Result<()> {
+        let result = self.call("preprocess", serde_json::json!({ "task": task, "vars": globals, "config": self.config }))?;
+        let answer = if result.is_null() { "{}".to_string() } else { result.to_string() };
+        apply_answer(task, &answer)
+    }
+
+    fn on_event(&self, event: &RunEvent) {
+        if matches!(event, RunEvent::TaskFinished { .. }) {
+            self.hook("on_task_finished", serde_json::to_value(event).unwrap_or_default());
+        }
+    }
+
+    fn on_pipeline_start(&self, run_id: &str, pipeline: &str) {
+        self.hook("on_pipeline_start", pipeline_start_params(run_id, pipeline));
+    }
+
+    fn on_task_start(&self, task: &TaskDef) {
+        self.hook("on_task_start", task_start_params(task));
+    }
+
+    fn on_task_complete(&self, task: &TaskCompletion) {
+        self.hook("on_task_complete", serde_json::to_value(task).unwrap_or_default());
+    }
+
+    fn on_pipeline_end(&self, run_id: &str, status: RunStatus) {
+        self.hook("on_pipeline_end", pipeline_end_params(run_id, status));
+    }
+}
+
+impl Drop for ProcessPlugin {
+    fn drop(&mut self) {
+        let process = self.process.get_mut().unwrap_or_else(|e| e.into_inner());
+        let _ = process.child.kill();
+        let _ = process.child.wait();
+    }
+}
+
+/// Plugins of the pipelines loaded by this process, which receive run events
+static ACTIVE: RwLock<Vec<Arc<dyn Plugin>>> = RwLock::new(Vec::new());
+
+/// Make `plugins` receive run events, replacing loaded plugins of the same name
+pub fn activate(plugins: &[Arc<dyn Plugin>]) {
+    let mut active = ACTIVE.write().unwrap_or_else(|e| e.into_inner());
+    active.retain(|a| !plugins.iter().any(|p| p.name() == a.name()));
+    active.extend(plugins.iter().cloned());
+}
+
+/// The active plugins named `names`
+pub fn active<'a>(names: impl IntoIterator<Item = &'a String>) -> Vec<Arc<dyn Plugin>> {
+    let active = ACTIVE.read().unwrap_or_else(|e| e.into_inner());
+    names.into_iter().filter_map(|n| active.iter().find(|p| p.name() == n).cloned()).collect()
+}
+
+/// Pass a run event to every active plugin
+pub fn notify(event: &RunEvent) {
+    for plugin in ACTIVE.read().unwrap_or_else(|e| e.into_inner()).iter() {
+        plugin.on_event(event);
+    }
+}
+
+/// Directory plugins are looked up in: `plugin_dir` (relative to the pipeline file),
+/// `$RUSTYPIPE_PLUGIN_DIR` or `.rustypipe/plugins`
+pub fn plugin_dir(configured: Option<&str>, base_dir: &Path) -> PathBuf {
+    match (configured, std::env::var_os("RUSTYPIPE_PLUGIN_DIR")) {
+        (Some(dir), _) => base_dir.join(dir),
+        (None, Some(dir)) => PathBuf::from(dir),
+        (None, None) => Path::new(".rustypipe").join("plugins"),
+    }
+}
+
+/// Load the enabled plugins; `base_dir` is the pipeline file's directory
+pub fn load(defs: &BTreeMap<String, PluginDef>, dir: &Path, base_dir: &Path) -> anyhow::Result<Vec<Arc<dyn Plugin>>> {
+    let mut plugins: Vec<Arc<dyn Plugin>> = Vec::new();
+    for (name, def) in defs {
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+            anyhow::bail!("invalid plugin name '{}'", name);
+        }
+        if !def.config.is_null() && !def.config.is_object() {
+            anyhow::bail!("plugin '{}': `config` must be a mapping", name);
+        }
+        let native = dir.join(format!("{}{}{}", std::env::consts::DLL_PREFIX, name, std::env::consts::DLL_SUFFIX));
+        let wasm = match &def.wasm {
+            Some(path) => base_dir.join(path),
+            None => dir.join(format!("{}.wasm", name)),
+        };
+        if def.wasm.is_some() && def.command.is_some() {
+            anyhow::bail!("plugin '{}': `wasm` and `command` are mutually exclusive", name);
+        }
+        let plugin: Arc<dyn Plugin> = if let Some(command) = &def.command {
+            Arc::new(ProcessPlugin::spawn(name, command, base_dir, def.config.clone()).with_context(|| format!("plugin '{}'", name))?)
+        } else if def.wasm.is_none() && native.is_file() {
+            Arc::new(NativePlugin::load(name, &native, def.config.clone()).with_context(|| format!("plugin '{}'", name))?)
+        } else if wasm.is_file() {
+            Arc::new(WasmPlugin::load(name, &wasm, def.config.clone()).with_context(|| format!("plugin '{}'", name))?)
+        } else if def.wasm.is_some() {
+            anyhow::bail!("plugin '{}': {:?} not found", name, wasm);
+        } else {
+            anyhow::bail!("plugin '{}': neither {:?} nor {:?} found", name, native, wasm);
+        };
+        plugins.push(plugin);
+    }
+    Ok(plugins)
+}
+
+/// Run every plugin's `preprocess` on every task, plugins in name order
+pub fn preprocess(plugins: &[Arc<dyn Plugin>], tasks: &mut [TaskDef], vars: &HashMap<String, String>) -> anyhow::Result<()> {
+    for plugin in plugins {
+        for task in tasks.iter_mut() {
+            let name = task.name.clone();
+            plugin.preprocess(task, vars).with_context(|| format!("plugin '{}' failed on task '{}'", plugin.name(), name))?;
+        }
+    }
+    Ok(())
+}
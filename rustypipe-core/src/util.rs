@@ -0,0 +1,307 @@
+use regex::Regex;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use uuid::Uuid;
+use std::fs;
+
+/// Target of the machine-readable progress events; only shown with `--log-format json`
+pub const EVENTS: &str = "rustypipe::events";
+
+/// Set by `--log-format json`: stdout then carries only JSON events and human-readable
+/// output moves to stderr.
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+pub fn set_json_output(enabled: bool) {
+    JSON_OUTPUT.store(enabled, Ordering::Relaxed);
+}
+
+pub fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Set by `run --tui`: the terminal belongs to the TUI, so output goes to it instead of the
+/// console.
+static TUI: AtomicBool = AtomicBool::new(false);
+
+pub fn set_tui(enabled: bool) {
+    TUI.store(enabled, Ordering::Relaxed);
+}
+
+pub fn tui() -> bool {
+    TUI.load(Ordering::Relaxed)
+}
+
+/// Set while the TUI or the progress display owns the terminal: human-readable output is handed
+/// to it (`events::message`) instead of being printed.
+static CONSOLE_OBSERVED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_console_observed(enabled: bool) {
+    CONSOLE_OBSERVED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn console_observed() -> bool {
+    CONSOLE_OBSERVED.load(Ordering::Relaxed)
+}
+
+/// Set by `--allow-missing-vars`: unknown template references render empty instead of failing.
+static ALLOW_MISSING_VARS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_allow_missing_vars(enabled: bool) {
+    ALLOW_MISSING_VARS.store(enabled, Ordering::Relaxed);
+}
+
+pub fn allow_missing_vars() -> bool {
+    ALLOW_MISSING_VARS.load(Ordering::Relaxed)
+}
+
+/// Environment rendering the `{{ ... }}` templates of task fields. Rendering an unknown
+/// reference fails unless `lenient` (testing it, as in `{% if vars.X %}`, is fine either way).
+/// Comments are `{## ... ##}`, since `{#` is common in shell (`${#array[@]}`).
+///
+/// Besides minijinja's builtin filters there is `jsonpath`: `{{ x.output | jsonpath('$.a[0].url') }}`.
+fn new_template_env<'s>(lenient: bool) -> minijinja::Environment<'s> {
+    let mut env = minijinja::Environment::new();
+    env.add_filter("jsonpath", move |value: minijinja::Value, path: String| jsonpath_filter(value, &path, lenient));
+    env.set_undefined_behavior(if lenient { minijinja::UndefinedBehavior::Chainable } else { minijinja::UndefinedBehavior::SemiStrict });
+    let syntax = minijinja::syntax::SyntaxConfig::builder()
+        .comment_delimiters("{##", "##}")
+        .keep_trailing_newline(true)
+        .build()
+        .expect("valid template syntax");
+    env.set_syntax(syntax);
+    env
+}
+
+/// Parses text as JSON; other values (e.g. `outputs:` of `type: json`) are used as they are.
+/// A path matching nothing fails unless `lenient`.
+fn jsonpath_filter(value: minijinja::Value, path: &str, lenient: bool) -> Result<minijinja::Value, minijinja::Error> {
+    let invalid = |msg: String| minijinja::Error::new(minijinja::ErrorKind::InvalidOperation, msg);
+    let json: serde_json::Value = match value.as_str() {
+        Some(text) => serde_json::from_str(text.trim()).map_err(|e| invalid(format!("jsonpath: input is not valid JSON: {}", e)))?,
+        None => serde_json::to_value(&value).map_err(|e| invalid(format!("jsonpath: {}", e)))?,
+    };
+    match crate::pipeline::filters::select_json(&json, path) {
+        Ok(found) => Ok(minijinja::Value::from(minijinja::value::Serde(&found))),
+        Err(_) if lenient && crate::pipeline::filters::json_path(&json, path).is_ok() => Ok(minijinja::Value::UNDEFINED),
+        Err(e) => Err(invalid(format!("jsonpath: {}", e))),
+    }
+}
+
+fn template_env(lenient: bool) -> &'static minijinja::Environment<'static> {
+    static STRICT: OnceLock<minijinja::Environment<'static>> = OnceLock::new();
+    static LENIENT: OnceLock<minijinja::Environment<'static>> = OnceLock::new();
+    if lenient {
+        LENIENT.get_or_init(|| new_template_env(true))
+    } else {
+        STRICT.get_or_init(|| new_template_env(false))
+    }
+}
+
+fn is_identifier(s: &str) -> bool {
+    s.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// `{{ build-app.output }}` is no valid expression when the task name is not an identifier
+/// (`-`, the `pipeline:` prefix of multi-file runs, `[...]` of matrix instances); such
+/// references are rewritten to `{{ tasks["build-app"].output }}`
+fn quote_task_refs(template: &str) -> std::borrow::Cow<'_, str> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    // only the reference is rewritten, so filters may follow (`{{ build-app.output | trim }}`)
+    let re = RE.get_or_init(|| Regex::new(r"\{\{(-?)\s*([^\s{}|()]+)\.(output\b|exit_code\b|outputs\.\w+)").unwrap());
+    re.replace_all(template, |c: &regex::Captures| {
+        if is_identifier(&c[2]) {
+            c[0].to_string()
+        } else {
+            format!("{{{{{} tasks[{}].{}", &c[1], serde_json::Value::from(&c[2]), &c[3])
+        }
+    })
+}
+
+/// Host environment variables whose name matches one of `allowlist` (names or globs); all of
+/// them without one
+pub fn host_env(allowlist: Option<&[String]>) -> HashMap<String, String> {
+    let patterns: Option<Vec<glob::Pattern>> = allowlist.map(|l| l.iter().filter_map(|p| glob::Pattern::new(p).ok()).collect());
+    std::env::vars().filter(|(k, _)| patterns.as_ref().is_none_or(|ps| ps.iter().any(|p| p.matches(k)))).collect()
+}
+
+/// Everything templates can reference
+#[derive(Debug, Default, Clone)]
+pub struct TemplateInputs {
+    /// `{{ task.output }}`
+    pub outputs: HashMap<String, String>,
+    /// `{{ task.exit_code }}`
+    pub exit_codes: HashMap<String, i32>,
+    /// `{{ task.outputs.NAME }}`: the typed values a task declares in `outputs:`
+    pub named: HashMap<String, BTreeMap<String, serde_json::Value>>,
+    pub vars: HashMap<String, String>,
+    /// `{{ env.NAME }}`: the host environment
+    pub env: HashMap<String, String>,
+}
+
+/// `vars`, `env`, `tasks` (name -> `output`, `exit_code`, `outputs`) and every task whose name is an identifier
+fn template_context(inputs: &TemplateInputs) -> minijinja::Value {
+    let mut tasks = serde_json::Map::new();
+    for (name, out) in &inputs.outputs {
+        tasks.insert(name.clone(), serde_json::json!({ "output": out.trim() }));
+    }
+    for (name, code) in &inputs.exit_codes {
+        tasks.entry(name.clone()).or_insert_with(|| serde_json::json!({}))["exit_code"] = (*code).into();
+    }
+    for (name, values) in &inputs.named {
+        tasks.entry(name.clone()).or_insert_with(|| serde_json::json!({}))["outputs"] = serde_json::json!(values);
+    }
+    let mut ctx: serde_json::Map<String, serde_json::Value> = tasks.iter().filter(|(n, _)| is_identifier(n)).map(|(n, t)| (n.clone(), t.clone())).collect();
+    ctx.insert("tasks".to_string(), tasks.into());
+    ctx.insert("vars".to_string(), serde_json::json!(inputs.vars));
+    ctx.insert("env".to_string(), serde_json::json!(inputs.env));
+    minijinja::Value::from(minijinja::value::Serde(&ctx))
+}
+
+/// Render a template (minijinja: filters, `{% if %}`, whitespace control) against
+/// `{{ vars.NAME }}`, `{{ env.NAME }}` (host environment), `{{ task.output }}` and
+/// `{{ task.outputs.NAME }}`
+pub fn interpolate_command(template: &str, inputs: &TemplateInputs) -> anyhow::Result<String> {
+    if !template.contains("{{") && !template.contains("{%") {
+        return Ok(template.to_string());
+    }
+    template_env(allow_missing_vars())
+        .render_str(&quote_task_refs(template), template_context(inputs))
+        .map_err(|e| anyhow::anyhow!("failed to render '{}': {}", template, e))
+}
+
+/// Value of a template expression (a `{{ }}` block of `when:`), as text; unknown references are empty
+pub fn evaluate_expression(expr: &str, inputs: &TemplateInputs) -> anyhow::Result<String> {
+    let source = quote_task_refs(&format!("{{{{ {} }}}}", expr)).into_owned();
+    let inner = source.trim_start_matches("{{").trim_end_matches("}}");
+    let value = template_env(true)
+        .compile_expression_owned(inner.to_string())
+        .and_then(|e| e.eval(template_context(inputs)))
+        .map_err(|e| anyhow::anyhow!("failed to evaluate '{}': {}", expr, e))?;
+    Ok(if value.is_undefined() || value.is_none() { String::new() } else { value.to_string() })
+}
+
+/// Syntax check of a template, used by validation
+pub fn check_template(template: &str) -> anyhow::Result<()> {
+    let source = quote_task_refs(template);
+    new_template_env(true)
+        .template_from_str(&source)
+        .map(|_| ())
+        .map_err(|e| anyhow::anyhow!("invalid template '{}': {}", template, e))
+}
+
+/// Create a run directory and return it
+pub fn create_run_dir(base: &Path) -> anyhow::Result<std::path::PathBuf> {
+    let run_id = Uuid::new_v4().to_string();
+    let dir = base.join("runs").join(run_id);
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+pub fn write_artifact(dir: &Path, name: &str, content: &str) -> anyhow::Result<()> {
+    let path = dir.join(name);
+    fs::write(path, content)?;
+    Ok(())
+}
+
+/// Expand a list of paths/glob patterns into concrete files.
+/// Patterns that match nothing (or are invalid) are returned as errors so callers can report them.
+pub fn expand_paths(patterns: &[String]) -> Vec<Result<std::path::PathBuf, String>> {
+    let mut out = Vec::new();
+    for p in patterns {
+        if !p.contains(['*', '?', '[']) {
+            out.push(Ok(std::path::PathBuf::from(p)));
+            continue;
+        }
+        match glob::glob(p) {
+            Ok(entries) => {
+                let mut matched = false;
+                for entry in entries {
+                    matched = true;
+                    out.push(entry.map_err(|e| e.to_string()));
+                }
+                if !matched {
+                    out.push(Err(format!("pattern '{}' matched no files", p)));
+                }
+            }
+            Err(e) => out.push(Err(format!("invalid pattern '{}': {}", p, e))),
+        }
+    }
+    out
+}
+
+/// Build a process exit status for work done in-process (built-in tasks) so it can be
+/// reported the same way as a real child process.
+pub fn exit_status(code: i32) -> std::process::ExitStatus {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        std::process::ExitStatus::from_raw(code << 8)
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::ExitStatusExt;
+        std::process::ExitStatus::from_raw(code as u32)
+    }
+}
+
+/// Parse a `$RUSTYPIPE_ENV` file: `KEY=value` lines, plus GitHub-style multiline values
+/// (`KEY<<DELIM`, value lines, `DELIM`). Blank lines and `#` comments are ignored.
+pub fn parse_env_file(content: &str) -> Vec<(String, String)> {
+    let mut vars = Vec::new();
+    let mut lines = content.lines();
+    while let Some(line) = lines.next() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        // `NAME<<DELIM` only: in `CMD=cat <<EOF` the `<<` is part of the value
+        if let Some((key, delim)) = line.split_once("<<").filter(|(key, _)| !key.contains('=')) {
+            let mut value = Vec::new();
+            for l in lines.by_ref() {
+                let l = l.trim_end_matches('\r');
+                if l == delim.trim() {
+                    break;
+                }
+                value.push(l);
+            }
+            vars.push((key.trim().to_string(), value.join("\n")));
+        } else if let Some((key, value)) = line.split_once('=') {
+            vars.push((key.trim().to_string(), value.to_string()));
+        }
+    }
+    vars
+}
+
+/// Decode `%XX` escapes (URL paths, `OTEL_*` header values)
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let Some(b) = std::str::from_utf8(&bytes[i + 1..i + 3]).ok().and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_env_file;
+
+    #[test]
+    fn env_file_heredoc_and_plain_values() {
+        let content = "NOTES<<EOF\nline 1\nline 2\nEOF\nCMD=cat <<EOF\nX=a<<b\nLAST=1\n";
+        let vars = parse_env_file(content);
+        let expected = [("NOTES", "line 1\nline 2"), ("CMD", "cat <<EOF"), ("X", "a<<b"), ("LAST", "1")];
+        assert_eq!(vars, expected.map(|(k, v)| (k.to_string(), v.to_string())));
+    }
+}
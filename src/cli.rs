@@ -1,18 +1,208 @@
-use std::env;
+use crate::pipeline::graph::GraphFormat;
+use crate::pipeline::report::{self, ReportSpec};
+use clap::{Args, Parser, Subcommand};
+use std::path::PathBuf;
 
+#[derive(Parser, Debug)]
+#[command(name = "rustypipe", version, about = "Run DAG pipelines of shell tasks locally, in containers or on remote hosts")]
 pub struct Opts {
-    pub subcommand: String,
-    pub path: String,
+    /// Print the exact invocation each backend spawns
+    #[arg(long, global = true)]
+    pub trace: bool,
+    /// `json` prints one JSON object per event on stdout (human-readable output goes to stderr)
+    #[arg(long, global = true, value_enum, default_value = "text")]
+    pub log_format: LogFormat,
+    /// Render unknown `{{ ... }}` references as empty strings instead of failing the task
+    #[arg(long, global = true)]
+    pub allow_missing_vars: bool,
+    #[command(subcommand)]
+    pub command: Command,
 }
 
-pub fn get_opts() -> Opts {
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 3 {
-        eprintln!("Usage: rustypipe <run|validate> <pipeline.yaml>");
-        std::process::exit(1);
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run one or more pipeline files (globs allowed) under a single scheduler
+    Run(RunArgs),
+    /// Run pipelines, and run them again whenever a watched file changes
+    Watch {
+        /// Pipeline files or globs
+        #[arg(required = true)]
+        pipelines: Vec<String>,
+        /// Files or directories to watch (default: the pipelines' directories)
+        #[arg(long = "paths", num_args = 1..)]
+        watch: Vec<PathBuf>,
+        /// Show task output live, prefixed with the task name
+        #[arg(long)]
+        stream: bool,
+        /// Override a pipeline `vars:` entry (repeatable)
+        #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+        vars: Vec<(String, String)>,
+        /// Maximum number of tasks running at once, overriding the pipelines' `concurrency`
+        #[arg(long)]
+        concurrency: Option<usize>,
+    },
+    /// Run pipelines on their `schedule:` (cron) until stopped
+    Daemon {
+        /// Pipeline files or globs
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// Override a pipeline `vars:` entry (repeatable)
+        #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+        vars: Vec<(String, String)>,
+    },
+    /// Show the execution order and resolved commands without running anything
+    Plan {
+        /// Pipeline files or globs
+        #[arg(required = true)]
+        paths: Vec<String>,
+        /// Override a pipeline `vars:` entry (repeatable)
+        #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+        vars: Vec<(String, String)>,
+        /// Only show this task and the tasks it depends on, transitively (repeatable)
+        #[arg(long = "task", value_name = "TASK")]
+        tasks: Vec<String>,
+    },
+    /// Print the task dependency graph as Graphviz DOT or Mermaid
+    Graph {
+        /// Pipeline files or globs
+        #[arg(required = true)]
+        paths: Vec<String>,
+        #[arg(long, value_enum, default_value = "dot")]
+        format: GraphFormat,
+    },
+    /// Check pipeline files without running them
+    Validate {
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Convert a pipeline between YAML, JSON and TOML (picked by file extension)
+    Convert { input: PathBuf, output: PathBuf },
+    /// Re-run the failed and unstarted tasks of a failed or cancelled run
+    Resume {
+        /// Run id, unique id prefix or `latest`
+        run: String,
+        /// Show task output live, prefixed with the task name
+        #[arg(long)]
+        stream: bool,
+    },
+    /// List previous runs, newest first
+    Runs {
+        /// Only show the most recent N runs
+        #[arg(long, short = 'n')]
+        limit: Option<usize>,
+        /// Print JSON instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Print captured output of a run (default: the latest)
+    Logs {
+        /// Run id, unique id prefix or `latest`
+        run: Option<String>,
+        /// Only show this task
+        task: Option<String>,
+    },
+    /// Serve a web dashboard of past and running runs
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        bind: String,
+    },
+    /// Start the language server on stdin/stdout
+    Lsp,
+    /// Push a pipeline file or directory to an OCI registry
+    Push {
+        path: PathBuf,
+        /// `registry/repo:tag`
+        reference: String,
+    },
+    /// Pull a pipeline bundle from an OCI registry
+    Pull {
+        /// `registry/repo:tag` or `registry/repo@digest`
+        reference: String,
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+    },
+    /// Install a systemd service running a pipeline on its `schedule:` (or a timer, or a Windows
+    /// scheduled task)
+    InstallService {
+        pipeline: PathBuf,
+        /// systemd OnCalendar expression, or on Windows e.g. "DAILY 03:00", instead of the
+        /// pipeline's `schedule:`
+        schedule: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    /// Pipeline files or globs
+    #[arg(required = true)]
+    pub paths: Vec<String>,
+    /// Show task output live, prefixed with the task name
+    #[arg(long)]
+    pub stream: bool,
+    /// Full-screen terminal UI with per-task status and scrollable output
+    #[arg(long, conflicts_with = "stream")]
+    pub tui: bool,
+    /// Print the plan (like `rustypipe plan`) instead of running
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Diff the new run against a baseline run
+    #[arg(long, value_name = "RUN_ID")]
+    pub compare: Option<String>,
+    /// Override a pipeline `vars:` entry (repeatable)
+    #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var)]
+    pub vars: Vec<(String, String)>,
+    /// Maximum number of tasks running at once, overriding the pipelines' `concurrency`
+    #[arg(long)]
+    pub concurrency: Option<usize>,
+    /// Seconds the whole run may take, overriding the pipelines' `timeout`
+    #[arg(long, value_name = "SECS")]
+    pub timeout: Option<u64>,
+    /// Abort on the first failing task in every pipeline
+    #[arg(long)]
+    pub stop_on_fail: bool,
+    /// Only run this task and the tasks it depends on, transitively (repeatable)
+    #[arg(long = "task", value_name = "TASK")]
+    pub tasks: Vec<String>,
+    /// Treat a task as already done: dependents start right away and see OUTPUT (default: the
+    /// output of the `--skip-from` run, or nothing) as its output (repeatable)
+    #[arg(long, value_name = "TASK[=OUTPUT]", value_parser = parse_skip)]
+    pub skip: Vec<(String, Option<String>)>,
+    /// Run (id, unique id prefix or `latest`) whose results `--skip`ped tasks take over
+    #[arg(long, value_name = "RUN", requires = "skip")]
+    pub skip_from: Option<String>,
+    /// Write a report once the run has finished, e.g. `junit=report.xml` (repeatable)
+    #[arg(long = "report", value_name = "FORMAT=PATH", value_parser = report::parse_spec)]
+    pub reports: Vec<ReportSpec>,
+    /// Push the run's metrics to this Prometheus Pushgateway once it has finished
+    #[arg(long, value_name = "URL")]
+    pub pushgateway: Option<String>,
+    /// Start without checking that the backends the tasks use are reachable
+    #[arg(long)]
+    pub no_preflight: bool,
+}
+
+fn parse_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((k, v)) if !k.is_empty() => Ok((k.to_string(), v.to_string())),
+        _ => Err(format!("expected NAME=value, got '{}'", s)),
     }
-    Opts {
-        subcommand: args[1].clone(),
-        path: args[2].clone(),
+}
+
+fn parse_skip(s: &str) -> Result<(String, Option<String>), String> {
+    match s.split_once('=') {
+        Some((t, v)) if !t.is_empty() => Ok((t.to_string(), Some(v.to_string()))),
+        None if !s.is_empty() => Ok((s.to_string(), None)),
+        _ => Err(format!("expected TASK or TASK=output, got '{}'", s)),
     }
 }
+
+pub fn get_opts() -> Opts {
+    Opts::parse()
+}
@@ -0,0 +1,144 @@
+//! `rustypipe daemon`: run pipelines on their `schedule:` (cron, local time) until Ctrl+C.
+//! Every pipeline file is scheduled on its own, and so is every task with its own `schedule:`
+//! and every tag in `schedules:`, which run just those tasks (with their dependencies). The
+//! pipeline's `schedule:` runs the remaining tasks. Runs of the same job never overlap: a time
+//! that comes while the previous run is still going is skipped. Runs are recorded in
+//! `.rustypipe` like any other run, so `rustypipe runs` and `rustypipe serve` show them.
+use crate::pipeline::parser::{load_resolved_pipeline, parse_schedule};
+use crate::pipeline::{self, RunConfig};
+use anyhow::Context;
+use chrono::{DateTime, Local};
+use croner::Cron;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// Longest single sleep, so clock changes (DST, suspend) are noticed
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+struct Job {
+    path: PathBuf,
+    name: String,
+    cron: Cron,
+    /// Main tasks to run (`--task`); all of them when empty
+    tasks: Vec<String>,
+}
+
+/// Sleep until `at`; false if the daemon is stopping
+async fn sleep_until(at: DateTime<Local>, stop: &mut watch::Receiver<bool>) -> bool {
+    loop {
+        let Ok(left) = (at - Local::now()).to_std() else { return true };
+        if left.is_zero() {
+            return true;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(left.min(MAX_SLEEP)) => {}
+            _ = stop.changed() => return false,
+        }
+    }
+}
+
+async fn schedule(job: Job, config: &RunConfig, mut stop: watch::Receiver<bool>) {
+    let mut after = Local::now();
+    loop {
+        let next = match job.cron.find_next_occurrence(&after, false) {
+            Ok(next) => next,
+            Err(e) => {
+                warn!("{}: no further scheduled runs: {}", job.name, e);
+                return;
+            }
+        };
+        info!("{}: next run at {}", job.name, next.format("%Y-%m-%d %H:%M"));
+        if !sleep_until(next, &mut stop).await {
+            return;
+        }
+        info!("{}: starting scheduled run", job.name);
+        let config = RunConfig { tasks: job.tasks.clone(), ..config.clone() };
+        match pipeline::run_pipelines(std::slice::from_ref(&job.path), &config).await {
+            Ok(run_dir) => info!("{}: run finished ({})", job.name, run_dir.display()),
+            Err(e) => warn!("{}: run failed: {:#}", job.name, e),
+        }
+        if *stop.borrow() {
+            return;
+        }
+        // times that passed during the run are dropped rather than run late
+        let now = Local::now();
+        let mut skipped = 0;
+        let mut t = next;
+        while let Ok(n) = job.cron.find_next_occurrence(&t, false) {
+            if n > now || skipped == 1000 {
+                break;
+            }
+            skipped += 1;
+            t = n;
+        }
+        if skipped > 0 {
+            warn!("{}: skipped {} scheduled run(s) while the previous run was still going", job.name, skipped);
+        }
+        after = now;
+    }
+}
+
+/// The jobs of one pipeline file: its own `schedule:`, task `schedule:`s and tag `schedules:`
+fn pipeline_jobs(path: &Path) -> anyhow::Result<Vec<Job>> {
+    let p = load_resolved_pipeline(path).with_context(|| format!("failed to load {:?}", path))?;
+    let name = p.name.clone().unwrap_or_else(|| path.display().to_string());
+    let mut specs = Vec::new();
+    for t in &p.tasks {
+        if let Some(expr) = &t.schedule {
+            specs.push((format!("{} ({})", name, t.name), expr, vec![t.name.clone()]));
+        }
+    }
+    for (tag, expr) in &p.schedules {
+        let tagged = p.tasks.iter().filter(|t| t.tags.contains(tag)).map(|t| t.name.clone()).collect();
+        specs.push((format!("{} (tag {})", name, tag), expr, tagged));
+    }
+    if let Some(expr) = &p.schedule {
+        // tasks with a schedule of their own are left to it
+        let own: Vec<String> = specs.iter().flat_map(|(_, _, tasks)| tasks.iter().cloned()).collect();
+        let rest: Vec<String> = p.tasks.iter().filter(|t| !own.contains(&t.name)).map(|t| t.name.clone()).collect();
+        if own.is_empty() {
+            specs.push((name.clone(), expr, Vec::new()));
+        } else if rest.is_empty() {
+            warn!("{}: every task has its own schedule; the pipeline's `schedule:` runs nothing", name);
+        } else {
+            specs.push((name.clone(), expr, rest));
+        }
+    }
+    if specs.is_empty() {
+        warn!("{}: no `schedule:`, not scheduled", name);
+    }
+    let mut jobs = Vec::new();
+    for (name, expr, tasks) in specs {
+        let cron = parse_schedule(expr).with_context(|| format!("in {:?}", path))?;
+        info!("{}: scheduled '{}' ({})", name, expr, cron.describe());
+        jobs.push(Job { path: path.to_path_buf(), name, cron, tasks });
+    }
+    Ok(jobs)
+}
+
+/// Schedule every pipeline in `paths` that has a `schedule:` (or tasks with one) and run them
+/// until Ctrl+C
+pub async fn run(paths: &[PathBuf], config: &RunConfig) -> anyhow::Result<()> {
+    let mut jobs = Vec::new();
+    for path in paths {
+        if jobs.iter().any(|j: &Job| j.path == *path) {
+            continue;
+        }
+        jobs.extend(pipeline_jobs(path)?);
+    }
+    if jobs.is_empty() {
+        anyhow::bail!("none of the pipelines has a `schedule:`");
+    }
+
+    // runs in progress see Ctrl+C themselves and cancel; the daemon stops once they have
+    let (stop_tx, stop) = watch::channel(false);
+    tokio::spawn(async move {
+        let _ = tokio::signal::ctrl_c().await;
+        info!("Received Ctrl+C — stopping the daemon");
+        let _ = stop_tx.send(true);
+    });
+    futures::future::join_all(jobs.into_iter().map(|job| schedule(job, config, stop.clone()))).await;
+    Ok(())
+}
@@ -0,0 +1,293 @@
+//! `rustypipe lsp`: a small Language Server for pipeline files, speaking JSON-RPC over stdio.
+//!
+//! Supports full-document sync with diagnostics (parse errors and `validate_pipeline` checks),
+//! completion of task names inside `depends_on` (and field names elsewhere) and hover docs for
+//! pipeline/task fields. `uses:` steps are not fetched, so step contents are not checked.
+use crate::pipeline::parser::{parse_pipeline, resolve_includes, validate_pipeline, PipelineFormat};
+use crate::pipeline::templates::expand_templates;
+use crate::util::percent_decode;
+use regex::Regex;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// Hover documentation for pipeline and task fields
+const FIELD_DOCS: &[(&str, &str)] = &[
+    ("name", "Pipeline name, or the unique name of a task."),
+    ("concurrency", "Maximum number of tasks running at once (default 4)."),
+    ("stop_on_fail", "Abort the run as soon as a task fails."),
+    ("schedule", "Cron expression, e.g. `\"0 3 * * *\"` (local time), on which `rustypipe daemon` runs the pipeline. On a task it runs just that task (with its dependencies), and the pipeline's `schedule:` leaves the task out."),
+    ("schedules", "Cron expressions per task tag, e.g. `{nightly: \"0 3 * * *\", hourly: \"@hourly\"}`: `rustypipe daemon` runs the tasks carrying the tag (with their dependencies) on that schedule."),
+    ("tags", "Labels of the task, e.g. `[nightly]`; the pipeline's `schedules:` run tasks by tag."),
+    ("step_registry", "Git URL template for `uses:` steps, e.g. `https://github.com/{org}/{name}.git`."),
+    ("plugins", "Plugins preprocessing every task when the pipeline is loaded, by name, each with an optional `config` mapping, which the plugin checks when it is loaded and gets with every task. Native plugins are `lib<name>.so` / `<name>.dll` files in the plugin directory, sandboxed WebAssembly plugins `<name>.wasm` files there or the module named by `wasm` (relative to the pipeline file); `command` starts an external process plugin speaking JSON-RPC over stdin/stdout. WebAssembly and process plugins also receive run events."),
+    ("plugin_dir", "Directory plugins are loaded from, relative to the pipeline file (default: `$RUSTYPIPE_PLUGIN_DIR`, then `.rustypipe/plugins`)."),
+    ("vars", "Values for `{{vars.NAME}}` in commands; `rustypipe run --var NAME=value` overrides them. Values may use `{{env.NAME}}`."),
+    ("secrets", "Environment variables for every task and hook, e.g. `TOKEN: { env: GH_TOKEN }`, `{ file: keys/deploy }` or `{ from: vault, path: kv/ci/token }` (`vault`, `aws`; `key:` picks a field); their values are masked as `***` in all output and logs."),
+    ("env_allowlist", "Host environment variables visible as `{{env.NAME}}`: names or globs such as `CI_*` (default: all)."),
+    ("matrix", "Expand the task once per combination of values, e.g. `os: [linux, windows]`; use `{{matrix.os}}` in the task."),
+    ("when", "Run the task only if the expression holds, e.g. `\"{{build.exit_code}} == 0 && vars.ENV == 'prod'\"`; otherwise it is skipped and dependents still run. Functions: `contains`, `starts_with`, `ends_with`, `matches`, `lower`, `upper`, `trim`, `len`."),
+    ("assert", "Expressions a successful attempt must satisfy, e.g. `\"{{self.outputs.count}} > 0\"` or `contains(self.output, 'ok')`; a false one fails the attempt."),
+    ("env", "Environment variables for the command (pipeline-wide or per task; task values win). `{{task.output}}`, `{{task.outputs.NAME}}`, `{{vars.NAME}}` and `{{env.NAME}}` are interpolated; `| jsonpath('$.a[0]')` extracts from JSON."),
+    ("backends", "Named backend instances, e.g. `builder: {type: docker, image: rust:1.78}`, selected with `backend: builder`; entries named after their type may omit `type:`. Types: `local` (shell), `docker` (image, args, `reuse` to run all tasks in one container, `host` or `context` for a remote engine, `copy` to copy the task directory in and out instead of mounting it, `user` (`host` for your uid:gid), `pass_env` for host variables to hand in, `pull`, `pool` to start that many containers when the run begins and `docker exec` tasks in them), `ssh` (host, user, port, key, args, `workdir` for the remote directory, `sync` (with `exclude`) to upload the pipeline directory before each task and download its artifacts, `native: true` for the built-in client with key file or ssh-agent auth, `multiplex: false` to connect per task, `pool` to open connections when the run begins), `wsl` (distro, user), `kubernetes` (image, namespace, args, `job` to run tasks as Jobs with backoff_limit, requests, limits and ttl_seconds_after_finished, `pull`, `pool` to start that many pods when the run begins and `kubectl exec` tasks in them), `ecs` (cluster, `image` with `execution_role` or `task_definition`, region, profile, subnets, security_groups, public_ip, task_role, cpu, memory, log_group: Fargate tasks through the aws CLI), `cloudrun` (image, service_account, region, project: one-off Cloud Run jobs through the gcloud CLI, output read from Cloud Logging), `aci` (image, resource_group, location, subscription, cpu, memory: a container group per task through the az CLI) and `lambda` (function, qualifier, region, profile: invokes the function with the command, or a JSON object command as the payload, and returns its response)."),
+    ("prepull", "Pull the images of the docker and kubernetes backends in use, in parallel, before the first task starts (default true)."),
+    ("workspace", "`isolated` runs tasks in a per-run copy of the pipeline directory (default `shared`)."),
+    ("collect", "Globs copied back from an isolated workspace into the pipeline directory after the run."),
+    ("artifact_store", "Push logs and artifacts of finished runs to `to:` (s3://, gs://, az:// or a local directory) under <run id>/."),
+    ("to", "Destination URL of an upload or artifact store."),
+    ("include", "Pipeline fragments (paths relative to this file) whose tasks, hooks, vars and env are merged in; a task name may only be defined once."),
+    ("templates", "Partial task bodies (e.g. `backend`, `env`, `retries`, `run_prefix`) that tasks pull in with `extends:`."),
+    ("extends", "Template this task is deep-merged over: maps merge key by key, other fields set on the task win."),
+    ("run_prefix", "Template field put in front of the task's `run`, e.g. `set -eu;`."),
+    ("setup", "Tasks run before `tasks`; if one fails the main tasks are skipped."),
+    ("tasks", "List of tasks; they form a DAG through `depends_on`."),
+    ("teardown", "Tasks that always run last, even after failures or Ctrl+C; failures are reported separately."),
+    ("on_success", "Hooks (`run`, optional `backend` and `timeout`) run after the task or the whole run succeeded; outside the DAG."),
+    ("on_failure", "Hooks (`run`, optional `backend` and `timeout`) run after the task or the whole run failed; outside the DAG."),
+    ("depends_on", "Tasks that must finish before this one starts. `<pipeline>:<task>` refers to another pipeline of a multi-file run."),
+    ("stdin", "Standard input of the command: literal text (interpolated), `{ file: path }` or `{ from_task: name }` (the output of a task listed in `depends_on`)."),
+    ("script", "List of commands run one after another in the same shell session (and container); the first failing command fails the task."),
+    ("run", "Shell command. `{{task.output}}`, `{{task.outputs.NAME}}`, `{{vars.NAME}}` and `{{env.NAME}}` are interpolated; `| jsonpath('$.a[0]')` extracts from JSON."),
+    ("retries", "Extra attempts when the task exits non-zero or cannot be executed."),
+    ("retry_delay", "Seconds before the first retry (default 0); grows by `retry_backoff` after each retry."),
+    ("retry_backoff", "Multiplier applied to the retry delay after every attempt (default 2)."),
+    ("retry_jitter", "Randomize each retry delay between half and the full value."),
+    ("retry_if", "Retry a failed attempt only while this expression holds; `self.stderr`, `self.output`, `self.exit_code` and `self.attempt` describe the attempt."),
+    ("timeout", "Timeout in seconds. On a task it bounds each attempt; at the pipeline level it bounds setup and tasks together: running tasks are cancelled, the rest is recorded as not run and the run fails (teardown still runs; `run --timeout` overrides it)."),
+    ("backend", "Name of the `backends:` instance executing the task (default `local`, the host)."),
+    ("artifacts", "Files or globs, relative to the task's directory, copied into the run directory after the task (checksums recorded in meta.json). With docker, absolute container paths outside /workdir are copied out of the container."),
+    ("cache_key", "Cache the task's result under this key (interpolated); later runs with the same key restore output, exports and artifacts from `.rustypipe/cache` instead of running."),
+    ("continue_on_fail", "Run dependents even if this task fails; otherwise they are skipped. The run is still reported as failed."),
+    ("output_filter", "Post-processing of stdout before it is stored: `head`, `tail`, `regex`, `json` (field path or `$.` JSONPath)."),
+    ("outputs", "Named values for `{{task.outputs.NAME}}`, extracted from stdout or a `file` with `head`, `tail`, `regex` and `json`; `type` is `string` (default), `number`, `bool` or `json`."),
+    ("no_output_timeout", "Seconds a task's command may go without writing to stdout or stderr, catching hung network calls even when `timeout` is long or unset. Applies to each attempt; what happens then is `no_output_action`."),
+    ("no_output_action", "What exceeding `no_output_timeout` does: `fail` (default) stops the command like a timeout, `warn` logs a warning once per silent stretch and lets it continue."),
+    ("grace_period", "Seconds a timed-out or cancelled command gets to exit after SIGTERM (CTRL_BREAK on Windows) before it is killed with SIGKILL (default 10, 0 kills right away). The task's error records which of the two ended it."),
+    ("priority", "When more tasks are ready than `concurrency` allows, higher priorities start first (default 0, may be negative); equal priorities start in the order they became ready."),
+    ("mutex", "Name of a lock: tasks sharing it never run at the same time, even when the DAG would allow it, e.g. `mutex: db-migrations`."),
+    ("concurrency_group", "Group from the pipeline's `concurrency_groups:` (e.g. `{deploy: 2}`) limiting how many of its tasks run at once."),
+    ("concurrency_groups", "Named limits for `concurrency_group:` on tasks, e.g. `{deploy: 2}`: at most that many tasks of the group run at once, on top of `concurrency`."),
+    ("tty", "Allocate a pseudo-terminal for the command."),
+    ("shell", "Interpreter of `run`: `bash`, `sh`, `pwsh`, `powershell`, `cmd`, `git-bash` (Git for Windows, not WSL), `python`, any program taking `-c`, or an argv list the command is appended to, e.g. `[bash, -eo, pipefail, -c]`. Default: the pipeline-level `shell` on the local backend, else PowerShell on Windows, `sh` elsewhere and in containers."),
+    ("nix", "Run the command with Nix packages instead of a container: `{packages: [nodejs_20, jq]}` for `nix shell` (attributes of `nixpkgs`, pinned with `nixpkgs: github:NixOS/nixpkgs/nixos-24.05`, or full installables) or `{develop: .#ci}` for a flake's dev shell. Works wherever `nix` is installed, including ssh hosts and images with Nix."),
+    ("tools", "Toolchain versions for the task, e.g. `{node: 20, terraform: \"1.7\"}`, resolved on the host through mise (or asdf when mise is missing): missing versions are installed and the task's PATH points at them. Local backend only; quote versions like `\"1.10\"` that YAML would read as numbers."),
+    ("pull", "Image pull policy on a docker or kubernetes backend: `always`, `if-not-present` or `never`; overrides the backend's `pull`."),
+    ("resources", "CPU and memory limits on a docker, kubernetes, ecs, cloudrun or aci backend, e.g. `{cpus: 2, memory: 4Gi}`: `--cpus`/`--memory` for docker, requests and limits for Kubernetes, the Fargate task size for ecs, `--cpu`/`--memory` of the Cloud Run job or container group. `cpus` may be millicores (`500m`)."),
+    ("gpus", "GPUs for the task: a count, or `all` on docker. Docker gets `--gpus` (needs the NVIDIA container toolkit), Kubernetes an `nvidia.com/gpu` limit."),
+    ("upload", "Built-in upload of local files to `s3://`, `gs://` or `az://`."),
+    ("http", "Built-in HTTP request; the response body becomes the task output."),
+    ("files", "Built-in file operations: `copy`, `move`, `delete`, `mkdir`, `template`."),
+    ("git", "Built-in git operation: `clone`, `checkout`, `fetch`, `tag`, `push`."),
+    ("uses", "Reusable step: `org/step@ref` or a local `./path`."),
+    ("with", "Inputs passed to the `uses:` step."),
+];
+
+/// Read one `Content-Length` framed message; `None` on EOF
+fn read_message(input: &mut impl BufRead) -> anyhow::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(v) = line.strip_prefix("Content-Length:") {
+            length = Some(v.trim().parse::<usize>()?);
+        }
+    }
+    let Some(length) = length else {
+        anyhow::bail!("message without Content-Length");
+    };
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn send(out: &mut impl Write, msg: &Value) -> anyhow::Result<()> {
+    let body = serde_json::to_string(msg)?;
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()?;
+    Ok(())
+}
+
+fn format_of(uri: &str) -> PipelineFormat {
+    PipelineFormat::from_path(Path::new(uri))
+}
+
+/// Line (0-based) that best locates a validation message: the first line mentioning a quoted name
+fn locate(text: &str, message: &str) -> usize {
+    let quoted = Regex::new(r"'([^']+)'").unwrap();
+    for cap in quoted.captures_iter(message) {
+        let needle = &cap[1];
+        if let Some(i) = text.lines().position(|l| l.contains(needle)) {
+            return i;
+        }
+    }
+    0
+}
+
+fn diagnostics(uri: &str, text: &str) -> Vec<Value> {
+    let resolved = parse_pipeline(text, format_of(uri)).and_then(|mut p| {
+        // fragments are read from disk, relative to the document
+        if let Some(path) = uri.strip_prefix("file://").filter(|_| !p.include.is_empty()) {
+            resolve_includes(&mut p, Path::new(&percent_decode(path)))?;
+        }
+        expand_templates(&mut p)?;
+        Ok(p)
+    });
+    let (line, col, message) = match resolved {
+        Ok(p) => match validate_pipeline(&p) {
+            Ok(()) => return Vec::new(),
+            Err(e) => {
+                let message = format!("{:#}", e);
+                (locate(text, &message), 0, message)
+            }
+        },
+        Err(e) => {
+            let (line, col) = if let Some(loc) = e.downcast_ref::<serde_yaml::Error>().and_then(|y| y.location()) {
+                (loc.line().saturating_sub(1), loc.column().saturating_sub(1))
+            } else if let Some(j) = e.downcast_ref::<serde_json::Error>() {
+                (j.line().saturating_sub(1), j.column().saturating_sub(1))
+            } else {
+                (0, 0)
+            };
+            (line, col, format!("{:#}", e))
+        }
+    };
+    let end = text.lines().nth(line).map(|l| l.len()).unwrap_or(0).max(col + 1);
+    vec![json!({
+        "range": { "start": { "line": line, "character": col }, "end": { "line": line, "character": end } },
+        "severity": 1,
+        "source": "rustypipe",
+        "message": message,
+    })]
+}
+
+/// Task names declared in the document (works on partially broken YAML)
+fn task_names(text: &str) -> Vec<String> {
+    let re = Regex::new(r#"^\s*-\s+name:\s*["']?([^"'#\s]+)"#).unwrap();
+    text.lines().filter_map(|l| re.captures(l).map(|c| c[1].to_string())).collect()
+}
+
+/// Whether the cursor line belongs to a `depends_on` value (inline list or block list)
+fn in_depends_on(text: &str, line: usize) -> bool {
+    let lines: Vec<&str> = text.lines().collect();
+    let Some(current) = lines.get(line) else { return false };
+    if current.contains("depends_on") {
+        return true;
+    }
+    if !current.trim_start().starts_with('-') {
+        return false;
+    }
+    // a block list item: look upwards for the key owning the list
+    for l in lines[..line].iter().rev() {
+        let t = l.trim_start();
+        if t.starts_with('-') && !t.contains(':') {
+            continue;
+        }
+        return t.starts_with("depends_on");
+    }
+    false
+}
+
+fn completion(text: &str, line: usize) -> Value {
+    let items: Vec<Value> = if in_depends_on(text, line) {
+        task_names(text)
+            .into_iter()
+            .map(|n| json!({ "label": n, "kind": 18, "detail": "task" }))
+            .collect()
+    } else {
+        FIELD_DOCS
+            .iter()
+            .map(|(f, d)| json!({ "label": f, "kind": 5, "documentation": d, "insertText": format!("{}: ", f) }))
+            .collect()
+    };
+    json!({ "isIncomplete": false, "items": items })
+}
+
+fn hover(text: &str, line: usize, character: usize) -> Value {
+    let Some(l) = text.lines().nth(line) else { return Value::Null };
+    let is_word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let chars: Vec<char> = l.chars().collect();
+    let mut start = character.min(chars.len());
+    while start > 0 && is_word(chars[start - 1]) {
+        start -= 1;
+    }
+    let mut end = character.min(chars.len());
+    while end < chars.len() && is_word(chars[end]) {
+        end += 1;
+    }
+    let word: String = chars[start..end].iter().collect();
+    match FIELD_DOCS.iter().find(|(f, _)| *f == word) {
+        Some((f, doc)) => json!({ "contents": { "kind": "markdown", "value": format!("**{}**\n\n{}", f, doc) } }),
+        None => Value::Null,
+    }
+}
+
+/// Serve LSP requests on stdin/stdout until the client sends `exit`
+pub fn serve() -> anyhow::Result<()> {
+    let stdin = std::io::stdin();
+    let mut input = stdin.lock();
+    let mut out = std::io::stdout();
+    let mut docs: HashMap<String, String> = HashMap::new();
+
+    while let Some(msg) = read_message(&mut input)? {
+        let method = msg.get("method").and_then(|m| m.as_str()).unwrap_or_default();
+        let params = msg.get("params").cloned().unwrap_or(Value::Null);
+        let id = msg.get("id").cloned();
+        let uri = params.pointer("/textDocument/uri").and_then(|u| u.as_str()).unwrap_or_default().to_string();
+        let pos = |key: &str| params.pointer(&format!("/position/{}", key)).and_then(|v| v.as_u64()).unwrap_or(0) as usize;
+
+        let result = match method {
+            "initialize" => Some(json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "completionProvider": { "triggerCharacters": ["[", ",", " ", "-"] },
+                    "hoverProvider": true,
+                },
+                "serverInfo": { "name": "rustypipe", "version": env!("CARGO_PKG_VERSION") },
+            })),
+            "shutdown" => Some(Value::Null),
+            "exit" => return Ok(()),
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let text = params
+                    .pointer("/textDocument/text")
+                    .or_else(|| params.pointer("/contentChanges/0/text"))
+                    .and_then(|t| t.as_str())
+                    .unwrap_or_default()
+                    .to_string();
+                let diags = diagnostics(&uri, &text);
+                docs.insert(uri.clone(), text);
+                send(&mut out, &json!({
+                    "jsonrpc": "2.0",
+                    "method": "textDocument/publishDiagnostics",
+                    "params": { "uri": uri, "diagnostics": diags },
+                }))?;
+                None
+            }
+            "textDocument/didClose" => {
+                docs.remove(&uri);
+                None
+            }
+            "textDocument/completion" => Some(completion(docs.get(&uri).map(String::as_str).unwrap_or_default(), pos("line"))),
+            "textDocument/hover" => {
+                Some(hover(docs.get(&uri).map(String::as_str).unwrap_or_default(), pos("line"), pos("character")))
+            }
+            _ => None,
+        };
+
+        // answer every request (messages with an id); unknown methods get an error
+        if let Some(id) = id {
+            let reply = match result {
+                Some(r) => json!({ "jsonrpc": "2.0", "id": id, "result": r }),
+                None => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": -32601, "message": format!("unsupported method {}", method) } }),
+            };
+            send(&mut out, &reply)?;
+        }
+    }
+    Ok(())
+}
@@ -1,31 +1,149 @@
 mod cli;
-mod util;
-mod plugins;
-mod backends;
-mod pipeline;
+mod oci;
+mod service;
+mod lsp;
+mod serve;
+mod tui;
+mod progress;
+mod watch;
+mod daemon;
 
 use anyhow::Context;
-use tracing_subscriber::{fmt, prelude::*};
+use cli::Command;
+use rustypipe_core::{backends, pipeline, util};
+use std::path::Path;
+use tracing_subscriber::{filter::{LevelFilter, Targets}, fmt, prelude::*};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Initialize logging
-    tracing_subscriber::registry()
-        .with(fmt::layer().with_target(false))
-        .init();
-
     let opts = cli::get_opts();
-    match opts.subcommand.as_str() {
-        "run" => {
-            let path = std::path::Path::new(&opts.path);
-            pipeline::run_pipeline(path).await.context("pipeline run failed")?;
+
+    // Initialize logging; progress events are only emitted as JSON
+    if opts.log_format == cli::LogFormat::Json {
+        util::set_json_output(true);
+        tracing_subscriber::registry()
+            .with(fmt::layer().json().flatten_event(true).with_target(false).with_writer(std::io::stdout))
+            .with(LevelFilter::INFO)
+            .init();
+    } else {
+        // while the TUI or progress display owns the terminal, log lines go through it
+        let console = || -> Box<dyn std::io::Write> {
+            if util::console_observed() {
+                Box::new(pipeline::events::MessageWriter::default())
+            } else {
+                Box::new(std::io::stdout())
+            }
+        };
+        tracing_subscriber::registry()
+            .with(fmt::layer().with_target(false).with_writer(console))
+            .with(Targets::new().with_default(LevelFilter::INFO).with_target(util::EVENTS, LevelFilter::OFF))
+            .init();
+    }
+    backends::set_trace(opts.trace);
+    util::set_allow_missing_vars(opts.allow_missing_vars);
+    match opts.command {
+        Command::Run(args) => {
+            if args.tui && opts.log_format == cli::LogFormat::Json {
+                anyhow::bail!("--tui cannot be combined with --log-format json");
+            }
+            // several files (or globs) run together under one scheduler
+            let paths = util::expand_paths(&args.paths)
+                .into_iter()
+                .collect::<Result<Vec<_>, String>>()
+                .map_err(anyhow::Error::msg)?;
+            let config = pipeline::RunConfig {
+                stream: args.stream,
+                vars: args.vars,
+                concurrency: args.concurrency,
+                timeout: args.timeout,
+                stop_on_fail: args.stop_on_fail,
+                tasks: args.tasks,
+                skip: args.skip,
+                skip_from: args.skip_from,
+                reports: args.reports,
+                pushgateway: args.pushgateway,
+                cancel: None,
+                skip_preflight: args.no_preflight,
+            };
+            if args.dry_run {
+                return pipeline::plan::print_plan(&paths, &config);
+            }
+            // resolve the baseline first so a typo fails before anything runs
+            let baseline = match &args.compare {
+                Some(id) => Some(pipeline::manifest::resolve_run(Path::new(".rustypipe"), Some(id))?),
+                None => None,
+            };
+            let run = pipeline::run_pipelines(&paths, &config);
+            let res = if args.tui {
+                tui::run(run).await
+            } else if !args.stream && !util::json_output() && progress::available() {
+                progress::run(run).await
+            } else {
+                run.await
+            };
+            // a run with failed tasks is compared too, and fails the command afterwards
+            let run_dir = match &res {
+                Ok(dir) => Some(dir.clone()),
+                Err(e) => e.downcast_ref::<pipeline::RunFailed>().map(|f| f.run_dir.clone()),
+            };
+            let compared = match (baseline, run_dir) {
+                (Some(baseline), Some(run_dir)) => pipeline::compare::compare_runs(&baseline, &run_dir),
+                _ => Ok(()),
+            };
+            res.context("pipeline run failed")?;
+            compared?;
+        }
+        Command::Resume { run, stream } => {
+            let config = pipeline::RunConfig { stream, ..Default::default() };
+            let run = pipeline::resume_run(&run, &config);
+            if !stream && !util::json_output() && progress::available() {
+                progress::run(run).await
+            } else {
+                run.await
+            }
+            .context("pipeline run failed")?;
+        }
+        Command::Watch { pipelines, watch, stream, vars, concurrency } => {
+            let paths = util::expand_paths(&pipelines)
+                .into_iter()
+                .collect::<Result<Vec<_>, String>>()
+                .map_err(anyhow::Error::msg)?;
+            let config = pipeline::RunConfig { stream, vars, concurrency, ..Default::default() };
+            watch::watch(&paths, &watch, &config).await?;
+        }
+        Command::Daemon { paths, vars } => {
+            let paths = util::expand_paths(&paths)
+                .into_iter()
+                .collect::<Result<Vec<_>, String>>()
+                .map_err(anyhow::Error::msg)?;
+            daemon::run(&paths, &pipeline::RunConfig { vars, ..Default::default() }).await?;
+        }
+        Command::Plan { paths, vars, tasks } => {
+            let paths = util::expand_paths(&paths)
+                .into_iter()
+                .collect::<Result<Vec<_>, String>>()
+                .map_err(anyhow::Error::msg)?;
+            pipeline::plan::print_plan(&paths, &pipeline::RunConfig { vars, tasks, ..Default::default() })?;
         }
-        "validate" => {
-            pipeline::validate_pipeline_file(std::path::Path::new(&opts.path))?;
+        Command::Graph { paths, format } => {
+            let paths = util::expand_paths(&paths)
+                .into_iter()
+                .collect::<Result<Vec<_>, String>>()
+                .map_err(anyhow::Error::msg)?;
+            pipeline::graph::print_graph(&paths, format)?;
         }
-        other => {
-            eprintln!("Unknown subcommand: {} (supported: run, validate)", other);
+        Command::Validate { paths } => pipeline::validate_pipeline_files(&paths)?,
+        Command::Convert { input, output } => pipeline::convert_pipeline_file(&input, &output)?,
+        Command::Runs { limit, json } => pipeline::history::print_runs(limit, json)?,
+        Command::Logs { run, task } => pipeline::manifest::print_logs(run.as_deref(), task.as_deref())?,
+        Command::Serve { bind } => serve::serve(&bind).await?,
+        Command::Lsp => {
+            // JSON-RPC on stdin/stdout; blocking I/O stays off the async workers
+            tokio::task::spawn_blocking(lsp::serve).await??;
         }
+        Command::Push { path, reference } => oci::push(&path, &reference).await?,
+        Command::Pull { reference, dir } => oci::pull(&reference, &dir).await?,
+        Command::InstallService { pipeline, schedule } => service::install(&pipeline, schedule.as_deref())?,
     }
 
     Ok(())
@@ -0,0 +1,292 @@
+//! Publish and fetch pipeline templates / step packages as OCI artifacts (ORAS-style).
+//!
+//! Every file becomes one layer annotated with its relative path (`org.opencontainers.image.title`),
+//! the config is the OCI empty descriptor and the manifest carries the rustypipe artifact type.
+//! References look like `registry.example.com/team/pipelines:v1` or `...@sha256:<digest>`;
+//! pulling by digest verifies the manifest digest as well as every layer.
+//! Credentials: `RUSTYPIPE_REGISTRY_USER` / `RUSTYPIPE_REGISTRY_PASSWORD` (token auth is negotiated
+//! from the registry's `WWW-Authenticate` challenge). `localhost` registries are reached over plain HTTP.
+use anyhow::Context;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::path::{Component, Path, PathBuf};
+
+pub const ARTIFACT_TYPE: &str = "application/vnd.rustypipe.pipeline.v1";
+const LAYER_MEDIA_TYPE: &str = "application/vnd.rustypipe.file.v1";
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+const EMPTY_MEDIA_TYPE: &str = "application/vnd.oci.empty.v1+json";
+const TITLE: &str = "org.opencontainers.image.title";
+
+/// Parsed `registry/repository[:tag|@digest]`
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub registry: String,
+    pub repository: String,
+    /// Tag or `sha256:` digest
+    pub reference: String,
+}
+
+impl Reference {
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let (registry, rest) = s.split_once('/').with_context(|| format!("'{}' is missing a registry host", s))?;
+        if !(registry.contains('.') || registry.contains(':') || registry == "localhost") {
+            anyhow::bail!("'{}' does not start with a registry host", s);
+        }
+        let (repository, reference) = if let Some((r, d)) = rest.split_once('@') {
+            (r, d.to_string())
+        } else {
+            match rest.rsplit_once(':') {
+                Some((r, t)) if !t.contains('/') => (r, t.to_string()),
+                _ => (rest, "latest".to_string()),
+            }
+        };
+        Ok(Reference { registry: registry.to_string(), repository: repository.to_string(), reference })
+    }
+
+    fn is_digest(&self) -> bool {
+        self.reference.starts_with("sha256:")
+    }
+
+    fn base_url(&self) -> String {
+        let host = self.registry.split(':').next().unwrap_or_default();
+        let scheme = if host == "localhost" || host == "127.0.0.1" { "http" } else { "https" };
+        format!("{}://{}/v2/{}", scheme, self.registry, self.repository)
+    }
+}
+
+fn digest_of(data: &[u8]) -> String {
+    format!("sha256:{}", hex::encode(Sha256::digest(data)))
+}
+
+/// Minimal registry client handling bearer-token challenges
+struct Registry {
+    client: reqwest::Client,
+    token: Option<String>,
+}
+
+impl Registry {
+    fn new() -> Self {
+        Self { client: reqwest::Client::new(), token: None }
+    }
+
+    fn credentials() -> Option<(String, String)> {
+        match (std::env::var("RUSTYPIPE_REGISTRY_USER"), std::env::var("RUSTYPIPE_REGISTRY_PASSWORD")) {
+            (Ok(u), Ok(p)) => Some((u, p)),
+            _ => None,
+        }
+    }
+
+    /// Send a request, answering one auth challenge if the registry asks for it
+    async fn send(
+        &mut self,
+        build: impl Fn(&reqwest::Client) -> reqwest::RequestBuilder,
+    ) -> anyhow::Result<reqwest::Response> {
+        let mut req = build(&self.client);
+        if let Some(t) = &self.token {
+            req = req.bearer_auth(t);
+        }
+        let resp = req.send().await?;
+        if resp.status() != reqwest::StatusCode::UNAUTHORIZED {
+            return Ok(resp);
+        }
+
+        let challenge = resp
+            .headers()
+            .get("www-authenticate")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        if let Some(params) = challenge.strip_prefix("Bearer ") {
+            self.token = Some(self.fetch_token(params).await?);
+            let req = build(&self.client).bearer_auth(self.token.as_deref().unwrap_or_default());
+            return Ok(req.send().await?);
+        }
+        match Self::credentials() {
+            Some((u, p)) => Ok(build(&self.client).basic_auth(u, Some(p)).send().await?),
+            None => anyhow::bail!("registry requires authentication; set RUSTYPIPE_REGISTRY_USER/PASSWORD"),
+        }
+    }
+
+    async fn fetch_token(&self, params: &str) -> anyhow::Result<String> {
+        let mut realm = String::new();
+        let mut query = Vec::new();
+        for part in params.split(',') {
+            if let Some((k, v)) = part.trim().split_once('=') {
+                let v = v.trim_matches('"').to_string();
+                if k == "realm" {
+                    realm = v;
+                } else {
+                    query.push((k.to_string(), v));
+                }
+            }
+        }
+        let mut req = self.client.get(&realm).query(&query);
+        if let Some((u, p)) = Self::credentials() {
+            req = req.basic_auth(u, Some(p));
+        }
+        let body: Value = check(req.send().await?).await?.json().await?;
+        body.get("token")
+            .or_else(|| body.get("access_token"))
+            .and_then(|t| t.as_str())
+            .map(|t| t.to_string())
+            .context("token endpoint returned no token")
+    }
+
+    async fn push_blob(&mut self, r: &Reference, data: &[u8]) -> anyhow::Result<String> {
+        let digest = digest_of(data);
+        let base = r.base_url();
+        let head = self.send(|c| c.head(format!("{}/blobs/{}", base, digest))).await?;
+        if head.status().is_success() {
+            return Ok(digest);
+        }
+
+        let start = check(self.send(|c| c.post(format!("{}/blobs/uploads/", base))).await?).await?;
+        let location = start
+            .headers()
+            .get("location")
+            .and_then(|v| v.to_str().ok())
+            .context("registry did not return an upload location")?
+            .to_string();
+        let mut url = reqwest::Url::parse(&base)?.join(&location)?;
+        url.query_pairs_mut().append_pair("digest", &digest);
+        let body = data.to_vec();
+        check(
+            self.send(|c| {
+                c.put(url.clone())
+                    .header("content-type", "application/octet-stream")
+                    .body(body.clone())
+            })
+            .await?,
+        )
+        .await?;
+        Ok(digest)
+    }
+}
+
+/// Return the response if successful, otherwise an error including the registry's message
+async fn check(resp: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    if resp.status().is_success() {
+        return Ok(resp);
+    }
+    let status = resp.status();
+    let body = resp.text().await.unwrap_or_default();
+    anyhow::bail!("registry responded {}: {}", status, body.trim())
+}
+
+/// Collect (relative title, absolute path) for a file or a directory tree
+fn collect(path: &Path) -> anyhow::Result<Vec<(String, PathBuf)>> {
+    if path.is_file() {
+        let title = path.file_name().context("invalid file name")?.to_string_lossy().to_string();
+        return Ok(vec![(title, path.to_path_buf())]);
+    }
+    let mut out = Vec::new();
+    let mut stack = vec![path.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        for entry in std::fs::read_dir(&dir).with_context(|| format!("failed to read {:?}", dir))? {
+            let p = entry?.path();
+            if p.is_dir() {
+                stack.push(p);
+            } else {
+                let rel = p.strip_prefix(path)?.to_string_lossy().replace('\\', "/");
+                out.push((rel, p));
+            }
+        }
+    }
+    out.sort();
+    Ok(out)
+}
+
+/// `rustypipe push <file|dir> <ref>`: upload files and tag the manifest; prints the digest to pin
+pub async fn push(path: &Path, reference: &str) -> anyhow::Result<()> {
+    let r = Reference::parse(reference)?;
+    if r.is_digest() {
+        anyhow::bail!("push needs a tag, not a digest");
+    }
+    let files = collect(path)?;
+    if files.is_empty() {
+        anyhow::bail!("nothing to push in {:?}", path);
+    }
+
+    let mut reg = Registry::new();
+    let mut layers = Vec::new();
+    for (title, file) in &files {
+        let data = std::fs::read(file).with_context(|| format!("failed to read {:?}", file))?;
+        let digest = reg.push_blob(&r, &data).await.with_context(|| format!("failed to upload {}", title))?;
+        layers.push(json!({
+            "mediaType": LAYER_MEDIA_TYPE,
+            "digest": digest,
+            "size": data.len(),
+            "annotations": { TITLE: title },
+        }));
+    }
+    let config_digest = reg.push_blob(&r, b"{}").await?;
+
+    let manifest = json!({
+        "schemaVersion": 2,
+        "mediaType": MANIFEST_MEDIA_TYPE,
+        "artifactType": ARTIFACT_TYPE,
+        "config": { "mediaType": EMPTY_MEDIA_TYPE, "digest": config_digest, "size": 2 },
+        "layers": layers,
+        "annotations": { "org.opencontainers.image.created": chrono::Utc::now().to_rfc3339() },
+    });
+    let body = serde_json::to_vec(&manifest)?;
+    let digest = digest_of(&body);
+    let url = format!("{}/manifests/{}", r.base_url(), r.reference);
+    check(
+        reg.send(|c| c.put(&url).header("content-type", MANIFEST_MEDIA_TYPE).body(body.clone()))
+            .await?,
+    )
+    .await?;
+
+    println!("Pushed {} file(s) to {}", files.len(), reference);
+    println!("Digest: {}", digest);
+    println!("Pin with: {}/{}@{}", r.registry, r.repository, digest);
+    Ok(())
+}
+
+/// `rustypipe pull <ref> [dir]`: download an artifact's files into `dest`
+pub async fn pull(reference: &str, dest: &Path) -> anyhow::Result<()> {
+    let r = Reference::parse(reference)?;
+    let mut reg = Registry::new();
+    let url = format!("{}/manifests/{}", r.base_url(), r.reference);
+    let resp = check(reg.send(|c| c.get(&url).header("accept", MANIFEST_MEDIA_TYPE)).await?).await?;
+    let body = resp.bytes().await?;
+    let digest = digest_of(&body);
+    if r.is_digest() && digest != r.reference {
+        anyhow::bail!("manifest digest mismatch: expected {} got {}", r.reference, digest);
+    }
+
+    let manifest: Value = serde_json::from_slice(&body).context("invalid manifest")?;
+    if manifest.get("artifactType").and_then(|v| v.as_str()) != Some(ARTIFACT_TYPE) {
+        anyhow::bail!("{} is not a rustypipe artifact", reference);
+    }
+    let layers = manifest.get("layers").and_then(|l| l.as_array()).cloned().unwrap_or_default();
+
+    std::fs::create_dir_all(dest)?;
+    for layer in &layers {
+        let layer_digest = layer.get("digest").and_then(|d| d.as_str()).context("layer without digest")?;
+        let title = layer
+            .pointer(&format!("/annotations/{}", TITLE.replace('/', "~1")))
+            .and_then(|t| t.as_str())
+            .context("layer without title annotation")?;
+        // Refuse titles that would escape the destination directory.
+        let rel = Path::new(title);
+        if rel.components().any(|c| !matches!(c, Component::Normal(_))) {
+            anyhow::bail!("refusing unsafe path '{}' in artifact", title);
+        }
+
+        let blob_url = format!("{}/blobs/{}", r.base_url(), layer_digest);
+        let data = check(reg.send(|c| c.get(&blob_url)).await?).await?.bytes().await?;
+        if digest_of(&data) != layer_digest {
+            anyhow::bail!("digest mismatch for {}", title);
+        }
+        let out = dest.join(rel);
+        if let Some(parent) = out.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&out, &data).with_context(|| format!("failed to write {:?}", out))?;
+        println!("{} ({})", out.display(), layer_digest);
+    }
+    println!("Pulled {} ({} file(s)), digest {}", reference, layers.len(), digest);
+    Ok(())
+}
@@ -1,4 +0,0 @@
-pub mod parser;
-pub mod executor;
-
-pub use executor::{run_pipeline, validate_pipeline_file};
@@ -0,0 +1,80 @@
+//! Progress display for `rustypipe run` when stdout is a terminal: a spinner with the elapsed
+//! time for every running task above a completed/total bar for the run. Messages of the run are
+//! printed above the bars. Piped output, `--stream`, `--tui` and JSON logs keep plain printing.
+use crate::pipeline::events::{self, RunEvent, Update};
+use crate::util;
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+/// Whether `run` should show progress bars
+pub fn available() -> bool {
+    std::io::stdout().is_terminal() && std::env::var("TERM").map_or(true, |t| t != "dumb")
+}
+
+fn show(updates: Receiver<Update>) {
+    let multi = MultiProgress::with_draw_target(ProgressDrawTarget::stdout());
+    let spinner = ProgressStyle::with_template("{spinner:.cyan} {msg} {elapsed:.dim}").unwrap_or_else(|_| ProgressStyle::default_spinner());
+    let bar_style = ProgressStyle::with_template("[{bar:30.green/dim}] {pos}/{len} tasks {elapsed}")
+        .unwrap_or_else(|_| ProgressStyle::default_bar())
+        .progress_chars("=> ");
+    let mut total: Option<ProgressBar> = None;
+    let mut running: HashMap<String, ProgressBar> = HashMap::new();
+    for update in updates {
+        match update {
+            Update::Message { text, stderr } => multi.suspend(|| {
+                if stderr {
+                    eprintln!("{}", text);
+                } else {
+                    println!("{}", text);
+                }
+            }),
+            Update::Event(RunEvent::RunStarted { tasks, .. }) => {
+                let bar = multi.add(ProgressBar::new(tasks.len() as u64).with_style(bar_style.clone()));
+                bar.enable_steady_tick(Duration::from_millis(500));
+                total = Some(bar);
+            }
+            Update::Event(RunEvent::TaskStarted { task, .. }) => {
+                let bar = ProgressBar::new_spinner().with_style(spinner.clone()).with_message(task.clone());
+                // spinners stay above the total bar
+                let bar = match &total {
+                    Some(t) => multi.insert_before(t, bar),
+                    None => multi.add(bar),
+                };
+                bar.enable_steady_tick(Duration::from_millis(100));
+                running.insert(task, bar);
+            }
+            Update::Event(RunEvent::TaskFinished { task, .. }) => {
+                if let Some(bar) = running.remove(&task) {
+                    bar.finish_and_clear();
+                    multi.remove(&bar);
+                }
+                if let Some(t) = &total {
+                    t.inc(1);
+                }
+            }
+            Update::Event(RunEvent::TaskOutput { .. } | RunEvent::PipelineFinished { .. }) => {}
+        }
+    }
+    running.values().for_each(ProgressBar::finish_and_clear);
+    if let Some(t) = total {
+        t.finish_and_clear();
+    }
+    let _ = multi.clear();
+}
+
+/// Run `run` with the progress display
+pub async fn run(run: impl Future<Output = anyhow::Result<PathBuf>>) -> anyhow::Result<PathBuf> {
+    util::set_console_observed(true);
+    let updates = events::observe();
+    let display = std::thread::spawn(move || show(updates));
+    let result = run.await;
+    events::unobserve();
+    let _ = display.join();
+    util::set_console_observed(false);
+    result
+}
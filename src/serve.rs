@@ -0,0 +1,152 @@
+//! `rustypipe serve`: a small web dashboard over `.rustypipe`. It lists past runs, follows the
+//! progress of running ones through their `events.jsonl`, shows per-task logs and draws the DAG
+//! of the resolved pipeline. Read-only; binds to localhost unless told otherwise.
+//!
+//! JSON API used by the page:
+//! - `GET /api/runs`: run history (like `rustypipe runs --json`)
+//! - `GET /api/runs/<id>`: the run's `manifest.json`
+//! - `GET /api/runs/<id>/events`: its progress events
+//! - `GET /api/runs/<id>/graph`: tasks with phase, dependencies and DAG column
+//! - `GET /api/runs/<id>/logs/<task>`: stdout and stderr of a task
+use crate::pipeline::events;
+use crate::pipeline::history::list_runs;
+use crate::pipeline::manifest::{resolve_run, task_dir, RunManifest};
+use crate::pipeline::parser::load_pipeline;
+use crate::util::percent_decode;
+use anyhow::Context;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json(v: &Value) -> Self {
+        Response { status: "200 OK", content_type: "application/json", body: v.to_string() }
+    }
+
+    fn error(status: &'static str, message: impl std::fmt::Display) -> Self {
+        Response { status, content_type: "application/json", body: json!({ "error": message.to_string() }).to_string() }
+    }
+}
+
+/// Serve the dashboard on `bind` until interrupted
+pub async fn serve(bind: &str) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(bind).await.with_context(|| format!("failed to listen on {}", bind))?;
+    println!("Dashboard at http://{}", listener.local_addr()?);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream).await {
+                tracing::debug!("dashboard connection: {:#}", e);
+            }
+        });
+    }
+}
+
+async fn handle(stream: TcpStream) -> anyhow::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    // headers are not needed; read up to the blank line
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or_default(), parts.next().unwrap_or_default());
+    let path = target.split('?').next().unwrap_or_default();
+    let response = match method {
+        "GET" | "HEAD" => tokio::task::block_in_place(|| route(path)),
+        _ => Response::error("405 Method Not Allowed", "only GET is supported"),
+    };
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}; charset=utf-8\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    let mut stream = reader.into_inner();
+    stream.write_all(head.as_bytes()).await?;
+    if method != "HEAD" {
+        stream.write_all(response.body.as_bytes()).await?;
+    }
+    stream.shutdown().await?;
+    Ok(())
+}
+
+fn route(path: &str) -> Response {
+    let base = Path::new(".rustypipe");
+    let segments: Vec<String> = path.trim_matches('/').split('/').map(percent_decode).collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+    let res = match segments.as_slice() {
+        [""] => return Response { status: "200 OK", content_type: "text/html", body: DASHBOARD.to_string() },
+        ["api", "runs"] => serde_json::to_value(list_runs(base)).map_err(Into::into),
+        ["api", "runs", id] => run_dir(base, id).and_then(|d| Ok(serde_json::to_value(RunManifest::load(&d)?)?)),
+        ["api", "runs", id, "events"] => run_dir(base, id).map(|d| Value::Array(events::load(&d))),
+        ["api", "runs", id, "graph"] => run_dir(base, id).and_then(|d| graph(&d)),
+        ["api", "runs", id, "logs", task] => run_dir(base, id).and_then(|d| logs(&d, task)),
+        _ => return Response::error("404 Not Found", format!("no such page: {}", path)),
+    };
+    match res {
+        Ok(v) => Response::json(&v),
+        Err(e) => Response::error("404 Not Found", format!("{:#}", e)),
+    }
+}
+
+fn run_dir(base: &Path, id: &str) -> anyhow::Result<std::path::PathBuf> {
+    resolve_run(base, Some(id))
+}
+
+/// Tasks of the run's resolved pipeline; `column` is the task's depth in the DAG, with setup
+/// before the main tasks and teardown after them
+fn graph(run_dir: &Path) -> anyhow::Result<Value> {
+    let pipeline = load_pipeline(&run_dir.join("pipeline.yaml"))?;
+    let mut columns: HashMap<&str, usize> = HashMap::new();
+    let mut tasks = Vec::new();
+    let mut offset = 0;
+    for (phase, list) in [("setup", &pipeline.setup), ("main", &pipeline.tasks), ("teardown", &pipeline.teardown)] {
+        let mut depth: HashMap<&str, usize> = HashMap::new();
+        // dependencies come first after validation, but a few passes tolerate any order
+        for _ in 0..list.len() {
+            for t in list.iter() {
+                let d = t.depends_on.iter().filter_map(|dep| depth.get(dep.as_str()).map(|d| d + 1)).max().unwrap_or(0);
+                depth.insert(&t.name, d);
+            }
+        }
+        for t in list.iter() {
+            let column = offset + depth[t.name.as_str()];
+            columns.insert(&t.name, column);
+            tasks.push(json!({ "name": t.name, "phase": phase, "depends_on": t.depends_on, "column": column }));
+        }
+        if let Some(max) = list.iter().map(|t| columns[t.name.as_str()]).max() {
+            offset = max + 1;
+        }
+    }
+    Ok(json!({ "name": pipeline.name, "tasks": tasks }))
+}
+
+fn logs(run_dir: &Path, task: &str) -> anyhow::Result<Value> {
+    let manifest = RunManifest::load(run_dir)?;
+    // only directories of known tasks, never a path from the URL; a running task has no
+    // manifest entry yet, but its logs fill up live when the run streams output
+    let dir = match manifest.tasks.iter().find(|t| t.name == task) {
+        Some(record) => run_dir.join(&record.dir),
+        None if events::load(run_dir).iter().any(|e| e["event"] == "task_started" && e["task"] == task) => task_dir(run_dir, task),
+        None => anyhow::bail!("task '{}' has not started", task),
+    };
+    Ok(json!({
+        "stdout": std::fs::read_to_string(dir.join("stdout.log")).unwrap_or_default(),
+        "stderr": std::fs::read_to_string(dir.join("stderr.log")).unwrap_or_default(),
+    }))
+}
@@ -0,0 +1,331 @@
+//! `rustypipe install-service`: run a pipeline unattended.
+//!
+//! Linux: generates a hardened `rustypipe-<name>.service` (output goes to journald). For a
+//! pipeline with a `schedule:` (or scheduled tasks) it is a long-running `rustypipe daemon <pipeline>`, enabled right
+//! away; when an `OnCalendar=` expression is given instead it is a oneshot `rustypipe run
+//! <pipeline>` plus a matching `.timer`; otherwise a oneshot unit to start by hand. Units go to
+//! `/etc/systemd/system` when run as root, otherwise to the user's systemd directory.
+//!
+//! Windows: registers a Task Scheduler entry (`schtasks`) appending output to
+//! `.rustypipe\service.log` next to the pipeline. A pipeline with schedules gets a task starting
+//! `rustypipe daemon <pipeline>` at boot (and right away), without a time limit and restarted when
+//! it fails; otherwise the pipeline runs on the schedule given on the command line.
+use crate::pipeline::parser::{load_pipeline, load_resolved_pipeline, validate_pipeline, Pipeline};
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+
+/// Unit base name derived from the pipeline name (or file stem)
+fn unit_name(pipeline: &Path) -> anyhow::Result<String> {
+    let p = load_pipeline(pipeline)?;
+    let raw = p
+        .name
+        .unwrap_or_else(|| pipeline.file_stem().unwrap_or_default().to_string_lossy().to_string());
+    let clean: String = raw
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '-' })
+        .collect();
+    Ok(format!("rustypipe-{}", clean))
+}
+
+/// `%` starts a specifier in unit files; `%%` is a literal one
+fn unit_escape(s: &str) -> String {
+    s.replace('%', "%%")
+}
+
+/// A path in a space-separated unit setting (`ReadWritePaths=`): double-quoted, so spaces stay
+/// in it, with specifiers escaped
+fn unit_quote(s: &str) -> String {
+    format!("\"{}\"", unit_escape(&s.replace('\\', "\\\\").replace('"', "\\\"")))
+}
+
+/// One argument of an `ExecStart=` command line, where `$` also expands variables
+fn exec_arg(arg: &Path) -> String {
+    unit_quote(&arg.to_string_lossy().replace('$', "$$"))
+}
+
+/// `rustypipe daemon <pipeline>` for a pipeline with a `schedule:`, otherwise a oneshot
+/// `rustypipe run <pipeline>`
+pub fn service_unit(name: &str, exe: &Path, pipeline: &Path, workdir: &Path, daemon: bool) -> String {
+    let service = if daemon {
+        // the daemon stops on Ctrl+C, cancelling a run in progress and running its teardown
+        format!("Type=simple\nExecStart={} daemon {}\nRestart=on-failure\nRestartSec=10\nKillSignal=SIGINT\nTimeoutStopSec=120", exec_arg(exe), exec_arg(pipeline))
+    } else {
+        format!("Type=oneshot\nExecStart={} run {}", exec_arg(exe), exec_arg(pipeline))
+    };
+    let install = if daemon { "\n[Install]\nWantedBy=default.target\n" } else { "" };
+    format!(
+        "[Unit]
+Description=rustypipe pipeline {name}
+Wants=network-online.target
+After=network-online.target
+
+[Service]
+{service}
+WorkingDirectory={workdir}
+StandardOutput=journal
+StandardError=journal
+SyslogIdentifier={name}
+NoNewPrivileges=yes
+PrivateTmp=yes
+ProtectSystem=strict
+ProtectHome=read-only
+ReadWritePaths={rw_path}
+ProtectKernelTunables=yes
+ProtectKernelModules=yes
+ProtectControlGroups=yes
+RestrictSUIDSGID=yes
+LockPersonality=yes
+{install}",
+        name = name,
+        service = service,
+        workdir = unit_escape(&workdir.to_string_lossy()),
+        rw_path = unit_quote(&workdir.to_string_lossy()),
+        install = install,
+    )
+}
+
+pub fn timer_unit(name: &str, on_calendar: &str) -> String {
+    format!(
+        "[Unit]
+Description=Schedule for rustypipe pipeline {name}
+
+[Timer]
+OnCalendar={on_calendar}
+Persistent=true
+
+[Install]
+WantedBy=timers.target
+"
+    )
+}
+
+/// Whether the pipeline, a tag or a task has a `schedule:` for the daemon to follow
+fn scheduled(p: &Pipeline) -> bool {
+    p.schedule.is_some() || !p.schedules.is_empty() || p.tasks.iter().any(|t| t.schedule.is_some())
+}
+
+#[cfg(unix)]
+fn is_root() -> bool {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+#[cfg(not(unix))]
+fn is_root() -> bool {
+    false
+}
+
+fn systemctl(user: bool, args: &[&str]) -> anyhow::Result<()> {
+    let mut c = std::process::Command::new("systemctl");
+    if user {
+        c.arg("--user");
+    }
+    let status = c.args(args).status().context("failed to run systemctl")?;
+    if !status.success() {
+        anyhow::bail!("systemctl {} failed", args.join(" "));
+    }
+    Ok(())
+}
+
+/// Write the unit(s), reload systemd and enable the timer or the daemon
+pub fn install_systemd(pipeline: &Path, on_calendar: Option<&str>) -> anyhow::Result<()> {
+    if !cfg!(target_os = "linux") {
+        anyhow::bail!("install-service with systemd is only supported on Linux");
+    }
+    let pipeline = pipeline
+        .canonicalize()
+        .with_context(|| format!("failed to canonicalize {:?}", pipeline))?;
+    let workdir = pipeline.parent().unwrap_or_else(|| Path::new("/")).to_path_buf();
+    let exe = std::env::current_exe().context("cannot locate the rustypipe executable")?;
+    let name = unit_name(&pipeline)?;
+    // without an explicit calendar the pipeline's own schedules are followed by the daemon
+    let p = load_resolved_pipeline(&pipeline)?;
+    let daemon = on_calendar.is_none() && scheduled(&p);
+    if daemon {
+        validate_pipeline(&p).with_context(|| format!("invalid pipeline {:?}", pipeline))?;
+    }
+
+    let user = !is_root();
+    let dir = if user {
+        let home = std::env::var("HOME").context("HOME is not set")?;
+        PathBuf::from(home).join(".config/systemd/user")
+    } else {
+        PathBuf::from("/etc/systemd/system")
+    };
+    std::fs::create_dir_all(&dir).with_context(|| format!("failed to create {:?}", dir))?;
+
+    let service_path = dir.join(format!("{}.service", name));
+    std::fs::write(&service_path, service_unit(&name, &exe, &pipeline, &workdir, daemon))
+        .with_context(|| format!("failed to write {:?}", service_path))?;
+    println!("Wrote {}", service_path.display());
+
+    if let Some(cal) = on_calendar {
+        let timer_path = dir.join(format!("{}.timer", name));
+        std::fs::write(&timer_path, timer_unit(&name, cal)).with_context(|| format!("failed to write {:?}", timer_path))?;
+        println!("Wrote {}", timer_path.display());
+    }
+
+    systemctl(user, &["daemon-reload"])?;
+    let scope = if user { "--user " } else { "" };
+    if on_calendar.is_some() {
+        systemctl(user, &["enable", "--now", &format!("{}.timer", name)])?;
+        println!("Enabled {}.timer", name);
+    } else if daemon {
+        systemctl(user, &["enable", "--now", &format!("{}.service", name)])?;
+        println!("Enabled {}.service (runs the pipeline on its `schedule:`)", name);
+    } else {
+        println!("Run it with: systemctl {}start {}.service", scope, name);
+    }
+    println!("Logs: journalctl {}-u {}.service", scope, name);
+    Ok(())
+}
+
+/// Translate "DAILY 03:00", "HOURLY", "MINUTE 15", "ONSTART" into schtasks arguments
+pub fn schtasks_schedule(schedule: &str) -> anyhow::Result<Vec<String>> {
+    let mut parts = schedule.split_whitespace();
+    let kind = parts.next().context("empty schedule")?.to_ascii_uppercase();
+    let mut args = vec!["/SC".to_string(), kind];
+    if let Some(value) = parts.next() {
+        if value.contains(':') {
+            args.extend(["/ST".to_string(), value.to_string()]);
+        } else {
+            args.extend(["/MO".to_string(), value.to_string()]);
+        }
+    }
+    if let Some(extra) = parts.next() {
+        anyhow::bail!("unexpected '{}' in schedule '{}'", extra, schedule);
+    }
+    Ok(args)
+}
+
+/// `&`, `<`, `>` and quotes as XML entities
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+/// Task Scheduler definition starting `rustypipe daemon <pipeline>` at boot as `user` (no password
+/// stored, so no network credentials), with no time limit and restarts when it exits with an error
+pub fn daemon_task_xml(name: &str, exe: &Path, pipeline: &Path, workdir: &Path, user: &str) -> String {
+    // cmd strips the outer quotes of `/c "..."` and keeps the inner ones
+    let arguments = format!("/c \"\"{}\" daemon \"{}\" >> .rustypipe\\service.log 2>&1\"", exe.display(), pipeline.display());
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-16"?>
+<Task version="1.2" xmlns="http://schemas.microsoft.com/windows/2004/02/mit/task">
+  <RegistrationInfo>
+    <Description>rustypipe pipeline {name}</Description>
+  </RegistrationInfo>
+  <Triggers>
+    <BootTrigger>
+      <Enabled>true</Enabled>
+    </BootTrigger>
+  </Triggers>
+  <Principals>
+    <Principal id="Author">
+      <UserId>{user}</UserId>
+      <LogonType>S4U</LogonType>
+    </Principal>
+  </Principals>
+  <Settings>
+    <MultipleInstancesPolicy>IgnoreNew</MultipleInstancesPolicy>
+    <DisallowStartIfOnBatteries>false</DisallowStartIfOnBatteries>
+    <StopIfGoingOnBatteries>false</StopIfGoingOnBatteries>
+    <ExecutionTimeLimit>PT0S</ExecutionTimeLimit>
+    <RestartOnFailure>
+      <Interval>PT1M</Interval>
+      <Count>999</Count>
+    </RestartOnFailure>
+  </Settings>
+  <Actions Context="Author">
+    <Exec>
+      <Command>cmd.exe</Command>
+      <Arguments>{arguments}</Arguments>
+      <WorkingDirectory>{workdir}</WorkingDirectory>
+    </Exec>
+  </Actions>
+</Task>
+"#,
+        name = xml_escape(name),
+        user = xml_escape(user),
+        arguments = xml_escape(&arguments),
+        workdir = xml_escape(&workdir.to_string_lossy()),
+    )
+}
+
+fn schtasks(args: &[&str]) -> anyhow::Result<()> {
+    let status = std::process::Command::new("schtasks").args(args).status().context("failed to run schtasks")?;
+    if !status.success() {
+        anyhow::bail!("schtasks {} failed", args.first().copied().unwrap_or_default());
+    }
+    Ok(())
+}
+
+/// Register the daemon task and start it
+fn install_windows_daemon(name: &str, exe: &Path, pipeline: &Path, workdir: &Path) -> anyhow::Result<()> {
+    let user = match (std::env::var("USERDOMAIN"), std::env::var("USERNAME")) {
+        (Ok(domain), Ok(user)) => format!("{}\\{}", domain, user),
+        (_, user) => user.context("USERNAME is not set")?,
+    };
+    let xml = daemon_task_xml(name, exe, pipeline, workdir, &user);
+    // schtasks reads task definitions as UTF-16 with a byte order mark
+    let mut bytes = vec![0xFF, 0xFE];
+    bytes.extend(xml.encode_utf16().flat_map(u16::to_le_bytes));
+    let path = workdir.join(".rustypipe").join(format!("{}.xml", name));
+    std::fs::write(&path, bytes).with_context(|| format!("failed to write {:?}", path))?;
+    let res = schtasks(&["/Create", "/F", "/TN", name, "/XML", &path.to_string_lossy()]);
+    let _ = std::fs::remove_file(&path);
+    res.context("registering a task that starts at boot needs an elevated prompt")?;
+    schtasks(&["/Run", "/TN", name])?;
+    println!("Registered scheduled task {} (starts `rustypipe daemon` at boot, running the pipeline on its schedules)", name);
+    println!("Stop it with: schtasks /End /TN {}", name);
+    Ok(())
+}
+
+/// Register a Windows scheduled task for the pipeline
+pub fn install_windows_task(pipeline: &Path, schedule: Option<&str>) -> anyhow::Result<()> {
+    if !cfg!(windows) {
+        anyhow::bail!("Task Scheduler installation is only supported on Windows");
+    }
+    let pipeline = pipeline
+        .canonicalize()
+        .with_context(|| format!("failed to canonicalize {:?}", pipeline))?;
+    let workdir = pipeline.parent().unwrap_or_else(|| Path::new(".")).to_path_buf();
+    let exe = std::env::current_exe().context("cannot locate the rustypipe executable")?;
+    let name = unit_name(&pipeline)?;
+    std::fs::create_dir_all(workdir.join(".rustypipe"))?;
+
+    let Some(schedule) = schedule else {
+        // without an explicit schedule the pipeline's own schedules are followed by the daemon
+        let p = load_resolved_pipeline(&pipeline)?;
+        if !scheduled(&p) {
+            anyhow::bail!("the pipeline has no `schedule:`; give one, e.g. \"DAILY 03:00\" or \"MINUTE 30\"");
+        }
+        validate_pipeline(&p).with_context(|| format!("invalid pipeline {:?}", pipeline))?;
+        return install_windows_daemon(&name, &exe, &pipeline, &workdir);
+    };
+    let action = format!(
+        "cmd /c cd /d \"{}\" && \"{}\" run \"{}\" >> .rustypipe\\service.log 2>&1",
+        workdir.display(),
+        exe.display(),
+        pipeline.display()
+    );
+    let status = std::process::Command::new("schtasks")
+        .args(["/Create", "/F", "/TN", &name, "/TR", &action])
+        .args(schtasks_schedule(schedule)?)
+        .status()
+        .context("failed to run schtasks")?;
+    if !status.success() {
+        anyhow::bail!("schtasks /Create failed for {}", name);
+    }
+    println!("Registered scheduled task {}", name);
+    println!("Run it now with: schtasks /Run /TN {}", name);
+    Ok(())
+}
+
+/// Install using the platform's service manager
+pub fn install(pipeline: &Path, schedule: Option<&str>) -> anyhow::Result<()> {
+    if cfg!(windows) {
+        install_windows_task(pipeline, schedule)
+    } else {
+        install_systemd(pipeline, schedule)
+    }
+}
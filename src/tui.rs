@@ -0,0 +1,268 @@
+//! `rustypipe run --tui`: a full-screen view of a running pipeline. Tasks are listed on the left
+//! with their status (a spinner while running), the output of the selected task on the right, and
+//! a counter plus the latest message at the bottom. Fed by the run's events; everything the run
+//! would otherwise have printed is printed once the screen is closed.
+//!
+//! Keys: ↑/↓ (k/j) select a task, PgUp/PgDn scroll its output, End follows it again,
+//! Ctrl+C cancels the run, q leaves once the run has finished.
+use crate::pipeline::events::{self, RunEvent, Update};
+use crate::pipeline::manifest::TaskStatus;
+use crate::util;
+use ratatui::crossterm::event::{self as term, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+/// Output kept per task; older lines are dropped
+const MAX_LINES: usize = 10_000;
+const SPINNER: [&str; 10] = ["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"];
+
+#[derive(Default)]
+struct TaskView {
+    running_since: Option<Instant>,
+    status: Option<TaskStatus>,
+    duration_ms: Option<u64>,
+    lines: VecDeque<(bool, String)>,
+}
+
+#[derive(Default)]
+struct App {
+    pipeline: Option<String>,
+    order: Vec<String>,
+    tasks: HashMap<String, TaskView>,
+    list: ListState,
+    /// The user picked a task; otherwise the latest started task is shown
+    pinned: bool,
+    /// Lines scrolled up from the end of the output
+    scroll: usize,
+    messages: Vec<(bool, String)>,
+    summary: Option<String>,
+    started: Option<Instant>,
+    finished: bool,
+}
+
+impl App {
+    fn task(&mut self, name: &str) -> &mut TaskView {
+        if !self.tasks.contains_key(name) {
+            self.order.push(name.to_string());
+        }
+        self.tasks.entry(name.to_string()).or_default()
+    }
+
+    fn select(&mut self, index: usize) {
+        self.list.select(Some(index.min(self.order.len().saturating_sub(1))));
+        self.scroll = 0;
+    }
+
+    fn apply(&mut self, update: Update) {
+        match update {
+            Update::Message { text, stderr } => self.messages.push((stderr, text)),
+            Update::Event(RunEvent::RunStarted { pipeline, tasks, .. }) => {
+                self.pipeline = pipeline;
+                self.started = Some(Instant::now());
+                for t in tasks {
+                    self.task(&t);
+                }
+            }
+            Update::Event(RunEvent::TaskStarted { task, .. }) => {
+                self.task(&task).running_since = Some(Instant::now());
+                if !self.pinned {
+                    let index = self.order.iter().position(|t| *t == task).unwrap_or_default();
+                    self.select(index);
+                }
+            }
+            Update::Event(RunEvent::TaskOutput { task, stream, line }) => {
+                let lines = &mut self.task(&task).lines;
+                if lines.len() == MAX_LINES {
+                    lines.pop_front();
+                }
+                lines.push_back((stream == "stderr", line));
+            }
+            Update::Event(RunEvent::TaskFinished { task, status, duration_ms, .. }) => {
+                let view = self.task(&task);
+                view.running_since = None;
+                view.status = Some(status);
+                view.duration_ms = Some(duration_ms);
+            }
+            Update::Event(RunEvent::PipelineFinished { status, succeeded, failed, skipped, cancelled, .. }) => {
+                self.summary = Some(format!(
+                    "{:?}: {} succeeded, {} failed, {} skipped, {} cancelled",
+                    status, succeeded, failed, skipped, cancelled
+                ));
+            }
+        }
+    }
+
+    fn counts(&self) -> (usize, usize) {
+        let done = self.tasks.values().filter(|t| t.status.is_some()).count();
+        (done, self.order.len())
+    }
+}
+
+fn status_span(view: &TaskView) -> Span<'static> {
+    match (view.status, view.running_since) {
+        (Some(TaskStatus::Succeeded), _) => Span::styled("✓", Style::new().fg(Color::Green)),
+        (Some(TaskStatus::CacheHit), _) => Span::styled("↺", Style::new().fg(Color::Green)),
+        (Some(TaskStatus::Failed | TaskStatus::Error), _) => Span::styled("✗", Style::new().fg(Color::Red)),
+        (Some(TaskStatus::Skipped | TaskStatus::Cancelled | TaskStatus::NotRun), _) => Span::styled("⊘", Style::new().fg(Color::DarkGray)),
+        (None, Some(since)) => {
+            let frame = (since.elapsed().as_millis() / 100) as usize % SPINNER.len();
+            Span::styled(SPINNER[frame], Style::new().fg(Color::Cyan))
+        }
+        (None, None) => Span::styled("·", Style::new().fg(Color::DarkGray)),
+    }
+}
+
+fn elapsed(view: &TaskView) -> String {
+    let ms = match (view.duration_ms, view.running_since) {
+        (Some(ms), _) => ms,
+        (None, Some(since)) => since.elapsed().as_millis() as u64,
+        (None, None) => return String::new(),
+    };
+    format!("{:.1}s", ms as f64 / 1000.0)
+}
+
+fn draw(frame: &mut Frame, app: &mut App) {
+    let [body, footer] = Layout::vertical([Constraint::Fill(1), Constraint::Length(3)]).areas(frame.area());
+    let [left, right] = Layout::horizontal([Constraint::Percentage(30), Constraint::Fill(1)]).areas(body);
+
+    let items: Vec<ListItem> = app
+        .order
+        .iter()
+        .map(|name| {
+            let view = &app.tasks[name];
+            Line::from(vec![status_span(view), Span::raw(format!(" {} ", name)), Span::styled(elapsed(view), Style::new().fg(Color::DarkGray))]).into()
+        })
+        .collect();
+    let title = format!(" {} ", app.pipeline.as_deref().unwrap_or("rustypipe"));
+    let list = List::new(items)
+        .block(Block::bordered().title(title))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, left, &mut app.list);
+
+    let selected = app.list.selected().and_then(|i| app.order.get(i)).cloned();
+    let (lines, title) = match selected.as_ref().and_then(|s| app.tasks.get(s).map(|v| (s, v))) {
+        Some((name, view)) => {
+            let visible = right.height.saturating_sub(2) as usize;
+            app.scroll = app.scroll.min(view.lines.len().saturating_sub(visible));
+            let end = view.lines.len() - app.scroll;
+            let lines: Vec<Line> = view
+                .lines
+                .range(end.saturating_sub(visible)..end)
+                .map(|(stderr, l)| if *stderr { Line::styled(l.clone(), Style::new().fg(Color::Red)) } else { Line::raw(l.clone()) })
+                .collect();
+            let follow = if app.scroll == 0 { "" } else { " (scrolled, End to follow)" };
+            (lines, format!(" {}{} ", name, follow))
+        }
+        None => (Vec::new(), " output ".to_string()),
+    };
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(title)), right);
+
+    let (done, total) = app.counts();
+    let clock = app.started.map(|s| format!("{:.0}s", s.elapsed().as_secs_f64())).unwrap_or_default();
+    let status = match (&app.summary, app.finished) {
+        (Some(summary), true) => format!("{} — q to quit", summary),
+        (Some(summary), false) => summary.clone(),
+        (None, _) => format!("{}/{} tasks done · {}", done, total, clock),
+    };
+    let last = app.messages.last().map(|(_, m)| m.as_str()).unwrap_or_default();
+    let keys = "↑↓ select · PgUp/PgDn scroll · Ctrl+C cancel";
+    frame.render_widget(
+        Paragraph::new(vec![Line::raw(status), Line::styled(last.to_string(), Style::new().fg(Color::Yellow))]).block(Block::bordered().title(format!(" {} ", keys))),
+        footer,
+    );
+}
+
+/// Ask the run to stop the same way Ctrl+C does outside raw mode
+fn interrupt() {
+    #[cfg(unix)]
+    // SAFETY: sending a signal to our own process has no memory-safety preconditions
+    unsafe {
+        libc::kill(libc::getpid(), libc::SIGINT);
+    }
+}
+
+fn ui_loop(terminal: &mut DefaultTerminal, updates: Receiver<Update>) -> std::io::Result<App> {
+    let mut app = App::default();
+    loop {
+        loop {
+            match updates.try_recv() {
+                Ok(update) => app.apply(update),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    app.finished = true;
+                    break;
+                }
+            }
+        }
+        terminal.draw(|f| draw(f, &mut app))?;
+        if !term::poll(Duration::from_millis(100))? {
+            continue;
+        }
+        let Event::Key(key) = term::read()? else { continue };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let selected = app.list.selected().unwrap_or_default();
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                if app.finished {
+                    return Ok(app);
+                }
+                interrupt();
+            }
+            KeyCode::Char('q') | KeyCode::Esc if app.finished => return Ok(app),
+            KeyCode::Up | KeyCode::Char('k') => {
+                app.pinned = true;
+                app.select(selected.saturating_sub(1));
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                app.pinned = true;
+                app.select(selected + 1);
+            }
+            KeyCode::PageUp => app.scroll += 10,
+            KeyCode::PageDown => app.scroll = app.scroll.saturating_sub(10),
+            KeyCode::End => app.scroll = 0,
+            _ => {}
+        }
+    }
+}
+
+/// Run `run` behind the TUI; without a terminal it runs with the normal output
+pub async fn run(run: impl Future<Output = anyhow::Result<PathBuf>>) -> anyhow::Result<PathBuf> {
+    if !std::io::stdout().is_terminal() {
+        eprintln!("--tui needs a terminal; using plain output");
+        return run.await;
+    }
+    let mut terminal = ratatui::try_init()?;
+    util::set_tui(true);
+    util::set_console_observed(true);
+    let updates = events::observe();
+    let ui = std::thread::spawn(move || {
+        let res = ui_loop(&mut terminal, updates);
+        ratatui::restore();
+        res
+    });
+    let result = run.await;
+    events::unobserve();
+    util::set_tui(false);
+    util::set_console_observed(false);
+    // printed after the screen is gone, so nothing is lost
+    let app = ui.join().map_err(|_| anyhow::anyhow!("the TUI thread panicked"))??;
+    for (stderr, text) in &app.messages {
+        if *stderr {
+            eprintln!("{}", text);
+        } else {
+            println!("{}", text);
+        }
+    }
+    result
+}
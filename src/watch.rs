@@ -0,0 +1,127 @@
+//! `rustypipe watch`: run pipelines, then run them again whenever a watched file changes.
+//! Changes are debounced; a change during a run cancels it (like Ctrl+C) and a new run starts
+//! once it has stopped. The pipeline files are always watched; `.rustypipe` and `.git` never are.
+use crate::pipeline::{self, events, RunConfig};
+use crate::{progress, util};
+use anyhow::Context;
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, Notify};
+
+/// Quiet time after the last change before the pipelines run again
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Printed on stderr, or above the progress display while a run owns the terminal
+fn note(text: String) {
+    if util::console_observed() {
+        events::message(text, true);
+    } else {
+        eprintln!("{}", text);
+    }
+}
+
+fn ignored(path: &Path) -> bool {
+    path.components().any(|c| matches!(c, Component::Normal(n) if n == ".rustypipe" || n == ".git"))
+}
+
+/// The first changed path, once no further change came in for `DEBOUNCE`
+async fn next_change(changes: &mut mpsc::UnboundedReceiver<PathBuf>) -> Option<PathBuf> {
+    let first = changes.recv().await?;
+    while let Ok(Some(_)) = tokio::time::timeout(DEBOUNCE, changes.recv()).await {}
+    Some(first)
+}
+
+/// Run `paths` now and on every change below `watch` (default: the pipelines' directories)
+pub async fn watch(paths: &[PathBuf], watch: &[PathBuf], config: &RunConfig) -> anyhow::Result<()> {
+    let canonical = |p: &Path| std::fs::canonicalize(p).with_context(|| format!("cannot watch {}", p.display()));
+    let files = paths.iter().map(|p| canonical(p)).collect::<anyhow::Result<Vec<_>>>()?;
+    let targets = if watch.is_empty() {
+        let mut dirs: Vec<PathBuf> = files.iter().filter_map(|f| f.parent().map(Path::to_path_buf)).collect();
+        dirs.sort();
+        dirs.dedup();
+        dirs
+    } else {
+        watch.iter().map(|p| canonical(p)).collect::<anyhow::Result<Vec<_>>>()?
+    };
+
+    let (tx, mut changes) = mpsc::unbounded_channel();
+    let (watched, pipeline_files) = (targets.clone(), files.clone());
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| match res {
+        Ok(event) if !matches!(event.kind, EventKind::Access(_)) => {
+            let relevant = event
+                .paths
+                .into_iter()
+                .filter(|p| !ignored(p) && (pipeline_files.contains(p) || watched.iter().any(|w| p.starts_with(w))));
+            for path in relevant {
+                let _ = tx.send(path);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => tracing::warn!("watch error: {}", e),
+    })?;
+    for target in &targets {
+        watcher.watch(target, RecursiveMode::Recursive).with_context(|| format!("cannot watch {}", target.display()))?;
+    }
+    // editors replace files on save, so the pipeline files are watched through their directory
+    for dir in files.iter().filter_map(|f| f.parent()).filter(|d| !targets.iter().any(|t| d.starts_with(t))) {
+        watcher.watch(dir, RecursiveMode::NonRecursive).with_context(|| format!("cannot watch {}", dir.display()))?;
+    }
+
+    let quit = Arc::new(Notify::new());
+    {
+        let quit = quit.clone();
+        tokio::spawn(async move {
+            let _ = tokio::signal::ctrl_c().await;
+            quit.notify_one();
+        });
+    }
+
+    loop {
+        let cancel = Arc::new(Notify::new());
+        let config = RunConfig { cancel: Some(cancel.clone()), ..config.clone() };
+        let run = pipeline::run_pipelines(paths, &config);
+        let run = async {
+            if !config.stream && !util::json_output() && progress::available() {
+                progress::run(run).await
+            } else {
+                run.await
+            }
+        };
+        tokio::pin!(run);
+        let (mut restart, mut stopping) = (false, false);
+        let result = loop {
+            tokio::select! {
+                res = &mut run => break res,
+                // the run sees Ctrl+C itself and cancels; leave once it has
+                _ = quit.notified(), if !stopping => stopping = true,
+                Some(path) = next_change(&mut changes), if !restart && !stopping => {
+                    note(format!("{} changed; cancelling the run", path.display()));
+                    cancel.notify_one();
+                    restart = true;
+                }
+            }
+        };
+        match result {
+            // cancelled on purpose, for a restart or Ctrl+C
+            Err(_) if restart || stopping => {}
+            Err(e) => eprintln!("Error: {:#}", e),
+            Ok(_) => {}
+        }
+        if stopping {
+            return Ok(());
+        }
+        if restart {
+            continue;
+        }
+        eprintln!("Watching {} for changes (Ctrl+C to stop)", targets.iter().map(|t| t.display().to_string()).collect::<Vec<_>>().join(", "));
+        tokio::select! {
+            _ = quit.notified() => return Ok(()),
+            change = next_change(&mut changes) => match change {
+                Some(path) => eprintln!("{} changed; running again", path.display()),
+                None => anyhow::bail!("file watcher stopped"),
+            },
+        }
+    }
+}